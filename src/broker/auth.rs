@@ -0,0 +1,60 @@
+//! Extension points for authenticating and authorizing clients.
+
+use crate::{v5, ClientID, TopicFilter, TopicName};
+
+/// Outcome of [Authenticator::authenticate].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AuthResult {
+    /// Accept the connection.
+    Accept,
+    /// Reject the connection, CONNACK is sent with the reason-code.
+    Reject(v5::ConnackReasonCode),
+    /// Continue with enhanced-authentication, AUTH packet shall be sent with the
+    /// authentication data.
+    Continue(Vec<u8>),
+}
+
+/// Trait to be implemented by applications to validate CONNECT packet's
+/// username/password or enhanced-authentication data.
+///
+/// Authenticator is supplied at [Cluster::from_config_with], and is consulted for
+/// every new connection before a session is created for the client.
+///
+/// [Cluster::from_config_with]: crate::broker::Cluster::from_config_with
+pub trait Authenticator {
+    fn authenticate(&self, connect: &v5::Connect) -> AuthResult;
+}
+
+/// Trait to be implemented by applications to enforce per-topic access control.
+///
+/// Authorizer is supplied at [Cluster::from_config_with]. A denied PUBLISH is
+/// acknowledged with `NotAuthorized` for QoS>0 and silently dropped for QoS0. A
+/// denied topic-filter is acknowledged with `NotAuthorized` in SUBACK.
+///
+/// [Cluster::from_config_with]: crate::broker::Cluster::from_config_with
+pub trait Authorizer {
+    fn can_publish(&self, client: &ClientID, topic: &TopicName) -> bool;
+
+    fn can_subscribe(&self, client: &ClientID, filter: &TopicFilter) -> bool;
+}
+
+/// Default authenticator and authorizer, that allows all connections, publishes
+/// and subscriptions.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AllowAll;
+
+impl Authenticator for AllowAll {
+    fn authenticate(&self, _connect: &v5::Connect) -> AuthResult {
+        AuthResult::Accept
+    }
+}
+
+impl Authorizer for AllowAll {
+    fn can_publish(&self, _client: &ClientID, _topic: &TopicName) -> bool {
+        true
+    }
+
+    fn can_subscribe(&self, _client: &ClientID, _filter: &TopicFilter) -> bool {
+        true
+    }
+}
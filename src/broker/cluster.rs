@@ -2,16 +2,20 @@ use log::{debug, error, info, trace};
 use mio::event::Events;
 use uuid::Uuid;
 
-use std::sync::{atomic::AtomicBool, atomic::Ordering::SeqCst, mpsc, Arc};
+use std::sync::{mpsc, Arc};
 use std::{collections::BTreeMap, fmt, net, path, result, time};
 
 use crate::broker::thread::{Rx, Thread, Threadable, Tx};
+use crate::broker::SysPublisher;
 use crate::broker::{rebalance, ticker};
+use crate::broker::{AllowAll, AuthResult, Authenticator, Authorizer};
 use crate::broker::{AppTx, Config, ConfigNode, Hostable, RetainedTrie, SubscribedTrie};
-use crate::broker::{Flusher, Listener, QueueStatus, Shard, Ticker};
+use crate::broker::{AtomicStats, ClientInfo, ConsensusLink, LinkState, StatsSnapshot};
+use crate::broker::{BrokerEvent, EventSink};
+use crate::broker::{ConnGuard, Flusher, Listener, Metrics, QueueStatus, Shard, Ticker};
 
-use crate::{util, v5, Timer, ToJson, TopicName};
-use crate::{Error, ErrorKind, Result};
+use crate::{util, v5, ClientID, ToJson, TopicFilter, TopicName};
+use crate::{Error, ErrorKind, ReasonCode, Result};
 
 type ThreadRx = Rx<Request, Result<Response>>;
 type QueueReq = crate::broker::thread::QueueReq<Request, Result<Response>>;
@@ -22,6 +26,11 @@ pub struct Cluster {
     pub name: String,
     prefix: String,
     config: Config,
+    authenticator: Arc<dyn Authenticator + Send + Sync>,
+    authorizer: Arc<dyn Authorizer + Send + Sync>,
+    event_sink: Option<Arc<dyn EventSink + Send + Sync>>,
+    // Shared with all shards, refer [Cluster::stats].
+    atomic_stats: Arc<AtomicStats>,
     inner: Inner,
 }
 
@@ -72,9 +81,14 @@ struct RunLoop {
     /// Index of retained messages for each topic-name, across all the sessions, local
     /// to this node.
     retained_messages: RetainedTrie, // indexed by TopicName.
+    /// Publish broker statistics under `$SYS/broker/`, if configured.
+    sys_publisher: Option<SysPublisher>,
+    /// Link to peer node's consensus port, registered under TOKEN_CONSENSUS. None
+    /// when [Config::consensus_peer] is not configured.
+    consensus_link: Option<ConsensusLink>,
 
     /// Statistics
-    stats: Stats,
+    stats: ClusterStats,
 
     /// Back channel communicate with application.
     app_tx: AppTx,
@@ -93,22 +107,30 @@ pub struct FinState {
     pub active_shards: Vec<Shard>,
     pub topic_filters: SubscribedTrie,
     pub retained_messages: RetainedTrie,
-    pub retain_timer: Timer<Arc<Retain>>,
-    pub retain_topics: BTreeMap<TopicName, Arc<Retain>>,
-    pub stats: Stats,
+    pub stats: ClusterStats,
 }
 
-#[derive(Clone, Copy, Default)]
-pub struct Stats {
-    n_events: usize,
-    n_requests: usize,
+/// Statistics gathered by the cluster thread, refer [Cluster::cluster_stats].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ClusterStats {
+    /// Number of poll events handled by the cluster thread.
+    pub n_events: usize,
+    /// Number of control requests handled by the cluster thread.
+    pub n_requests: usize,
+    /// Current state of the link to peer node's consensus port, if any.
+    pub consensus_link: Option<LinkState>,
 }
 
 impl FinState {
     fn to_json(&self) -> String {
         format!(
-            concat!("{{ {:?}: {}, {:?}: {} }}"),
-            "n_events", self.stats.n_events, "n_requests", self.stats.n_requests,
+            concat!("{{ {:?}: {}, {:?}: {}, {:?}: {:?} }}"),
+            "n_events",
+            self.stats.n_events,
+            "n_requests",
+            self.stats.n_requests,
+            "consensus_link",
+            format!("{:?}", self.stats.consensus_link),
         )
     }
 }
@@ -120,6 +142,10 @@ impl Default for Cluster {
             name: config.name.to_string(),
             prefix: String::default(),
             config,
+            authenticator: Arc::new(AllowAll),
+            authorizer: Arc::new(AllowAll),
+            event_sink: None,
+            atomic_stats: Arc::default(),
             inner: Inner::Init,
         };
         def.prefix = def.prefix();
@@ -190,7 +216,18 @@ impl Cluster {
     /// Create a cluster from configuration. Returned Cluster shall be in `Init` state.
     /// To start the cluster call [Cluster::spawn].
     pub fn from_config(config: Config) -> Result<Cluster> {
+        Cluster::from_config_with(config, Arc::new(AllowAll), Arc::new(AllowAll))
+    }
+
+    /// Same as [Cluster::from_config], with `authenticator` consulted for every
+    /// new connection and `authorizer` consulted for every PUBLISH and SUBSCRIBE.
+    pub fn from_config_with(
+        config: Config,
+        authenticator: Arc<dyn Authenticator + Send + Sync>,
+        authorizer: Arc<dyn Authorizer + Send + Sync>,
+    ) -> Result<Cluster> {
         // validate
+        config.validate()?;
         if config.num_shards == 0 {
             err!(InvalidInput, desc: "num_shards can't be ZERO")?;
         } else if !util::is_power_of_2(config.num_shards) {
@@ -205,6 +242,10 @@ impl Cluster {
             name: config.name.clone(),
             prefix: String::default(),
             config,
+            authenticator,
+            authorizer,
+            event_sink: None,
+            atomic_stats: Arc::default(),
             inner: Inner::Init,
         };
         val.prefix = val.prefix();
@@ -212,6 +253,11 @@ impl Cluster {
         Ok(val)
     }
 
+    /// Emit [BrokerEvent]s to `sink`. Shall be called before [Cluster::spawn].
+    pub fn set_event_sink(&mut self, sink: Arc<dyn EventSink + Send + Sync>) {
+        self.event_sink = Some(sink);
+    }
+
     /// Start this cluster instance
     pub fn spawn(self, app_tx: AppTx) -> Result<Cluster> {
         use mio::Waker;
@@ -224,16 +270,22 @@ impl Cluster {
             algo: rebalance::Algorithm::SingleNode,
         };
 
-        let state = match self.config.nodes.len() {
+        let (state, uuid) = match self.config.nodes.len() {
             1 => {
                 let node = Node::try_from(self.config.nodes[0].clone())?;
+                let uuid = node.uuid;
                 let topology = rebalancer.rebalance(&vec![node.clone()], Vec::new());
-                ClusterState::SingleNode {
-                    state: SingleNode { config: self.config.clone(), node, topology },
-                }
+                let state = SingleNode { config: self.config.clone(), node, topology };
+                (ClusterState::SingleNode { state }, uuid)
             }
             _ => todo!(),
         };
+        // link is registered with `poll` on its first reconnect, refer
+        // Cluster::consensus_reconnect.
+        let consensus_link = self
+            .config
+            .consensus_peer
+            .map(|peer| ConsensusLink::new(&self.config, uuid, peer));
 
         let flusher = Flusher::from_config(&self.config)?.spawn(app_tx.clone())?;
         let flusher_tx = flusher.to_tx("cluster-spawn");
@@ -245,6 +297,10 @@ impl Cluster {
             name: self.config.name.clone(),
             prefix: String::default(),
             config: self.config.clone(),
+            authenticator: Arc::clone(&self.authenticator),
+            authorizer: Arc::clone(&self.authorizer),
+            event_sink: self.event_sink.clone(),
+            atomic_stats: Arc::clone(&self.atomic_stats),
             inner: Inner::Main(RunLoop {
                 state,
 
@@ -257,8 +313,10 @@ impl Cluster {
                 rebalancer,
                 topic_filters: topic_filters.clone(),
                 retained_messages: retained_messages.clone(),
+                sys_publisher: self.config.sys_interval.map(SysPublisher::new),
+                consensus_link,
 
-                stats: Stats::default(),
+                stats: ClusterStats::default(),
 
                 app_tx: app_tx.clone(),
             }),
@@ -271,6 +329,10 @@ impl Cluster {
             name: self.config.name.clone(),
             prefix: String::default(),
             config: self.config.clone(),
+            authenticator: Arc::clone(&self.authenticator),
+            authorizer: Arc::clone(&self.authorizer),
+            event_sink: self.event_sink.clone(),
+            atomic_stats: Arc::clone(&self.atomic_stats),
             inner: Inner::Handle(waker, thrd),
         };
         cluster.prefix = cluster.prefix();
@@ -309,6 +371,7 @@ impl Cluster {
 
             match &cluster.inner {
                 Inner::Handle(_waker, thrd) => {
+                    let listener = Box::new(listener);
                     thrd.request(Request::Set { listener, ticker, active_shards })??;
                 }
                 inner => unreachable!("{} {:?}", self.prefix, inner),
@@ -326,6 +389,14 @@ impl Cluster {
     }
 
     fn spawn_active_shards(args: SpawnShards) -> Result<BTreeMap<u32, Shard>> {
+        let mut work_queues = match args.config.enable_work_stealing {
+            true => {
+                let shard_ids: Vec<u32> = (0..args.config.num_shards).collect();
+                crate::broker::work_queues(&shard_ids)
+            }
+            false => BTreeMap::default(),
+        };
+
         let mut active_shards = BTreeMap::default();
         for shard_id in 0..args.config.num_shards {
             let shard = {
@@ -334,6 +405,8 @@ impl Cluster {
                     flusher: args.flusher_tx.to_tx("shard"),
                     topic_filters: args.topic_filters.clone(),
                     retained_messages: args.retained_messages.clone(),
+                    atomic_stats: Arc::clone(&args.cluster.atomic_stats),
+                    work_queue: work_queues.remove(&shard_id),
                 };
                 let shard = Shard::from_config(args.config, shard_id)?;
                 shard.spawn_active(spawn_args, args.app_tx)?
@@ -371,6 +444,10 @@ impl Cluster {
             name: self.config.name.clone(),
             prefix: String::default(),
             config: self.config.clone(),
+            authenticator: Arc::clone(&self.authenticator),
+            authorizer: Arc::clone(&self.authorizer),
+            event_sink: self.event_sink.clone(),
+            atomic_stats: Arc::clone(&self.atomic_stats),
             inner,
         };
         val.prefix = val.prefix();
@@ -382,7 +459,7 @@ impl Cluster {
 
 pub enum Request {
     Set {
-        listener: Listener,
+        listener: Box<Listener>,
         ticker: Ticker,
         active_shards: BTreeMap<u32, Shard>,
     },
@@ -393,20 +470,48 @@ pub enum Request {
         topic_name: TopicName,
     },
     AddConnection(AddConnectionArgs),
+    Metrics,
+    Stats,
+    Clients,
+    Drain {
+        deadline: time::Duration,
+    },
     Close,
 }
 
 pub enum Response {
     Ok,
+    Metrics(Metrics),
+    Stats(ClusterStats),
+    Clients(Vec<ClientInfo>),
 }
 
 pub struct AddConnectionArgs {
     pub sock: mio::net::TcpStream,
     pub pkt: v5::Connect,
+    pub guard: Option<ConnGuard>,
 }
 
 // calls to interface with cluster-thread.
 impl Cluster {
+    pub(crate) fn authenticate(&self, connect: &v5::Connect) -> AuthResult {
+        self.authenticator.authenticate(connect)
+    }
+
+    pub(crate) fn emit(&self, ev: BrokerEvent) {
+        if let Some(sink) = &self.event_sink {
+            sink.on_event(ev)
+        }
+    }
+
+    pub(crate) fn can_publish(&self, client: &ClientID, topic: &TopicName) -> bool {
+        self.authorizer.can_publish(client, topic)
+    }
+
+    pub(crate) fn can_subscribe(&self, client: &ClientID, filter: &TopicFilter) -> bool {
+        self.authorizer.can_subscribe(client, filter)
+    }
+
     pub(crate) fn wake(&self) -> Result<()> {
         match &self.inner {
             Inner::Tx(waker, _) => Ok(waker.wake()?),
@@ -450,6 +555,46 @@ impl Cluster {
         Ok(())
     }
 
+    /// Return cluster and shard statistics in Prometheus text exposition format.
+    pub fn metrics_text(&self) -> Result<String> {
+        match &self.inner {
+            Inner::Handle(_waker, thrd) => match thrd.request(Request::Metrics)?? {
+                Response::Metrics(metrics) => Ok(metrics.to_text()),
+                _ => unreachable!(),
+            },
+            inner => unreachable!("{} {:?}", self.prefix, inner),
+        }
+    }
+
+    /// Return statistics gathered by the cluster thread, including the state of the
+    /// link to peer node's consensus port.
+    pub fn cluster_stats(&self) -> Result<ClusterStats> {
+        match &self.inner {
+            Inner::Handle(_waker, thrd) => match thrd.request(Request::Stats)?? {
+                Response::Stats(stats) => Ok(stats),
+                _ => unreachable!(),
+            },
+            inner => unreachable!("{} {:?}", self.prefix, inner),
+        }
+    }
+
+    /// Return a snapshot of clients connected to this cluster, across all shards.
+    pub fn clients(&self) -> Result<Vec<ClientInfo>> {
+        match &self.inner {
+            Inner::Handle(_waker, thrd) => match thrd.request(Request::Clients)?? {
+                Response::Clients(clients) => Ok(clients),
+                _ => unreachable!(),
+            },
+            inner => unreachable!("{} {:?}", self.prefix, inner),
+        }
+    }
+
+    /// Return a near-real-time snapshot of statistics aggregated across all shards.
+    /// Unlike [Cluster::metrics_text], this does not involve any IPC.
+    pub fn stats(&self) -> StatsSnapshot {
+        self.atomic_stats.to_snapshot()
+    }
+
     /// Close this cluster and get back the statistics. Call return only after all the
     /// children threads are gracefully shutdown.
     pub fn close_wait(mut self) -> Cluster {
@@ -464,6 +609,23 @@ impl Cluster {
             inner => unreachable!("{} {:?}", self.prefix, inner),
         }
     }
+
+    /// Drain and close this cluster. Stop accepting new connections, flush pending
+    /// packets to connected clients and disconnect them with `ServerShutdown`. Close
+    /// the cluster once all connections are drained or `deadline` has elapsed.
+    pub fn drain_wait(mut self, deadline: time::Duration) -> Cluster {
+        use std::mem;
+
+        let inner = mem::replace(&mut self.inner, Inner::Init);
+        match inner {
+            Inner::Handle(_waker, thrd) => {
+                thrd.request(Request::Drain { deadline }).ok();
+                thrd.request(Request::Close).ok();
+                thrd.close_wait()
+            }
+            inner => unreachable!("{} {:?}", self.prefix, inner),
+        }
+    }
 }
 
 impl Threadable for Cluster {
@@ -475,26 +637,23 @@ impl Threadable for Cluster {
 
         info!("{} spawn thread config:{}", self.prefix, self.to_config_json());
 
-        let mut rt = Rt {
-            retain_timer: Timer::default(),
-            retain_topics: BTreeMap::default(),
-        };
-
         let mut events = Events::with_capacity(POLL_EVENTS_SIZE);
         loop {
             let timeout: Option<time::Duration> = None;
             allow_panic!(&self, self.as_mut_poll().poll(&mut events, timeout));
 
-            match self.mio_events(&rx, &events, &mut rt) {
+            match self.mio_events(&rx, &events) {
                 true => break,
                 _exit => (),
             };
 
-            self.retain_expires(&mut rt);
+            self.retain_expires();
+            self.sys_publish();
+            self.consensus_reconnect();
         }
 
         match &self.inner {
-            Inner::Main(_) => self.handle_close(Request::Close, &mut rt),
+            Inner::Main(_) => self.handle_close(Request::Close),
             Inner::Close(_) => Response::Ok,
             inner => unreachable!("{} {:?}", self.prefix, inner),
         };
@@ -506,7 +665,7 @@ impl Threadable for Cluster {
 
 impl Cluster {
     // return (exit,)
-    fn mio_events(&mut self, rx: &ThreadRx, events: &Events, rt: &mut Rt) -> bool {
+    fn mio_events(&mut self, rx: &ThreadRx, events: &Events) -> bool {
         let mut count = 0_usize;
         let mut iter = events.iter();
         let exit = 'outer: loop {
@@ -517,14 +676,14 @@ impl Cluster {
                     match event.token() {
                         Self::TOKEN_WAKE => loop {
                             // keep repeating until all control requests are drained
-                            match self.drain_control_chan(rx, rt) {
+                            match self.drain_control_chan(rx) {
                                 (_status, true) => break 'outer true,
                                 (QueueStatus::Ok(_), _exit) => (),
                                 (QueueStatus::Block(_), _) => break,
                                 (QueueStatus::Disconnected(_), _) => break 'outer true,
                             }
                         },
-                        Self::TOKEN_CONSENSUS => todo!(),
+                        Self::TOKEN_CONSENSUS => self.consensus_event(event),
                         _ => unreachable!(),
                     }
                 }
@@ -539,7 +698,7 @@ impl Cluster {
 
     // Return (queue-status, exit)
     // IPCFail,
-    fn drain_control_chan(&mut self, rx: &ThreadRx, rt: &mut Rt) -> (QueueReq, bool) {
+    fn drain_control_chan(&mut self, rx: &ThreadRx) -> (QueueReq, bool) {
         use crate::broker::{thread::pending_requests, CONTROL_CHAN_SIZE};
         use Request::*;
 
@@ -558,17 +717,33 @@ impl Cluster {
                     err!(IPCFail, try: tx.send(Ok(resp))).ok();
                 }
                 (req @ SetRetainTopic { .. }, None) => {
-                    self.handle_set_retain_topic(req, rt);
+                    self.handle_set_retain_topic(req);
                 }
                 (req @ ResetRetainTopic { .. }, None) => {
-                    self.handle_reset_retain_topic(req, rt);
+                    self.handle_reset_retain_topic(req);
                 }
                 (req @ AddConnection(_), Some(tx)) => {
                     let resp = self.handle_add_connection(req);
                     err!(IPCFail, try: tx.send(Ok(resp))).ok();
                 }
+                (Metrics, Some(tx)) => {
+                    let resp = self.handle_metrics();
+                    err!(IPCFail, try: tx.send(resp)).ok();
+                }
+                (Stats, Some(tx)) => {
+                    let resp = self.handle_stats();
+                    err!(IPCFail, try: tx.send(resp)).ok();
+                }
+                (Clients, Some(tx)) => {
+                    let resp = self.handle_clients();
+                    err!(IPCFail, try: tx.send(resp)).ok();
+                }
+                (req @ Drain { .. }, Some(tx)) => {
+                    let resp = self.handle_drain(req);
+                    err!(IPCFail, try: tx.send(Ok(resp))).ok();
+                }
                 (req @ Close, Some(tx)) => {
-                    let resp = self.handle_close(req, rt);
+                    let resp = self.handle_close(req);
                     err!(IPCFail, try: tx.send(Ok(resp))).ok();
                     closed = true;
                 }
@@ -580,27 +755,84 @@ impl Cluster {
         (status, closed)
     }
 
-    fn retain_expires(&mut self, rt: &mut Rt) {
-        use crate::timer::TimeoutValue;
-
+    // Called every time cluster wakes up, ticker shall wake up the cluster thread
+    // periodically.
+    fn retain_expires(&mut self) {
         let RunLoop { retained_messages, .. } = match &mut self.inner {
             Inner::Main(run_loop) => run_loop,
             inner => unreachable!("{} {:?}", self.prefix, inner),
         };
 
-        // gather all retained messages and cleanup the RetainedTrie and
-        // `retain_topics` index.
-        for item in rt.retain_timer.expired(None).collect::<Vec<Arc<Retain>>>() {
-            assert!(item.is_deleted() == false);
+        let n = retained_messages.purge_expired(time::Instant::now());
+        if n > 0 {
+            debug!("{} purged {} expired retain messages", self.prefix, n);
+        }
+    }
+
+    // Called every time cluster wakes up, re-establish a dropped consensus link
+    // once its backoff has elapsed.
+    fn consensus_reconnect(&mut self) {
+        let RunLoop { poll, consensus_link, stats, .. } = match &mut self.inner {
+            Inner::Main(run_loop) => run_loop,
+            inner => unreachable!("{} {:?}", self.prefix, inner),
+        };
+
+        if let Some(link) = consensus_link {
+            link.reconnect(poll.registry(), time::Instant::now());
+            stats.consensus_link = Some(link.to_state());
+        }
+    }
 
-            retained_messages.remove(&item.topic_name);
+    fn consensus_event(&mut self, event: &mio::event::Event) {
+        let RunLoop { poll, consensus_link, stats, .. } = match &mut self.inner {
+            Inner::Main(run_loop) => run_loop,
+            inner => unreachable!("{} {:?}", self.prefix, inner),
+        };
 
-            match rt.retain_topics.remove(&item.topic_name) {
-                Some(_) => (),
-                None => unreachable!(
-                    "{} unexpected, missing topic_name {:?}",
-                    self.prefix, item.topic_name
-                ),
+        match consensus_link {
+            Some(link) => {
+                link.on_event(poll.registry(), event, time::Instant::now());
+                stats.consensus_link = Some(link.to_state());
+            }
+            None => error!("{} consensus event without a link", self.prefix),
+        }
+    }
+
+    // Called every time cluster wakes up, publish `$SYS` topics once sys_interval
+    // has elapsed. Messages are retained here and routed to subscribers via shard.
+    fn sys_publish(&mut self) {
+        let now = time::Instant::now();
+        match &self.inner {
+            Inner::Main(RunLoop { sys_publisher: Some(sp), .. }) if sp.is_due(now) => (),
+            Inner::Main(_) => return,
+            inner => unreachable!("{} {:?}", self.prefix, inner),
+        }
+
+        let metrics = match self.to_metrics() {
+            Ok(metrics) => metrics,
+            Err(err) => {
+                error!("{} fail to gather $SYS metrics err:{}", self.prefix, err);
+                return;
+            }
+        };
+
+        let RunLoop {
+            active_shards, retained_messages, sys_publisher, ..
+        } = match &mut self.inner {
+            Inner::Main(run_loop) => run_loop,
+            inner => unreachable!("{} {:?}", self.prefix, inner),
+        };
+
+        let publishes = sys_publisher.as_mut().unwrap().to_publishes(now, &metrics);
+        if self.config.mqtt_retain_available {
+            for publish in publishes.iter() {
+                retained_messages.set(&publish.topic_name, publish.clone());
+            }
+        }
+
+        if let Some(shard) = active_shards.values().next() {
+            if let Err(err) = shard.publish_sys(publishes) {
+                error!("{} fail to publish $SYS topics err:{}", self.prefix, err);
             }
         }
     }
@@ -617,7 +849,7 @@ impl Cluster {
         match req {
             Request::Set { listener, ticker, active_shards } => {
                 run_loop.ticker = ticker;
-                run_loop.listener = listener;
+                run_loop.listener = *listener;
                 run_loop.active_shards = active_shards;
             }
             _ => unreachable!(),
@@ -626,9 +858,7 @@ impl Cluster {
         Response::Ok
     }
 
-    fn handle_set_retain_topic(&mut self, req: Request, rt: &mut Rt) {
-        use crate::timer::TimeoutValue;
-
+    fn handle_set_retain_topic(&mut self, req: Request) {
         let RunLoop { retained_messages, .. } = match &mut self.inner {
             Inner::Main(run_loop) => run_loop,
             inner => unreachable!("{} {:?}", self.prefix, inner),
@@ -639,33 +869,12 @@ impl Cluster {
             _ => unreachable!(),
         };
 
-        let retain = Arc::new(Retain {
-            topic_name: publish.topic_name.clone(),
-            deleted: AtomicBool::new(false),
-        });
-        match rt.retain_topics.insert(publish.topic_name.clone(), Arc::clone(&retain)) {
-            Some(old_retain) => old_retain.delete(),
-            None => (),
-        };
-
-        // set this retain message as the latest one.
+        // set this retain message as the latest one, RetainedTrie shall book keep
+        // its message expiry.
         retained_messages.set(&publish.topic_name, publish.clone());
-
-        // book keeping for message expiry.
-        match publish
-            .properties
-            .as_ref()
-            .map(|p| p.message_expiry_interval.as_ref())
-            .flatten()
-        {
-            Some(secs) => rt.retain_timer.add_timeout(*secs as u64, retain),
-            None => (),
-        }
     }
 
-    fn handle_reset_retain_topic(&mut self, req: Request, rt: &mut Rt) {
-        use crate::timer::TimeoutValue;
-
+    fn handle_reset_retain_topic(&mut self, req: Request) {
         let RunLoop { retained_messages, .. } = match &mut self.inner {
             Inner::Main(run_loop) => run_loop,
             inner => unreachable!("{} {:?}", self.prefix, inner),
@@ -676,11 +885,6 @@ impl Cluster {
             _ => unreachable!(),
         };
 
-        match rt.retain_topics.remove(&topic_name) {
-            Some(old_retain) => old_retain.delete(), // this will affect retain_timer.
-            None => (),
-        };
-
         retained_messages.remove(&topic_name);
     }
 
@@ -693,7 +897,7 @@ impl Cluster {
             inner => unreachable!("{} {:?}", self.prefix, inner),
         };
 
-        let AddConnectionArgs { sock, pkt: connect } = match req {
+        let AddConnectionArgs { sock, pkt: connect, guard } = match req {
             Request::AddConnection(args) => args,
             _ => unreachable!(),
         };
@@ -701,7 +905,7 @@ impl Cluster {
 
         let client_id = connect.payload.client_id.clone();
         let shard_id =
-            rebalance::Rebalancer::session_partition(&*client_id, self.config.num_shards);
+            rebalance::Rebalancer::session_partition(&client_id, self.config.num_shards);
 
         let shard = match active_shards.get_mut(&shard_id) {
             Some(shard) => shard,
@@ -717,14 +921,108 @@ impl Cluster {
         );
 
         // Add session to the shard.
-        if let Err(err) = shard.add_session(AddSessionArgs { sock, pkt: connect }) {
-            error!("{} error adding session err:{}", self.prefix, err);
+        match shard.add_session(AddSessionArgs { sock, pkt: connect, guard }) {
+            Ok(()) => (),
+            Err(err) if err.kind() == ErrorKind::ShardFull => {
+                error!("{} raddr:{} refused session err:{}", self.prefix, raddr, err);
+            }
+            Err(err) if err.code() == ReasonCode::InvalidClientID => {
+                error!("{} raddr:{} rejected session err:{}", self.prefix, raddr, err);
+            }
+            Err(err) => error!("{} error adding session err:{}", self.prefix, err),
         }
 
         Response::Ok
     }
 
-    fn handle_close(&mut self, _: Request, rt: &mut Rt) -> Response {
+    fn handle_metrics(&self) -> Result<Response> {
+        Ok(Response::Metrics(self.to_metrics()?))
+    }
+
+    fn handle_stats(&self) -> Result<Response> {
+        match &self.inner {
+            Inner::Main(RunLoop { stats, .. }) => Ok(Response::Stats(*stats)),
+            inner => unreachable!("{} {:?}", self.prefix, inner),
+        }
+    }
+
+    fn to_metrics(&self) -> Result<Metrics> {
+        let RunLoop {
+            active_shards,
+            topic_filters,
+            retained_messages,
+            stats,
+            ..
+        } = match &self.inner {
+            Inner::Main(run_loop) => run_loop,
+            inner => unreachable!("{} {:?}", self.prefix, inner),
+        };
+
+        let mut metrics = Metrics {
+            n_events: stats.n_events,
+            n_requests: stats.n_requests,
+            n_subscriptions: topic_filters.len(),
+            n_retained: retained_messages.len(),
+            shards: Vec::with_capacity(active_shards.len()),
+        };
+        for shard in active_shards.values() {
+            metrics.shards.push(shard.to_metrics()?);
+        }
+
+        Ok(metrics)
+    }
+
+    fn handle_clients(&self) -> Result<Response> {
+        let RunLoop { active_shards, .. } = match &self.inner {
+            Inner::Main(run_loop) => run_loop,
+            inner => unreachable!("{} {:?}", self.prefix, inner),
+        };
+
+        let mut clients = vec![];
+        for shard in active_shards.values() {
+            clients.extend(shard.clients()?);
+        }
+
+        Ok(Response::Clients(clients))
+    }
+
+    fn handle_drain(&mut self, req: Request) -> Response {
+        let RunLoop { listener, flusher, active_shards, .. } = match &mut self.inner {
+            Inner::Main(run_loop) => run_loop,
+            inner => unreachable!("{} {:?}", self.prefix, inner),
+        };
+
+        let deadline = match req {
+            Request::Drain { deadline } => time::Instant::now() + deadline,
+            _ => unreachable!(),
+        };
+
+        info!("{} draining cluster", self.prefix);
+        if let Err(err) = listener.pause() {
+            error!("{} pausing listener err:{}", self.prefix, err);
+        }
+
+        for (shard_id, shard) in active_shards.iter() {
+            let now = time::Instant::now();
+            if now > deadline {
+                error!("{} drain deadline elapsed", self.prefix);
+                break;
+            }
+            if let Err(err) = shard.drain(deadline - now) {
+                error!("{} shard_id:{} drain err:{}", self.prefix, shard_id, err);
+            }
+        }
+
+        // drained connections are flushed by the flusher, wait for them.
+        let timeout = deadline.saturating_duration_since(time::Instant::now());
+        if let Err(err) = flusher.flush_wait(timeout) {
+            error!("{} drain flush err:{}", self.prefix, err);
+        }
+
+        Response::Ok
+    }
+
+    fn handle_close(&mut self, _: Request) -> Response {
         use std::mem;
 
         let mut run_loop = match mem::replace(&mut self.inner, Inner::Init) {
@@ -763,8 +1061,6 @@ impl Cluster {
             active_shards: shards,
             topic_filters: run_loop.topic_filters,
             retained_messages: run_loop.retained_messages,
-            retain_timer: mem::replace(&mut rt.retain_timer, Timer::default()),
-            retain_topics: mem::replace(&mut rt.retain_topics, BTreeMap::default()),
             stats: run_loop.stats,
         };
 
@@ -902,22 +1198,6 @@ impl ClusterState {
     }
 }
 
-pub struct Retain {
-    topic_name: TopicName,
-    deleted: AtomicBool,
-}
-
-impl crate::timer::TimeoutValue for Arc<Retain> {
-    fn delete(&self) {
-        self.deleted.store(true, SeqCst);
-    }
-
-    fn is_deleted(&self) -> bool {
-        self.deleted.load(SeqCst)
-    }
-}
-
-struct Rt {
-    retain_timer: Timer<Arc<Retain>>,
-    retain_topics: BTreeMap<TopicName, Arc<Retain>>,
-}
+#[cfg(test)]
+#[path = "cluster_test.rs"]
+mod cluster_test;
@@ -0,0 +1,104 @@
+use std::io::{Read, Write};
+use std::thread;
+
+use crate::{Packetize, SLEEP_10MS};
+
+use super::*;
+
+fn new_cluster() -> (Cluster, u16, mpsc::Receiver<String>) {
+    let port = {
+        let sock = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        sock.local_addr().unwrap().port()
+    };
+    let config = Config { port, num_shards: 1, ..Config::default() };
+    let (app_tx, app_rx) = mpsc::sync_channel(1024);
+    let cluster = Cluster::from_config(config).unwrap().spawn(app_tx).unwrap();
+
+    (cluster, port, app_rx)
+}
+
+fn decode_packets(data: Vec<u8>) -> Vec<v5::Packet> {
+    let mut stream = std::io::Cursor::new(data);
+    let mut packetr = crate::MQTTRead::new(Config::DEF_MQTT_MAX_PACKET_SIZE);
+    let mut pkts = Vec::new();
+    loop {
+        packetr = match packetr.read(&mut stream) {
+            Ok((val, _would_block)) => val,
+            Err(_) => break pkts,
+        };
+        if let crate::MQTTRead::Fin { .. } = &packetr {
+            pkts.push(packetr.parse().unwrap());
+            packetr = packetr.reset();
+        }
+    }
+}
+
+#[test]
+fn test_cluster_drain() {
+    let (cluster, port, _app_rx) = new_cluster();
+
+    let mut client = net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+    let connect = v5::Connect::default();
+    client.write_all(connect.encode().unwrap().as_ref()).unwrap();
+
+    thread::sleep(time::Duration::from_millis(200));
+
+    // don't read the socket until the cluster is drained.
+    let n = 10;
+    for _ in 0..n {
+        client.write_all(v5::Packet::PingReq.encode().unwrap().as_ref()).unwrap();
+    }
+    thread::sleep(time::Duration::from_millis(200));
+
+    cluster.drain_wait(time::Duration::from_secs(5));
+
+    let mut data = Vec::new();
+    client.read_to_end(&mut data).unwrap();
+    let pkts = decode_packets(data);
+
+    assert!(matches!(&pkts[0], v5::Packet::ConnAck(_)));
+    let n_pingresp = pkts.iter().filter(|p| matches!(p, v5::Packet::PingResp)).count();
+    assert_eq!(n_pingresp, n);
+    match pkts.last() {
+        Some(v5::Packet::Disconnect(disconn)) => {
+            assert_eq!(disconn.code, v5::DisconnReasonCode::ServerShutdown)
+        }
+        pkt => panic!("unexpected {:?}", pkt),
+    }
+}
+
+#[test]
+fn test_cluster_consensus_link() {
+    let peer = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = {
+        let sock = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        sock.local_addr().unwrap().port()
+    };
+    let config = Config {
+        port,
+        num_shards: 1,
+        consensus_peer: Some(peer.local_addr().unwrap()),
+        ..Config::default()
+    };
+    let uuid: uuid::Uuid = config.nodes[0].uuid.parse().unwrap();
+    let (app_tx, _app_rx) = mpsc::sync_channel(1024);
+    let cluster = Cluster::from_config(config).unwrap().spawn(app_tx).unwrap();
+
+    // cluster connects to its peer on wake up, and reports the link state.
+    let deadline = time::Instant::now() + time::Duration::from_secs(5);
+    loop {
+        let stats = cluster.cluster_stats().unwrap();
+        match stats.consensus_link {
+            Some(LinkState::Connected) => break,
+            _ if time::Instant::now() < deadline => thread::sleep(SLEEP_10MS),
+            state => panic!("unexpected consensus link {:?}", state),
+        }
+    }
+
+    let (mut conn, _) = peer.accept().unwrap();
+    let mut frame = [0_u8; 20];
+    conn.read_exact(&mut frame).unwrap();
+    assert_eq!(&frame[4..], uuid.as_bytes());
+
+    cluster.close_wait();
+}
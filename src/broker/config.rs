@@ -1,6 +1,6 @@
-use std::{fs, net, path};
+use std::{fmt, fs, net, path, result, str::FromStr};
 
-use crate::util;
+use crate::{util, v5};
 use crate::{Error, ErrorKind, Result};
 
 macro_rules! config_field {
@@ -31,7 +31,7 @@ macro_rules! config_field {
 }
 
 /// Cluster configuration.
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct Config {
     /// Human readable name of the cluster.
     /// * **Default**: None, must be supplied
@@ -62,6 +62,12 @@ pub struct Config {
     /// * **Mutable**: No
     pub nodes: Vec<ConfigNode>,
 
+    /// Endpoints on which this node listens for plain MQTT client connections. If
+    /// not provided, listen on `port`. Refer to [Config::to_listeners].
+    /// * **Default**: [],
+    /// * **Mutable**: No
+    pub listeners: Vec<ConfigListener>,
+
     /// Connect handshake timeout on MQTT socket, in seconds. For every new connection,
     /// this timer will kick in, and within the timeout period if connect/connack
     /// handshake is not complete, connection will be closed.
@@ -71,7 +77,8 @@ pub struct Config {
 
     /// Read timeout on MQTT socket, in seconds. For every new packet this timeout
     /// will kick in, and within the timeout period if a new packet is not completely
-    /// read, connection will be closed.
+    /// read, connection will be closed with `UnspecifiedError`. Idle connections
+    /// are not affected, they are subject to keep-alive.
     /// * **Default**: [Config::DEF_SOCK_MQTT_READ_TIMEOUT]
     /// * **Mutable**: No
     pub sock_mqtt_read_timeout: u32,
@@ -93,6 +100,36 @@ pub struct Config {
     /// * **Mutable**: No
     pub sock_mqtt_flush_timeout: u32,
 
+    /// Initial backoff, in milliseconds, while flushing packets on a blocked MQTT
+    /// socket. Every consecutive block doubles the backoff, upto
+    /// `sock_mqtt_flush_backoff_cap`. A connection that stays blocked beyond
+    /// `sock_mqtt_flush_timeout` is disconnected with `QuotaExceeded`.
+    /// * **Default**: [Config::DEF_SOCK_MQTT_FLUSH_BACKOFF_BASE]
+    /// * **Mutable**: No
+    pub sock_mqtt_flush_backoff_base: u32,
+
+    /// Maximum backoff, in milliseconds, while flushing packets on a blocked MQTT
+    /// socket. Refer to `sock_mqtt_flush_backoff_base`.
+    /// * **Default**: [Config::DEF_SOCK_MQTT_FLUSH_BACKOFF_CAP]
+    /// * **Mutable**: No
+    pub sock_mqtt_flush_backoff_cap: u32,
+
+    /// Set TCP_NODELAY on accepted MQTT sockets, disabling Nagle's algorithm, for
+    /// latency sensitive deployments.
+    /// * **Default**: [Config::DEF_TCP_NODELAY]
+    /// * **Mutable**: No
+    pub tcp_nodelay: bool,
+
+    /// Receive buffer size, SO_RCVBUF, in bytes, for accepted MQTT sockets.
+    /// * **Default**: None, use operating system's default.
+    /// * **Mutable**: No
+    pub so_rcvbuf: Option<u32>,
+
+    /// Send buffer size, SO_SNDBUF, in bytes, for accepted MQTT sockets.
+    /// * **Default**: None, use operating system's default.
+    /// * **Mutable**: No
+    pub so_sndbuf: Option<u32>,
+
     /// Maximum packet size allowed by the broker, this shall be communicated with
     /// remote client during handshake.
     /// * **Default**: [Config::DEF_MQTT_MAX_PACKET_SIZE]
@@ -106,6 +143,13 @@ pub struct Config {
     /// * **Mutable**: No
     pub mqtt_pkt_batch_size: u32,
 
+    /// Maximum number of bytes, read from MQTT sockets, that can be buffered in a
+    /// shard before they are processed by its sessions. Once exceeded, reads on all
+    /// sockets of the shard are held back until buffered packets are drained.
+    /// * **Default**: [Config::DEF_SHARD_MAX_BUFFER_BYTES]
+    /// * **Mutable**: No
+    pub shard_max_buffer_bytes: u32,
+
     /// MQTT Keep Alive, in secs, that server can suggest to the client. If configured
     /// with non-zero value, clients should use this keep-alive instead of the client
     /// configured keep-alive-timeout.
@@ -120,8 +164,17 @@ pub struct Config {
     /// * **Mutable**: No
     pub mqtt_keep_alive_factor: f32,
 
+    /// Maximum MQTT Keep Alive, in secs, that server shall allow. Clients with a
+    /// larger keep-alive, or with keep-alive as ZERO, are clamped to this value and
+    /// the clamped value is advertised in CONNACK's `server_keep_alive`.
+    /// * **Default**: None, no limit
+    /// * **Mutable**: No
+    pub mqtt_max_keep_alive: Option<u32>,
+
     /// MQTT Receive-maximum, control the number of unacknowledged PUBLISH packets
-    /// server can receive and process concurrently for the client.
+    /// server can receive and process concurrently for the client. Advertised to
+    /// the client in CONNACK, and reading PUBLISH packets from the client is paused
+    /// while as many QoS>0 PUBLISH are unacknowledged. Can't be ZERO.
     /// * **Default**: [Config::DEF_MQTT_RECEIVE_MAXIMUM]
     /// * **Mutable**: No
     pub mqtt_receive_maximum: u16,
@@ -135,7 +188,9 @@ pub struct Config {
     pub mqtt_session_expiry_interval: Option<u32>,
 
     /// MQTT `maximum_qos` on the broker side. This is the advertised maximum supported
-    /// QoS level by the broker.
+    /// QoS level by the broker. PUBLISH with higher QoS leads to DISCONNECT, and
+    /// SUBSCRIBE with higher QoS are granted this QoS. Only 0 and 1 are accepted,
+    /// QoS-2 is not yet supported.
     /// * **Default**: [Config::DEF_MQTT_MAX_QOS]
     /// * **Mutable**: No
     pub mqtt_maximum_qos: u8,
@@ -146,6 +201,24 @@ pub struct Config {
     /// * **Mutable**: No
     pub mqtt_retain_available: bool,
 
+    /// MQTT wildcard subscriptions available and supported by broker. Disabling this
+    /// would reject topic-filters containing `#` or `+` wildcards.
+    /// * **Default**: [Config::DEF_MQTT_WILDCARD_SUBSCRIPTION_AVAILABLE]
+    /// * **Mutable**: No
+    pub mqtt_wildcard_subscription_available: bool,
+
+    /// MQTT subscription identifiers available and supported by broker. Disabling
+    /// this would reject SUBSCRIBE carrying subscription-identifier.
+    /// * **Default**: [Config::DEF_MQTT_SUBSCRIPTION_IDENTIFIERS_AVAILABLE]
+    /// * **Mutable**: No
+    pub mqtt_subscription_identifiers_available: bool,
+
+    /// MQTT shared subscriptions available and supported by broker. Disabling this
+    /// would reject topic-filters starting with `$share/`.
+    /// * **Default**: [Config::DEF_MQTT_SHARED_SUBSCRIPTION_AVAILABLE]
+    /// * **Mutable**: No
+    pub mqtt_shared_subscription_available: bool,
+
     /// MQTT Maximum value for topic_alias allowed. Specifying a value of N would mean
     /// broker can handle N-1 aliases for topic-name. Setting this value to ZERO is
     /// same as specifying None, that is, broker won't accept any topic-aliases.
@@ -158,6 +231,195 @@ pub struct Config {
     /// * **Default**: [Config::DEF_MQTT_IGNORE_DUPLICATE]
     /// * **Mutable**: No
     pub mqtt_ignore_duplicate: bool,
+
+    /// Interval, in milliseconds, at which a shard publishes its acknowledgements,
+    /// for messages routed from other shards, as `Message::LocalAck`.
+    /// * **Default**: [Config::DEF_LOCAL_ACK_INTERVAL_MS]
+    /// * **Mutable**: No
+    pub local_ack_interval_ms: u32,
+
+    /// Interval, in milliseconds, at which the ticker thread wakes up the cluster
+    /// and shards.
+    /// * **Default**: [Config::DEF_TICKER_INTERVAL_MS]
+    /// * **Mutable**: No
+    pub ticker_interval_ms: u32,
+
+    /// Upper bound, in milliseconds, for random jitter added to every tick, so that
+    /// wakeups don't fall in lock-step. Setting this value to ZERO disables jitter.
+    /// * **Default**: [Config::DEF_TICKER_JITTER_MS]
+    /// * **Mutable**: No
+    pub ticker_jitter_ms: u32,
+
+    /// Maximum number of live connections that the listener shall admit. Once this
+    /// limit is reached, new connections are refused with CONNACK `QuotaExceeded`.
+    /// * **Default**: None, no limit
+    /// * **Mutable**: No
+    pub max_connections: Option<u32>,
+
+    /// Maximum number of connections the listener shall admit within a second.
+    /// Connections beyond this rate are refused with CONNACK `QuotaExceeded`.
+    /// * **Default**: None, no limit
+    /// * **Mutable**: No
+    pub accept_rate_per_sec: Option<u32>,
+
+    /// Maximum number of sessions, active and waiting to reconnect, that a shard
+    /// shall host. Once this limit is reached, new sessions mapped to the shard are
+    /// refused with CONNACK `QuotaExceeded`.
+    /// * **Default**: None, no limit
+    /// * **Mutable**: No
+    pub max_sessions_per_shard: Option<u32>,
+
+    /// Interval, in seconds, at which broker statistics are published as retained
+    /// messages under `$SYS/broker/`. Refer [SysPublisher] for the list of topics.
+    /// * **Default**: None, `$SYS` topics are not published
+    /// * **Mutable**: No
+    ///
+    /// [SysPublisher]: crate::broker::SysPublisher
+    pub sys_interval: Option<u32>,
+
+    /// Idle timeout, in seconds, for client connections. Sessions that have not sent
+    /// any packet for this duration are disconnected with `KeepAliveTimeout`. This
+    /// applies independent of the negotiated keep-alive, and guards against clients
+    /// that CONNECT with keep-alive ZERO and go silent.
+    /// * **Default**: None, no idle timeout
+    /// * **Mutable**: No
+    pub idle_timeout: Option<u32>,
+
+    /// Maximum number of outgoing PUBLISH messages that can be queued up for a
+    /// session, before they are sent to the client. Once exceeded, refer
+    /// [Config::backlog_policy].
+    /// * **Default**: None, 4 times of [Config::mqtt_pkt_batch_size]
+    /// * **Mutable**: No
+    pub max_backlog_per_session: Option<u32>,
+
+    /// Policy to apply on a session whose outgoing queue exceeds
+    /// [Config::max_backlog_per_session].
+    /// * **Default**: [Config::DEF_BACKLOG_POLICY]
+    /// * **Mutable**: No
+    pub backlog_policy: BacklogPolicy,
+
+    /// Initial backoff, in milliseconds, before reconnecting to a peer node's
+    /// consensus port. Every consecutive failure doubles the backoff, upto
+    /// `consensus_backoff_cap`.
+    /// * **Default**: [Config::DEF_CONSENSUS_BACKOFF_BASE]
+    /// * **Mutable**: No
+    pub consensus_backoff_base: u32,
+
+    /// Maximum backoff, in milliseconds, before reconnecting to a peer node's
+    /// consensus port. Refer to `consensus_backoff_base`.
+    /// * **Default**: [Config::DEF_CONSENSUS_BACKOFF_CAP]
+    /// * **Mutable**: No
+    pub consensus_backoff_cap: u32,
+
+    /// Address of a peer node's consensus port. When configured, cluster maintains
+    /// a [ConsensusLink][crate::broker::ConsensusLink] to the peer, reconnecting
+    /// with backoff whenever the link drops.
+    /// * **Default**: None, no peer.
+    /// * **Mutable**: No
+    pub consensus_peer: Option<net::SocketAddr>,
+
+    /// Response information, a topic prefix that clients shall use to build their
+    /// response-topic for request/response interaction. Sent in CONNACK, only to
+    /// clients that set `request_response_info` in CONNECT.
+    /// * **Default**: None, not sent to clients.
+    /// * **Mutable**: No
+    pub response_information: Option<String>,
+
+    /// Timeout, in seconds, for outgoing PUBLISH QoS-1 and QoS-2 messages that are
+    /// not acknowledged by the client. Timed-out messages are re-delivered with the
+    /// DUP flag set, refer [Config::max_retransmits].
+    /// * **Default**: None, messages are re-delivered only on reconnect.
+    /// * **Mutable**: No
+    pub inflight_timeout: Option<u32>,
+
+    /// Maximum number of times an un-acknowledged PUBLISH message is re-delivered
+    /// to the client, before disconnecting the client. Refer
+    /// [Config::inflight_timeout].
+    /// * **Default**: [Config::DEF_MAX_RETRANSMITS]
+    /// * **Mutable**: No
+    pub max_retransmits: u32,
+
+    /// Maximum number of user-properties allowed in a single packet's properties.
+    /// Packets exceeding this limit are rejected with ProtocolError while decoding.
+    /// * **Default**: [Config::DEF_MAX_USER_PROPERTIES]
+    /// * **Mutable**: No
+    pub max_user_properties: u32,
+
+    /// Enable work-stealing across shards. Broker originated PUBLISH, like `$SYS`
+    /// and will messages, is queued on the shard's work-queue, other shards with
+    /// an empty queue can steal and route them when they wake up for their own
+    /// events. Client PUBLISH is always routed by its session's shard. Refer to
+    /// [WorkQueue].
+    /// * **Default**: [Config::DEF_ENABLE_WORK_STEALING]
+    /// * **Mutable**: No
+    ///
+    /// [WorkQueue]: crate::broker::WorkQueue
+    pub enable_work_stealing: bool,
+
+    /// Reject a CONNECT whose client-id matches a session with a live connection.
+    /// Such connections are refused with CONNACK `ClientIdentifierNotValid`, instead
+    /// of taking over the existing session.
+    /// * **Default**: [Config::DEF_REJECT_DUPLICATE_CLIENT_ID]
+    /// * **Mutable**: No
+    pub reject_duplicate_client_id: bool,
+
+    /// Maximum size, in bytes, of will-payload in CONNECT. Such connections are
+    /// refused with CONNACK QuotaExceeded.
+    /// * **Default**: [Config::DEF_MAX_WILL_PAYLOAD_SIZE]
+    /// * **Mutable**: No
+    pub max_will_payload_size: u32,
+
+    /// Maximum size, in bytes, of payload in retained PUBLISH. Such messages are
+    /// not retained nor routed, and acknowledged with QuotaExceeded.
+    /// * **Default**: [Config::DEF_MAX_RETAINED_PAYLOAD_SIZE]
+    /// * **Mutable**: No
+    pub max_retained_payload_size: u32,
+
+    /// Reject packets carrying a property that is not allowed in that packet, with
+    /// ProtocolError. When false, such properties, if well-formed, are skipped while
+    /// decoding, for forward compatibility.
+    /// * **Default**: [Config::DEF_STRICT_PROPERTIES]
+    /// * **Mutable**: No
+    pub strict_properties: bool,
+
+    /// Forward user properties, supplied by the publishing client, to subscribers.
+    /// When false, user properties are stripped from routed PUBLISH, properties
+    /// added by the broker, like subscription-identifier, are retained.
+    /// * **Default**: [Config::DEF_FORWARD_USER_PROPERTIES]
+    /// * **Mutable**: No
+    pub forward_user_properties: bool,
+}
+
+/// Policy to apply when a session's outgoing queue, of PUBLISH messages, overflows.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BacklogPolicy {
+    /// Drop oldest QoS-0 PUBLISH messages, QoS-1 and QoS-2 messages are never
+    /// dropped. If QoS-1 and QoS-2 messages alone exceed the limit, client is
+    /// disconnected.
+    DropOldestQos0,
+    /// Disconnect the client with `QuotaExceeded`.
+    DisconnectClient,
+}
+
+impl fmt::Display for BacklogPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BacklogPolicy::DropOldestQos0 => write!(f, "drop_oldest_qos0"),
+            BacklogPolicy::DisconnectClient => write!(f, "disconnect_client"),
+        }
+    }
+}
+
+impl FromStr for BacklogPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> result::Result<BacklogPolicy, Error> {
+        match s {
+            "drop_oldest_qos0" => Ok(BacklogPolicy::DropOldestQos0),
+            "disconnect_client" => Ok(BacklogPolicy::DisconnectClient),
+            _ => err!(InvalidInput, desc: "invalid backlog_policy {:?}", s),
+        }
+    }
 }
 
 impl Default for Config {
@@ -169,20 +431,57 @@ impl Default for Config {
             num_shards: util::num_cores_ceiled(),
             port: Self::DEF_MQTT_PORT,
             nodes: vec![node],
+            listeners: Vec::default(),
             sock_mqtt_connect_timeout: Self::DEF_SOCK_MQTT_CONNECT_TIMEOUT,
             sock_mqtt_read_timeout: Self::DEF_SOCK_MQTT_READ_TIMEOUT,
             sock_mqtt_write_timeout: Self::DEF_SOCK_MQTT_WRITE_TIMEOUT,
             sock_mqtt_flush_timeout: Self::DEF_SOCK_MQTT_FLUSH_TIMEOUT,
+            sock_mqtt_flush_backoff_base: Self::DEF_SOCK_MQTT_FLUSH_BACKOFF_BASE,
+            sock_mqtt_flush_backoff_cap: Self::DEF_SOCK_MQTT_FLUSH_BACKOFF_CAP,
+            tcp_nodelay: Self::DEF_TCP_NODELAY,
+            so_rcvbuf: None,
+            so_sndbuf: None,
             mqtt_max_packet_size: Self::DEF_MQTT_MAX_PACKET_SIZE,
             mqtt_pkt_batch_size: Self::DEF_MQTT_PKT_BATCH_SIZE,
+            shard_max_buffer_bytes: Self::DEF_SHARD_MAX_BUFFER_BYTES,
             mqtt_keep_alive: None,
             mqtt_keep_alive_factor: Self::DEF_MQTT_KEEP_ALIVE_FACTOR,
+            mqtt_max_keep_alive: None,
             mqtt_receive_maximum: Self::DEF_MQTT_RECEIVE_MAXIMUM,
             mqtt_session_expiry_interval: None,
             mqtt_maximum_qos: Self::DEF_MQTT_MAX_QOS,
             mqtt_retain_available: Self::DEF_MQTT_RETAIN_AVAILABLE,
+            mqtt_wildcard_subscription_available:
+                Self::DEF_MQTT_WILDCARD_SUBSCRIPTION_AVAILABLE,
+            mqtt_subscription_identifiers_available:
+                Self::DEF_MQTT_SUBSCRIPTION_IDENTIFIERS_AVAILABLE,
+            mqtt_shared_subscription_available:
+                Self::DEF_MQTT_SHARED_SUBSCRIPTION_AVAILABLE,
             mqtt_topic_alias_max: Some(Self::DEF_MQTT_TOPIC_ALIAS_MAX),
             mqtt_ignore_duplicate: Self::DEF_MQTT_IGNORE_DUPLICATE,
+            local_ack_interval_ms: Self::DEF_LOCAL_ACK_INTERVAL_MS,
+            ticker_interval_ms: Self::DEF_TICKER_INTERVAL_MS,
+            ticker_jitter_ms: Self::DEF_TICKER_JITTER_MS,
+            max_connections: None,
+            accept_rate_per_sec: None,
+            max_sessions_per_shard: None,
+            sys_interval: None,
+            idle_timeout: None,
+            max_backlog_per_session: None,
+            backlog_policy: Self::DEF_BACKLOG_POLICY,
+            consensus_backoff_base: Self::DEF_CONSENSUS_BACKOFF_BASE,
+            consensus_backoff_cap: Self::DEF_CONSENSUS_BACKOFF_CAP,
+            consensus_peer: None,
+            response_information: None,
+            inflight_timeout: None,
+            max_retransmits: Self::DEF_MAX_RETRANSMITS,
+            max_user_properties: Self::DEF_MAX_USER_PROPERTIES,
+            enable_work_stealing: Self::DEF_ENABLE_WORK_STEALING,
+            reject_duplicate_client_id: Self::DEF_REJECT_DUPLICATE_CLIENT_ID,
+            max_will_payload_size: Self::DEF_MAX_WILL_PAYLOAD_SIZE,
+            max_retained_payload_size: Self::DEF_MAX_RETAINED_PAYLOAD_SIZE,
+            strict_properties: Self::DEF_STRICT_PROPERTIES,
+            forward_user_properties: Self::DEF_FORWARD_USER_PROPERTIES,
         }
     }
 }
@@ -222,6 +521,31 @@ impl TryFrom<toml::Value> for Config {
                     def,
                     as_integer().map(|n| n.to_string())
                 );
+                config_field!(
+                    t,
+                    sock_mqtt_flush_backoff_base,
+                    def,
+                    as_integer().map(|n| n.to_string())
+                );
+                config_field!(
+                    t,
+                    sock_mqtt_flush_backoff_cap,
+                    def,
+                    as_integer().map(|n| n.to_string())
+                );
+                config_field!(t, tcp_nodelay, def, as_bool().map(|b| b.to_string()));
+                config_field!(
+                    opt: t,
+                    so_rcvbuf,
+                    def,
+                    as_integer().map(|n| n.to_string())
+                );
+                config_field!(
+                    opt: t,
+                    so_sndbuf,
+                    def,
+                    as_integer().map(|n| n.to_string())
+                );
                 config_field!(
                     t,
                     mqtt_max_packet_size,
@@ -234,6 +558,12 @@ impl TryFrom<toml::Value> for Config {
                     def,
                     as_integer().map(|n| n.to_string())
                 );
+                config_field!(
+                    t,
+                    shard_max_buffer_bytes,
+                    def,
+                    as_integer().map(|n| n.to_string())
+                );
                 config_field!(
                     opt: t,
                     mqtt_keep_alive,
@@ -244,6 +574,12 @@ impl TryFrom<toml::Value> for Config {
                     t,
                     mqtt_keep_alive_factor,
                     def,
+                    as_float().map(|n| n.to_string())
+                );
+                config_field!(
+                    opt: t,
+                    mqtt_max_keep_alive,
+                    def,
                     as_integer().map(|n| n.to_string())
                 );
                 config_field!(
@@ -270,11 +606,29 @@ impl TryFrom<toml::Value> for Config {
                     def,
                     as_bool().map(|b| b.to_string())
                 );
+                config_field!(
+                    t,
+                    mqtt_wildcard_subscription_available,
+                    def,
+                    as_bool().map(|b| b.to_string())
+                );
+                config_field!(
+                    t,
+                    mqtt_subscription_identifiers_available,
+                    def,
+                    as_bool().map(|b| b.to_string())
+                );
+                config_field!(
+                    t,
+                    mqtt_shared_subscription_available,
+                    def,
+                    as_bool().map(|b| b.to_string())
+                );
                 config_field!(
                     opt: t,
                     mqtt_topic_alias_max,
                     def,
-                    as_bool().map(|b| b.to_string())
+                    as_integer().map(|n| n.to_string())
                 );
                 config_field!(
                     t,
@@ -282,6 +636,129 @@ impl TryFrom<toml::Value> for Config {
                     def,
                     as_bool().map(|b| b.to_string())
                 );
+                config_field!(
+                    t,
+                    local_ack_interval_ms,
+                    def,
+                    as_integer().map(|n| n.to_string())
+                );
+                config_field!(
+                    t,
+                    ticker_interval_ms,
+                    def,
+                    as_integer().map(|n| n.to_string())
+                );
+                config_field!(
+                    t,
+                    ticker_jitter_ms,
+                    def,
+                    as_integer().map(|n| n.to_string())
+                );
+                config_field!(
+                    opt: t,
+                    max_connections,
+                    def,
+                    as_integer().map(|n| n.to_string())
+                );
+                config_field!(
+                    opt: t,
+                    accept_rate_per_sec,
+                    def,
+                    as_integer().map(|n| n.to_string())
+                );
+                config_field!(
+                    opt: t,
+                    max_sessions_per_shard,
+                    def,
+                    as_integer().map(|n| n.to_string())
+                );
+                config_field!(
+                    opt: t,
+                    sys_interval,
+                    def,
+                    as_integer().map(|n| n.to_string())
+                );
+                config_field!(
+                    opt: t,
+                    idle_timeout,
+                    def,
+                    as_integer().map(|n| n.to_string())
+                );
+                config_field!(
+                    opt: t,
+                    max_backlog_per_session,
+                    def,
+                    as_integer().map(|n| n.to_string())
+                );
+                config_field!(t, backlog_policy, def, as_str());
+                config_field!(
+                    t,
+                    consensus_backoff_base,
+                    def,
+                    as_integer().map(|n| n.to_string())
+                );
+                config_field!(
+                    t,
+                    consensus_backoff_cap,
+                    def,
+                    as_integer().map(|n| n.to_string())
+                );
+                config_field!(opt: t, consensus_peer, def, as_str());
+                config_field!(opt: t, response_information, def, as_str());
+                config_field!(
+                    opt: t,
+                    inflight_timeout,
+                    def,
+                    as_integer().map(|n| n.to_string())
+                );
+                config_field!(
+                    t,
+                    max_retransmits,
+                    def,
+                    as_integer().map(|n| n.to_string())
+                );
+                config_field!(
+                    t,
+                    max_user_properties,
+                    def,
+                    as_integer().map(|n| n.to_string())
+                );
+                config_field!(
+                    t,
+                    enable_work_stealing,
+                    def,
+                    as_bool().map(|b| b.to_string())
+                );
+                config_field!(
+                    t,
+                    reject_duplicate_client_id,
+                    def,
+                    as_bool().map(|b| b.to_string())
+                );
+                config_field!(
+                    t,
+                    max_will_payload_size,
+                    def,
+                    as_integer().map(|n| n.to_string())
+                );
+                config_field!(
+                    t,
+                    max_retained_payload_size,
+                    def,
+                    as_integer().map(|n| n.to_string())
+                );
+                config_field!(
+                    t,
+                    strict_properties,
+                    def,
+                    as_bool().map(|b| b.to_string())
+                );
+                config_field!(
+                    t,
+                    forward_user_properties,
+                    def,
+                    as_bool().map(|b| b.to_string())
+                );
 
                 if let Some(val) = t.get("node").map(|v| v.as_array()).flatten() {
                     def.nodes = vec![];
@@ -289,6 +766,12 @@ impl TryFrom<toml::Value> for Config {
                         def.nodes.push(ConfigNode::try_from(val)?);
                     }
                 }
+                if let Some(val) = t.get("listener").and_then(|v| v.as_array()) {
+                    def.listeners = vec![];
+                    for val in val.clone().into_iter() {
+                        def.listeners.push(ConfigListener::try_from(val)?);
+                    }
+                }
             }
             None => (),
         };
@@ -298,6 +781,61 @@ impl TryFrom<toml::Value> for Config {
 }
 
 impl Config {
+    // List of keys allowed in `[cluster]` table.
+    const FIELDS: &'static [&'static str] = &[
+        "name",
+        "max_nodes",
+        "num_shards",
+        "port",
+        "sock_mqtt_connect_timeout",
+        "sock_mqtt_read_timeout",
+        "sock_mqtt_write_timeout",
+        "sock_mqtt_flush_timeout",
+        "sock_mqtt_flush_backoff_base",
+        "sock_mqtt_flush_backoff_cap",
+        "tcp_nodelay",
+        "so_rcvbuf",
+        "so_sndbuf",
+        "mqtt_max_packet_size",
+        "mqtt_pkt_batch_size",
+        "shard_max_buffer_bytes",
+        "mqtt_keep_alive",
+        "mqtt_keep_alive_factor",
+        "mqtt_max_keep_alive",
+        "mqtt_receive_maximum",
+        "mqtt_session_expiry_interval",
+        "mqtt_maximum_qos",
+        "mqtt_retain_available",
+        "mqtt_wildcard_subscription_available",
+        "mqtt_subscription_identifiers_available",
+        "mqtt_shared_subscription_available",
+        "mqtt_topic_alias_max",
+        "mqtt_ignore_duplicate",
+        "local_ack_interval_ms",
+        "ticker_interval_ms",
+        "ticker_jitter_ms",
+        "max_connections",
+        "accept_rate_per_sec",
+        "max_sessions_per_shard",
+        "sys_interval",
+        "idle_timeout",
+        "max_backlog_per_session",
+        "backlog_policy",
+        "consensus_backoff_base",
+        "consensus_backoff_cap",
+        "consensus_peer",
+        "response_information",
+        "inflight_timeout",
+        "max_retransmits",
+        "max_user_properties",
+        "enable_work_stealing",
+        "reject_duplicate_client_id",
+        "max_will_payload_size",
+        "max_retained_payload_size",
+        "strict_properties",
+        "forward_user_properties",
+    ];
+
     /// Refer to [Config::port]
     pub const DEF_MQTT_PORT: u16 = 1883;
     /// Refer to [Config::max_nodes]
@@ -310,10 +848,18 @@ impl Config {
     pub const DEF_SOCK_MQTT_WRITE_TIMEOUT: u32 = 5; // in seconds.
     /// Refer to [Config::sock_mqtt_flush_timeout]
     pub const DEF_SOCK_MQTT_FLUSH_TIMEOUT: u32 = 10; // in seconds.
+    /// Refer to [Config::sock_mqtt_flush_backoff_base]
+    pub const DEF_SOCK_MQTT_FLUSH_BACKOFF_BASE: u32 = 10; // in milliseconds.
+    /// Refer to [Config::sock_mqtt_flush_backoff_cap]
+    pub const DEF_SOCK_MQTT_FLUSH_BACKOFF_CAP: u32 = 1000; // in milliseconds.
+    /// Refer to [Config::tcp_nodelay]
+    pub const DEF_TCP_NODELAY: bool = false;
     /// Refer to [Config::mqtt_max_packet_size]
     pub const DEF_MQTT_MAX_PACKET_SIZE: u32 = 1024 * 1024; // default is 1MB.
     /// Refer to [Config::mqtt_pkt_batch_size]
     pub const DEF_MQTT_PKT_BATCH_SIZE: u32 = 1024; // default is 1MB.
+    /// Refer to [Config::shard_max_buffer_bytes]
+    pub const DEF_SHARD_MAX_BUFFER_BYTES: u32 = 64 * 1024 * 1024; // default is 64MB.
     /// Refer to [Config::mqtt_keep_alive_factor]
     pub const DEF_MQTT_KEEP_ALIVE_FACTOR: f32 = 1.5; // suggested by the spec.
     /// Refer to [Config::mqtt_receive_maximum]
@@ -322,10 +868,44 @@ impl Config {
     pub const DEF_MQTT_MAX_QOS: u8 = 1;
     /// Refer to [Config::mqtt_retain_available]
     pub const DEF_MQTT_RETAIN_AVAILABLE: bool = true;
+    /// Refer to [Config::mqtt_wildcard_subscription_available]
+    pub const DEF_MQTT_WILDCARD_SUBSCRIPTION_AVAILABLE: bool = true;
+    /// Refer to [Config::mqtt_subscription_identifiers_available]
+    pub const DEF_MQTT_SUBSCRIPTION_IDENTIFIERS_AVAILABLE: bool = true;
+    /// Refer to [Config::mqtt_shared_subscription_available]
+    pub const DEF_MQTT_SHARED_SUBSCRIPTION_AVAILABLE: bool = false;
     /// Refer to [Config::mqtt_topic_alias_max]
     pub const DEF_MQTT_TOPIC_ALIAS_MAX: u16 = 65535;
     /// Refer to [Config::mqtt_ignore_duplicate]
     pub const DEF_MQTT_IGNORE_DUPLICATE: bool = true;
+    /// Refer to [Config::local_ack_interval_ms]
+    pub const DEF_LOCAL_ACK_INTERVAL_MS: u32 = 30;
+    /// Refer to [Config::ticker_interval_ms]
+    pub const DEF_TICKER_INTERVAL_MS: u32 = 10;
+    /// Refer to [Config::ticker_jitter_ms]
+    pub const DEF_TICKER_JITTER_MS: u32 = 0;
+    /// Refer to [Config::backlog_policy]
+    pub const DEF_BACKLOG_POLICY: BacklogPolicy = BacklogPolicy::DisconnectClient;
+    /// Refer to [Config::consensus_backoff_base]
+    pub const DEF_CONSENSUS_BACKOFF_BASE: u32 = 1000; // in milliseconds.
+    /// Refer to [Config::consensus_backoff_cap]
+    pub const DEF_CONSENSUS_BACKOFF_CAP: u32 = 60_000; // in milliseconds.
+    /// Refer to [Config::max_retransmits]
+    pub const DEF_MAX_RETRANSMITS: u32 = 3;
+    /// Refer to [Config::max_user_properties]
+    pub const DEF_MAX_USER_PROPERTIES: u32 = v5::MAX_USER_PROPERTIES as u32;
+    /// Refer to [Config::enable_work_stealing]
+    pub const DEF_ENABLE_WORK_STEALING: bool = false;
+    /// Refer to [Config::reject_duplicate_client_id]
+    pub const DEF_REJECT_DUPLICATE_CLIENT_ID: bool = false;
+    /// Refer to [Config::max_will_payload_size]
+    pub const DEF_MAX_WILL_PAYLOAD_SIZE: u32 = 64 * 1024; // default is 64KB.
+    /// Refer to [Config::max_retained_payload_size]
+    pub const DEF_MAX_RETAINED_PAYLOAD_SIZE: u32 = 256 * 1024; // default is 256KB.
+    /// Refer to [Config::strict_properties]
+    pub const DEF_STRICT_PROPERTIES: bool = true;
+    /// Refer to [Config::forward_user_properties]
+    pub const DEF_FORWARD_USER_PROPERTIES: bool = true;
 
     /// Construct a new configuration from a file located by `loc`.
     pub fn from_file<P>(loc: P) -> Result<Config>
@@ -345,13 +925,250 @@ impl Config {
         Config::try_from(val)
     }
 
+    /// Construct a new configuration from a toml file located by `loc`. Cluster
+    /// parameters are parsed from `[cluster]` table and nodes from `[[node]]` tables.
+    /// Unknown keys are treated as error, and the parsed configuration is validated
+    /// using [Config::validate].
+    pub fn from_toml(loc: &path::Path) -> Result<Config> {
+        use std::str::from_utf8;
+
+        let data = err!(IOError, try: fs::read(loc), "reading config from {:?}", loc)?;
+        let s = err!(InvalidInput, try: from_utf8(&data), "config not utf8 {:?}", loc)?;
+
+        let val: toml::Value =
+            err!(InvalidInput, try: toml::from_str(s), "config not toml {:?}", loc)?;
+
+        let root = match val.as_table() {
+            Some(root) => root,
+            None => err!(InvalidInput, desc: "config not a table {:?}", loc)?,
+        };
+        check_keys("config", root, &["cluster", "node", "listener"])?;
+
+        let mut config = match root.get("cluster") {
+            Some(val) if val.is_table() => {
+                let t = val.as_table().unwrap();
+                check_keys("cluster", t, Self::FIELDS)?;
+                Config::try_from(val.clone())?
+            }
+            Some(val) => err!(InvalidInput, desc: "invalid cluster table {}", val)?,
+            None => Config::default(),
+        };
+
+        match root.get("node").map(|v| v.as_array()) {
+            Some(Some(vals)) => {
+                config.nodes = vec![];
+                for val in vals.iter() {
+                    config.nodes.push(ConfigNode::from_toml(val)?);
+                }
+            }
+            Some(None) => err!(InvalidInput, desc: "invalid node tables")?,
+            None => (),
+        }
+
+        match root.get("listener").map(|v| v.as_array()) {
+            Some(Some(vals)) => {
+                config.listeners = vec![];
+                for val in vals.iter() {
+                    config.listeners.push(ConfigListener::from_toml(val)?);
+                }
+            }
+            Some(None) => err!(InvalidInput, desc: "invalid listener tables")?,
+            None => (),
+        }
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Serialize this configuration into toml text, that can be loaded back using
+    /// [Config::from_toml].
+    pub fn to_toml(&self) -> String {
+        use toml::{map::Map, Value};
+
+        let int = |n: u32| Value::Integer(n.into());
+
+        let mut t = Map::new();
+        t.insert("name".to_string(), Value::String(self.name.clone()));
+        t.insert("max_nodes".to_string(), int(self.max_nodes));
+        t.insert("num_shards".to_string(), int(self.num_shards));
+        t.insert("port".to_string(), int(self.port.into()));
+        t.insert(
+            "sock_mqtt_connect_timeout".to_string(),
+            int(self.sock_mqtt_connect_timeout),
+        );
+        t.insert("sock_mqtt_read_timeout".to_string(), int(self.sock_mqtt_read_timeout));
+        t.insert(
+            "sock_mqtt_write_timeout".to_string(),
+            int(self.sock_mqtt_write_timeout),
+        );
+        t.insert(
+            "sock_mqtt_flush_timeout".to_string(),
+            int(self.sock_mqtt_flush_timeout),
+        );
+        t.insert(
+            "sock_mqtt_flush_backoff_base".to_string(),
+            int(self.sock_mqtt_flush_backoff_base),
+        );
+        t.insert(
+            "sock_mqtt_flush_backoff_cap".to_string(),
+            int(self.sock_mqtt_flush_backoff_cap),
+        );
+        t.insert("tcp_nodelay".to_string(), Value::Boolean(self.tcp_nodelay));
+        if let Some(val) = self.so_rcvbuf {
+            t.insert("so_rcvbuf".to_string(), int(val));
+        }
+        if let Some(val) = self.so_sndbuf {
+            t.insert("so_sndbuf".to_string(), int(val));
+        }
+        t.insert("mqtt_max_packet_size".to_string(), int(self.mqtt_max_packet_size));
+        t.insert("mqtt_pkt_batch_size".to_string(), int(self.mqtt_pkt_batch_size));
+        t.insert("shard_max_buffer_bytes".to_string(), int(self.shard_max_buffer_bytes));
+        if let Some(val) = self.mqtt_keep_alive {
+            t.insert("mqtt_keep_alive".to_string(), int(val));
+        }
+        t.insert(
+            "mqtt_keep_alive_factor".to_string(),
+            Value::Float(self.mqtt_keep_alive_factor.into()),
+        );
+        if let Some(val) = self.mqtt_max_keep_alive {
+            t.insert("mqtt_max_keep_alive".to_string(), int(val));
+        }
+        t.insert(
+            "mqtt_receive_maximum".to_string(),
+            int(self.mqtt_receive_maximum.into()),
+        );
+        if let Some(val) = self.mqtt_session_expiry_interval {
+            t.insert("mqtt_session_expiry_interval".to_string(), int(val));
+        }
+        t.insert("mqtt_maximum_qos".to_string(), int(self.mqtt_maximum_qos.into()));
+        t.insert(
+            "mqtt_retain_available".to_string(),
+            Value::Boolean(self.mqtt_retain_available),
+        );
+        t.insert(
+            "mqtt_wildcard_subscription_available".to_string(),
+            Value::Boolean(self.mqtt_wildcard_subscription_available),
+        );
+        t.insert(
+            "mqtt_subscription_identifiers_available".to_string(),
+            Value::Boolean(self.mqtt_subscription_identifiers_available),
+        );
+        t.insert(
+            "mqtt_shared_subscription_available".to_string(),
+            Value::Boolean(self.mqtt_shared_subscription_available),
+        );
+        if let Some(val) = self.mqtt_topic_alias_max {
+            t.insert("mqtt_topic_alias_max".to_string(), int(val.into()));
+        }
+        t.insert(
+            "mqtt_ignore_duplicate".to_string(),
+            Value::Boolean(self.mqtt_ignore_duplicate),
+        );
+        t.insert("local_ack_interval_ms".to_string(), int(self.local_ack_interval_ms));
+        t.insert("ticker_interval_ms".to_string(), int(self.ticker_interval_ms));
+        t.insert("ticker_jitter_ms".to_string(), int(self.ticker_jitter_ms));
+        if let Some(val) = self.max_connections {
+            t.insert("max_connections".to_string(), int(val));
+        }
+        if let Some(val) = self.accept_rate_per_sec {
+            t.insert("accept_rate_per_sec".to_string(), int(val));
+        }
+        if let Some(val) = self.max_sessions_per_shard {
+            t.insert("max_sessions_per_shard".to_string(), int(val));
+        }
+        if let Some(val) = self.sys_interval {
+            t.insert("sys_interval".to_string(), int(val));
+        }
+        if let Some(val) = self.idle_timeout {
+            t.insert("idle_timeout".to_string(), int(val));
+        }
+        if let Some(val) = self.max_backlog_per_session {
+            t.insert("max_backlog_per_session".to_string(), int(val));
+        }
+        t.insert(
+            "backlog_policy".to_string(),
+            Value::String(self.backlog_policy.to_string()),
+        );
+        t.insert("consensus_backoff_base".to_string(), int(self.consensus_backoff_base));
+        t.insert("consensus_backoff_cap".to_string(), int(self.consensus_backoff_cap));
+        if let Some(val) = self.consensus_peer {
+            t.insert("consensus_peer".to_string(), Value::String(val.to_string()));
+        }
+        if let Some(val) = &self.response_information {
+            t.insert("response_information".to_string(), Value::String(val.clone()));
+        }
+        if let Some(val) = self.inflight_timeout {
+            t.insert("inflight_timeout".to_string(), int(val));
+        }
+        t.insert("max_retransmits".to_string(), int(self.max_retransmits));
+        t.insert("max_user_properties".to_string(), int(self.max_user_properties));
+        t.insert(
+            "enable_work_stealing".to_string(),
+            Value::Boolean(self.enable_work_stealing),
+        );
+        t.insert(
+            "reject_duplicate_client_id".to_string(),
+            Value::Boolean(self.reject_duplicate_client_id),
+        );
+        t.insert("max_will_payload_size".to_string(), int(self.max_will_payload_size));
+        t.insert(
+            "max_retained_payload_size".to_string(),
+            int(self.max_retained_payload_size),
+        );
+        t.insert("strict_properties".to_string(), Value::Boolean(self.strict_properties));
+        t.insert(
+            "forward_user_properties".to_string(),
+            Value::Boolean(self.forward_user_properties),
+        );
+
+        let nodes: Vec<Value> = self.nodes.iter().map(|n| n.to_toml_value()).collect();
+        let listeners: Vec<Value> =
+            self.listeners.iter().map(|l| l.to_toml_value()).collect();
+
+        let mut root = Map::new();
+        root.insert("cluster".to_string(), Value::Table(t));
+        root.insert("node".to_string(), Value::Array(nodes));
+        if !listeners.is_empty() {
+            root.insert("listener".to_string(), Value::Array(listeners));
+        }
+
+        Value::Table(root).to_string()
+    }
+
+    /// Validate configuration, fields that can cause runtime failures are checked
+    /// here, before spawning the cluster.
     pub fn validate(&self) -> Result<()> {
+        let val = self.max_nodes;
+        if val == 0 {
+            err!(InvalidInput, desc: "max_nodes can't be ZERO")?;
+        }
+        let val = self.sock_mqtt_read_timeout;
+        if val == 0 {
+            err!(InvalidInput, desc: "sock_mqtt_read_timeout can't be ZERO")?;
+        }
+        let val = self.sock_mqtt_write_timeout;
+        if val == 0 {
+            err!(InvalidInput, desc: "sock_mqtt_write_timeout can't be ZERO")?;
+        }
         let val = self.mqtt_max_packet_size;
-        if val > 268435456 {
-            err!(InvalidInput, desc: "mqtt_max_packet_size is {}", val)
-        } else {
-            Ok(())
+        if val == 0 || val > 268435456 {
+            err!(InvalidInput, desc: "mqtt_max_packet_size is {}", val)?;
+        }
+        let val = self.mqtt_pkt_batch_size;
+        if val == 0 {
+            err!(InvalidInput, desc: "mqtt_pkt_batch_size can't be ZERO")?;
+        }
+        if self.mqtt_receive_maximum == 0 {
+            err!(InvalidInput, desc: "mqtt_receive_maximum can't be ZERO")?;
         }
+        // TODO: allow QoS-2 once exactly-once delivery is implemented.
+        let val = self.mqtt_maximum_qos;
+        if val > 1 {
+            err!(InvalidInput, desc: "mqtt_maximum_qos is {}, QoS-2 not supported", val)?;
+        }
+
+        Ok(())
     }
 
     pub fn mqtt_keep_alive(&self) -> Option<u32> {
@@ -361,6 +1178,38 @@ impl Config {
         }
     }
 
+    pub fn mqtt_max_keep_alive(&self) -> Option<u16> {
+        match self.mqtt_max_keep_alive {
+            Some(0) | None => None,
+            Some(val) => Some(u16::try_from(val).unwrap_or(u16::MAX)),
+        }
+    }
+
+    /// Return [Config::mqtt_maximum_qos] as QoS, validated by [Config::validate].
+    pub fn mqtt_maximum_qos(&self) -> v5::QoS {
+        v5::QoS::from_u8_saturating(self.mqtt_maximum_qos)
+    }
+
+    /// Return the limits and policies applied while decoding packets from clients.
+    pub fn to_decode_config(&self) -> v5::DecodeConfig {
+        v5::DecodeConfig {
+            max_user_properties: self.max_user_properties as usize,
+            strict_properties: self.strict_properties,
+        }
+    }
+
+    /// Return the list of endpoints to listen on, defaults to plain MQTT on all
+    /// interfaces using [Config::port].
+    pub fn to_listeners(&self) -> Vec<ConfigListener> {
+        match self.listeners.len() {
+            0 => {
+                let address = net::SocketAddr::from(([0, 0, 0, 0], self.port));
+                vec![ConfigListener { address }]
+            }
+            _ => self.listeners.clone(),
+        }
+    }
+
     pub fn mqtt_topic_alias_max(&self) -> Option<u16> {
         match &self.mqtt_topic_alias_max {
             Some(0) => None,
@@ -371,7 +1220,7 @@ impl Config {
 }
 
 /// Node configuration
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct ConfigNode {
     /// Unique identifier for this node within this cluster. There may be other
     /// requirement on the unique-id, like randomness, cyptographic security, public-key.
@@ -423,3 +1272,106 @@ impl TryFrom<toml::Value> for ConfigNode {
         Ok(def)
     }
 }
+
+impl ConfigNode {
+    // List of keys allowed in `[[node]]` table.
+    const FIELDS: &'static [&'static str] = &["uuid", "mqtt_address", "path", "weight"];
+
+    /// Construct node configuration from a `[[node]]` table. Unlike the `TryFrom`
+    /// conversion, unknown keys are treated as error.
+    pub fn from_toml(val: &toml::Value) -> Result<ConfigNode> {
+        match val.as_table() {
+            Some(t) => check_keys("node", t, Self::FIELDS)?,
+            None => err!(InvalidInput, desc: "invalid node table {}", val)?,
+        }
+
+        ConfigNode::try_from(val.clone())
+    }
+
+    fn to_toml_value(&self) -> toml::Value {
+        use toml::{map::Map, Value};
+
+        let mut t = Map::new();
+        t.insert("uuid".to_string(), Value::String(self.uuid.clone()));
+        t.insert(
+            "mqtt_address".to_string(),
+            Value::String(self.mqtt_address.to_string()),
+        );
+        t.insert("path".to_string(), Value::String(self.path.display().to_string()));
+        if let Some(weight) = self.weight {
+            t.insert("weight".to_string(), Value::Integer(weight.into()));
+        }
+
+        Value::Table(t)
+    }
+}
+
+/// Listen endpoint configuration, from `[[listener]]` table. Connections accepted
+/// on the endpoint are plain MQTT over TCP.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ConfigListener {
+    /// Address on which the node listens, and clients can connect.
+    /// * **Default**: "0.0.0.0:1883"
+    /// * **Mutable**: No
+    pub address: net::SocketAddr,
+}
+
+impl Default for ConfigListener {
+    fn default() -> ConfigListener {
+        ConfigListener { address: "0.0.0.0:1883".parse().unwrap() }
+    }
+}
+
+impl TryFrom<toml::Value> for ConfigListener {
+    type Error = Error;
+
+    fn try_from(val: toml::Value) -> Result<ConfigListener> {
+        let mut def = ConfigListener::default();
+
+        if let Some(t) = val.as_table() {
+            config_field!(t, address, def, as_str());
+        }
+
+        Ok(def)
+    }
+}
+
+impl ConfigListener {
+    // List of keys allowed in `[[listener]]` table.
+    const FIELDS: &'static [&'static str] = &["address"];
+
+    /// Construct listener configuration from a `[[listener]]` table. Unknown keys are
+    /// treated as error.
+    pub fn from_toml(val: &toml::Value) -> Result<ConfigListener> {
+        match val.as_table() {
+            Some(t) => check_keys("listener", t, Self::FIELDS)?,
+            None => err!(InvalidInput, desc: "invalid listener table {}", val)?,
+        }
+
+        ConfigListener::try_from(val.clone())
+    }
+
+    fn to_toml_value(&self) -> toml::Value {
+        use toml::{map::Map, Value};
+
+        let mut t = Map::new();
+        t.insert("address".to_string(), Value::String(self.address.to_string()));
+
+        Value::Table(t)
+    }
+}
+
+fn check_keys(
+    table: &str,
+    t: &toml::map::Map<String, toml::Value>,
+    keys: &[&str],
+) -> Result<()> {
+    match t.keys().find(|key| !keys.contains(&key.as_str())) {
+        Some(key) => err!(InvalidInput, desc: "unknown key {:?} in {} table", key, table),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+#[path = "config_test.rs"]
+mod config_test;
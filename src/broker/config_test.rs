@@ -0,0 +1,101 @@
+use super::*;
+
+#[test]
+fn test_config_validate() {
+    Config::default().validate().unwrap();
+
+    let configs = [
+        ("max_nodes", Config { max_nodes: 0, ..Config::default() }),
+        (
+            "sock_mqtt_read_timeout",
+            Config { sock_mqtt_read_timeout: 0, ..Config::default() },
+        ),
+        (
+            "sock_mqtt_write_timeout",
+            Config { sock_mqtt_write_timeout: 0, ..Config::default() },
+        ),
+        ("mqtt_max_packet_size", Config { mqtt_max_packet_size: 0, ..Config::default() }),
+        (
+            "mqtt_max_packet_size",
+            Config {
+                mqtt_max_packet_size: 268435457,
+                ..Config::default()
+            },
+        ),
+        ("mqtt_pkt_batch_size", Config { mqtt_pkt_batch_size: 0, ..Config::default() }),
+        ("mqtt_receive_maximum", Config { mqtt_receive_maximum: 0, ..Config::default() }),
+        ("mqtt_maximum_qos", Config { mqtt_maximum_qos: 2, ..Config::default() }),
+        ("mqtt_maximum_qos", Config { mqtt_maximum_qos: 3, ..Config::default() }),
+    ];
+
+    for (field, config) in configs.into_iter() {
+        let err = config.validate().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput, "{}", field);
+        assert!(err.to_string().contains(field), "{} {}", field, err);
+    }
+}
+
+#[test]
+fn test_config_toml() {
+    let loc =
+        std::env::temp_dir().join(format!("mymq-config-{}.toml", std::process::id()));
+
+    let config = Config {
+        name: "test-cluster".to_string(),
+        num_shards: 4,
+        tcp_nodelay: true,
+        so_rcvbuf: Some(65536),
+        mqtt_keep_alive: Some(30),
+        mqtt_max_keep_alive: Some(300),
+        mqtt_topic_alias_max: Some(16),
+        max_connections: Some(1000),
+        max_sessions_per_shard: Some(64),
+        sys_interval: Some(10),
+        idle_timeout: Some(300),
+        max_backlog_per_session: Some(4096),
+        backlog_policy: BacklogPolicy::DropOldestQos0,
+        consensus_backoff_base: 500,
+        consensus_backoff_cap: 8000,
+        consensus_peer: Some("10.0.0.2:1884".parse().unwrap()),
+        response_information: Some("response/".to_string()),
+        inflight_timeout: Some(30),
+        max_retransmits: 5,
+        max_user_properties: 64,
+        enable_work_stealing: true,
+        reject_duplicate_client_id: true,
+        max_will_payload_size: 1024,
+        max_retained_payload_size: 2048,
+        strict_properties: false,
+        forward_user_properties: false,
+        nodes: vec![ConfigNode::default(), ConfigNode::default()],
+        listeners: vec![
+            ConfigListener::default(),
+            ConfigListener { address: "0.0.0.0:8883".parse().unwrap() },
+        ],
+        ..Config::default()
+    };
+    fs::write(&loc, config.to_toml()).unwrap();
+    assert_eq!(Config::from_toml(&loc).unwrap(), config);
+
+    // unknown keys are rejected.
+    for text in [
+        "[cluster]\nname = \"x\"\nnum_shardz = 2\n",
+        "[cluster]\nname = \"x\"\n[[node]]\nuuid = \"x\"\nweigth = 1\n",
+        "[cluster]\nname = \"x\"\n[server]\nport = 1883\n",
+    ] {
+        fs::write(&loc, text).unwrap();
+        let err = Config::from_toml(&loc).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput, "{}", text);
+    }
+
+    // parsed config is validated.
+    fs::write(&loc, "[cluster]\nmqtt_pkt_batch_size = 0\n").unwrap();
+    assert!(Config::from_toml(&loc).is_err());
+
+    // listeners are plain MQTT, there is no transport to choose.
+    fs::write(&loc, "[[listener]]\ntransport = \"tls\"\n").unwrap();
+    let err = Config::from_toml(&loc).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+    fs::remove_file(&loc).ok();
+}
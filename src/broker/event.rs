@@ -0,0 +1,120 @@
+use log::error;
+
+use std::{fs, io::Write, net, path, sync::Mutex};
+
+use crate::{v5, ClientID, ReasonCode, TopicFilter, TopicName};
+use crate::{Error, ErrorKind, Result};
+
+/// Broker events, emitted to [EventSink] for observability pipelines.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BrokerEvent {
+    ClientConnected {
+        client_id: ClientID,
+        addr: net::SocketAddr,
+    },
+    ClientDisconnected {
+        client_id: ClientID,
+        reason: ReasonCode,
+    },
+    Subscribed {
+        client_id: ClientID,
+        filters: Vec<TopicFilter>,
+    },
+    Published {
+        client_id: ClientID,
+        topic: TopicName,
+        qos: v5::QoS,
+        len: usize,
+    },
+}
+
+impl BrokerEvent {
+    /// Return the event as single line JSON object.
+    pub fn to_json(&self) -> String {
+        use BrokerEvent::*;
+
+        match self {
+            ClientConnected { client_id, addr } => format!(
+                concat!("{{ {:?}: {:?}, {:?}: {:?}, {:?}: {:?} }}"),
+                "event",
+                "client_connected",
+                "client_id",
+                client_id.as_str(),
+                "addr",
+                addr.to_string(),
+            ),
+            ClientDisconnected { client_id, reason } => format!(
+                concat!("{{ {:?}: {:?}, {:?}: {:?}, {:?}: {:?} }}"),
+                "event",
+                "client_disconnected",
+                "client_id",
+                client_id.as_str(),
+                "reason",
+                reason.to_string(),
+            ),
+            Subscribed { client_id, filters } => format!(
+                concat!("{{ {:?}: {:?}, {:?}: {:?}, {:?}: {:?} }}"),
+                "event",
+                "subscribed",
+                "client_id",
+                client_id.as_str(),
+                "filters",
+                filters.iter().map(|f| f.as_str()).collect::<Vec<&str>>(),
+            ),
+            Published { client_id, topic, qos, len } => format!(
+                concat!("{{ {:?}: {:?}, {:?}: {:?}, {:?}: {:?}, {:?}: {}, {:?}: {} }}"),
+                "event",
+                "published",
+                "client_id",
+                client_id.as_str(),
+                "topic",
+                topic.as_str(),
+                "qos",
+                *qos as u8,
+                "len",
+                len,
+            ),
+        }
+    }
+}
+
+/// Trait to be implemented by applications to receive [BrokerEvent]s.
+///
+/// EventSink is supplied via [Cluster::set_event_sink], events are emitted from
+/// cluster and shard threads, hence implementations must not block.
+///
+/// [Cluster::set_event_sink]: crate::broker::Cluster::set_event_sink
+pub trait EventSink {
+    fn on_event(&self, ev: BrokerEvent);
+}
+
+/// Default event-sink, that appends events as JSON lines to a file.
+pub struct JsonFileSink {
+    loc: path::PathBuf,
+    file: Mutex<fs::File>,
+}
+
+impl JsonFileSink {
+    /// Create or open the file at `loc` in append mode.
+    pub fn new<P: AsRef<path::Path>>(loc: P) -> Result<JsonFileSink> {
+        let loc = loc.as_ref().to_path_buf();
+        let mut opts = fs::OpenOptions::new();
+        let file = err!(
+            IOError,
+            try: opts.append(true).create(true).open(&loc),
+            "fail opening event file {:?}",
+            loc
+        )?;
+
+        Ok(JsonFileSink { loc, file: Mutex::new(file) })
+    }
+}
+
+impl EventSink for JsonFileSink {
+    fn on_event(&self, ev: BrokerEvent) {
+        let mut file = self.file.lock().unwrap();
+        if let Err(err) = writeln!(file, "{}", ev.to_json()) {
+            error!("fail writing event to {:?} err:{}", self.loc, err);
+        }
+    }
+}
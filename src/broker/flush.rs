@@ -3,10 +3,10 @@ use log::{debug, error, info, trace, warn};
 use std::{thread, time};
 
 use crate::broker::thread::{Rx, Thread, Threadable, Tx};
-use crate::broker::{socket, AppTx, Config, QueueStatus, Socket};
+use crate::broker::{socket, AppTx, BufferPool, Config, QueueStatus, Socket};
 
-use crate::{v5, ToJson, SLEEP_10MS};
-use crate::{Error, ErrorKind, Result};
+use crate::{v5, ToJson};
+use crate::{Error, ErrorKind, ReasonCode, Result};
 
 type ThreadRx = Rx<Request, Result<Response>>;
 
@@ -101,9 +101,13 @@ impl Drop for Flusher {
 impl ToJson for Flusher {
     fn to_config_json(&self) -> String {
         format!(
-            concat!("{{ {:?}: {}, {:?}: {} }}"),
+            concat!("{{ {:?}: {}, {:?}: {}, {:?}: {}, {:?}: {} }}"),
             "sock_mqtt_flush_timeout",
             self.config.sock_mqtt_flush_timeout,
+            "sock_mqtt_flush_backoff_base",
+            self.config.sock_mqtt_flush_backoff_base,
+            "sock_mqtt_flush_backoff_cap",
+            self.config.sock_mqtt_flush_backoff_cap,
             "mqtt_max_packet_size",
             self.config.mqtt_max_packet_size
         )
@@ -172,6 +176,7 @@ impl Flusher {
 
 pub enum Request {
     FlushConnection { socket: Socket, err: Option<Error> },
+    Sync,
     Close,
 }
 
@@ -203,6 +208,26 @@ impl Flusher {
         Ok(())
     }
 
+    /// Wait for connections, queued so far, to be flushed. Requests are handled in
+    /// the order they are queued, hence the response to this call marks the end of
+    /// flushing for all previous connections. Fails if `timeout` elapses before.
+    pub fn flush_wait(&self, timeout: time::Duration) -> Result<()> {
+        use std::sync::mpsc;
+
+        let (tx, rx) = mpsc::channel();
+        match &self.inner {
+            Inner::Handle(thrd) => thrd.request_with(Request::Sync, tx)?,
+            _ => unreachable!(),
+        };
+        match rx.recv_timeout(timeout) {
+            Ok(res) => res.map(|_| ()),
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                err!(IPCFail, desc: "{} flush_wait timeout {:?}", self.prefix, timeout)
+            }
+            Err(err) => err!(IPCFail, try: Err(err)),
+        }
+    }
+
     pub fn close_wait(mut self) -> Flusher {
         use std::mem;
 
@@ -244,6 +269,9 @@ impl Threadable for Flusher {
                             _ => unreachable!(),
                         }
                     }
+                    (Sync, Some(tx)) => {
+                        err!(IPCFail, try: tx.send(Ok(Response::Ok))).ok();
+                    }
                     (Close, Some(tx)) => {
                         let resp = self.handle_close();
                         err!(IPCFail, try: tx.send(Ok(resp))).ok();
@@ -274,13 +302,13 @@ impl Threadable for Flusher {
 impl Flusher {
     fn handle_flush_connection(&self, req: Request) -> Response {
         use crate::broker::socket::Stats as SockStats;
+        use crate::broker::BUFFER_CAPACITY;
         use crate::packet::send_disconnect;
 
-        let now = time::Instant::now();
         let max_size = self.config.mqtt_max_packet_size;
         let flush_timeout = self.config.sock_mqtt_flush_timeout;
 
-        let (mut socket, conn_err) = match req {
+        let (mut socket, mut conn_err) = match req {
             Request::FlushConnection { socket, err } => (socket, err),
             _ => unreachable!(),
         };
@@ -291,24 +319,33 @@ impl Flusher {
             self.prefix, raddr, *socket.client_id
         );
 
-        let timeout = now + time::Duration::from_secs(flush_timeout as u64);
+        let mut backoff = Backoff::from_config(&self.config);
+        // packets of a closing connection are written one after the other.
+        let mut pool = BufferPool::new(1, BUFFER_CAPACITY);
         let mut stats = SockStats::default();
         let sock_stats = loop {
             let mut status = socket.wt.miot_rx.try_recvs(&self.prefix);
             socket.wt.packets.extend(status.take_values().into_iter());
 
-            let sock_stats = match socket.flush_packets(&self.prefix, &self.config) {
-                (QueueStatus::Ok(_), sock_stats) => sock_stats,
-                (QueueStatus::Block(_), sock_stats) if time::Instant::now() > timeout => {
-                    error!(
-                        "{} timeout:{:?} give up flushing packets",
-                        self.prefix, timeout
-                    );
-                    break sock_stats;
+            let res = socket.flush_packets(&self.prefix, &self.config, &mut pool);
+            let sock_stats = match res {
+                (QueueStatus::Ok(_), sock_stats) => {
+                    backoff.reset();
+                    sock_stats
                 }
                 (QueueStatus::Block(_), sock_stats) => {
-                    thread::sleep(SLEEP_10MS);
-                    sock_stats
+                    match backoff.next(time::Instant::now()) {
+                        Ok(delay) => {
+                            socket.extend_write_timeout(delay);
+                            thread::sleep(delay);
+                            sock_stats
+                        }
+                        Err(err) => {
+                            error!("{} give up flushing packets, {}", self.prefix, err);
+                            conn_err = Some(err);
+                            break sock_stats;
+                        }
+                    }
                 }
                 (QueueStatus::Disconnected(_), sock_stats) => {
                     warn!("{} stop flush, socket disconnected", self.prefix);
@@ -318,7 +355,8 @@ impl Flusher {
 
             // after flushing the packets see, the source `miot_tx` is disconnected.
             // we will have to run this loop under `miot_tx` has disconnected or
-            // downstream socket has disconnected, or timeout exceeds.
+            // downstream socket has disconnected, or socket stays blocked beyond
+            // the flush timeout.
 
             if let QueueStatus::Disconnected(_) = status {
                 break sock_stats;
@@ -328,13 +366,17 @@ impl Flusher {
         };
         stats.update(&sock_stats);
 
-        let timeout = now + time::Duration::from_secs(flush_timeout as u64);
+        let timeout =
+            time::Instant::now() + time::Duration::from_secs(flush_timeout as u64);
         let code = match conn_err {
-            Some(err) => v5::DisconnReasonCode::try_from(err.code() as u8).unwrap(),
+            Some(err) => v5::DisconnReasonCode::from_error(&err),
             None => v5::DisconnReasonCode::NormalDisconnect,
         };
 
-        if let Ok(()) =
+        if socket.wt.disconnected {
+            info!("{} raddr:{} DISCONNECT sent by session", self.prefix, raddr);
+            info!("{} conn_stats:{}", self.prefix, stats.to_json());
+        } else if let Ok(()) =
             send_disconnect(&self.prefix, code, &mut socket.conn, timeout, max_size)
         {
             info!("{} raddr:{} DISCONNECT", self.prefix, raddr);
@@ -365,6 +407,48 @@ impl Flusher {
     }
 }
 
+/// Exponential backoff while flushing packets on a blocked socket.
+struct Backoff {
+    base: time::Duration,
+    cap: time::Duration,
+    limit: time::Duration,
+    delay: time::Duration,
+    since: Option<time::Instant>,
+}
+
+impl Backoff {
+    fn from_config(config: &Config) -> Backoff {
+        let base =
+            time::Duration::from_millis(config.sock_mqtt_flush_backoff_base as u64);
+        Backoff {
+            base,
+            cap: time::Duration::from_millis(config.sock_mqtt_flush_backoff_cap as u64),
+            limit: time::Duration::from_secs(config.sock_mqtt_flush_timeout as u64),
+            delay: base,
+            since: None,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.delay = self.base;
+        self.since = None;
+    }
+
+    // Return the delay to wait before retrying, socket is blocked since the first
+    // call to `next` after a `reset`. Fail with QuotaExceeded beyond `limit`.
+    fn next(&mut self, now: time::Instant) -> Result<time::Duration> {
+        let since = *self.since.get_or_insert(now);
+        let elapsed = now.saturating_duration_since(since);
+        if elapsed > self.limit {
+            err!(SlowClient, code: QuotaExceeded, "blocked for {:?}", elapsed)
+        } else {
+            let delay = self.delay;
+            self.delay = std::cmp::min(self.delay * 2, self.cap);
+            Ok(delay)
+        }
+    }
+}
+
 impl Flusher {
     fn incr_n_flush_conns(&mut self) {
         match &mut self.inner {
@@ -402,3 +486,7 @@ impl Flusher {
         }
     }
 }
+
+#[cfg(test)]
+#[path = "flush_test.rs"]
+mod flush_test;
@@ -0,0 +1,39 @@
+use super::*;
+
+#[test]
+fn test_flush_backoff() {
+    let config = Config {
+        sock_mqtt_flush_timeout: 1,
+        sock_mqtt_flush_backoff_base: 10,
+        sock_mqtt_flush_backoff_cap: 40,
+        ..Config::default()
+    };
+    let mut backoff = Backoff::from_config(&config);
+    let now = time::Instant::now();
+
+    let delays: Vec<u128> =
+        (0..5).map(|_| backoff.next(now).unwrap().as_millis()).collect();
+    assert_eq!(delays, vec![10, 20, 40, 40, 40]);
+
+    // a successful flush starts all over again.
+    backoff.reset();
+    let later = now + time::Duration::from_millis(1500);
+    assert_eq!(backoff.next(later).unwrap().as_millis(), 10);
+}
+
+#[test]
+fn test_flush_backoff_persistent_block() {
+    let config = Config { sock_mqtt_flush_timeout: 1, ..Config::default() };
+    let mut backoff = Backoff::from_config(&config);
+    let now = time::Instant::now();
+
+    let mut elapsed = time::Duration::default();
+    while elapsed <= time::Duration::from_secs(1) {
+        elapsed += backoff.next(now + elapsed).unwrap();
+    }
+
+    let err = backoff.next(now + elapsed).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::SlowClient);
+    let code = v5::DisconnReasonCode::try_from(err.code() as u8).unwrap();
+    assert_eq!(code, v5::DisconnReasonCode::QuotaExceeded);
+}
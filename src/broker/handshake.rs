@@ -3,7 +3,7 @@ use log::{error, info};
 use std::{io, net, thread, time};
 
 use crate::broker::thread::{Rx, Threadable};
-use crate::broker::{Cluster, Config};
+use crate::broker::{AuthResult, Cluster, Config, ConnEvent, ConnGuard, ConnState};
 
 use crate::{v5, MQTTRead, Packetize, ToJson, SLEEP_10MS};
 use crate::{Error, ErrorKind, ReasonCode, Result};
@@ -19,6 +19,7 @@ pub struct Handshake {
     pub raddr: net::SocketAddr,
     pub config: Config,
     pub cluster: Cluster,
+    pub guard: Option<ConnGuard>,
 }
 
 impl ToJson for Handshake {
@@ -45,6 +46,7 @@ impl Threadable for Handshake {
         use crate::broker::cluster::AddConnectionArgs;
 
         let mut packetr = MQTTRead::new(self.config.mqtt_max_packet_size);
+        let decode_config = self.config.to_decode_config();
         let mut sock = self.sock.take().unwrap();
         let timeout = {
             let now = time::Instant::now();
@@ -52,6 +54,7 @@ impl Threadable for Handshake {
             now + time::Duration::from_secs(connect_timeout as u64)
         };
         let raddr = sock.peer_addr().unwrap();
+        let mut state = ConnState::Handshake;
 
         info!("{} spawn thread config:{}", self.prefix, self.to_config_json());
         info!(
@@ -85,18 +88,40 @@ impl Threadable for Handshake {
                 MQTTRead::Remain { .. } if time::Instant::now() < timeout => {
                     thread::sleep(SLEEP_10MS);
                 }
-                MQTTRead::Fin { .. } => match packetr.parse() {
-                    Ok(v5::Packet::Connect(connect)) => match connect.validate() {
-                        Ok(()) => break (ReasonCode::Success, false, Some(connect)),
-                        Err(err) => {
-                            error!("{}, invalid connect err:{}", self.prefix, err);
-                            break (err.code(), true, None);
+                MQTTRead::Fin { .. } => match packetr.parse_with(&decode_config) {
+                    Ok(v5::Packet::Connect(connect)) => {
+                        match validate_connect(&connect, &self.config) {
+                            Ok(()) => match self.cluster.authenticate(&connect) {
+                                AuthResult::Accept => {
+                                    break (ReasonCode::Success, false, Some(connect))
+                                }
+                                AuthResult::Reject(code) => {
+                                    error!("{} auth rejected {:?}", self.prefix, code);
+                                    let code = ReasonCode::try_from(code as u8).unwrap();
+                                    break (code, true, None);
+                                }
+                                AuthResult::Continue(_) => {
+                                    // TODO: enhanced authentication, AUTH exchange.
+                                    error!("{} enhanced auth unsupported", self.prefix);
+                                    let code = ReasonCode::BadAuthenticationMethod;
+                                    break (code, true, None);
+                                }
+                            },
+                            Err(err) => {
+                                error!("{}, invalid connect err:{}", self.prefix, err);
+                                break (err.code(), true, None);
+                            }
                         }
-                    },
+                    }
                     Ok(pkt) => {
                         let pt = pkt.to_packet_type();
                         error!("{} packet:{:?} unexpect in connection", self.prefix, pt);
-                        break (ReasonCode::ProtocolError, true, None);
+                        let code = match state.transition(ConnEvent::Packet(pt)) {
+                            // TODO: enhanced authentication, AUTH exchange.
+                            Ok(()) => ReasonCode::BadAuthenticationMethod,
+                            Err(err) => err.code(),
+                        };
+                        break (code, true, None);
                     }
                     Err(err) if err.kind() == ErrorKind::MalformedPacket => {
                         error!("{} fail parse, err:{}", self.prefix, err);
@@ -125,7 +150,7 @@ impl Threadable for Handshake {
             self.send_connack(code, &mut sock).ok();
         } else if let Some(connect) = connect {
             info!("{} raddr:{} handing over to cluster ...", self.prefix, self.raddr);
-            let args = AddConnectionArgs { sock, pkt: connect };
+            let args = AddConnectionArgs { sock, pkt: connect, guard: self.guard.take() };
             let res = err!(
                 IPCFail,
                 try: self.cluster.add_connection(args),
@@ -145,6 +170,28 @@ impl Threadable for Handshake {
     }
 }
 
+// client-id is validated here, it shall be assigned by the shard hosting the session.
+// Over-limit CONNECT is rejected by MQTTRead using its fixed-header, before reading
+// the payload.
+fn validate_connect(connect: &v5::Connect, config: &Config) -> Result<()> {
+    let (clean_start, _, _, _) = connect.flags.unwrap();
+
+    connect.validate()?;
+
+    let size = connect.payload.will_payload.as_ref().map(|p| p.len()).unwrap_or(0);
+    if size > (config.max_will_payload_size as usize) {
+        err!(
+            MalformedPacket,
+            code: QuotaExceeded,
+            "will payload size:{} > {}",
+            size,
+            config.max_will_payload_size
+        )?;
+    }
+
+    connect.payload.client_id.validate(clean_start)
+}
+
 impl Handshake {
     fn send_connack<W>(&self, code: v5::ConnackReasonCode, sock: &mut W) -> Result<()>
     where
@@ -171,7 +218,7 @@ impl Handshake {
             };
             packetw = val;
 
-            if would_block && timeout < time::Instant::now() {
+            if would_block && time::Instant::now() < timeout {
                 thread::sleep(SLEEP_10MS);
             } else if would_block {
                 break err!(
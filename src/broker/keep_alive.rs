@@ -1,4 +1,4 @@
-use std::{net, time};
+use std::{cmp, net, time};
 
 use crate::{broker::Config, v5};
 use crate::{Error, ErrorKind, ReasonCode, Result};
@@ -7,26 +7,68 @@ use crate::{Error, ErrorKind, ReasonCode, Result};
 pub struct KeepAlive {
     pub prefix: String,
     pub interval: Option<u16>,
+    /// Keep-alive that the broker imposes on the client, advertised in CONNACK.
+    pub server_keep_alive: Option<u16>,
+    /// Idle timeout, refer [Config::idle_timeout], applied even if keep-alive is ZERO.
+    pub idle_timeout: Option<time::Duration>,
     pub alive_at: time::Instant,
 }
 
 impl KeepAlive {
     pub fn new(addr: net::SocketAddr, pkt: &v5::Connect, config: &Config) -> KeepAlive {
         let factor = config.mqtt_keep_alive_factor;
-        let interval = match config.mqtt_keep_alive() {
-            Some(val) => Some(((val as f32) * factor) as u16),
-            None if pkt.keep_alive == 0 => None,
-            None => Some(((pkt.keep_alive as f32) * factor) as u16),
+        let max = config.mqtt_max_keep_alive();
+        let (keep_alive, server_keep_alive) = match config.mqtt_keep_alive() {
+            Some(val) => {
+                let val = u16::try_from(val).unwrap_or(u16::MAX);
+                let val = max.map(|max| cmp::min(val, max)).unwrap_or(val);
+                (val, Some(val))
+            }
+            None => match max {
+                Some(max) if pkt.keep_alive == 0 || pkt.keep_alive > max => {
+                    (max, Some(max))
+                }
+                _ => (pkt.keep_alive, None),
+            },
+        };
+        let interval = match keep_alive {
+            0 => None,
+            val => Some(((val as f32) * factor) as u16),
         };
+        let idle_timeout =
+            config.idle_timeout.map(|secs| time::Duration::from_secs(u64::from(secs)));
         let prefix = format!("{}:keepalive", addr);
-        KeepAlive { prefix, interval, alive_at: time::Instant::now() }
+        KeepAlive {
+            prefix,
+            interval,
+            server_keep_alive,
+            idle_timeout,
+            alive_at: time::Instant::now(),
+        }
     }
 
     pub fn keep_alive(&self) -> Option<u16> {
         self.interval
     }
 
+    pub fn server_keep_alive(&self) -> Option<u16> {
+        self.server_keep_alive
+    }
+
     pub fn check_expired(&self) -> Result<time::Duration> {
+        if let Some(idle_timeout) = self.idle_timeout {
+            if self.alive_at.elapsed() >= idle_timeout {
+                err!(
+                    ProtocolError,
+                    code: KeepAliveTimeout,
+                    "{} idle timeout alive_at:{:?} idle_timeout:{:?}",
+                    self.prefix,
+                    self.alive_at,
+                    idle_timeout
+                )?;
+            }
+        }
+
         match self.interval {
             Some(interval) => {
                 let interval = time::Duration::from_secs(interval as u64);
@@ -0,0 +1,102 @@
+use crate::v5;
+use crate::{Error, ErrorKind, ReasonCode, Result};
+
+/// Life-cycle of a client connection, from handshake until its session expires.
+///
+/// ```text
+/// Handshake --CONNECT--> Connecting --ConnackSent--> Active
+/// Active --Disconnected/KeepAliveTimeout--> Draining --Expire--> Expired
+///   ^                                          |
+///   +-----------------Resumed------------------+
+/// ```
+///
+/// Refer [ConnState::transition] for legal moves.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConnState {
+    /// Connection accepted, waiting for CONNECT.
+    #[default]
+    Handshake,
+    /// CONNECT received, yet to send CONNACK.
+    Connecting,
+    /// CONNACK sent, client can exchange packets with the broker.
+    Active,
+    /// Connection is gone, session is waiting for the client to reconnect.
+    Draining,
+    /// Session has expired, it shall be removed.
+    Expired,
+}
+
+/// Events that drive a [ConnState] from one state to another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnEvent {
+    /// Packet of this type received from the client.
+    Packet(v5::PacketType),
+    /// Handshake completed with a successful CONNACK.
+    ConnackSent,
+    /// Network connection closed, or DISCONNECT received.
+    Disconnected,
+    /// Client was silent beyond its keep-alive interval.
+    KeepAliveTimeout,
+    /// Client reconnected to a draining session.
+    Resumed,
+    /// Session expiry interval has elapsed.
+    Expire,
+}
+
+impl ConnState {
+    /// Move to the next state for event `ev`. Illegal moves fail with
+    /// `ProtocolError` and leave the state unchanged.
+    pub fn transition(&mut self, ev: ConnEvent) -> Result<()> {
+        use v5::PacketType::{Auth, Connect};
+        use ConnEvent::*;
+        use ConnState::*;
+
+        let next = match (*self, ev) {
+            // first packet must be CONNECT, AUTH can follow for enhanced auth.
+            (Handshake, Packet(Connect)) => Connecting,
+            (Handshake, Packet(pt)) => err!(
+                ProtocolError,
+                code: ProtocolError,
+                "conn {:?} first packet {:?} is not connect",
+                self,
+                pt
+            )?,
+            (Connecting, Packet(Auth)) => Connecting,
+            (Connecting, ConnackSent) => Active,
+            (Handshake | Connecting, Disconnected | KeepAliveTimeout) => Expired,
+            // second CONNECT on the same connection is a protocol violation.
+            (Connecting | Active, Packet(Connect)) => err!(
+                ProtocolError,
+                code: ProtocolError,
+                "conn {:?} duplicate connect packet",
+                self
+            )?,
+            (Active, Packet(_)) => Active,
+            (Active, Disconnected | KeepAliveTimeout) => Draining,
+            (Draining, Resumed) => Active,
+            (Draining | Active, Expire) => Expired,
+            (state, ev) => err!(
+                ProtocolError,
+                code: ProtocolError,
+                "conn {:?} illegal event {:?}",
+                state,
+                ev
+            )?,
+        };
+        *self = next;
+
+        Ok(())
+    }
+
+    pub fn is_active(&self) -> bool {
+        matches!(self, ConnState::Active)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        matches!(self, ConnState::Expired)
+    }
+}
+
+#[cfg(test)]
+#[path = "lifecycle_test.rs"]
+mod lifecycle_test;
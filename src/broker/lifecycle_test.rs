@@ -0,0 +1,53 @@
+use super::*;
+
+#[test]
+fn test_lifecycle_publish_before_connack() {
+    let mut state = ConnState::default();
+    assert_eq!(state, ConnState::Handshake);
+
+    let err = state.transition(ConnEvent::Packet(v5::PacketType::Publish)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ProtocolError);
+    assert_eq!(err.code(), ReasonCode::ProtocolError);
+    assert_eq!(state, ConnState::Handshake);
+
+    state.transition(ConnEvent::Packet(v5::PacketType::Connect)).unwrap();
+    let err = state.transition(ConnEvent::Packet(v5::PacketType::Subscribe)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ProtocolError);
+    assert_eq!(state, ConnState::Connecting);
+
+    // second CONNECT, before CONNACK is sent, is also rejected.
+    let err = state.transition(ConnEvent::Packet(v5::PacketType::Connect)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ProtocolError);
+    assert_eq!(state, ConnState::Connecting);
+}
+
+#[test]
+fn test_lifecycle_transitions() {
+    use v5::PacketType::{Connect, PingReq, Publish, Subscribe};
+
+    let mut state = ConnState::Handshake;
+    state.transition(ConnEvent::Packet(Connect)).unwrap();
+    state.transition(ConnEvent::ConnackSent).unwrap();
+    assert!(state.is_active());
+
+    for pt in [Subscribe, Publish, PingReq] {
+        state.transition(ConnEvent::Packet(pt)).unwrap();
+        assert_eq!(state, ConnState::Active);
+    }
+    let err = state.transition(ConnEvent::Packet(Connect)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ProtocolError);
+    assert_eq!(state, ConnState::Active);
+
+    state.transition(ConnEvent::KeepAliveTimeout).unwrap();
+    assert_eq!(state, ConnState::Draining);
+    let err = state.transition(ConnEvent::Packet(Publish)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ProtocolError);
+
+    state.transition(ConnEvent::Resumed).unwrap();
+    state.transition(ConnEvent::Disconnected).unwrap();
+    assert_eq!(state, ConnState::Draining);
+
+    state.transition(ConnEvent::Expire).unwrap();
+    assert!(state.is_expired());
+    assert!(state.transition(ConnEvent::Resumed).is_err());
+}
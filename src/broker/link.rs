@@ -0,0 +1,205 @@
+use log::{debug, error, info, trace};
+use uuid::Uuid;
+
+use std::io::{self, Read, Write};
+use std::{net, time};
+
+use crate::broker::{Cluster, Config};
+use crate::{Error, ErrorKind, Result};
+
+/// State of the TCP link to a peer node's consensus port.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkState {
+    /// Link is down, waiting for the backoff to elapse before reconnecting.
+    Disconnected,
+    /// Non-blocking connect is in progress.
+    Connecting,
+    /// Link is up and node-join was sent to the peer.
+    Connected,
+}
+
+/// Type implement the TCP link between this node and a peer node's consensus port.
+///
+/// Link is registered with the cluster's [mio::Poll] under [Cluster::TOKEN_CONSENSUS].
+/// When the link drops, it is re-established with exponential backoff, starting at
+/// [Config::consensus_backoff_base] and doubling upto [Config::consensus_backoff_cap].
+/// On every successful connect, the backoff is reset and node-join is re-sent to
+/// the peer.
+pub struct ConsensusLink {
+    node: Uuid,
+    peer: net::SocketAddr,
+    state: LinkState,
+    stream: Option<mio::net::TcpStream>,
+
+    base: time::Duration,
+    cap: time::Duration,
+    delay: time::Duration,
+    retry_at: Option<time::Instant>,
+}
+
+impl ConsensusLink {
+    /// Create a link from local `node` to `peer`, first connect is attempted on the
+    /// next call to [ConsensusLink::reconnect].
+    pub fn new(config: &Config, node: Uuid, peer: net::SocketAddr) -> ConsensusLink {
+        let base = time::Duration::from_millis(config.consensus_backoff_base as u64);
+        ConsensusLink {
+            node,
+            peer,
+            state: LinkState::Disconnected,
+            stream: None,
+
+            base,
+            cap: time::Duration::from_millis(config.consensus_backoff_cap as u64),
+            delay: base,
+            retry_at: None,
+        }
+    }
+
+    pub fn to_state(&self) -> LinkState {
+        self.state
+    }
+
+    pub fn to_peer(&self) -> net::SocketAddr {
+        self.peer
+    }
+
+    /// Connect to peer if the link is down and its backoff has elapsed as of `now`.
+    pub fn reconnect(&mut self, registry: &mio::Registry, now: time::Instant) {
+        match (self.state, self.retry_at) {
+            (LinkState::Disconnected, Some(retry_at)) if now < retry_at => return,
+            (LinkState::Disconnected, _) => (),
+            (_, _) => return,
+        }
+
+        match mio::net::TcpStream::connect(self.peer) {
+            Ok(mut stream) => {
+                let interests = mio::Interest::READABLE | mio::Interest::WRITABLE;
+                let token = Cluster::TOKEN_CONSENSUS;
+                match registry.register(&mut stream, token, interests) {
+                    Ok(()) => {
+                        debug!("consensus link connecting to {}", self.peer);
+                        self.stream = Some(stream);
+                        self.state = LinkState::Connecting;
+                    }
+                    Err(err) => {
+                        error!("consensus link {} register err:{}", self.peer, err);
+                        self.dropped(registry, now);
+                    }
+                }
+            }
+            Err(err) => {
+                error!("consensus link {} connect err:{}", self.peer, err);
+                self.dropped(registry, now);
+            }
+        }
+    }
+
+    /// Handle poll event for [Cluster::TOKEN_CONSENSUS].
+    pub fn on_event(
+        &mut self,
+        registry: &mio::Registry,
+        event: &mio::event::Event,
+        now: time::Instant,
+    ) {
+        if event.is_error() {
+            error!("consensus link {} dropped, socket error", self.peer);
+            self.dropped(registry, now);
+            return;
+        }
+
+        if self.state == LinkState::Connecting && event.is_writable() {
+            match self.is_connected() {
+                Ok(true) => match self.send_node_join() {
+                    Ok(()) => {
+                        info!("consensus link connected to {}", self.peer);
+                        self.delay = self.base;
+                        self.retry_at = None;
+                        self.state = LinkState::Connected;
+                    }
+                    Err(err) => {
+                        error!("consensus link {} err:{}", self.peer, err);
+                        self.dropped(registry, now);
+                        return;
+                    }
+                },
+                Ok(false) => return,
+                Err(err) => {
+                    error!("consensus link {} err:{}", self.peer, err);
+                    self.dropped(registry, now);
+                    return;
+                }
+            }
+        }
+
+        if self.state == LinkState::Connected && event.is_readable() {
+            if let Err(err) = self.read_packets() {
+                error!("consensus link {} err:{}", self.peer, err);
+                self.dropped(registry, now);
+            }
+        }
+    }
+
+    // Return the delay before the next reconnect, doubles on every call upto `cap`.
+    fn next_backoff(&mut self) -> time::Duration {
+        let delay = self.delay;
+        self.delay = std::cmp::min(self.delay * 2, self.cap);
+        delay
+    }
+
+    fn dropped(&mut self, registry: &mio::Registry, now: time::Instant) {
+        if let Some(mut stream) = self.stream.take() {
+            registry.deregister(&mut stream).ok();
+        }
+
+        let delay = self.next_backoff();
+        debug!("consensus link {} reconnect after {:?}", self.peer, delay);
+
+        self.state = LinkState::Disconnected;
+        self.retry_at = Some(now + delay);
+    }
+
+    // Refer to mio::net::TcpStream::connect for detecting a completed connect.
+    fn is_connected(&mut self) -> Result<bool> {
+        let stream = self.stream.as_mut().unwrap();
+        if let Some(err) = err!(IOError, try: stream.take_error())? {
+            err!(IOError, desc: "connect failed {}", err)?
+        }
+
+        match stream.peer_addr() {
+            Ok(_) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::NotConnected => Ok(false),
+            Err(err) => err!(IOError, desc: "connect failed {}", err),
+        }
+    }
+
+    // Node-join is framed as a 4-byte big-endian length followed by local node's
+    // uuid. TODO: replace this with consensus packets.
+    fn send_node_join(&mut self) -> Result<()> {
+        let uuid = self.node.as_bytes();
+        let mut frame = Vec::with_capacity(4 + uuid.len());
+        frame.extend_from_slice(&(uuid.len() as u32).to_be_bytes());
+        frame.extend_from_slice(uuid);
+
+        let stream = self.stream.as_mut().unwrap();
+        err!(IOError, try: stream.write_all(&frame), "fail sending node-join")
+    }
+
+    // TODO: consensus packets are not handled yet, drain and discard them. Detects
+    // a link closed by the peer.
+    fn read_packets(&mut self) -> Result<()> {
+        let stream = self.stream.as_mut().unwrap();
+        let mut buf = [0_u8; 4096];
+        loop {
+            match stream.read(&mut buf) {
+                Ok(0) => break err!(IOError, desc: "closed by peer"),
+                Ok(n) => trace!("consensus link {} discard {} bytes", self.peer, n),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+                Err(err) => break err!(IOError, try: Err(err)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[path = "link_test.rs"]
+mod link_test;
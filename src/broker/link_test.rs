@@ -0,0 +1,82 @@
+use mio::event::Events;
+
+use std::io::Read;
+
+use super::*;
+
+// Poll for consensus events, until link moves out of `Connecting` state.
+fn poll_link(poll: &mut mio::Poll, link: &mut ConsensusLink, now: time::Instant) {
+    let mut events = Events::with_capacity(16);
+    for _ in 0..100 {
+        if link.to_state() != LinkState::Connecting {
+            break;
+        }
+        let timeout = Some(time::Duration::from_millis(10));
+        poll.poll(&mut events, timeout).unwrap();
+        for event in events.iter() {
+            assert_eq!(event.token(), Cluster::TOKEN_CONSENSUS);
+            link.on_event(poll.registry(), event, now);
+        }
+    }
+}
+
+#[test]
+fn test_consensus_link_backoff() {
+    let config = Config {
+        consensus_backoff_base: 1000,
+        consensus_backoff_cap: 8000,
+        ..Config::default()
+    };
+
+    // reserve a port and close it, so that connects are refused.
+    let peer = net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+    let mut poll = mio::Poll::new().unwrap();
+    let node = Uuid::new_v4();
+    let mut link = ConsensusLink::new(&config, node, peer);
+    assert_eq!(link.to_state(), LinkState::Disconnected);
+
+    let mut now = time::Instant::now();
+    let mut delays = vec![];
+    for _ in 0..6 {
+        link.reconnect(poll.registry(), now);
+        poll_link(&mut poll, &mut link, now);
+        assert_eq!(link.to_state(), LinkState::Disconnected);
+
+        let retry_at = link.retry_at.unwrap();
+        delays.push((retry_at - now).as_secs());
+
+        // no reconnect before the backoff elapses.
+        link.reconnect(poll.registry(), retry_at - time::Duration::from_millis(1));
+        assert_eq!(link.to_state(), LinkState::Disconnected);
+        now = retry_at;
+    }
+    assert_eq!(delays, vec![1, 2, 4, 8, 8, 8]);
+
+    // peer is back, link is re-established and node-join is re-sent.
+    let listener = net::TcpListener::bind(peer).unwrap();
+    link.reconnect(poll.registry(), now);
+    poll_link(&mut poll, &mut link, now);
+    assert_eq!(link.to_state(), LinkState::Connected);
+    assert_eq!(link.retry_at, None);
+
+    let (mut conn, _) = listener.accept().unwrap();
+    let mut frame = [0_u8; 20];
+    conn.read_exact(&mut frame).unwrap();
+    assert_eq!(&frame[..4], &16_u32.to_be_bytes());
+    assert_eq!(&frame[4..], node.as_bytes());
+
+    // link dropped by the peer, backoff starts all over again.
+    std::mem::drop(conn);
+    let mut events = Events::with_capacity(16);
+    let deadline = time::Instant::now() + time::Duration::from_secs(5);
+    while link.to_state() == LinkState::Connected {
+        assert!(time::Instant::now() < deadline, "link drop not detected");
+        poll.poll(&mut events, Some(time::Duration::from_millis(10))).unwrap();
+        for event in events.iter() {
+            link.on_event(poll.registry(), event, now);
+        }
+    }
+    assert_eq!(link.to_state(), LinkState::Disconnected);
+    assert_eq!(link.retry_at.unwrap() - now, time::Duration::from_secs(1));
+}
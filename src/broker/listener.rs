@@ -1,12 +1,13 @@
-use log::{debug, error, info, trace};
+use log::{debug, error, info, trace, warn};
 use mio::event::Events;
 
-use std::{fmt, net, result, sync::Arc, time};
+use std::sync::{atomic::AtomicUsize, atomic::Ordering::SeqCst, Arc};
+use std::{fmt, io, mem, result, time};
 
 use crate::broker::thread::{Rx, Thread, Threadable};
-use crate::broker::{AppTx, Cluster, Config, QueueStatus};
+use crate::broker::{AppTx, Cluster, Config, ConfigListener, QueueStatus};
 
-use crate::ToJson;
+use crate::{v5, MQTTRead, MQTTWrite, Packetize, ToJson};
 use crate::{Error, ErrorKind, Result};
 
 type ThreadRx = Rx<Request, Result<Response>>;
@@ -14,6 +15,10 @@ type QueueReq = crate::broker::thread::QueueReq<Request, Result<Response>>;
 
 /// Type binds to MQTT port and listens for incoming connection.
 ///
+/// Listener can bind to several endpoints, refer [Config::listeners], each endpoint
+/// is registered with the listener's [mio::Poll] under its own token, starting from
+/// [Listener::TOKEN_LISTENER]. Connections are accepted as plain MQTT over TCP.
+///
 /// This type is threadable and singleton.
 pub struct Listener {
     /// Human readable name for this mio thread.
@@ -48,10 +53,15 @@ struct RunLoop {
     /// Mio poller for asynchronous handling, aggregate events from listener and
     /// thread-waker.
     poll: mio::Poll,
-    /// MQTT listener listening on `port`.
-    listener: mio::net::TcpListener,
+    /// MQTT listeners, one for each endpoint, indexed by their poll token.
+    endpoints: Vec<mio::net::TcpListener>,
     /// Tx-handle to send messages to cluster.
     cluster: Box<Cluster>,
+    /// Number of live connections accepted by this listener.
+    n_conns: Arc<AtomicUsize>,
+    /// Start of the current one-second accept window, and number of connections
+    /// accepted within that window.
+    accept_window: (time::Instant, u32),
 
     /// Statistics
     stats: Stats,
@@ -62,6 +72,8 @@ struct RunLoop {
 
 pub struct FinState {
     stats: Stats,
+    /// Number of live connections, when the listener was closed.
+    pub n_conns: usize,
 }
 
 #[derive(Clone, Copy, Default)]
@@ -74,12 +86,15 @@ pub struct Stats {
     pub n_requests: usize,
     /// Total number of connections accepted.
     pub n_accepted: usize,
+    /// Total number of connections refused, due to `max_connections` or
+    /// `accept_rate_per_sec`.
+    pub n_refused: usize,
 }
 
 impl FinState {
     fn to_json(&self) -> String {
         format!(
-            concat!("{{ {:?}: {}, {:?}: {}, {:?}: {}, {:?}: {} }}"),
+            concat!("{{ {:?}: {}, {:?}: {}, {:?}: {}, {:?}: {}, {:?}: {}, {:?}: {} }}"),
             "n_polls",
             self.stats.n_polls,
             "n_events",
@@ -87,7 +102,11 @@ impl FinState {
             "n_requests",
             self.stats.n_requests,
             "n_accepted",
-            self.stats.n_accepted
+            self.stats.n_accepted,
+            "n_refused",
+            self.stats.n_refused,
+            "n_conns",
+            self.n_conns
         )
     }
 }
@@ -122,7 +141,19 @@ impl Drop for Listener {
 
 impl ToJson for Listener {
     fn to_config_json(&self) -> String {
-        format!(concat!("{{ {:?}: {} }}"), "port", self.config.port)
+        format!(
+            concat!("{{ {:?}: {:?}, {:?}: {:?}, {:?}: {:?} }}"),
+            "listeners",
+            self.config
+                .to_listeners()
+                .iter()
+                .map(|l| l.address.to_string())
+                .collect::<Vec<String>>(),
+            "max_connections",
+            self.config.max_connections,
+            "accept_rate_per_sec",
+            self.config.accept_rate_per_sec
+        )
     }
 
     fn to_stats_json(&self) -> String {
@@ -136,7 +167,8 @@ impl ToJson for Listener {
 impl Listener {
     /// Poll register token for waker event.
     pub const TOKEN_WAKE: mio::Token = mio::Token(1);
-    /// Poll register for listener TcpStream.
+    /// Poll register for listener TcpStream, of the first endpoint. Subsequent
+    /// endpoints are registered with consecutive tokens.
     pub const TOKEN_LISTENER: mio::Token = mio::Token(2);
 
     /// Create a listener from configuration. Listener shall be in `Init` state. To start
@@ -154,16 +186,10 @@ impl Listener {
     }
 
     pub fn spawn(self, cluster: Cluster, app_tx: AppTx) -> Result<Listener> {
-        use mio::{Interest, Waker};
-
-        let mut listener = {
-            let sock_addr: net::SocketAddr = self.server_address().parse().unwrap();
-            mio::net::TcpListener::bind(sock_addr)?
-        };
+        use mio::Waker;
 
-        let interests = Interest::READABLE;
         let poll = err!(IOError, try: mio::Poll::new(), "fail creating mio::Poll")?;
-        poll.registry().register(&mut listener, Self::TOKEN_LISTENER, interests)?;
+        let endpoints = bind_endpoints(&poll, &self.config.to_listeners())?;
         let waker = Arc::new(Waker::new(poll.registry(), Self::TOKEN_WAKE)?);
 
         let mut listener = Listener {
@@ -172,8 +198,10 @@ impl Listener {
             config: self.config.clone(),
             inner: Inner::Main(RunLoop {
                 poll,
-                listener,
+                endpoints,
                 cluster: Box::new(cluster),
+                n_conns: Arc::new(AtomicUsize::new(0)),
+                accept_window: (time::Instant::now(), 0),
 
                 stats: Stats::default(),
 
@@ -197,6 +225,7 @@ impl Listener {
 }
 
 pub enum Request {
+    Pause,
     Close,
 }
 
@@ -206,6 +235,17 @@ pub enum Response {
 
 // calls to interface with listener-thread, and shall wake the thread
 impl Listener {
+    /// Stop accepting new connections, incoming connections shall wait in the
+    /// backlog until the listener is closed.
+    pub fn pause(&self) -> Result<()> {
+        match &self.inner {
+            Inner::Handle(_waker, thrd) => match thrd.request(Request::Pause)?? {
+                Response::Ok => Ok(()),
+            },
+            inner => unreachable!("{} {:?}", self.prefix, inner),
+        }
+    }
+
     pub fn close_wait(mut self) -> Listener {
         use std::mem;
 
@@ -272,8 +312,9 @@ impl Listener {
                                 (QueueStatus::Disconnected(_), _) => break 'outer true,
                             }
                         },
-                        Self::TOKEN_LISTENER => loop {
-                            match self.accept_conn() {
+                        token if token.0 >= Self::TOKEN_LISTENER.0 => loop {
+                            let off = token.0 - Self::TOKEN_LISTENER.0;
+                            match self.accept_conn(off) {
                                 QueueStatus::Ok(_) => (),
                                 QueueStatus::Block(_) => break,
                                 QueueStatus::Disconnected(_) => break 'outer true,
@@ -304,6 +345,10 @@ impl Listener {
         let mut closed = false;
         for req in reqs.into_iter() {
             match req {
+                (req @ Pause, Some(tx)) => {
+                    let resp = self.handle_pause(req);
+                    err!(IPCFail, try: tx.send(Ok(resp))).ok();
+                }
                 (req @ Close, Some(tx)) => {
                     let resp = self.handle_close(req);
                     err!(IPCFail, try: tx.send(Ok(resp))).ok();
@@ -316,21 +361,34 @@ impl Listener {
         (status, closed)
     }
 
-    fn accept_conn(&mut self) -> QueueStatus<()> {
+    // Accept connection on endpoint at offset `off`.
+    fn accept_conn(&mut self, off: usize) -> QueueStatus<()> {
         use crate::broker::Handshake;
-        use std::io;
 
-        let RunLoop { listener, cluster, stats, .. } = match &mut self.inner {
+        let run_loop = match &mut self.inner {
             Inner::Main(run_loop) => run_loop,
             inner => unreachable!("{} {:?}", self.prefix, inner),
         };
 
-        match listener.accept() {
+        match run_loop.endpoints[off].accept() {
             Ok((sock, addr)) => {
                 info!("{} raddr:{} incoming CONNECT", self.prefix, addr);
                 let raddr = sock.peer_addr().unwrap();
 
                 assert_eq!(raddr, addr);
+
+                let guard = match run_loop.admit(&self.config, time::Instant::now()) {
+                    Some(guard) => guard,
+                    None => {
+                        error!("{} raddr:{} refusing connection", self.prefix, raddr);
+                        refuse_conn(sock, self.config.mqtt_max_packet_size);
+                        run_loop.stats.n_refused += 1;
+                        return QueueStatus::Ok(Vec::new());
+                    }
+                };
+                set_sock_options(&self.prefix, &sock, &self.config);
+
+                let RunLoop { cluster, stats, .. } = run_loop;
                 // for every successful accept launch a handshake thread.
                 let hs = Handshake {
                     prefix: format!("<h:{}>", self.config.name),
@@ -338,6 +396,7 @@ impl Listener {
                     raddr,
                     config: self.config.clone(),
                     cluster: cluster.to_tx("handshake"),
+                    guard: Some(guard),
                 };
                 let thrd = Thread::spawn_sync("handshake", 1, hs);
                 thrd.drop(); // alternative to close_wait()
@@ -356,7 +415,159 @@ impl Listener {
     }
 }
 
+impl RunLoop {
+    // Admit a new connection, unless `max_connections` or `accept_rate_per_sec`
+    // is exceeded.
+    fn admit(&mut self, config: &Config, now: time::Instant) -> Option<ConnGuard> {
+        let (start, n) = &mut self.accept_window;
+        if now.saturating_duration_since(*start) >= time::Duration::from_secs(1) {
+            *start = now;
+            *n = 0;
+        }
+
+        match (config.accept_rate_per_sec, config.max_connections) {
+            (Some(rate), _) if *n >= rate => None,
+            (_, Some(max)) if self.n_conns.load(SeqCst) >= (max as usize) => None,
+            (_, _) => {
+                *n += 1;
+                Some(ConnGuard::new(&self.n_conns))
+            }
+        }
+    }
+}
+
+// Bind and register a TcpListener for each of the `listeners` with `poll`.
+fn bind_endpoints(
+    poll: &mio::Poll,
+    listeners: &[ConfigListener],
+) -> Result<Vec<mio::net::TcpListener>> {
+    let mut endpoints = Vec::with_capacity(listeners.len());
+    for (off, config) in listeners.iter().enumerate() {
+        let mut listener = err!(
+            IOError,
+            try: mio::net::TcpListener::bind(config.address),
+            "fail binding listener {}",
+            config.address
+        )?;
+        let token = mio::Token(Listener::TOKEN_LISTENER.0 + off);
+        poll.registry().register(&mut listener, token, mio::Interest::READABLE)?;
+
+        endpoints.push(listener);
+    }
+
+    Ok(endpoints)
+}
+
+// Refuse connection, if CONNECT packet has already arrived, send CONNACK with
+// QuotaExceeded. Socket is closed when dropped.
+fn refuse_conn(mut sock: mio::net::TcpStream, max_size: u32) {
+    let mut packetr = MQTTRead::new(max_size);
+    let connect = loop {
+        packetr = match packetr.read(&mut sock) {
+            Ok((val, false)) => val,
+            Ok((_, true)) | Err(_) => break false,
+        };
+        if let MQTTRead::Fin { .. } = &packetr {
+            break matches!(packetr.parse(), Ok(v5::Packet::Connect(_)));
+        }
+    };
+
+    if connect {
+        let cack = v5::ConnAck::from_reason_code(v5::ConnackReasonCode::QuotaExceeded);
+        let packetw = MQTTWrite::new(cack.encode().unwrap().as_ref(), max_size);
+        packetw.write(&mut sock).ok();
+    }
+}
+
+// Apply socket options from `config` on an accepted connection. Failures are only
+// logged, connection shall be served with the default options.
+fn set_sock_options(prefix: &str, sock: &mio::net::TcpStream, config: &Config) {
+    if config.tcp_nodelay {
+        if let Err(err) = sock.set_nodelay(true) {
+            warn!("{} fail setting tcp_nodelay err:{}", prefix, err);
+        }
+    }
+    if let Some(size) = config.so_rcvbuf {
+        if let Err(err) = set_sock_buffer(sock, libc::SO_RCVBUF, size) {
+            warn!("{} fail setting so_rcvbuf:{} err:{}", prefix, size, err);
+        }
+    }
+    if let Some(size) = config.so_sndbuf {
+        if let Err(err) = set_sock_buffer(sock, libc::SO_SNDBUF, size) {
+            warn!("{} fail setting so_sndbuf:{} err:{}", prefix, size, err);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn set_sock_buffer(
+    sock: &mio::net::TcpStream,
+    opt: libc::c_int,
+    size: u32,
+) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let val = libc::c_int::try_from(size).unwrap_or(libc::c_int::MAX);
+    let rc = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            libc::SOL_SOCKET,
+            opt,
+            &val as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    match rc {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(unix))]
+fn set_sock_buffer(
+    _sock: &mio::net::TcpStream,
+    _opt: libc::c_int,
+    _size: u32,
+) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "socket buffer size"))
+}
+
+/// Type held by every live connection accepted by the [Listener]. Dropping the guard
+/// shall decrement the listener's live-connection count.
+pub struct ConnGuard {
+    n_conns: Arc<AtomicUsize>,
+}
+
+impl Drop for ConnGuard {
+    fn drop(&mut self) {
+        self.n_conns.fetch_sub(1, SeqCst);
+    }
+}
+
+impl ConnGuard {
+    fn new(n_conns: &Arc<AtomicUsize>) -> ConnGuard {
+        n_conns.fetch_add(1, SeqCst);
+        ConnGuard { n_conns: Arc::clone(n_conns) }
+    }
+}
+
 impl Listener {
+    fn handle_pause(&mut self, _req: Request) -> Response {
+        let RunLoop { poll, endpoints, .. } = match &mut self.inner {
+            Inner::Main(run_loop) => run_loop,
+            inner => unreachable!("{} {:?}", self.prefix, inner),
+        };
+
+        info!("{} pausing listener", self.prefix);
+        for listener in endpoints.iter_mut() {
+            if let Err(err) = poll.registry().deregister(listener) {
+                error!("{} deregister listener err:{}", self.prefix, err);
+            }
+        }
+
+        Response::Ok
+    }
+
     fn handle_close(&mut self, _req: Request) -> Response {
         use std::mem;
 
@@ -369,11 +580,12 @@ impl Listener {
         info!("{} closing listener", self.prefix);
 
         mem::drop(run_loop.poll);
-        mem::drop(run_loop.listener);
+        mem::drop(run_loop.endpoints);
         mem::drop(run_loop.cluster);
         mem::drop(run_loop.app_tx);
 
-        let fin_state = FinState { stats: run_loop.stats };
+        let n_conns = run_loop.n_conns.load(SeqCst);
+        let fin_state = FinState { stats: run_loop.stats, n_conns };
         info!("{} stats:{}", self.prefix, fin_state.to_json());
 
         let _init = mem::replace(&mut self.inner, Inner::Close(fin_state));
@@ -406,10 +618,6 @@ impl Listener {
         }
     }
 
-    fn server_address(&self) -> String {
-        format!("0.0.0.0:{}", self.config.port)
-    }
-
     fn prefix(&self) -> String {
         let state = match &self.inner {
             Inner::Init => "init",
@@ -434,3 +642,7 @@ impl Listener {
         }
     }
 }
+
+#[cfg(test)]
+#[path = "listener_test.rs"]
+mod listener_test;
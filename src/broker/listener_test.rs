@@ -0,0 +1,179 @@
+use std::io::{Read, Write};
+use std::{sync::mpsc, thread};
+
+use super::*;
+
+fn new_listener(config: Config) -> (Listener, mpsc::Receiver<String>) {
+    let poll = mio::Poll::new().unwrap();
+    // bind ephemeral ports on loopback, for each configured endpoint.
+    let listeners: Vec<ConfigListener> = match config.listeners.len() {
+        0 => vec![ConfigListener::default()],
+        _ => config.listeners.clone(),
+    };
+    let listeners: Vec<ConfigListener> = listeners
+        .into_iter()
+        .map(|l| ConfigListener { address: "127.0.0.1:0".parse().unwrap(), ..l })
+        .collect();
+    let endpoints = bind_endpoints(&poll, &listeners).unwrap();
+    let _waker = mio::Waker::new(poll.registry(), Listener::TOKEN_WAKE).unwrap();
+    let (app_tx, app_rx) = mpsc::sync_channel(16);
+
+    let mut listener = Listener {
+        name: config.name.clone(),
+        prefix: String::default(),
+        config,
+        inner: Inner::Main(RunLoop {
+            poll,
+            endpoints,
+            cluster: Box::new(Cluster::default()),
+            n_conns: Arc::new(AtomicUsize::new(0)),
+            accept_window: (time::Instant::now(), 0),
+
+            stats: Stats::default(),
+
+            app_tx,
+        }),
+    };
+    listener.prefix = listener.prefix();
+
+    (listener, app_rx)
+}
+
+fn as_mut_run_loop(listener: &mut Listener) -> &mut RunLoop {
+    match &mut listener.inner {
+        Inner::Main(run_loop) => run_loop,
+        inner => unreachable!("{:?}", inner),
+    }
+}
+
+#[test]
+fn test_listener_max_connections() {
+    let config = Config { max_connections: Some(2), ..Config::default() };
+    let (mut listener, _app_rx) = new_listener(config.clone());
+    let run_loop = as_mut_run_loop(&mut listener);
+    let now = time::Instant::now();
+
+    let guard1 = run_loop.admit(&config, now).unwrap();
+    let _guard2 = run_loop.admit(&config, now).unwrap();
+    assert!(run_loop.admit(&config, now).is_none());
+    assert_eq!(run_loop.n_conns.load(SeqCst), 2);
+
+    // a connection goes away.
+    std::mem::drop(guard1);
+    assert_eq!(run_loop.n_conns.load(SeqCst), 1);
+    assert!(run_loop.admit(&config, now).is_some());
+}
+
+#[test]
+fn test_listener_accept_rate() {
+    let config = Config { accept_rate_per_sec: Some(2), ..Config::default() };
+    let (mut listener, _app_rx) = new_listener(config.clone());
+    let run_loop = as_mut_run_loop(&mut listener);
+    let now = time::Instant::now();
+
+    assert!(run_loop.admit(&config, now).is_some());
+    assert!(run_loop.admit(&config, now).is_some());
+    assert!(run_loop.admit(&config, now).is_none());
+
+    let later = now + time::Duration::from_millis(1001);
+    assert!(run_loop.admit(&config, later).is_some());
+}
+
+#[test]
+fn test_listener_refuse() {
+    let config = Config { max_connections: Some(1), ..Config::default() };
+    let (mut listener, _app_rx) = new_listener(config.clone());
+
+    // first connection is live.
+    let run_loop = as_mut_run_loop(&mut listener);
+    let _guard = run_loop.admit(&config, time::Instant::now()).unwrap();
+    let laddr = run_loop.endpoints[0].local_addr().unwrap();
+
+    let mut client = std::net::TcpStream::connect(laddr).unwrap();
+    let connect = v5::Connect::default();
+    client.write_all(connect.encode().unwrap().as_ref()).unwrap();
+    thread::sleep(time::Duration::from_millis(100));
+
+    assert!(matches!(listener.accept_conn(0), QueueStatus::Ok(_)));
+
+    let mut buf = Vec::new();
+    client.read_to_end(&mut buf).unwrap();
+    let (cack, _) = v5::ConnAck::decode(&buf).unwrap();
+    assert_eq!(cack.code, v5::ConnackReasonCode::QuotaExceeded);
+
+    let run_loop = as_mut_run_loop(&mut listener);
+    assert_eq!(run_loop.stats.n_refused, 1);
+    assert_eq!(run_loop.stats.n_accepted, 0);
+}
+
+#[test]
+fn test_listener_sock_options() {
+    let config = Config {
+        tcp_nodelay: true,
+        so_rcvbuf: Some(64 * 1024),
+        so_sndbuf: Some(64 * 1024),
+        ..Config::default()
+    };
+    let (mut listener, _app_rx) = new_listener(config.clone());
+    let run_loop = as_mut_run_loop(&mut listener);
+    let laddr = run_loop.endpoints[0].local_addr().unwrap();
+
+    let _client = std::net::TcpStream::connect(laddr).unwrap();
+    thread::sleep(time::Duration::from_millis(100));
+    let (sock, _) = run_loop.endpoints[0].accept().unwrap();
+    assert!(!sock.nodelay().unwrap());
+
+    set_sock_options("test", &sock, &config);
+    assert!(sock.nodelay().unwrap());
+}
+
+#[test]
+fn test_listener_endpoints() {
+    // connections are refused, so that accept does not spawn handshake threads.
+    let config = Config {
+        max_connections: Some(0),
+        listeners: vec![ConfigListener::default(), ConfigListener::default()],
+        ..Config::default()
+    };
+    let (mut listener, _app_rx) = new_listener(config);
+
+    let run_loop = as_mut_run_loop(&mut listener);
+    let laddrs: Vec<std::net::SocketAddr> =
+        run_loop.endpoints.iter().map(|e| e.local_addr().unwrap()).collect();
+    assert_eq!(laddrs.len(), 2);
+    assert_ne!(laddrs[0].port(), laddrs[1].port());
+
+    let mut clients = vec![];
+    for laddr in laddrs.iter() {
+        let mut client = std::net::TcpStream::connect(laddr).unwrap();
+        let connect = v5::Connect::default();
+        client.write_all(connect.encode().unwrap().as_ref()).unwrap();
+        clients.push(client);
+    }
+    thread::sleep(time::Duration::from_millis(100));
+
+    // each endpoint is polled under its own token.
+    let mut tokens = vec![];
+    let mut events = Events::with_capacity(16);
+    let timeout = Some(time::Duration::from_millis(100));
+    listener.as_mut_poll().poll(&mut events, timeout).unwrap();
+    for event in events.iter() {
+        tokens.push(event.token());
+    }
+    tokens.sort();
+    assert_eq!(tokens, vec![mio::Token(2), mio::Token(3)]);
+
+    for token in tokens.into_iter() {
+        let off = token.0 - Listener::TOKEN_LISTENER.0;
+        assert!(matches!(listener.accept_conn(off), QueueStatus::Ok(_)));
+    }
+    for mut client in clients.into_iter() {
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).unwrap();
+        let (cack, _) = v5::ConnAck::decode(&buf).unwrap();
+        assert_eq!(cack.code, v5::ConnackReasonCode::QuotaExceeded);
+    }
+
+    let run_loop = as_mut_run_loop(&mut listener);
+    assert_eq!(run_loop.stats.n_refused, 2);
+}
@@ -0,0 +1,142 @@
+use std::{collections::VecDeque, io};
+
+use crate::broker::socket::{Sink, Source};
+use crate::broker::{Config, ConnState, PktRx, PktTx, Socket};
+use crate::{ClientID, MQTTRead, MQTTWrite};
+
+/// Socket over an in-memory connection, for testing session and shard logic
+/// deterministically, without OS sockets.
+pub type MemSocket = Socket<MemConn>;
+
+/// Type implement a non-blocking, in-memory connection.
+///
+/// Bytes sent by the client, via [MemConn::feed], are read by the socket, and bytes
+/// written by the socket are collected by the client via [MemConn::drain]. Reads and
+/// writes return `WouldBlock` when there is no data, or when injected via
+/// [MemConn::block_reads] and [MemConn::block_writes]. Partial writes are injected
+/// via [MemConn::short_write].
+#[derive(Default)]
+pub struct MemConn {
+    rd: VecDeque<u8>,
+    wt: VecDeque<u8>,
+    rd_blocks: usize,
+    wt_blocks: usize,
+    wt_short: Option<usize>,
+    closed: bool,
+}
+
+impl MemConn {
+    /// Client sends `data` to the socket.
+    pub fn feed(&mut self, data: &[u8]) {
+        self.rd.extend(data.iter());
+    }
+
+    /// Client receives all the bytes written by the socket so far.
+    pub fn drain(&mut self) -> Vec<u8> {
+        self.wt.drain(..).collect()
+    }
+
+    /// Fail the next `n` reads with `WouldBlock`, even if there is data to read.
+    pub fn block_reads(&mut self, n: usize) {
+        self.rd_blocks = n;
+    }
+
+    /// Fail the next `n` writes with `WouldBlock`.
+    pub fn block_writes(&mut self, n: usize) {
+        self.wt_blocks = n;
+    }
+
+    /// Accept only `n` bytes in the next write, and fail the write after that with
+    /// `WouldBlock`, like a connection whose send buffer is full.
+    pub fn short_write(&mut self, n: usize) {
+        self.wt_short = Some(n);
+    }
+
+    /// Client closes the connection, reads return 0 once pending bytes are read.
+    pub fn close(&mut self) {
+        self.closed = true;
+    }
+}
+
+impl io::Read for MemConn {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.rd_blocks > 0 {
+            self.rd_blocks -= 1;
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        match self.rd.len() {
+            0 if self.closed => Ok(0),
+            0 => Err(io::ErrorKind::WouldBlock.into()),
+            _ => {
+                let n = std::cmp::min(buf.len(), self.rd.len());
+                for (i, byte) in self.rd.drain(..n).enumerate() {
+                    buf[i] = byte;
+                }
+                Ok(n)
+            }
+        }
+    }
+}
+
+impl io::Write for MemConn {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        } else if self.wt_blocks > 0 {
+            self.wt_blocks -= 1;
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        let n = match self.wt_short.take() {
+            Some(n) => {
+                self.wt_blocks = 1;
+                std::cmp::min(n, buf.len())
+            }
+            None => buf.len(),
+        };
+        self.wt.extend(buf[..n].iter());
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Create a [MemSocket] for `client_id`. Packets read from the socket are sent via
+/// `session_tx` and packets received on `miot_rx` are written to the socket. Like
+/// a connection handed over after handshake when `state` is `Active`.
+pub fn mem_socket(
+    client_id: ClientID,
+    config: &Config,
+    session_tx: PktTx,
+    miot_rx: PktRx,
+    state: ConnState,
+) -> MemSocket {
+    let max_size = config.mqtt_max_packet_size;
+    Socket {
+        client_id,
+        conn: MemConn::default(),
+        guard: None,
+        token: mio::Token(1),
+        rd: Source {
+            pr: MQTTRead::new(max_size),
+            timeout: None,
+            session_tx,
+            packets: VecDeque::default(),
+            state,
+        },
+        wt: Sink {
+            pw: MQTTWrite::new(&[], max_size),
+            timeout: None,
+            miot_rx,
+            packets: VecDeque::default(),
+            disconnected: false,
+        },
+    }
+}
+
+#[cfg(test)]
+#[path = "mem_socket_test.rs"]
+mod mem_socket_test;
@@ -0,0 +1,132 @@
+use std::sync::{atomic::AtomicUsize, Arc};
+
+use super::*;
+use crate::broker::{pkt_channel, BufferPool, QueueStatus};
+use crate::{v5, Packetize};
+
+#[test]
+fn test_mem_socket_connect() {
+    let config = Config::default();
+    let poll = mio::Poll::new().unwrap();
+    let waker = Arc::new(mio::Waker::new(poll.registry(), mio::Token(0)).unwrap());
+    let buffered = Arc::new(AtomicUsize::new(0));
+
+    let (session_tx, session_rx) =
+        pkt_channel(0, 16, Arc::clone(&waker), Arc::clone(&buffered));
+    let (mut miot_tx, miot_rx) =
+        pkt_channel(0, 16, Arc::clone(&waker), Arc::clone(&buffered));
+
+    let client_id = ClientID("mem-socket".to_string());
+    let state = ConnState::Handshake;
+    let mut sock = mem_socket(client_id.clone(), &config, session_tx, miot_rx, state);
+    let mut pool = BufferPool::default();
+
+    // client sends CONNECT, trickled in with the socket blocking in between.
+    let connect = {
+        let mut connect = v5::Connect::default();
+        connect.payload.client_id = client_id.clone();
+        connect
+    };
+    let data = v5::Packet::Connect(connect.clone()).encode().unwrap();
+    let (a, b) = data.as_ref().split_at(3);
+
+    sock.conn.feed(a);
+    sock.read_packets("test", &config, &mut pool).unwrap();
+    sock.conn.block_reads(2);
+    for _ in 0..2 {
+        sock.read_packets("test", &config, &mut pool).unwrap();
+    }
+    assert_eq!(sock.rd.state, ConnState::Handshake);
+    assert!(session_rx.try_recvs("test").take_values().is_empty());
+
+    sock.conn.feed(b);
+    for _ in 0..4 {
+        sock.read_packets("test", &config, &mut pool).unwrap();
+    }
+    assert_eq!(sock.rd.state, ConnState::Connecting);
+    match session_rx.try_recvs("test").take_values().as_slice() {
+        [v5::Packet::Connect(val)] => assert_eq!(val, &connect),
+        pkts => panic!("unexpected {:?}", pkts),
+    }
+
+    // session sends CONNACK, first write would block.
+    let connack = v5::ConnAck::new_success(None);
+    let status = miot_tx.try_sends("test", vec![v5::Packet::ConnAck(connack.clone())]);
+    assert!(matches!(status, QueueStatus::Ok(_)));
+
+    sock.conn.block_writes(1);
+    let (status, _) = sock.write_packets("test", &config, &mut pool);
+    assert!(matches!(status, QueueStatus::Block(_)));
+    assert!(sock.conn.drain().is_empty());
+
+    let (status, _) = sock.write_packets("test", &config, &mut pool);
+    assert!(matches!(status, QueueStatus::Ok(_)));
+    assert_eq!(sock.rd.state, ConnState::Active);
+
+    let data = sock.conn.drain();
+    match v5::Packet::decode(&data).unwrap() {
+        (v5::Packet::ConnAck(val), n) => {
+            assert_eq!(val, connack);
+            assert_eq!(n, data.len());
+        }
+        (pkt, _) => panic!("unexpected {:?}", pkt),
+    }
+
+    // client closes the connection.
+    sock.conn.close();
+    let status = sock.read_packets("test", &config, &mut pool).unwrap();
+    assert!(matches!(status, QueueStatus::Disconnected(_)));
+}
+
+#[test]
+fn test_mem_socket_short_write() {
+    let config = Config::default();
+    let poll = mio::Poll::new().unwrap();
+    let waker = Arc::new(mio::Waker::new(poll.registry(), mio::Token(0)).unwrap());
+    let buffered = Arc::new(AtomicUsize::new(0));
+
+    let (session_tx, _session_rx) =
+        pkt_channel(0, 16, Arc::clone(&waker), Arc::clone(&buffered));
+    let (mut miot_tx, miot_rx) =
+        pkt_channel(0, 16, Arc::clone(&waker), Arc::clone(&buffered));
+
+    let client_id = ClientID("mem-socket-short-write".to_string());
+    let state = ConnState::Active;
+    let mut sock = mem_socket(client_id, &config, session_tx, miot_rx, state);
+    let mut pool = BufferPool::default();
+
+    let publish = |id: u16| {
+        v5::Packet::Publish(v5::Publish {
+            retain: false,
+            qos: v5::QoS::AtLeastOnce,
+            duplicate: false,
+            topic_name: crate::TopicName::from("a/b/c".to_string()),
+            packet_id: Some(id),
+            properties: None,
+            payload: Some(vec![id as u8; 1024]),
+        })
+    };
+    let pkts = vec![publish(1), publish(2)];
+    let status = miot_tx.try_sends("test", pkts.clone());
+    assert!(matches!(status, QueueStatus::Ok(_)));
+
+    // first packet is written partially, and the connection blocks.
+    sock.conn.short_write(100);
+    let (status, _) = sock.write_packets("test", &config, &mut pool);
+    assert!(matches!(status, QueueStatus::Block(_)));
+    let mut data = sock.conn.drain();
+    assert_eq!(data.len(), 100);
+
+    // remaining bytes of the in-progress packet, followed by the next packet.
+    let (status, _) = sock.write_packets("test", &config, &mut pool);
+    assert!(matches!(status, QueueStatus::Ok(_)));
+    data.extend(sock.conn.drain());
+
+    let mut off = 0;
+    for pkt in pkts.iter() {
+        let (val, n) = v5::Packet::decode(&data[off..]).unwrap();
+        assert_eq!(&val, pkt);
+        off += n;
+    }
+    assert_eq!(off, data.len());
+}
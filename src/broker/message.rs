@@ -2,6 +2,7 @@
 use arbitrary::{Arbitrary, Error as ArbitraryError, Unstructured};
 use log::{error, warn};
 
+use std::collections::BTreeMap;
 use std::sync::{mpsc, Arc};
 use std::{fmt, result};
 
@@ -126,7 +127,8 @@ pub enum Message {
         publish: v5::Publish, // publish packet, as received from publishing client
         ack_needed: bool,
     },
-    /// Message that is periodically published by a session to other local shards.
+    /// Message that is periodically published by a shard to other local shards,
+    /// refer to [crate::broker::Config::local_ack_interval_ms].
     LocalAck {
         shard_id: u32,        // shard sending the acknowledgement
         last_acked: InpSeqno, // from publishing-shard.
@@ -219,7 +221,7 @@ impl Message {
             src_shard_id: sess.to_shard_id(),
             client_id,
             inp_seqno: seqno,
-            out_seqno: 0,
+            out_seqno: OutSeqno::default(),
             publish,
             ack_needed,
         }
@@ -229,6 +231,22 @@ impl Message {
         Message::Index { src_client_id: src_client_id.clone(), packet_id }
     }
 
+    /// Group subscriptions by the shard hosting the subscribing client, computed
+    /// from its client-id, refer [ClientID::shard_hash]. Sender can then batch its
+    /// messages with one [MsgTx::try_sends] per target shard.
+    pub fn route_targets(
+        subs: &[v5::Subscription],
+        num_shards: u32,
+    ) -> BTreeMap<u32, Vec<v5::Subscription>> {
+        let mut targets: BTreeMap<u32, Vec<v5::Subscription>> = BTreeMap::new();
+        for subscr in subs.iter() {
+            let shard_id = subscr.client_id.shard_hash(num_shards);
+            targets.entry(shard_id).or_default().push(subscr.clone());
+        }
+
+        targets
+    }
+
     pub fn into_packet(self, pktid: Option<PacketID>) -> Message {
         match self {
             Message::Routed { out_seqno, mut publish, .. } => {
@@ -249,6 +267,16 @@ impl Message {
         }
     }
 
+    /// Return true if this is a QoS-0 PUBLISH message.
+    pub fn is_qos0(&self) -> bool {
+        match self {
+            Message::Routed { publish, .. } | Message::Packet { publish, .. } => {
+                publish.qos == v5::QoS::AtMostOnce
+            }
+            _ => false,
+        }
+    }
+
     pub fn to_out_seqno(&self) -> OutSeqno {
         match self {
             Message::Routed { out_seqno, .. } => *out_seqno,
@@ -277,6 +305,46 @@ impl Message {
             _ => unreachable!(),
         }
     }
+
+    /// Return a one line summary of this message, suitable for tracing. PUBLISH
+    /// payload is summarized by its length, its content is never rendered.
+    pub fn summary(&self) -> String {
+        let pkt_id = |packet_id: &Option<PacketID>| match packet_id {
+            Some(packet_id) => packet_id.to_string(),
+            None => "-".to_string(),
+        };
+        let len = |publish: &v5::Publish| publish.payload.as_ref().map(|p| p.len());
+
+        match self {
+            Message::ClientAck { packet } => {
+                format!("ClientAck{{packet={:?}}}", packet.to_packet_type())
+            }
+            Message::Packet { out_seqno, packet_id, publish } => format!(
+                "Packet{{seqno={},packet_id={},topic={:?},len={}}}",
+                out_seqno,
+                pkt_id(packet_id),
+                publish.topic_name.as_str(),
+                len(publish).unwrap_or(0),
+            ),
+            Message::Index { src_client_id, packet_id } => {
+                format!("Index{{src={},packet_id={}}}", **src_client_id, packet_id)
+            }
+            Message::Routed { src_shard_id, client_id, inp_seqno, publish, .. } => {
+                format!(
+                    "Routed{{src={},client={},seqno={},packet_id={},topic={:?},len={}}}",
+                    src_shard_id,
+                    **client_id,
+                    inp_seqno,
+                    pkt_id(&publish.packet_id),
+                    publish.topic_name.as_str(),
+                    len(publish).unwrap_or(0),
+                )
+            }
+            Message::LocalAck { shard_id, last_acked } => {
+                format!("LocalAck{{shard={},last_acked={}}}", shard_id, last_acked)
+            }
+        }
+    }
 }
 
 /// Create a message-queue for shard `shard_id` that can hold upto `size` messages.
@@ -290,3 +358,7 @@ pub fn msg_channel(shard_id: u32, size: usize, waker: Arc<mio::Waker>) -> (MsgTx
 
     (msg_tx, msg_rx)
 }
+
+#[cfg(test)]
+#[path = "message_test.rs"]
+mod message_test;
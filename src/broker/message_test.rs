@@ -0,0 +1,95 @@
+use crate::broker::{InpSeqno, Seqno};
+use crate::{TopicFilter, TopicName};
+
+use super::*;
+
+const PAYLOAD: &[u8] = b"secret-payload";
+
+fn new_publish(packet_id: Option<PacketID>) -> v5::Publish {
+    v5::Publish {
+        retain: false,
+        qos: v5::QoS::AtLeastOnce,
+        duplicate: false,
+        topic_name: TopicName::from("a/b".to_string()),
+        packet_id,
+        properties: None,
+        payload: Some(PAYLOAD.to_vec()),
+    }
+}
+
+#[test]
+fn test_message_summary() {
+    let routed = Message::Routed {
+        src_shard_id: 2,
+        client_id: ClientID("c1".to_string()),
+        inp_seqno: InpSeqno::from(7),
+        out_seqno: OutSeqno::default(),
+        publish: new_publish(None),
+        ack_needed: true,
+    };
+    assert_eq!(
+        routed.summary(),
+        r#"Routed{src=2,client=c1,seqno=7,packet_id=-,topic="a/b",len=14}"#
+    );
+
+    let packet = routed.into_packet(Some(11));
+    assert_eq!(packet.summary(), r#"Packet{seqno=0,packet_id=11,topic="a/b",len=14}"#);
+
+    let msgs = vec![
+        packet,
+        Message::new_index(&ClientID("c1".to_string()), 11),
+        Message::LocalAck { shard_id: 2, last_acked: InpSeqno::from(7) },
+        Message::new_ping_resp(),
+    ];
+    for msg in msgs.into_iter() {
+        let summary = msg.summary();
+        assert!(!summary.contains("secret"), "{}", summary);
+        assert!(!summary.contains(&format!("{:?}", PAYLOAD)), "{}", summary);
+    }
+}
+
+#[test]
+fn test_seqno() {
+    let mut seqno = OutSeqno::default();
+    assert_eq!(u64::from(seqno), 0);
+    assert_eq!(seqno.next(), OutSeqno::from(1));
+    assert_eq!(seqno.next(), OutSeqno::from(2));
+    assert_eq!(seqno, OutSeqno::from(2));
+    assert!(seqno > OutSeqno::from(1));
+    assert_eq!(seqno + 3, OutSeqno::from(5));
+
+    // never yields ZERO, saturates at the upper bound.
+    let mut seqno = InpSeqno::from(u64::MAX - 1);
+    assert_eq!(seqno.next(), InpSeqno::from(u64::MAX));
+    assert_eq!(seqno.next(), InpSeqno::from(u64::MAX));
+    assert_eq!(seqno + 1, InpSeqno::from(u64::MAX));
+}
+
+#[test]
+fn test_route_targets() {
+    let num_shards = 4;
+    let subs: Vec<v5::Subscription> = (0..32)
+        .map(|i| v5::Subscription {
+            topic_filter: TopicFilter::from("a/#".to_string()),
+            client_id: ClientID(format!("client-{}", i)),
+            shard_id: 0,
+            subscription_id: None,
+            qos: v5::QoS::AtMostOnce,
+            no_local: false,
+            retain_as_published: false,
+            retain_forward_rule: v5::RetainForwardRule::OnEverySubscribe,
+        })
+        .collect();
+
+    let targets = Message::route_targets(&subs, num_shards);
+    assert_eq!(targets.len(), 4, "{:?}", targets.keys());
+    assert_eq!(targets.values().map(|v| v.len()).sum::<usize>(), subs.len());
+    for (shard_id, subs) in targets.iter() {
+        assert!(*shard_id < num_shards);
+        for subscr in subs.iter() {
+            assert_eq!(subscr.client_id.shard_hash(num_shards), *shard_id);
+        }
+    }
+
+    assert!(Message::route_targets(&[], num_shards).is_empty());
+}
@@ -0,0 +1,83 @@
+use std::fmt::Write;
+
+/// Type aggregates statistics across the cluster and its shards.
+///
+/// Refer [Cluster::metrics_text] to render them in Prometheus text exposition format.
+///
+/// [Cluster::metrics_text]: crate::broker::Cluster::metrics_text
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    /// Number of poll events handled by cluster thread.
+    pub n_events: usize,
+    /// Number of control requests handled by cluster thread.
+    pub n_requests: usize,
+    /// Number of unique topic-filters subscribed across all sessions.
+    pub n_subscriptions: usize,
+    /// Number of retained messages.
+    pub n_retained: usize,
+    /// Metrics from each shard, in shard_id order.
+    pub shards: Vec<ShardMetrics>,
+}
+
+/// Type captures a snapshot of statistics from a single shard.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ShardMetrics {
+    pub shard_id: u32,
+    /// Number of sessions with an active connection.
+    pub n_sessions: usize,
+    /// Number of sessions waiting for their client to reconnect.
+    pub n_reconnects: usize,
+    /// Number of poll events handled by shard thread.
+    pub n_events: usize,
+    /// Number of control requests handled by shard thread.
+    pub n_requests: usize,
+    /// Number of packets received from clients.
+    pub n_packets: usize,
+    /// Number of times an outbound queue, to a client or a shard, was full.
+    pub n_queue_blocks: usize,
+    /// Number of connections dropped because their queue was disconnected.
+    pub n_queue_drops: usize,
+}
+
+impl Metrics {
+    /// Render metrics in Prometheus text exposition format.
+    pub fn to_text(&self) -> String {
+        let mut text = String::default();
+
+        let cluster = [
+            ("events_total", "counter", "Poll events handled", self.n_events),
+            ("requests_total", "counter", "Control requests handled", self.n_requests),
+            ("subscriptions", "gauge", "Subscribed topic-filters", self.n_subscriptions),
+            ("retained_messages", "gauge", "Retained messages", self.n_retained),
+        ];
+        for (name, typ, help, value) in cluster.iter() {
+            write_header(&mut text, &format!("cluster_{}", name), typ, help);
+            writeln!(text, "mymq_cluster_{} {}", name, value).ok();
+        }
+
+        type Getter = fn(&ShardMetrics) -> usize;
+        let shards: [(&str, &str, &str, Getter); 7] = [
+            ("connections", "gauge", "Sessions with active connection", |m| m.n_sessions),
+            ("reconnects", "gauge", "Sessions waiting to reconnect", |m| m.n_reconnects),
+            ("events_total", "counter", "Shard poll events handled", |m| m.n_events),
+            ("requests_total", "counter", "Shard control requests", |m| m.n_requests),
+            ("packets_total", "counter", "Packets from clients", |m| m.n_packets),
+            ("queue_blocks_total", "counter", "Queue was full", |m| m.n_queue_blocks),
+            ("queue_drops_total", "counter", "Connections dropped", |m| m.n_queue_drops),
+        ];
+        for (name, typ, help, getter) in shards.iter() {
+            write_header(&mut text, name, typ, help);
+            for m in self.shards.iter() {
+                let (id, value) = (m.shard_id, getter(m));
+                writeln!(text, "mymq_{}{{shard=\"{}\"}} {}", name, id, value).ok();
+            }
+        }
+
+        text
+    }
+}
+
+fn write_header(text: &mut String, name: &str, typ: &str, help: &str) {
+    writeln!(text, "# HELP mymq_{} {}", name, help).ok();
+    writeln!(text, "# TYPE mymq_{} {}", name, typ).ok();
+}
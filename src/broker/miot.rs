@@ -4,7 +4,10 @@ use std::collections::{BTreeMap, VecDeque};
 use std::{fmt, mem, net, result, sync::Arc, time};
 
 use crate::broker::thread::{Rx, Thread, Threadable};
-use crate::broker::{socket, AppTx, Config, QueueStatus, Shard, Socket};
+use crate::broker::{
+    socket, AppTx, AtomicStats, Config, ConnGuard, ConnState, QueueStatus,
+};
+use crate::broker::{BufferPool, PoolStats, Shard, Socket};
 
 use crate::{ClientID, MQTTRead, MQTTWrite, ToJson};
 use crate::{Error, ErrorKind, Result};
@@ -59,9 +62,13 @@ struct RunLoop {
     next_token: mio::Token,
     /// collection of all active socket connections, and its associated data.
     conns: BTreeMap<ClientID, Socket>,
+    /// buffers recycled by sockets for reading and writing packets.
+    pool: BufferPool,
 
     /// Statistics
     stats: Stats,
+    /// Statistics aggregated across all shards, refer [AtomicStats].
+    atomic_stats: Arc<AtomicStats>,
 
     /// Back channel communicate with application.
     app_tx: AppTx,
@@ -73,6 +80,7 @@ pub struct FinState {
     pub addrs: Vec<net::SocketAddr>,
     pub tokens: Vec<mio::Token>,
     pub stats: Stats,
+    pub pool_stats: PoolStats,
 }
 
 #[derive(Clone, Copy, Default)]
@@ -92,7 +100,7 @@ impl FinState {
             concat!(
                 "{{ ",
                 "{:?}: {}, {:?}: {}, {:?}: {}, {:?}: {}, {:?}: {}, {:?}: {},",
-                "{:?}: {}, {:?}: {}",
+                "{:?}: {}, {:?}: {}, {:?}: {}, {:?}: {}",
                 "}}"
             ),
             "next_token",
@@ -111,6 +119,10 @@ impl FinState {
             self.stats.n_wpkts,
             "n_wbytes",
             self.stats.n_wbytes,
+            "n_pool_hits",
+            self.pool_stats.n_hits,
+            "n_pool_misses",
+            self.pool_stats.n_misses,
         )
     }
 }
@@ -177,7 +189,12 @@ impl Miot {
         Ok(val)
     }
 
-    pub fn spawn(self, shard: Shard, app_tx: AppTx) -> Result<Miot> {
+    pub fn spawn(
+        self,
+        shard: Shard,
+        app_tx: AppTx,
+        atomic_stats: Arc<AtomicStats>,
+    ) -> Result<Miot> {
         let poll = mio::Poll::new()?;
         let waker = Arc::new(mio::Waker::new(poll.registry(), Self::WAKE_TOKEN)?);
 
@@ -193,8 +210,10 @@ impl Miot {
 
                 next_token: Self::FIRST_TOKEN,
                 conns: BTreeMap::default(),
+                pool: BufferPool::default(),
 
                 stats: Stats::default(),
+                atomic_stats,
 
                 app_tx: app_tx.clone(),
             }),
@@ -237,6 +256,7 @@ pub enum Response {
 pub struct AddConnectionArgs {
     pub client_id: ClientID,
     pub conn: mio::net::TcpStream,
+    pub guard: Option<ConnGuard>,
     pub upstream: socket::PktTx,
     pub downstream: socket::PktRx,
     pub max_packet_size: u32,
@@ -391,8 +411,8 @@ impl Miot {
 
 impl Miot {
     fn socket_to_session(&mut self) {
-        let conns = match &mut self.inner {
-            Inner::Main(RunLoop { conns, .. }) => conns,
+        let (conns, pool) = match &mut self.inner {
+            Inner::Main(RunLoop { conns, pool, .. }) => (conns, pool),
             inner => unreachable!("{} {:?}", self.prefix, inner),
         };
 
@@ -402,16 +422,19 @@ impl Miot {
                 let raddr = socket.conn.peer_addr().unwrap();
                 format!("rconn:{}:{}", raddr, **client_id)
             };
-            match socket.read_packets(&prefix, &self.config) {
+            match socket.read_packets(&prefix, &self.config, pool) {
                 Ok(QueueStatus::Ok(_)) | Ok(QueueStatus::Block(_)) => (),
                 Ok(QueueStatus::Disconnected(_)) => {
                     fail_queues.push((client_id.clone(), None));
                 }
-                Err(err) if err.kind() == ErrorKind::ProtocolError => {
-                    error!("{} error in read_packets err:{}", prefix, err);
-                    fail_queues.push((client_id.clone(), Some(err)));
-                }
-                Err(err) if err.kind() == ErrorKind::MalformedPacket => {
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        ErrorKind::ProtocolError
+                            | ErrorKind::MalformedPacket
+                            | ErrorKind::SlowClient
+                    ) =>
+                {
                     error!("{} error in read_packets err:{}", prefix, err);
                     fail_queues.push((client_id.clone(), Some(err)));
                 }
@@ -430,8 +453,10 @@ impl Miot {
     fn session_to_socket(&mut self) {
         use crate::broker::socket::Stats as SockStats;
 
-        let conns = match &mut self.inner {
-            Inner::Main(RunLoop { conns, .. }) => conns,
+        let (conns, pool, atomic_stats) = match &mut self.inner {
+            Inner::Main(RunLoop { conns, pool, atomic_stats, .. }) => {
+                (conns, pool, atomic_stats)
+            }
             inner => unreachable!("{} {:?}", self.prefix, inner),
         };
 
@@ -443,7 +468,9 @@ impl Miot {
                 let raddr = socket.conn.peer_addr().unwrap();
                 format!("wconn:{}:{}", raddr, **client_id)
             };
-            match socket.write_packets(&prefix, &self.config) {
+            let res = socket.write_packets(&prefix, &self.config, pool);
+            atomic_stats.incr(&res.1);
+            match res {
                 (QueueStatus::Ok(_), stats) => {
                     wstats.update(&stats);
                     () // TODO: should we wake the session here.
@@ -480,11 +507,11 @@ impl Miot {
         };
         let raddr = args.conn.peer_addr().unwrap();
 
-        let (poll, conns, token) = match &mut self.inner {
-            Inner::Main(RunLoop { poll, conns, next_token, .. }) => {
+        let (poll, conns, atomic_stats, token) = match &mut self.inner {
+            Inner::Main(RunLoop { poll, conns, atomic_stats, next_token, .. }) => {
                 let token = *next_token;
                 *next_token = mio::Token(next_token.0 + 1);
-                (poll, conns, token)
+                (poll, conns, atomic_stats, token)
             }
             inner => unreachable!("{} {:?}", self.prefix, inner),
         };
@@ -498,20 +525,24 @@ impl Miot {
         allow_panic!(self, poll.registry().register(&mut args.conn, token, interests));
 
         let rd = socket::Source {
-            pr: MQTTRead::new(max_packet_size),
+            pr: MQTTRead::with_buffer(Vec::new(), max_packet_size),
             timeout: None,
             session_tx,
             packets: VecDeque::default(),
+            state: ConnState::Active, // CONNECT was consumed by handshake.
         };
         let wt = socket::Sink {
-            pw: MQTTWrite::new(&[], args.max_packet_size),
+            pw: MQTTWrite::with_buffer(Vec::new(), args.max_packet_size),
             timeout: None,
             miot_rx,
             packets: VecDeque::default(),
+            disconnected: false,
         };
-        let (client_id, conn) = (args.client_id.clone(), args.conn);
-        let socket = socket::Socket { client_id, conn, token, rd, wt };
-        conns.insert(args.client_id, socket);
+        let (client_id, conn, guard) = (args.client_id.clone(), args.conn, args.guard);
+        let socket = socket::Socket { client_id, conn, guard, token, rd, wt };
+        if conns.insert(args.client_id, socket).is_none() {
+            atomic_stats.incr_connections(1);
+        }
 
         self.incr_n_add_conns();
 
@@ -524,13 +555,16 @@ impl Miot {
             _ => unreachable!(),
         };
 
-        let (poll, conns) = match &mut self.inner {
-            Inner::Main(RunLoop { poll, conns, .. }) => (poll, conns),
+        let (poll, conns, atomic_stats) = match &mut self.inner {
+            Inner::Main(RunLoop { poll, conns, atomic_stats, .. }) => {
+                (poll, conns, atomic_stats)
+            }
             inner => unreachable!("{} {:?}", self.prefix, inner),
         };
 
         let res = match conns.remove(&client_id) {
             Some(mut socket) => {
+                atomic_stats.decr_connections(1);
                 let raddr = socket.conn.peer_addr().unwrap();
                 info!("{} raddr:{} removing connection ...", self.prefix, raddr);
                 allow_panic!(&self, poll.registry().deregister(&mut socket.conn));
@@ -560,6 +594,7 @@ impl Miot {
         mem::drop(run_loop.poll);
         mem::drop(run_loop.shard);
         let conns = mem::replace(&mut run_loop.conns, BTreeMap::default());
+        run_loop.atomic_stats.decr_connections(conns.len());
 
         let mut client_ids = Vec::with_capacity(conns.len());
         let mut addrs = Vec::with_capacity(conns.len());
@@ -579,6 +614,7 @@ impl Miot {
             addrs,
             tokens,
             stats: run_loop.stats,
+            pool_stats: run_loop.pool.to_stats(),
         };
 
         info!("{} stats:{}", self.prefix, fin_state.to_json());
@@ -1,6 +1,9 @@
 //! Broker implementation.
 
-use std::{mem, net, path, sync::mpsc};
+#[cfg(any(feature = "fuzzy", test))]
+use arbitrary::Arbitrary;
+
+use std::{fmt, mem, net, ops, path, sync::mpsc};
 
 /// Used with [mio] library while polling for events.
 pub const POLL_EVENTS_SIZE: usize = 1024;
@@ -8,17 +11,84 @@ pub const POLL_EVENTS_SIZE: usize = 1024;
 /// Control Queue is processed in batches of this constant.
 pub const CONTROL_CHAN_SIZE: usize = 1024;
 
+/// Maximum number of buffers pooled by each shard, refer [BufferPool].
+pub const BUFFER_POOL_SIZE: usize = 1024;
+
+/// Capacity of buffers allocated and pooled by [BufferPool].
+pub const BUFFER_CAPACITY: usize = 4096;
+
 /// Type alias for back-channel to application.
 ///
 /// While creating the Cluster, application can pass an mpsc channel to [Cluster] that
 /// the application will be listening on.
 pub type AppTx = mpsc::SyncSender<String>;
 
-/// Seqno counted for every outgoing publish packet for each session.
-type OutSeqno = u64;
+/// Monotonically increasing sequence numbers, starting from 1. ZERO is never yielded
+/// by [Seqno::next], and the default value of ZERO is used to mean no-seqno.
+///
+/// [InpSeqno] and [OutSeqno] are distinct types and can't be mixed.
+///
+/// ```compile_fail
+/// use mymq::broker::{InpSeqno, OutSeqno};
+///
+/// let out_seqno: OutSeqno = InpSeqno::from(1);
+/// ```
+pub trait Seqno: Copy + Ord {
+    /// Allocate the next sequence number, and remember it as the last allocated.
+    fn next(&mut self) -> Self;
+}
+
+macro_rules! seqno_type {
+    ($(#[$doc:meta])* $name:ident) => {
+        $(#[$doc])*
+        #[cfg_attr(any(feature = "fuzzy", test), derive(Arbitrary))]
+        #[derive(Clone, Copy, Default, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(u64);
+
+        impl Seqno for $name {
+            fn next(&mut self) -> $name {
+                self.0 = self.0.saturating_add(1);
+                *self
+            }
+        }
+
+        impl ops::Add<u64> for $name {
+            type Output = $name;
+
+            fn add(self, rhs: u64) -> $name {
+                $name(self.0.saturating_add(rhs))
+            }
+        }
+
+        impl From<u64> for $name {
+            fn from(val: u64) -> $name {
+                $name(val)
+            }
+        }
+
+        impl From<$name> for u64 {
+            fn from(val: $name) -> u64 {
+                val.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}", self.0)
+            }
+        }
+    };
+}
+
+seqno_type!(
+    /// Seqno counted for every outgoing publish packet for each session.
+    OutSeqno
+);
 
-/// Seqno counted for every incoming publish packet for each shard.
-type InpSeqno = u64;
+seqno_type!(
+    /// Seqno counted for every incoming publish packet for each shard.
+    InpSeqno
+);
 
 /// Timestamp list managed at incoming publish, used to track lossless publish to all
 /// subscribed-clients.
@@ -100,37 +170,57 @@ pub fn mqtt_listen_address4(port: Option<u16>) -> net::SocketAddr {
     net::SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port)
 }
 
+mod auth;
 mod cluster;
 // TODO: mod consensus;
 mod flush;
 mod handshake;
 mod keep_alive;
+mod lifecycle;
+mod link;
 mod listener;
+#[cfg(test)]
+mod mem_socket;
 mod message;
+mod metrics;
 mod miot;
+mod pool;
 mod rebalance;
 // TODO: mod rr;
 mod config;
+mod event;
 mod session;
 mod shard;
 mod socket;
 mod spinlock;
+mod steal;
+mod sys;
 mod thread;
 mod ticker;
 mod ttrie;
 
-pub use cluster::{Cluster, Node};
-pub use config::{Config, ConfigNode};
+pub use auth::{AllowAll, AuthResult, Authenticator, Authorizer};
+pub use cluster::{Cluster, ClusterStats, Node};
+pub use config::{BacklogPolicy, Config, ConfigListener, ConfigNode};
+pub use event::{BrokerEvent, EventSink, JsonFileSink};
 pub use flush::Flusher;
 pub use handshake::Handshake;
 pub use keep_alive::KeepAlive;
-pub use listener::Listener;
+pub use lifecycle::{ConnEvent, ConnState};
+pub use link::{ConsensusLink, LinkState};
+pub use listener::{ConnGuard, Listener};
+#[cfg(test)]
+pub use mem_socket::{mem_socket, MemConn, MemSocket};
 pub use message::{msg_channel, Message, MsgRx, MsgTx};
+pub use metrics::{Metrics, ShardMetrics};
 pub use miot::Miot;
-pub use session::Session;
+pub use pool::{BufferPool, PoolStats};
+pub use session::{ClientInfo, ClientOut, Session};
 pub use shard::Shard;
-pub use socket::{pkt_channel, PktRx, PktTx, Socket};
+pub use socket::{pkt_channel, AtomicStats, PktRx, PktTx, Socket, StatsSnapshot};
 pub use spinlock::Spinlock;
+pub use steal::{work_queues, Work, WorkQueue, WorkStats};
+pub use sys::SysPublisher;
 pub use thread::{Rx, Thread, Threadable, Tx};
 pub use ticker::Ticker;
 pub use ttrie::{RetainedTrie, SubscribedTrie};
@@ -0,0 +1,82 @@
+/// Type implement a pool of byte-buffers, recycled by sockets for reading and
+/// writing MQTT packets.
+///
+/// There is a pool for every [Miot] thread, that is, one per shard. A buffer is taken
+/// out of the pool when a socket starts reading a packet, or when a packet is encoded
+/// for writing, and it is given back once the packet is read or flushed. This way
+/// idle sockets do not hold on to buffers, and buffers are re-used across packets
+/// and sockets instead of being allocated afresh.
+///
+/// Pool holds upto `size` buffers, buffers given back beyond that are freed. Buffers
+/// that grew beyond `capacity`, to hold a large packet, are shrunk back to `capacity`
+/// before they are pooled.
+///
+/// [Miot]: crate::broker::Miot
+pub struct BufferPool {
+    size: usize,
+    capacity: usize,
+    buffers: Vec<Vec<u8>>,
+    stats: PoolStats,
+}
+
+/// Statistics for [BufferPool].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Number of buffers handed out from the pool.
+    pub n_hits: usize,
+    /// Number of buffers allocated, because the pool was empty.
+    pub n_misses: usize,
+    /// Number of buffers given back to the pool.
+    pub n_returns: usize,
+    /// Number of buffers currently held in the pool.
+    pub n_pooled: usize,
+}
+
+impl Default for BufferPool {
+    fn default() -> BufferPool {
+        use crate::broker::{BUFFER_CAPACITY, BUFFER_POOL_SIZE};
+
+        BufferPool::new(BUFFER_POOL_SIZE, BUFFER_CAPACITY)
+    }
+}
+
+impl BufferPool {
+    pub fn new(size: usize, capacity: usize) -> BufferPool {
+        BufferPool {
+            size,
+            capacity,
+            buffers: Vec::with_capacity(size),
+            stats: PoolStats::default(),
+        }
+    }
+
+    /// Take an empty buffer from the pool, allocate a new one if pool is empty.
+    pub fn take(&mut self) -> Vec<u8> {
+        match self.buffers.pop() {
+            Some(buf) => {
+                self.stats.n_hits += 1;
+                buf
+            }
+            None => {
+                self.stats.n_misses += 1;
+                Vec::with_capacity(self.capacity)
+            }
+        }
+    }
+
+    /// Give back `buf` to the pool. Buffers without allocation are ignored.
+    pub fn give(&mut self, mut buf: Vec<u8>) {
+        if buf.capacity() == 0 || self.buffers.len() >= self.size {
+            return;
+        }
+
+        buf.clear();
+        buf.shrink_to(self.capacity);
+        self.buffers.push(buf);
+        self.stats.n_returns += 1;
+    }
+
+    pub fn to_stats(&self) -> PoolStats {
+        PoolStats { n_pooled: self.buffers.len(), ..self.stats }
+    }
+}
@@ -35,6 +35,7 @@
 //! * Promotion of replica-shard as master-shard.
 
 use crate::broker::{Config, Node};
+use crate::ClientID;
 
 #[derive(Clone, Eq, PartialEq)]
 pub struct Topology {
@@ -51,13 +52,10 @@ pub struct Rebalancer {
 
 impl Rebalancer {
     /// Clients are mapped to shards using ClientID, so that a client will always
-    /// map to the same shard no matter where or when it is computed.
-    pub fn session_partition<U: AsRef<[u8]>>(id: &U, num_shards: u32) -> u32 {
-        let id: &[u8] = id.as_ref();
-        let hash = cityhash_rs::cityhash_110_128(id);
-        let hash = (hash & 0xFFFFFFFFFFFFFFFF) ^ ((hash >> 64) & 0xFFFFFFFFFFFFFFFF);
-        let hash = ((hash & 0xFFFFFFFF) ^ ((hash >> 32) & 0xFFFFFFFF)) as u32;
-        hash & (num_shards - 1)
+    /// map to the same shard no matter where or when it is computed. Refer
+    /// [ClientID::shard_hash].
+    pub fn session_partition(client_id: &ClientID, num_shards: u32) -> u32 {
+        client_id.shard_hash(num_shards)
     }
 
     /// Rebalance topology for supplied set of nodes. Subsequently use
@@ -14,7 +14,7 @@ fn test_session_to_shard() {
     };
     for off in (0..num_sessions)
         .into_par_iter()
-        .map(|_| Rebalancer::session_partition(&uuid::Uuid::new_v4(), num_shards))
+        .map(|_| Rebalancer::session_partition(&ClientID::new_uuid_v4(), num_shards))
         .collect::<Vec<u32>>()
         .into_iter()
     {
@@ -1,14 +1,14 @@
-use log::{debug, error, trace};
+use log::{debug, error, trace, warn};
 
-use std::{cmp, collections::BTreeMap, fmt, mem, net, result};
+use std::{cmp, collections::BTreeMap, fmt, mem, net, result, time};
 
-use crate::broker::{Config, SubscribedTrie};
+use crate::broker::SubscribedTrie;
+use crate::broker::{BacklogPolicy, BrokerEvent, Config, RetainedTrie, Seqno};
 use crate::broker::{KeepAlive, Message, OutSeqno, PktRx, PktTx, QueueStatus, Shard};
 
-use crate::{v5, ClientID, PacketID, TopicFilter, TopicName};
+use crate::{v5, ClientID, PacketID, Packetize, TopicFilter, TopicName};
 use crate::{Error, ErrorKind, ReasonCode, Result};
 
-type Messages = Vec<Message>;
 type Packets = Vec<v5::Packet>;
 type QueuePkt = QueueStatus<v5::Packet>;
 type QueueMsg = QueueStatus<Message>;
@@ -38,13 +38,19 @@ enum SessionState {
         config: Config,
 
         // Immutable set of parameters for this session, after handshake.
-        keep_alive: KeepAlive, // Negotiated keep-alive.
-        connect: v5::Connect,  // Connect msg that created this session.
-        miot_tx: PktTx,        // Outbound channel to Miot thread.
-        session_rx: PktRx,     // Inbound channel from Miot thread.
+        keep_alive: KeepAlive,       // Negotiated keep-alive.
+        connect: v5::Connect,        // Connect msg that created this session.
+        connected_at: time::Instant, // Instant at which the client connected.
+        miot_tx: PktTx,              // Outbound channel to Miot thread.
+        // Reason code of DISCONNECT received from the client. None, if the connection
+        // was closed without a DISCONNECT. Decides whether will message is published.
+        disconnect: Option<v5::DisconnReasonCode>,
+        session_rx: PktRx, // Inbound channel from Miot thread.
 
         // MQTT topic-aliases if enabled. ZERO is not allowed.
-        topic_aliases: BTreeMap<u16, TopicName>,
+        inbound_aliases: InboundAliases,
+        // MQTT topic-aliases assigned by broker, if enabled by client.
+        outbound_aliases: OutboundAliases,
         // List of topic-filters subscribed by this client, when ever
         // SUBSCRIBE/UNSUBSCRIBE messages are committed here, [Cluster::topic_filters]
         // will also be updated.
@@ -53,8 +59,6 @@ enum SessionState {
         // Sorted list of QoS-1 & QoS-2 PacketID for managing incoming duplicate publish.
         inp_qos12: Vec<PacketID>,
 
-        // outgoing QoS-0 PUBLISH messages.
-        qos0_back_log: Vec<Message>,
         // Message::ClientAck that needs to be sent to remote client.
         // CONNACK - happens during add_session.
         // PUBACK  - happens after QoS-1 and QoS-2 messaegs are replicated.
@@ -70,22 +74,33 @@ enum SessionState {
         // Note that length of this collection is only as high as the allowed limit of
         // concurrent PUBLISH specified by client.
         qos12_unacks: BTreeMap<PacketID, Message>,
+        // Instant at which an inflight PUBLISH was last sent to the client and the
+        // number of times it was re-delivered, refer [Config::inflight_timeout].
+        inflight_sent: BTreeMap<PacketID, (time::Instant, u32)>,
+        // Number of PUBLISH(qos>0) that can be sent to the client before it is
+        // acknowledged, initialized from client's `receive_maximum`. Decremented for
+        // every out-going PUBLISH(qos>0) and restored for every PUBACK.
+        send_quota: u16,
         // This value is incremented for every out-going PUBLISH(qos>0).
-        // If index.len() > `receive_maximum`, don't increment this value.
         next_packet_id: PacketID,
-        /// Monotonically increasing `seqno`, starting from 1, that is bumped up for
-        /// every outgoing publish packet.
+        // Client's maximum packet size, PUBLISH packets exceeding this limit are
+        // dropped instead of being sent to the client.
+        max_out_packet_size: u32,
+        // Number of PUBLISH packets dropped for exceeding `max_out_packet_size`.
+        n_oversized: usize,
+        // Outbound metrics for this client, refer [ClientInfo].
+        client_out: ClientOut,
+        /// Last allocated `seqno`, monotonically increasing from 1, that is bumped up
+        /// for every outgoing publish packet.
         out_seqno: OutSeqno,
-        /// Message::Packet outgoing PUBLISH > QoS-0, first land here.
+        /// Message::Packet outgoing PUBLISH, of all QoS, first land here. Messages are
+        /// sent to the client in `out_seqno` order, that is in their arrival order.
         ///
         /// Entries from this index are deleted after they are removed from
         /// `qos12_unacks` and after they go through the consensus loop.
         back_log: BTreeMap<OutSeqno, Message>,
     },
-    #[allow(dead_code)]
     Reconnect {
-        // MQTT topic-aliases if enabled. ZERO is not allowed.
-        topic_aliases: BTreeMap<u16, TopicName>,
         // List of topic-filters subscribed by this client, when ever
         // SUBSCRIBE/UNSUBSCRIBE messages are committed here, [Cluster::topic_filters]
         // will also be updated.
@@ -97,17 +112,27 @@ enum SessionState {
         // This value is incremented for every out-going PUBLISH(qos>0).
         // If index.len() > `receive_maximum`, don't increment this value.
         next_packet_id: PacketID,
-        /// Monotonically increasing `seqno`, starting from 1, that is bumped up for
-        /// every outgoing publish packet.
+        /// Last allocated `seqno`, monotonically increasing from 1, that is bumped up
+        /// for every outgoing publish packet.
         out_seqno: OutSeqno,
+        /// Message::Packet outgoing PUBLISH > QoS-0, that are either un-acked by the
+        /// previous connection or routed to this session after the client went away.
+        /// Shall be re-delivered when client reconnects with `clean_start` as false.
+        back_log: BTreeMap<OutSeqno, Message>,
+
+        // Instant at which the client disconnected.
+        disconnect_instant: time::Instant,
+        // Negotiated session-expiry-interval, in seconds, ZERO means session shall
+        // end along with the network connection and 0xFFFFFFFF means never expire.
+        session_expiry_interval: u32,
     },
     #[allow(dead_code)]
     Replica {
         prefix: String,
         config: Config,
 
-        /// Monotonically increasing `seqno`, starting from 1, that is bumped up for
-        /// every outgoing publish packet.
+        /// Last allocated `seqno`, monotonically increasing from 1, that is bumped up
+        /// for every outgoing publish packet.
         out_seqno: OutSeqno,
         /// Message::Packet outgoing PUBLISH > QoS-0, first land here.
         ///
@@ -117,6 +142,130 @@ enum SessionState {
     },
 }
 
+/// Topic-aliases registered by the client in its PUBLISH packets. Aliases are
+/// bounded by broker's `topic_alias_max`, refer [Config::mqtt_topic_alias_max].
+#[derive(Debug, Default)]
+struct InboundAliases {
+    alias_max: u16,
+    aliases: BTreeMap<u16, TopicName>,
+}
+
+impl InboundAliases {
+    fn new(alias_max: Option<u16>) -> InboundAliases {
+        InboundAliases {
+            alias_max: alias_max.unwrap_or(0),
+            aliases: BTreeMap::default(),
+        }
+    }
+
+    // register `alias` for `topic_name`, return the old topic_name if any.
+    fn register(
+        &mut self,
+        alias: u16,
+        topic_name: TopicName,
+    ) -> Result<Option<TopicName>> {
+        if self.alias_max == 0 {
+            err!(
+                ProtocolError,
+                code: TopicAliasInvalid,
+                "topic-alias-is-not-supported by broker"
+            )?;
+        } else if alias == 0 || alias > self.alias_max {
+            err!(
+                ProtocolError,
+                code: TopicAliasInvalid,
+                "topic-alias-exceeds broker limit {} > {}",
+                alias,
+                self.alias_max
+            )?;
+        }
+
+        Ok(self.aliases.insert(alias, topic_name))
+    }
+
+    fn resolve(&self, alias: u16) -> Option<&TopicName> {
+        self.aliases.get(&alias)
+    }
+}
+
+/// Topic-aliases assigned by the broker to PUBLISH packets sent to the client.
+/// Aliases are bounded by client's `topic_alias_max`, refer
+/// [v5::ConnectProperties::topic_alias_max]. Once all aliases are assigned, alias
+/// of the least-recently-used topic is re-assigned to the new topic.
+#[derive(Debug, Default)]
+struct OutboundAliases {
+    alias_max: u16,
+    // topic-name and its (alias, last-used tick)
+    aliases: BTreeMap<TopicName, (u16, u64)>,
+    // last-used tick and its topic-name, in least-recently-used order.
+    lru: BTreeMap<u64, TopicName>,
+    // aliases released by `unregister`, that can be assigned again.
+    free: Vec<u16>,
+    tick: u64,
+}
+
+impl OutboundAliases {
+    fn new(alias_max: Option<u16>) -> OutboundAliases {
+        OutboundAliases {
+            alias_max: alias_max.unwrap_or(0),
+            ..OutboundAliases::default()
+        }
+    }
+
+    // Set topic-alias for `publish`, topic-name is cleared if topic was already
+    // aliased. Return true if a new alias was assigned to the topic, in which case
+    // the full topic-name is sent along with the alias.
+    fn alias_publish(&mut self, publish: &mut v5::Publish) -> bool {
+        if self.alias_max == 0 {
+            return false;
+        }
+
+        self.tick += 1;
+        let (alias, new) = match self.aliases.get_mut(&publish.topic_name) {
+            Some((alias, tick)) => {
+                self.lru.remove(tick);
+                *tick = self.tick;
+                (*alias, false)
+            }
+            None => (self.next_alias(), true),
+        };
+        if new {
+            self.aliases.insert(publish.topic_name.clone(), (alias, self.tick));
+        }
+        self.lru.insert(self.tick, publish.topic_name.clone());
+
+        let props = publish.properties.get_or_insert_with(v5::PublishProperties::default);
+        props.topic_alias = Some(alias);
+        if !new {
+            publish.topic_name = TopicName::default();
+        }
+
+        new
+    }
+
+    // Return an unused alias, if all aliases are assigned, evict the
+    // least-recently-used topic and return its alias.
+    fn next_alias(&mut self) -> u16 {
+        let n = self.aliases.len() + self.free.len();
+        match self.free.pop() {
+            Some(alias) => alias,
+            None if n < usize::from(self.alias_max) => (n as u16) + 1,
+            None => {
+                let (_, topic_name) = self.lru.pop_first().unwrap();
+                self.aliases.remove(&topic_name).unwrap().0
+            }
+        }
+    }
+
+    // Forget alias assigned for `topic_name`, that was never sent to the client.
+    fn unregister(&mut self, topic_name: &TopicName) {
+        if let Some((alias, tick)) = self.aliases.remove(topic_name) {
+            self.lru.remove(&tick);
+            self.free.push(alias);
+        }
+    }
+}
+
 impl fmt::Debug for SessionState {
     fn fmt(&self, f: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
         match self {
@@ -130,9 +279,9 @@ impl fmt::Debug for SessionState {
 impl SessionState {
     fn incr_out_seqno(&mut self, msg: &mut Message) {
         match self {
-            SessionState::Active { out_seqno, .. } => {
-                let seqno = *out_seqno;
-                *out_seqno = out_seqno.saturating_add(1);
+            SessionState::Active { out_seqno, .. }
+            | SessionState::Reconnect { out_seqno, .. } => {
+                let seqno = out_seqno.next();
                 match msg {
                     Message::Routed { out_seqno, .. } => *out_seqno = seqno,
                     _ => (),
@@ -142,104 +291,190 @@ impl SessionState {
         }
     }
 
-    fn out_qos0(&mut self, msgs: Vec<Message>) -> QueueStatus<Message> {
-        let (prefix, config, miot_tx, qos0_back_log) = match self {
-            SessionState::Active { prefix, config, miot_tx, qos0_back_log, .. } => {
-                (prefix, config, miot_tx, qos0_back_log)
-            }
-            ss => unreachable!("{:?}", ss),
-        };
-
-        let m = qos0_back_log.len();
-        // TODO: separate back-log limit from mqtt_pkt_batch_size.
-        let n = (config.mqtt_pkt_batch_size as usize) * 4;
-        if m > n {
-            // TODO: if back-pressure is increasing due to a slow receiving client,
-            // we will have to take drastic steps, like, closing this connection.
-            error!("{} session.qos0_back_log {} pressure > {}", prefix, m, n);
-            return QueueStatus::Disconnected(Vec::new());
-        }
-
-        for msg in msgs.into_iter() {
-            let msg = msg.into_packet(None);
-            qos0_back_log.push(msg)
-        }
-        let back_log = mem::replace(qos0_back_log, vec![]);
-
-        let mut status = flush_to_miot(prefix, miot_tx, back_log);
-        let _empty = mem::replace(qos0_back_log, status.take_values());
-        status
-    }
-
     fn out_qos(&mut self, msgs: Vec<Message>) -> QueueStatus<Message> {
         match self {
             SessionState::Active { .. } => self.out_qos_active(msgs),
+            SessionState::Reconnect { .. } => self.out_qos_reconnect(msgs),
             SessionState::Replica { .. } => self.out_qos_replica(msgs),
-            ss => unreachable!("{:?}", ss),
         }
     }
 
     fn out_qos_active(&mut self, msgs: Vec<Message>) -> QueueMsg {
-        let (prefix, config, miot_tx, qos12_unacks, next_packet_id, back_log) = match self
+        let can_send = self.can_send();
+        let suback_pending = self.is_suback_pending();
+        let (prefix, config, miot_tx, outbound_aliases, qos12_unacks, state) = match self
         {
             SessionState::Active {
                 prefix,
                 config,
                 miot_tx,
+                outbound_aliases,
                 qos12_unacks,
+                inflight_sent,
+                send_quota,
                 next_packet_id,
+                max_out_packet_size,
+                n_oversized,
+                client_out,
                 back_log,
                 ..
-            } => (prefix, config, miot_tx, qos12_unacks, next_packet_id, back_log),
+            } => {
+                let state = (send_quota, next_packet_id, back_log, inflight_sent);
+                let limit = (*max_out_packet_size, n_oversized, client_out);
+                (prefix, config, miot_tx, outbound_aliases, qos12_unacks, (state, limit))
+            }
             ss => unreachable!("{:?}", ss),
         };
+        let ((send_quota, next_packet_id, back_log, inflight_sent), limit) = state;
+        let (max_size, n_oversized, client_out) = limit;
+        // sending a packet larger than client's maximum packet size is a protocol
+        // violation, such packets are dropped.
+        let is_oversized = |publish: &v5::Publish| match max_size {
+            u32::MAX => false,
+            max_size => match publish.encoded_len() {
+                Ok(size) => size > (max_size as usize),
+                Err(_) => true,
+            },
+        };
 
+        for msg in msgs.into_iter() {
+            // packet_id shall be assigned when the message is sent to the client.
+            let msg = msg.into_packet(None);
+            back_log.insert(msg.to_out_seqno(), msg);
+        }
+
+        let n = match config.max_backlog_per_session {
+            Some(n) => n as usize,
+            None => (config.mqtt_pkt_batch_size as usize) * 4,
+        };
+        if back_log.len() > n && config.backlog_policy == BacklogPolicy::DropOldestQos0 {
+            let out_seqnos: Vec<OutSeqno> = back_log
+                .iter()
+                .filter(|(_, msg)| msg.is_qos0())
+                .map(|(out_seqno, _)| *out_seqno)
+                .take(back_log.len() - n)
+                .collect();
+            let (m, k) = (back_log.len(), out_seqnos.len());
+            warn!("{} session.back_log {} pressure > {}, drop {} QoS-0", prefix, m, n, k);
+            for out_seqno in out_seqnos.iter() {
+                back_log.remove(out_seqno);
+            }
+            client_out.messages_dropped += k;
+        }
+        client_out.set_backlog_len(back_log.len());
+
+        // back-pressure is increasing due to a slow receiving client, QoS>0 messages
+        // are never dropped, close this connection.
         let m = back_log.len();
-        // TODO: separate back-log limit from mqtt_pkt_batch_size.
-        let n = (config.mqtt_pkt_batch_size as usize) * 4;
         if m > n {
-            // TODO: if back-pressure is increasing due to a slow receiving client,
-            // we will have to take drastic steps, like, closing this connection.
             error!("{} session.back_log {} pressure > {}", prefix, m, n);
             return QueueStatus::Disconnected(Vec::new());
         }
 
-        if qos12_unacks.len() >= usize::from(config.mqtt_receive_maximum) {
+        // retained messages delivered on SUBSCRIBE shall follow its SUBACK.
+        if suback_pending {
             return QueueStatus::Block(Vec::new());
         }
 
-        for msg in msgs.into_iter() {
-            let packet_id = *next_packet_id;
-            *next_packet_id = next_packet_id.wrapping_add(1);
-
-            let msg = msg.into_packet(Some(packet_id));
-            back_log.insert(msg.to_out_seqno(), msg);
+        // QoS-0 and QoS>0 messages are drained in the same order, a QoS>0 message
+        // that exceeds the client's quota of un-acked PUBLISH blocks the rest.
+        match back_log.first_key_value() {
+            Some((_, msg)) if !can_send && !msg.is_qos0() => {
+                return QueueStatus::Block(Vec::new());
+            }
+            _ => (),
         }
-
-        let max = usize::try_from(config.mqtt_pkt_batch_size).unwrap();
+        let max = config.mqtt_pkt_batch_size as usize;
         let mut msgs = Vec::default();
         while msgs.len() < max {
-            match back_log.pop_first() {
-                Some((_, msg)) => msgs.push(msg),
+            let entry = match back_log.first_entry() {
+                Some(entry) if entry.get().is_qos0() => entry,
+                Some(_) if *send_quota == 0 => break,
+                Some(entry) => entry,
                 None => break,
+            };
+            match entry.remove() {
+                Message::Packet { out_seqno, packet_id: None, mut publish }
+                    if publish.qos != v5::QoS::AtMostOnce =>
+                {
+                    let packet_id = *next_packet_id;
+                    publish.set_packet_id(packet_id);
+                    if is_oversized(&publish) {
+                        let s = out_seqno;
+                        error!("{} out_seqno:{} drop oversized publish", prefix, s);
+                        *n_oversized += 1;
+                        client_out.messages_dropped += 1;
+                        continue;
+                    }
+                    *next_packet_id = next_packet_id.wrapping_add(1);
+
+                    let msg = Message::Packet {
+                        out_seqno,
+                        packet_id: Some(packet_id),
+                        publish,
+                    };
+                    qos12_unacks.insert(packet_id, msg.clone());
+                    *send_quota -= 1;
+                    msgs.push(msg);
+                }
+                Message::Packet { out_seqno, publish, .. } if is_oversized(&publish) => {
+                    error!("{} out_seqno:{} drop oversized publish", prefix, out_seqno);
+                    *n_oversized += 1;
+                    client_out.messages_dropped += 1;
+                }
+                msg if msg.is_qos0() => msgs.push(msg),
+                msg => {
+                    qos12_unacks.insert(msg.to_packet_id(), msg.clone());
+                    *send_quota -= 1;
+                    msgs.push(msg);
+                }
             }
         }
-        for msg in msgs.clone().into_iter() {
-            qos12_unacks.insert(msg.to_packet_id(), msg);
+        if msgs.is_empty() && !back_log.is_empty() {
+            client_out.set_backlog_len(back_log.len());
+            return QueueStatus::Block(Vec::new());
         }
 
-        let mut status = flush_to_miot(prefix, miot_tx, msgs);
+        // re-delivered messages carry forward their retransmit count.
+        let now = time::Instant::now();
+        for msg in msgs.iter() {
+            if let Message::Packet { packet_id: Some(packet_id), publish, .. } = msg {
+                let entry = inflight_sent.entry(*packet_id).or_insert((now, 0));
+                *entry = (now, if publish.duplicate { entry.1 } else { 0 });
+            }
+        }
+
+        let mut status =
+            flush_publish_to_miot(prefix, miot_tx, outbound_aliases, client_out, msgs);
 
         // re-insert, cleanup for remaining messages.
         for msg in status.take_values().into_iter() {
-            let packet_id = msg.to_packet_id();
+            if !msg.is_qos0() {
+                qos12_unacks.remove(&msg.to_packet_id());
+                *send_quota += 1;
+            }
             back_log.insert(msg.to_out_seqno(), msg);
-            qos12_unacks.remove(&packet_id);
         }
+        client_out.set_backlog_len(back_log.len());
 
         status
     }
 
+    fn out_qos_reconnect(&mut self, msgs: Vec<Message>) -> QueueMsg {
+        let back_log = match self {
+            SessionState::Reconnect { back_log, .. } => back_log,
+            ss => unreachable!("{:?}", ss),
+        };
+
+        for msg in msgs.into_iter() {
+            // packet_id shall be assigned when the client reconnects.
+            let msg = msg.into_packet(None);
+            back_log.insert(msg.to_out_seqno(), msg);
+        }
+
+        QueueStatus::Ok(Vec::new())
+    }
+
     fn out_qos_replica(&mut self, msgs: Vec<Message>) -> QueueMsg {
         let back_log = match self {
             SessionState::Active { back_log, .. } => back_log,
@@ -274,6 +509,123 @@ impl SessionState {
         out_acks.push(Message::new_pub_ack(v5::Pub::new_pub_ack(packet_id)));
     }
 
+    // Return whether PUBLISH(qos>0) can be sent to the client, without exceeding
+    // the client's `receive_maximum`.
+    fn can_send(&self) -> bool {
+        match self {
+            SessionState::Active { send_quota, .. } => *send_quota > 0,
+            ss => unreachable!("{:?}", ss),
+        }
+    }
+
+    // Return whether a SUBACK is yet to be flushed to miot.
+    fn is_suback_pending(&self) -> bool {
+        match self {
+            SessionState::Active { out_acks, .. } => out_acks.iter().any(|msg| {
+                matches!(msg, Message::ClientAck { packet: v5::Packet::SubAck(_) })
+            }),
+            ss => unreachable!("{:?}", ss),
+        }
+    }
+
+    // Handle PUBACK from the client, restore the quota and return the acknowledged
+    // message's seqno.
+    fn rx_puback(&mut self, puback: &v5::Pub) -> Option<OutSeqno> {
+        let (connect, qos12_unacks, inflight_sent, send_quota) = match self {
+            SessionState::Active {
+                connect,
+                qos12_unacks,
+                inflight_sent,
+                send_quota,
+                ..
+            } => (connect, qos12_unacks, inflight_sent, send_quota),
+            ss => unreachable!("{:?}", ss),
+        };
+
+        let msg = qos12_unacks.remove(&puback.packet_id)?;
+        inflight_sent.remove(&puback.packet_id);
+        *send_quota = cmp::min(send_quota.saturating_add(1), connect.receive_maximum());
+        Some(msg.to_out_seqno())
+    }
+
+    // Move inflight PUBLISH messages, not acknowledged within `inflight_timeout`,
+    // back into `back_log` with DUP flag set. Return the number of messages moved,
+    // fail if a message has exhausted `max_retransmits`.
+    fn retransmit(&mut self, now: time::Instant) -> Result<usize> {
+        let (prefix, config, qos12_unacks, inflight_sent, send_quota, back_log) =
+            match self {
+                SessionState::Active {
+                    prefix,
+                    config,
+                    qos12_unacks,
+                    inflight_sent,
+                    send_quota,
+                    back_log,
+                    ..
+                } => (prefix, config, qos12_unacks, inflight_sent, send_quota, back_log),
+                ss => unreachable!("{:?}", ss),
+            };
+
+        let timeout = match config.inflight_timeout {
+            Some(secs) => time::Duration::from_secs(u64::from(secs)),
+            None => return Ok(0),
+        };
+
+        let packet_ids: Vec<PacketID> = inflight_sent
+            .iter()
+            .filter(|(packet_id, _)| qos12_unacks.contains_key(packet_id))
+            .filter(|(_, (at, _))| now.saturating_duration_since(*at) >= timeout)
+            .map(|(packet_id, _)| *packet_id)
+            .collect();
+
+        for packet_id in packet_ids.iter() {
+            let (_, n) = inflight_sent.get_mut(packet_id).unwrap();
+            if *n >= config.max_retransmits {
+                err!(
+                    SlowClient,
+                    code: UnspecifiedError,
+                    "{} packet_id:{} un-acked after {} retransmits",
+                    prefix,
+                    packet_id,
+                    n
+                )?;
+            }
+            *n += 1;
+
+            let msg = match qos12_unacks.remove(packet_id) {
+                Some(Message::Packet { out_seqno, packet_id, mut publish }) => {
+                    let (retain, qos) = (publish.retain, publish.qos);
+                    publish.set_fixed_header(retain, qos, true);
+                    Message::Packet { out_seqno, packet_id, publish }
+                }
+                msg => unreachable!("{:?}", msg),
+            };
+            debug!("{} packet_id:{} retransmit attempt {}", prefix, packet_id, n);
+            *send_quota = send_quota.saturating_add(1);
+            back_log.insert(msg.to_out_seqno(), msg);
+        }
+
+        Ok(packet_ids.len())
+    }
+
+    // Release the QoS-2 PacketID held for an incoming PUBLISH and return PUBCOMP.
+    // PUBREL is retransmitted by the client until PUBCOMP is received, hence a
+    // PUBREL without prior state is acknowledged with `Success` as well.
+    fn rx_pubrel(&mut self, packet_id: PacketID) -> v5::Packet {
+        let (prefix, inp_qos12) = match self {
+            SessionState::Active { prefix, inp_qos12, .. } => (prefix, inp_qos12),
+            ss => unreachable!("{:?}", ss),
+        };
+
+        match inp_qos12.binary_search(&packet_id) {
+            Ok(off) => {
+                inp_qos12.remove(off);
+            }
+            Err(_) => trace!("{} packet_id:{} pubrel without state", prefix, packet_id),
+        }
+        v5::Packet::PubComp(v5::Pub::new_pub_comp(packet_id))
+    }
+
     fn out_acks_flush(&mut self) -> QueueStatus<Message> {
         let (prefix, miot_tx, inp_qos12, out_acks) = match self {
             SessionState::Active { prefix, miot_tx, inp_qos12, out_acks, .. } => {
@@ -282,18 +634,18 @@ impl SessionState {
             ss => unreachable!("{:?}", ss),
         };
 
+        // PacketID of QoS-1 PUBLISH is released once its PUBACK is sent.
         for ack in out_acks.iter() {
             match ack {
                 Message::ClientAck { packet } => match packet {
-                    v5::Packet::Publish(publish) => match publish.packet_id {
-                        Some(packet_id) => match inp_qos12.binary_search(&packet_id) {
+                    v5::Packet::PubAck(puback) => {
+                        match inp_qos12.binary_search(&puback.packet_id) {
                             Ok(off) => {
                                 inp_qos12.remove(off);
                             }
                             Err(_off) => (), // TODO: warning messages
-                        },
-                        None => (),
-                    },
+                        }
+                    }
                     _ => (),
                 },
                 msg => unreachable!("{:?}", msg),
@@ -308,6 +660,84 @@ impl SessionState {
         status
     }
 
+    // Convert an active session state into reconnect state. Inflight PUBLISH packets,
+    // that are not yet acknowledged by the client, are moved back to `back_log` with
+    // DUP flag set, so that they shall be re-delivered when the client reconnects.
+    fn into_reconnect(self) -> SessionState {
+        match self {
+            SessionState::Active {
+                config,
+                connect,
+                subscriptions,
+                inp_qos12,
+                qos12_unacks,
+                next_packet_id,
+                out_seqno,
+                mut back_log,
+                ..
+            } => {
+                // QoS-0 messages are not queued for disconnected clients.
+                back_log.retain(|_, msg| !msg.is_qos0());
+                for (_, msg) in qos12_unacks.into_iter() {
+                    let msg = match msg {
+                        Message::Packet { out_seqno, packet_id, mut publish } => {
+                            let (retain, qos) = (publish.retain, publish.qos);
+                            publish.set_fixed_header(retain, qos, true);
+                            Message::Packet { out_seqno, packet_id, publish }
+                        }
+                        msg => unreachable!("{:?}", msg),
+                    };
+                    back_log.insert(msg.to_out_seqno(), msg);
+                }
+
+                let sei = session_expiry_interval(&config, &connect);
+                SessionState::Reconnect {
+                    subscriptions,
+                    inp_qos12,
+                    next_packet_id,
+                    out_seqno,
+                    back_log,
+                    disconnect_instant: time::Instant::now(),
+                    session_expiry_interval: sei.unwrap_or(0),
+                }
+            }
+            ss @ SessionState::Reconnect { .. } => ss,
+            ss => unreachable!("{:?}", ss),
+        }
+    }
+
+    // Move the persistent part of session state from `old` into `self`. Topic-aliases
+    // are scoped to the network connection and are not carried over.
+    fn take_over(&mut self, old: SessionState) {
+        match (self, old.into_reconnect()) {
+            (
+                SessionState::Active {
+                    subscriptions,
+                    inp_qos12,
+                    next_packet_id,
+                    out_seqno,
+                    back_log,
+                    ..
+                },
+                SessionState::Reconnect {
+                    subscriptions: old_subscriptions,
+                    inp_qos12: old_inp_qos12,
+                    next_packet_id: old_next_packet_id,
+                    out_seqno: old_out_seqno,
+                    back_log: old_back_log,
+                    ..
+                },
+            ) => {
+                *subscriptions = old_subscriptions;
+                *inp_qos12 = old_inp_qos12;
+                *next_packet_id = old_next_packet_id;
+                *out_seqno = old_out_seqno;
+                *back_log = old_back_log;
+            }
+            (ss, old) => unreachable!("{:?} {:?}", ss, old),
+        }
+    }
+
     fn commit_acks(&mut self, out_seqnos: Vec<OutSeqno>) {
         match self {
             SessionState::Active { .. } => (),
@@ -323,33 +753,18 @@ impl SessionState {
 
 impl SessionState {
     fn publish_topic_name(&mut self, publ: &v5::Publish) -> Result<TopicName> {
-        let (prefix, config, topic_aliases) = match self {
-            SessionState::Active { prefix, config, topic_aliases, .. } => {
-                (prefix, config, topic_aliases)
+        let (prefix, inbound_aliases) = match self {
+            SessionState::Active { prefix, inbound_aliases, .. } => {
+                (prefix, inbound_aliases)
             }
             ss => unreachable!("{:?}", ss),
         };
 
         let (topic_name, topic_alias) = (publ.as_topic_name(), publ.topic_alias());
-        let server_alias_max = config.mqtt_topic_alias_max();
 
         let topic_name = match topic_alias {
-            Some(_alias) if server_alias_max.is_none() => err!(
-                ProtocolError,
-                code: TopicAliasInvalid,
-                "{} topic-alias-is-not-supported by broker",
-                prefix
-            )?,
-            Some(alias) if alias > server_alias_max.unwrap() => err!(
-                ProtocolError,
-                code: TopicAliasInvalid,
-                "{} topic-alias-exceeds broker limit {} > {}",
-                prefix,
-                alias,
-                server_alias_max.unwrap()
-            )?,
             Some(alias) if topic_name.len() > 0 => {
-                match topic_aliases.insert(alias, topic_name.clone()) {
+                match inbound_aliases.register(alias, topic_name.clone())? {
                     Some(old) => debug!(
                         concat!(
                             "{} topic_alias:{} old_topic:{:?} new_topic:{:?}",
@@ -361,7 +776,7 @@ impl SessionState {
                 };
                 topic_name.clone()
             }
-            Some(alias) => match topic_aliases.get(&alias) {
+            Some(alias) => match inbound_aliases.resolve(alias) {
                 Some(topic_name) => topic_name.clone(),
                 None => err!(
                     ProtocolError,
@@ -435,6 +850,7 @@ impl SessionState {
     fn as_subscriptions(&self) -> &BTreeMap<TopicFilter, v5::Subscription> {
         match self {
             SessionState::Active { subscriptions, .. } => subscriptions,
+            SessionState::Reconnect { subscriptions, .. } => subscriptions,
             ss => unreachable!("{:?}", ss),
         }
     }
@@ -456,6 +872,52 @@ impl SessionState {
 
 pub struct SessionStats;
 
+/// Type captures a snapshot of a connected client and its session.
+#[derive(Clone, Debug)]
+pub struct ClientInfo {
+    pub client_id: ClientID,
+    pub raddr: net::SocketAddr,
+    pub shard_id: u32,
+    pub connected_at: time::Instant,
+    /// Negotiated keep-alive, in seconds, ZERO means keep-alive is disabled.
+    pub keep_alive: u16,
+    pub clean_start: bool,
+    /// Number of topic-filters subscribed by this client.
+    pub n_subscriptions: usize,
+    /// Number of incoming QoS-1 & QoS-2 PUBLISH, yet to be acknowledged.
+    pub n_inflight_inp: usize,
+    /// Number of outgoing QoS-1 & QoS-2 PUBLISH, yet to be acknowledged by client.
+    pub n_inflight_out: usize,
+    /// Outbound metrics, refer [ClientOut].
+    pub client_out: ClientOut,
+}
+
+/// Type captures metrics of outgoing PUBLISH messages to a connected client. A
+/// growing back-log marks a slow subscriber, refer [Shard::slow_subscribers].
+#[derive(Clone, Debug, Default)]
+pub struct ClientOut {
+    /// Number of PUBLISH messages sent to the client.
+    pub messages_delivered: usize,
+    /// Number of PUBLISH messages dropped, either under back-log pressure or for
+    /// exceeding client's maximum packet size.
+    pub messages_dropped: usize,
+    /// Number of bytes, of PUBLISH messages, sent to the client.
+    pub bytes_out: usize,
+    /// Number of PUBLISH messages in the back-log, yet to be sent to the client.
+    pub current_backlog_len: usize,
+    /// Highest back-log length seen so far.
+    pub max_backlog_len: usize,
+    /// Instant at which PUBLISH messages were last sent to the client.
+    pub last_flush_instant: Option<time::Instant>,
+}
+
+impl ClientOut {
+    fn set_backlog_len(&mut self, len: usize) {
+        self.current_backlog_len = len;
+        self.max_backlog_len = cmp::max(self.max_backlog_len, len);
+    }
+}
+
 pub struct SessionArgs {
     pub raddr: net::SocketAddr,
     pub client_id: ClientID,
@@ -479,56 +941,222 @@ impl Session {
                 config: config.clone(),
                 keep_alive: KeepAlive::new(args.raddr, &pkt, &config),
                 connect: pkt.clone(),
+                connected_at: time::Instant::now(),
                 miot_tx: args.miot_tx,
+                disconnect: None,
                 session_rx: args.session_rx,
-                topic_aliases: BTreeMap::default(),
+                inbound_aliases: InboundAliases::new(config.mqtt_topic_alias_max()),
+                outbound_aliases: OutboundAliases::new(
+                    pkt.properties.as_ref().and_then(|p| p.topic_alias_max()),
+                ),
                 subscriptions: BTreeMap::default(),
 
                 inp_qos12: Vec::default(),
 
                 out_acks: Vec::default(),
-                qos0_back_log: Vec::default(),
 
                 qos12_unacks: BTreeMap::default(),
+                inflight_sent: BTreeMap::default(),
+                send_quota: pkt.receive_maximum(),
                 next_packet_id: 1,
-                out_seqno: 1,
+                max_out_packet_size: pkt.max_packet_size(u32::MAX),
+                n_oversized: 0,
+                client_out: ClientOut::default(),
+                out_seqno: OutSeqno::default(),
                 back_log: BTreeMap::default(),
             },
         }
     }
 
-    pub fn success_ack(&mut self, pkt: &v5::Connect, _shard: &Shard) -> v5::ConnAck {
-        let val = pkt.session_expiry_interval();
-        let sei = match (self.config.mqtt_session_expiry_interval, val) {
-            (Some(_one), Some(two)) => Some(two),
-            (Some(one), None) => Some(one),
-            (None, Some(two)) => Some(two),
-            (None, None) => None,
-        };
+    /// `assigned` client-id, if any, is echoed back to the client in CONNACK.
+    pub fn success_ack(
+        &mut self,
+        pkt: &v5::Connect,
+        assigned: Option<ClientID>,
+        _shard: &Shard,
+    ) -> v5::ConnAck {
+        let sei = session_expiry_interval(&self.config, pkt);
         let mut props = v5::ConnAckProperties {
             session_expiry_interval: sei,
             receive_maximum: Some(self.config.mqtt_receive_maximum),
-            maximum_qos: Some(self.config.mqtt_maximum_qos.try_into().unwrap()),
+            // absence of maximum_qos implies QoS2 support.
+            maximum_qos: match self.config.mqtt_maximum_qos() {
+                v5::QoS::ExactlyOnce => None,
+                qos => Some(qos),
+            },
             retain_available: Some(self.config.mqtt_retain_available),
             max_packet_size: Some(self.config.mqtt_max_packet_size),
-            assigned_client_identifier: None,
-            wildcard_subscription_available: Some(true),
-            subscription_identifiers_available: Some(true),
-            shared_subscription_available: None,
+            assigned_client_identifier: assigned.map(|client_id| client_id.0),
+            wildcard_subscription_available: Some(
+                self.config.mqtt_wildcard_subscription_available,
+            ),
+            subscription_identifiers_available: Some(
+                self.config.mqtt_subscription_identifiers_available,
+            ),
+            shared_subscription_available: Some(
+                self.config.mqtt_shared_subscription_available,
+            ),
             topic_alias_max: self.config.mqtt_topic_alias_max(),
             ..v5::ConnAckProperties::default()
         };
-        if pkt.payload.client_id.len() == 0 {
-            props.assigned_client_identifier = Some((*self.client_id).clone());
-        }
-        if let Some(keep_alive) = self.to_keep_alive() {
+        if let Some(keep_alive) = self.to_server_keep_alive() {
             props.server_keep_alive = Some(keep_alive)
         }
+        match &pkt.properties {
+            Some(cprops) if cprops.request_response_info() => {
+                props.response_information = self.config.response_information.clone();
+            }
+            _ => (),
+        }
         let connack = v5::ConnAck::new_success(Some(props));
 
         connack
     }
 
+    /// Take over the session state of `old`, which was created by an earlier
+    /// connection using the same client_id. `old` can either be an active session or
+    /// a session waiting for reconnect. Subscriptions, inflight QoS-1 & QoS-2
+    /// book-keeping and outgoing back-log are moved into this session.
+    pub fn take_over(&mut self, old: Session) {
+        debug!(
+            "{} old_raddr:{} taking over session for client_id:{}",
+            self.prefix, old.raddr, *self.client_id
+        );
+        self.state.take_over(old.state)
+    }
+
+    /// Return the will-delay-interval, in seconds, for this session's will message.
+    pub fn to_will_delay_interval(&self) -> u32 {
+        match &self.state {
+            SessionState::Active { connect, .. } => {
+                match &connect.payload.will_properties {
+                    Some(props) => props.will_delay_interval(),
+                    None => 0,
+                }
+            }
+            ss => unreachable!("{} {:?}", self.prefix, ss),
+        }
+    }
+
+    /// Take the will message, if any, that was specified by the client in CONNECT.
+    /// Once taken, subsequent calls shall return None.
+    ///
+    /// Will message is discarded if the client has sent a DISCONNECT with normal
+    /// reason code, refer [Session::rx_disconnect]. It is returned if the client sent
+    /// DISCONNECT with `DiconnectWillMessage`, or if the connection was closed without
+    /// a DISCONNECT.
+    pub fn take_will(&mut self) -> Option<v5::Publish> {
+        let (connect, disconnect) = match &mut self.state {
+            SessionState::Active { connect, disconnect, .. } => (connect, *disconnect),
+            ss => unreachable!("{} {:?}", self.prefix, ss),
+        };
+
+        let (_clean_start, will_flag, will_qos, will_retain) = connect.flags.unwrap();
+        match disconnect {
+            _ if !will_flag => return None,
+            Some(v5::DisconnReasonCode::NormalDisconnect) => {
+                connect.payload.will_topic = None;
+                connect.payload.will_payload = None;
+                connect.payload.will_properties = None;
+                return None;
+            }
+            Some(_) | None => (),
+        }
+
+        let topic_name = connect.payload.will_topic.take()?;
+        let payload = connect.payload.will_payload.take();
+        let properties =
+            connect.payload.will_properties.take().map(|props| v5::PublishProperties {
+                payload_format_indicator: props.payload_format_indicator,
+                message_expiry_interval: props.message_expiry_interval,
+                response_topic: props.response_topic,
+                correlation_data: props.correlation_data,
+                content_type: props.content_type,
+                user_properties: props.user_properties,
+                ..v5::PublishProperties::default()
+            });
+
+        let publish = v5::Publish {
+            retain: will_retain,
+            qos: will_qos,
+            duplicate: false,
+            topic_name,
+            packet_id: None,
+            properties,
+            payload,
+        };
+
+        Some(publish)
+    }
+
+    /// Client has sent a DISCONNECT, remember its reason code for [Session::take_will].
+    pub fn rx_disconnect(&mut self, disconn: &v5::Disconnect) {
+        match &mut self.state {
+            SessionState::Active { disconnect, .. } => *disconnect = Some(disconn.code),
+            ss => unreachable!("{} {:?}", self.prefix, ss),
+        }
+    }
+
+    /// Convert this session, whose client has disconnected, into a session waiting
+    /// for the client to reconnect with `clean_start` as false.
+    pub fn into_reconnect(self) -> Session {
+        let state = self.state.into_reconnect();
+        Session { state, ..self }
+    }
+
+    #[inline]
+    pub fn is_reconnect(&self) -> bool {
+        matches!(&self.state, SessionState::Reconnect { .. })
+    }
+
+    /// Return whether this session, waiting for its client to reconnect, has expired
+    /// as of `now`. Active sessions never expire.
+    pub fn is_expired(&self, now: time::Instant) -> bool {
+        match &self.state {
+            SessionState::Reconnect {
+                disconnect_instant,
+                session_expiry_interval,
+                ..
+            } => match *session_expiry_interval {
+                0 => true,
+                0xFFFFFFFF => false,
+                secs => {
+                    let secs = time::Duration::from_secs(u64::from(secs));
+                    (*disconnect_instant + secs) <= now
+                }
+            },
+            _ => false,
+        }
+    }
+
+    /// Return a snapshot of this session, which must be active.
+    pub fn to_client_info(&self) -> ClientInfo {
+        match &self.state {
+            SessionState::Active {
+                keep_alive,
+                connect,
+                connected_at,
+                subscriptions,
+                inp_qos12,
+                qos12_unacks,
+                client_out,
+                ..
+            } => ClientInfo {
+                client_id: self.client_id.clone(),
+                raddr: self.raddr,
+                shard_id: self.shard_id,
+                connected_at: *connected_at,
+                keep_alive: keep_alive.server_keep_alive().unwrap_or(connect.keep_alive),
+                clean_start: connect.flags.unwrap().0,
+                n_subscriptions: subscriptions.len(),
+                n_inflight_inp: inp_qos12.len(),
+                n_inflight_out: qos12_unacks.len(),
+                client_out: client_out.clone(),
+            },
+            ss => unreachable!("{} {:?}", self.prefix, ss),
+        }
+    }
+
     pub fn close(self) -> SessionStats {
         std::mem::drop(self);
         SessionStats
@@ -546,13 +1174,20 @@ impl Session {
 // handle incoming packets.
 impl Session {
     pub fn route_packets(&mut self, shard: &mut Shard) -> Result<(QueuePkt, OutSeqnos)> {
+        let quota = self.to_receive_quota();
         let (session_rx, keep_alive) = match &mut self.state {
             SessionState::Active { session_rx, keep_alive, .. } => {
                 (session_rx, keep_alive)
             }
             ss => unreachable!("{} {:?}", self.prefix, ss),
         };
-        let mut down_status = session_rx.try_recvs(&self.prefix);
+
+        // don't read QoS>0 PUBLISH beyond the receive-maximum advertised in CONNACK.
+        // Other packets are still read, PUBREL releases QoS-2 packet-ids.
+        if quota == 0 {
+            trace!("{} receive-maximum reached, pause reading publish", self.prefix);
+        }
+        let mut down_status = session_rx.try_recvs_quota(&self.prefix, quota);
 
         mem::drop(session_rx);
 
@@ -564,6 +1199,7 @@ impl Session {
             }
             pkts => {
                 keep_alive.live();
+                shard.incr_n_packets(pkts.len());
 
                 let (status, out_seqnos) = self.handle_packets(shard, pkts)?;
 
@@ -596,31 +1232,37 @@ impl Session {
                     out_acks.push(Message::new_ping_resp());
                 }
                 v5::Packet::Publish(publ) => {
-                    let has_subscrs = self.rx_publish(shard, publ.clone())?;
-                    match (has_subscrs, publ.qos) {
-                        (_, v5::QoS::AtMostOnce) => (),
-                        (false, _) => {
-                            let puback = v5::Pub::new_pub_ack(publ.packet_id.unwrap());
-                            out_acks.push(Message::new_pub_ack(puback))
-                        }
-                        (true, v5::QoS::AtLeastOnce) => (),
-                        (true, v5::QoS::ExactlyOnce) => (),
+                    if let Some(ack) = self.rx_publish(shard, publ)? {
+                        out_acks.push(ack)
                     }
                 }
                 v5::Packet::Subscribe(sub) => {
-                    out_acks.extend(self.rx_subscribe(shard, sub)?.into_iter());
+                    let (sub_ack, retains) = self.rx_subscribe(shard, sub)?;
+                    out_acks.push(sub_ack);
+                    // retained messages are routed only after SUBACK, along with
+                    // acks ahead of it, is in miot_tx. Disconnect is detected by
+                    // the shard when it flushes the remaining acks.
+                    if !retains.is_empty() {
+                        self.state.out_acks_extend(mem::take(&mut out_acks));
+                        self.state.out_acks_flush();
+                        self.route_retains(shard, retains);
+                    }
                 }
                 v5::Packet::UnSubscribe(_unsub) => todo!(),
-                v5::Packet::PubAck(_puback) => {
-                    // TODO: cleanup qos12_unacks
-                    out_seqnos.push(0);
-                    todo!();
+                v5::Packet::PubAck(puback) => {
+                    if let Some(out_seqno) = self.state.rx_puback(&puback) {
+                        out_seqnos.push(out_seqno);
+                    }
                 }
                 v5::Packet::PubRec(_puback) => todo!(),
-                v5::Packet::PubRel(_puback) => todo!(),
+                v5::Packet::PubRel(pubrel) => {
+                    let packet = self.state.rx_pubrel(pubrel.packet_id);
+                    out_acks.push(Message::ClientAck { packet });
+                }
                 v5::Packet::PubComp(_puback) => todo!(),
-                v5::Packet::Disconnect(_disconn) => {
+                v5::Packet::Disconnect(disconn) => {
                     // TODO: handle disconnect packet, its header and properties.
+                    self.rx_disconnect(&disconn);
                     err!(
                         Disconnected,
                         code: Success,
@@ -660,41 +1302,73 @@ impl Session {
     }
 
     // return suback and retained-messages if any.
-    fn rx_subscribe(&mut self, shard: &Shard, sub: v5::Subscribe) -> Result<Messages> {
+    fn rx_subscribe(
+        &mut self,
+        shard: &mut Shard,
+        sub: v5::Subscribe,
+    ) -> Result<(Message, Vec<v5::Publish>)> {
         let subscription_id: Option<u32> = match &sub.properties {
             Some(props) => props.subscription_id.clone().map(|x| *x),
             None => None,
         };
 
         let mut return_codes = Vec::with_capacity(sub.filters.len());
+        let mut filters = Vec::with_capacity(sub.filters.len());
+        // retained messages matching overlapping filters are delivered once.
+        let mut retains: BTreeMap<TopicName, (v5::Publish, Vec<v5::Subscription>)> =
+            BTreeMap::default();
         for filter in sub.filters.iter() {
-            let (rfr, retain_as_published, no_local, qos) = filter.opt.unwrap();
-            let subscription = v5::Subscription {
-                topic_filter: filter.topic_filter.clone(),
+            if let Some(code) = self.reject_filter(&filter.topic_filter, subscription_id)
+            {
+                return_codes.push(code);
+                continue;
+            }
+            let cluster = shard.as_cluster();
+            if !cluster.can_subscribe(&self.client_id, &filter.topic_filter) {
+                return_codes.push(v5::SubAckReasonCode::NotAuthorized);
+                continue;
+            }
 
-                client_id: self.client_id.clone(),
-                shard_id: shard.shard_id,
-                subscription_id: subscription_id,
-                qos,
-                no_local,
-                retain_as_published,
-                retain_forward_rule: rfr,
-            };
+            let client_id = self.client_id.clone();
+            let mut subscription =
+                v5::Subscription::from_filter(filter, client_id, shard.shard_id);
+            // granted QoS is capped to the broker's maximum QoS.
+            let qos = cmp::min(subscription.qos, self.config.mqtt_maximum_qos());
+            subscription.qos = qos;
+            subscription.subscription_id = subscription_id;
 
             shard
                 .as_topic_filters()
                 .subscribe(&filter.topic_filter, subscription.clone());
-            self.state
+            let existed = self
+                .state
                 .as_mut_subscriptions()
-                .insert(filter.topic_filter.clone(), subscription);
+                .insert(filter.topic_filter.clone(), subscription.clone())
+                .is_some();
+            let trie = shard.as_retained_messages();
+            for publish in self.on_subscribe(filter, trie, existed).into_iter() {
+                match retains.get_mut(&publish.topic_name) {
+                    // on_subscribe downgrades QoS to that of the filter.
+                    Some((oldval, subscrs)) => {
+                        if publish.qos > oldval.qos {
+                            *oldval = publish;
+                        }
+                        subscrs.push(subscription.clone())
+                    }
+                    None => {
+                        let subscrs = vec![subscription.clone()];
+                        retains.insert(publish.topic_name.clone(), (publish, subscrs));
+                    }
+                }
+            }
 
-            let server_qos = v5::QoS::try_from(self.config.mqtt_maximum_qos).unwrap();
-            let rc = match cmp::max(server_qos, qos) {
-                v5::QoS::AtMostOnce => v5::SubAckReasonCode::QoS0,
-                v5::QoS::AtLeastOnce => v5::SubAckReasonCode::QoS1,
-                v5::QoS::ExactlyOnce => v5::SubAckReasonCode::QoS2,
-            };
-            return_codes.push(rc)
+            return_codes.push(v5::SubAckReasonCode::from(qos));
+            filters.push(filter.topic_filter.clone());
+        }
+
+        if !filters.is_empty() {
+            let client_id = self.client_id.clone();
+            shard.as_cluster().emit(BrokerEvent::Subscribed { client_id, filters });
         }
 
         let sub_ack = v5::SubAck {
@@ -703,29 +1377,134 @@ impl Session {
             return_codes,
         };
 
-        // When a new Non‑shared Subscription is made, the last retained message, if any,
-        // on each matching topic name is sent to the Client as directed by the
-        // Retain Handling Subscription Option. These messages are sent with the RETAIN
-        // flag set to 1. Which retained messages are sent is controlled by the Retain
-        // Handling Subscription Option. At the time of the Subscription:
-        //
-        // * If Retain Handling is set to 0 the Server MUST send the retained messages
-        //   matching the Topic Filter of the subscription to the Client [MQTT-3.3.1-9].
-        // * If Retain Handling is set to 1 then if the subscription did not already exist,
-        //   the Server MUST send all retained message matching the Topic Filter of the
-        //   subscription to the Client, and if the subscription did exist the Server
-        //   MUST NOT send the retained messages. [MQTT-3.3.1-10].
-        // * If Retain Handling is set to 2, the Server MUST NOT send the retained
-        //   messages [MQTT-3.3.1-11].
+        let retains = retains
+            .into_values()
+            .filter_map(|(publish, subscrs)| Session::dedup_deliveries(subscrs, &publish))
+            .collect();
+
+        Ok((Message::ClientAck { packet: v5::Packet::SubAck(sub_ack) }, retains))
+    }
+
+    // Route retained messages, delivered on SUBSCRIBE, to this session.
+    fn route_retains(&mut self, shard: &mut Shard, retains: Vec<v5::Publish>) {
+        for publish in retains.into_iter() {
+            let inp_seqno = shard.incr_inp_seqno();
+            let id = self.client_id.clone();
+            let msg = Message::new_routed(self, inp_seqno, publish, id, false);
+            shard.route_to_client(shard.shard_id, msg);
+        }
+    }
+
+    /// Return retained messages to be sent for a new subscription on `filter`,
+    /// `existed` is whether the client was already subscribed to the same filter.
+    ///
+    /// When a new Non‑shared Subscription is made, the last retained message, if any,
+    /// on each matching topic name is sent to the Client as directed by the
+    /// Retain Handling Subscription Option. These messages are sent with the RETAIN
+    /// flag set to 1. At the time of the Subscription:
+    ///
+    /// * If Retain Handling is set to 0 the Server MUST send the retained messages
+    ///   matching the Topic Filter of the subscription to the Client [MQTT-3.3.1-9].
+    /// * If Retain Handling is set to 1 then if the subscription did not already
+    ///   exist, the Server MUST send all retained message matching the Topic Filter
+    ///   of the subscription to the Client, and if the subscription did exist the
+    ///   Server MUST NOT send the retained messages. [MQTT-3.3.1-10].
+    /// * If Retain Handling is set to 2, the Server MUST NOT send the retained
+    ///   messages [MQTT-3.3.1-11].
+    pub fn on_subscribe(
+        &mut self,
+        filter: &v5::SubscribeFilter,
+        trie: &RetainedTrie,
+        existed: bool,
+    ) -> Vec<v5::Publish> {
+        let (rfr, _rap, _nl, qos) = filter.opt.unwrap();
+        match rfr {
+            _ if filter.topic_filter.is_shared() => return Vec::default(),
+            v5::RetainForwardRule::OnEverySubscribe => (),
+            v5::RetainForwardRule::OnNewSubscribe if !existed => (),
+            v5::RetainForwardRule::OnNewSubscribe => return Vec::default(),
+            v5::RetainForwardRule::Never => return Vec::default(),
+        }
+
+        let qos = cmp::min(qos, self.config.mqtt_maximum_qos());
+        let mut publishes = trie.match_retained(&filter.topic_filter);
+        for publish in publishes.iter_mut() {
+            let qos = cmp::min(qos, publish.qos);
+            publish.set_fixed_header(true, qos, false);
+            publish.packet_id = None;
+            if let Some(props) = publish.properties.as_mut() {
+                // topic-alias is scoped to the publishing client's connection.
+                props.topic_alias = None;
+                if !self.config.forward_user_properties {
+                    props.user_properties.clear();
+                }
+            }
+        }
+        trace!(
+            "{} filter:{:?} {} retained messages",
+            self.prefix,
+            filter.topic_filter,
+            publishes.len()
+        );
+
+        publishes
+    }
 
-        Ok(vec![Message::ClientAck { packet: v5::Packet::SubAck(sub_ack) }])
+    /// Collapse deliveries of `publish` for `subs`, overlapping subscriptions from
+    /// the same client, into a single delivery. It is sent at the maximum QoS granted
+    /// across `subs`, capped to the QoS of `publish`, and carries the union of
+    /// subscription-identifiers from `subs`. Return None if `subs` is empty.
+    pub fn dedup_deliveries(
+        subs: Vec<v5::Subscription>,
+        publish: &v5::Publish,
+    ) -> Option<v5::Publish> {
+        let qos = subs.iter().map(|subscr| subscr.qos).max()?;
+
+        let mut ids: Vec<u32> = subs.iter().filter_map(|s| s.subscription_id).collect();
+        ids.sort_unstable();
+        ids.dedup();
+
+        let mut publish = publish.clone();
+        let qos = cmp::min(qos, publish.qos);
+        publish.set_fixed_header(publish.retain, qos, publish.duplicate);
+        publish.set_subscription_ids(ids);
+
+        Some(publish)
+    }
+
+    // return reason-code, if topic-filter uses a capability disabled on the broker.
+    fn reject_filter(
+        &self,
+        topic_filter: &TopicFilter,
+        subscription_id: Option<u32>,
+    ) -> Option<v5::SubAckReasonCode> {
+        use v5::SubAckReasonCode::*;
+
+        if !self.config.mqtt_shared_subscription_available && topic_filter.is_shared() {
+            Some(SharedSubscriptionsNotSupported)
+        } else if !self.config.mqtt_wildcard_subscription_available
+            && topic_filter.has_wildcards()
+        {
+            Some(WildcardSubscriptionsNotSupported)
+        } else if !self.config.mqtt_subscription_identifiers_available
+            && subscription_id.is_some()
+        {
+            Some(SubscriptionIdNotSupported)
+        } else {
+            None
+        }
     }
 }
 
 impl Session {
-    // return `true` if there where subscribers.
-    fn rx_publish(&mut self, shard: &mut Shard, publish: v5::Publish) -> Result<bool> {
-        if publish.qos > v5::QoS::try_from(self.config.mqtt_maximum_qos).unwrap() {
+    // return the acknowledgement to be sent right away, if any. For QoS>0 PUBLISH
+    // routed to subscribers, the acknowledgement is sent once they are committed.
+    fn rx_publish(
+        &mut self,
+        shard: &mut Shard,
+        publish: v5::Publish,
+    ) -> Result<Option<Message>> {
+        if publish.qos > self.config.mqtt_maximum_qos() {
             err!(
                 ProtocolError,
                 code: QoSNotSupported,
@@ -736,21 +1515,51 @@ impl Session {
         }
 
         if self.state.is_duplicate(&publish) {
-            return Ok(false);
+            let ack = publish.packet_id.map(v5::Pub::new_pub_ack);
+            return Ok(ack.map(Message::new_pub_ack));
         }
 
-        self.book_retain(shard, &publish)?;
+        let topic_name = self.state.publish_topic_name(&publish)?;
+        if !shard.as_cluster().can_publish(&self.client_id, &topic_name) {
+            error!("{} topic:{:?} publish not authorized", self.prefix, topic_name);
+            return Ok(refuse_publish(&publish, ReasonCode::NotAuthorized));
+        }
+
+        let size = publish.payload.as_ref().map(|p| p.len()).unwrap_or(0);
+        if publish.retain && size > (self.config.max_retained_payload_size as usize) {
+            error!(
+                "{} topic:{:?} retain payload size:{} > {}",
+                self.prefix, topic_name, size, self.config.max_retained_payload_size
+            );
+            return Ok(refuse_publish(&publish, ReasonCode::QuotaExceeded));
+        }
+
+        if publish.retain {
+            // retain under the resolved topic, without publisher's topic-alias.
+            let mut publish = publish.clone();
+            publish.topic_name = topic_name.clone();
+            if let Some(props) = publish.properties.as_mut() {
+                props.topic_alias = None;
+            }
+            self.book_retain(shard, &publish)?;
+        }
         self.state.book_qos(&publish)?;
 
+        shard.as_cluster().emit(BrokerEvent::Published {
+            client_id: self.client_id.clone(),
+            topic: topic_name.clone(),
+            qos: publish.qos,
+            len: publish.payload.as_ref().map(|p| p.len()).unwrap_or(0),
+        });
+
         let inp_seqno = shard.incr_inp_seqno();
-        let topic_name = self.state.publish_topic_name(&publish)?;
         let subscrs = shard.match_subscribers(&topic_name);
         let has_subscrs = subscrs.len() > 0;
 
         let ack_needed = match publish.packet_id {
             Some(packet_id) => {
                 let msg = Message::new_index(&self.client_id, packet_id);
-                shard.book_index(publish.qos, msg);
+                shard.book_index(publish.qos, inp_seqno, msg);
                 true
             }
             None => false,
@@ -769,6 +1578,14 @@ impl Session {
 
             let publish = {
                 let mut publish = publish.clone();
+                // topic-alias is scoped to the publishing client's connection.
+                publish.topic_name = topic_name.clone();
+                if let Some(props) = publish.properties.as_mut() {
+                    props.topic_alias = None;
+                    if !self.config.forward_user_properties {
+                        props.user_properties.clear();
+                    }
+                }
                 let retain = subscr.retain_as_published && publish.retain;
                 let qos = subscr.route_qos(&publish, self.config.mqtt_maximum_qos);
                 publish.set_fixed_header(retain, qos, false);
@@ -779,7 +1596,12 @@ impl Session {
             shard.route_to_client(subscr.shard_id, msg);
         }
 
-        Ok(has_subscrs)
+        match publish.packet_id {
+            Some(packet_id) if !has_subscrs => {
+                Ok(Some(Message::new_pub_ack(v5::Pub::new_pub_ack(packet_id))))
+            }
+            _ => Ok(None),
+        }
     }
 
     fn book_retain(&mut self, shard: &mut Shard, publish: &v5::Publish) -> Result<()> {
@@ -791,6 +1613,8 @@ impl Session {
                 self.prefix
             )?;
         } else if publish.retain {
+            // zero-length payload deletes the retained message, the PUBLISH itself
+            // is still routed to current subscribers.
             if publish.payload.as_ref().map(|x| x.len() == 0).unwrap_or(true) {
                 shard.as_cluster().reset_retain_topic(publish.topic_name.clone())?;
             } else {
@@ -807,11 +1631,6 @@ impl Session {
         self.state.incr_out_seqno(msg)
     }
 
-    // Handle PUBLISH QoS-0
-    pub fn out_qos0(&mut self, msgs: Vec<Message>) -> QueueStatus<Message> {
-        self.state.out_qos0(msgs)
-    }
-
     // Handle PUBLISH QoS-1 and QoS-2
     pub fn out_qos(&mut self, msgs: Vec<Message>) -> QueueStatus<Message> {
         self.state.out_qos(msgs)
@@ -826,9 +1645,42 @@ impl Session {
         self.state.out_acks_publish(packet_id)
     }
 
+    /// Return the number of QoS>0 PUBLISH packets that the client can send, before
+    /// hitting [Config::mqtt_receive_maximum]. Reading PUBLISH packets from the
+    /// client is paused while this is ZERO, other packets are still read.
+    pub fn to_receive_quota(&self) -> usize {
+        match &self.state {
+            SessionState::Active { inp_qos12, .. } => {
+                let max = usize::from(self.config.mqtt_receive_maximum);
+                max.saturating_sub(inp_qos12.len())
+            }
+            ss => unreachable!("{} {:?}", self.prefix, ss),
+        }
+    }
+
+    /// Queue DISCONNECT with `reason` behind pending acks and flush them to the
+    /// client, ahead of closing the connection on a protocol violation.
+    pub fn protocol_disconnect(&mut self, reason: v5::DisconnReasonCode) -> QueuePkt {
+        debug!("{} protocol disconnect reason:{:?}", self.prefix, reason);
+
+        let packet = v5::Packet::Disconnect(v5::Disconnect::new(reason, None));
+        self.state.out_acks_extend(vec![Message::ClientAck { packet }]);
+        self.out_acks_flush()
+    }
+
     pub fn commit_acks(&mut self, out_seqnos: Vec<OutSeqno>) {
         self.state.commit_acks(out_seqnos)
     }
+
+    /// Re-deliver inflight PUBLISH messages that are not acknowledged within
+    /// [Config::inflight_timeout], as of `now`. Fail if a message is not
+    /// acknowledged even after [Config::max_retransmits] attempts.
+    pub fn retransmit(&mut self, now: time::Instant) -> Result<QueueStatus<Message>> {
+        match self.state.retransmit(now)? {
+            0 => Ok(QueueStatus::Ok(Vec::new())),
+            _ => Ok(self.state.out_qos(Vec::new())),
+        }
+    }
 }
 
 impl Session {
@@ -837,6 +1689,14 @@ impl Session {
         self.shard_id
     }
 
+    /// Return the outbound counters of this session, which must be active.
+    pub fn as_client_out(&self) -> &ClientOut {
+        match &self.state {
+            SessionState::Active { client_out, .. } => client_out,
+            ss => unreachable!("{} {:?}", self.prefix, ss),
+        }
+    }
+
     #[inline]
     pub fn as_config(&self) -> &Config {
         &self.config
@@ -856,14 +1716,72 @@ impl Session {
     }
 
     #[inline]
-    fn to_keep_alive(&self) -> Option<u16> {
+    fn to_server_keep_alive(&self) -> Option<u16> {
         match &self.state {
-            SessionState::Active { keep_alive, .. } => keep_alive.keep_alive(),
+            SessionState::Active { keep_alive, .. } => keep_alive.server_keep_alive(),
             ss => unreachable!("{} {:?}", self.prefix, ss),
         }
     }
 }
 
+// Session expiry interval negotiated between broker and client, client's value
+// shall override the broker's value.
+fn session_expiry_interval(config: &Config, connect: &v5::Connect) -> Option<u32> {
+    match (config.mqtt_session_expiry_interval, connect.session_expiry_interval()) {
+        (Some(_one), Some(two)) => Some(two),
+        (Some(one), None) => Some(one),
+        (None, Some(two)) => Some(two),
+        (None, None) => None,
+    }
+}
+
+// Same as flush_to_miot, but PUBLISH packets are sent with topic-alias if enabled by
+// client. Packets carry the alias, while `msgs` retain the full topic-name, so that
+// they can be re-delivered on a new connection.
+fn flush_publish_to_miot(
+    prefix: &str,
+    miot_tx: &mut PktTx,
+    aliases: &mut OutboundAliases,
+    client_out: &mut ClientOut,
+    mut msgs: Vec<Message>,
+) -> QueueMsg {
+    let mut assigned = Vec::default();
+    let mut sizes = Vec::with_capacity(msgs.len());
+    let mut pkts: Vec<v5::Packet> = Vec::with_capacity(msgs.len());
+    for (i, msg) in msgs.iter().enumerate() {
+        match msg.to_v5_packet() {
+            v5::Packet::Publish(mut publish) => {
+                if aliases.alias_publish(&mut publish) {
+                    assigned.push((i, publish.topic_name.clone()));
+                }
+                sizes.push(publish.encoded_len().unwrap_or(0));
+                pkts.push(v5::Packet::Publish(publish));
+            }
+            pkt => {
+                sizes.push(0);
+                pkts.push(pkt)
+            }
+        }
+    }
+    let mut status = miot_tx.try_sends(prefix, pkts);
+    let pkts = status.take_values();
+
+    let m = msgs.len();
+    let n = pkts.len();
+    if m > n {
+        client_out.messages_delivered += m - n;
+        client_out.bytes_out += sizes[..(m - n)].iter().sum::<usize>();
+        client_out.last_flush_instant = Some(time::Instant::now());
+    }
+    // aliases assigned to packets that are not sent, shall be assigned again.
+    for (_, topic_name) in assigned.iter().filter(|(i, _)| *i >= (m - n)) {
+        aliases.unregister(topic_name);
+    }
+    msgs.drain(..(m - n));
+
+    status.map(msgs)
+}
+
 fn flush_to_miot(prefix: &str, miot_tx: &mut PktTx, mut msgs: Vec<Message>) -> QueueMsg {
     let pkts: Vec<v5::Packet> = msgs.iter().map(|m| m.to_v5_packet()).collect();
     let mut status = miot_tx.try_sends(&prefix, pkts);
@@ -875,3 +1793,24 @@ fn flush_to_miot(prefix: &str, miot_tx: &mut PktTx, mut msgs: Vec<Message>) -> Q
 
     status.map(msgs)
 }
+
+// Acknowledge a PUBLISH that is neither routed nor retained, with reason `code`.
+// QoS-0 PUBLISH is silently dropped.
+fn refuse_publish(publish: &v5::Publish, code: ReasonCode) -> Option<Message> {
+    publish.packet_id.map(|packet_id| match publish.qos {
+        v5::QoS::ExactlyOnce => {
+            let mut pubrec = v5::Pub::new_pub_rec(packet_id);
+            pubrec.code = code;
+            Message::ClientAck { packet: v5::Packet::PubRec(pubrec) }
+        }
+        _ => {
+            let mut puback = v5::Pub::new_pub_ack(packet_id);
+            puback.code = code;
+            Message::new_pub_ack(puback)
+        }
+    })
+}
+
+#[cfg(test)]
+#[path = "session_test.rs"]
+mod session_test;
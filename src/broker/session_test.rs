@@ -0,0 +1,1135 @@
+use std::{sync::Arc, time};
+
+use crate::{broker::socket, broker::InpSeqno, Packetize};
+
+use super::*;
+
+fn new_session(waker: &Arc<mio::Waker>, client_id: &ClientID, port: u16) -> Session {
+    new_session_rx(waker, client_id, port).0
+}
+
+fn new_session_rx(
+    waker: &Arc<mio::Waker>,
+    client_id: &ClientID,
+    port: u16,
+) -> (Session, PktRx) {
+    new_session_with(waker, client_id, port, v5::Connect::default())
+}
+
+fn new_session_with(
+    waker: &Arc<mio::Waker>,
+    client_id: &ClientID,
+    port: u16,
+    connect: v5::Connect,
+) -> (Session, PktRx) {
+    new_session_config(waker, client_id, port, connect, Config::default())
+}
+
+fn new_session_config(
+    waker: &Arc<mio::Waker>,
+    client_id: &ClientID,
+    port: u16,
+    connect: v5::Connect,
+    config: Config,
+) -> (Session, PktRx) {
+    let (miot_tx, downstream) =
+        socket::pkt_channel(0, 16, Arc::clone(waker), Arc::default());
+    let (_, session_rx) = socket::pkt_channel(0, 16, Arc::clone(waker), Arc::default());
+
+    let args = SessionArgs {
+        raddr: format!("127.0.0.1:{}", port).parse().unwrap(),
+        client_id: client_id.clone(),
+        shard_id: 0,
+        miot_tx,
+        session_rx,
+    };
+    (Session::start_active(args, config, &connect), downstream)
+}
+
+fn send_quota(session: &Session) -> u16 {
+    match &session.state {
+        SessionState::Active { send_quota, .. } => *send_quota,
+        ss => unreachable!("{:?}", ss),
+    }
+}
+
+fn new_publish(qos: v5::QoS, packet_id: Option<PacketID>) -> v5::Publish {
+    v5::Publish {
+        retain: false,
+        qos,
+        duplicate: false,
+        topic_name: TopicName::from("a/b/c".to_string()),
+        packet_id,
+        properties: None,
+        payload: Some(b"hello".to_vec()),
+    }
+}
+
+#[test]
+fn test_session_take_over() {
+    let poll = mio::Poll::new().unwrap();
+    let waker = Arc::new(mio::Waker::new(poll.registry(), mio::Token(0)).unwrap());
+    let client_id = ClientID("test-session-take-over".to_string());
+
+    let mut old = new_session(&waker, &client_id, 10001);
+    let topic_filter = TopicFilter::from("a/b/#".to_string());
+    let subscription = v5::Subscription {
+        topic_filter: topic_filter.clone(),
+        client_id: client_id.clone(),
+        shard_id: 0,
+        subscription_id: None,
+        qos: v5::QoS::AtLeastOnce,
+        no_local: false,
+        retain_as_published: false,
+        retain_forward_rule: v5::RetainForwardRule::OnEverySubscribe,
+    };
+    old.state.as_mut_subscriptions().insert(topic_filter.clone(), subscription);
+    match &mut old.state {
+        SessionState::Active { next_packet_id, out_seqno, inbound_aliases, .. } => {
+            *next_packet_id = 10;
+            *out_seqno = OutSeqno::from(20);
+            let topic_name = TopicName::from("a/b/c".to_string());
+            inbound_aliases.register(1, topic_name).unwrap();
+        }
+        ss => unreachable!("{:?}", ss),
+    }
+
+    let mut new = new_session(&waker, &client_id, 10002);
+    new.take_over(old);
+
+    assert_eq!(new.state.as_subscriptions().len(), 1);
+    assert!(new.state.as_subscriptions().contains_key(&topic_filter));
+    match &new.state {
+        SessionState::Active { next_packet_id, out_seqno, inbound_aliases, .. } => {
+            assert_eq!(*next_packet_id, 10);
+            assert_eq!(*out_seqno, OutSeqno::from(20));
+            assert!(inbound_aliases.resolve(1).is_none());
+        }
+        ss => unreachable!("{:?}", ss),
+    }
+}
+
+#[test]
+fn test_session_resume() {
+    let poll = mio::Poll::new().unwrap();
+    let waker = Arc::new(mio::Waker::new(poll.registry(), mio::Token(0)).unwrap());
+    let client_id = ClientID("test-session-resume".to_string());
+
+    // un-acked QoS-1 PUBLISH, sent on the old connection.
+    let mut old = new_session(&waker, &client_id, 10003);
+    match &mut old.state {
+        SessionState::Active { qos12_unacks, next_packet_id, out_seqno, .. } => {
+            let publish = new_publish(v5::QoS::AtLeastOnce, Some(5));
+            let msg = Message::Packet {
+                out_seqno: OutSeqno::from(1),
+                packet_id: Some(5),
+                publish,
+            };
+            qos12_unacks.insert(5, msg);
+            *next_packet_id = 6;
+            *out_seqno = OutSeqno::from(1);
+        }
+        ss => unreachable!("{:?}", ss),
+    }
+
+    // client goes away, and a QoS-1 PUBLISH is routed to it.
+    let mut old = old.into_reconnect();
+    assert!(old.is_reconnect());
+    let mut msg = Message::Routed {
+        src_shard_id: 0,
+        client_id: client_id.clone(),
+        inp_seqno: InpSeqno::from(1),
+        out_seqno: OutSeqno::default(),
+        publish: new_publish(v5::QoS::AtLeastOnce, None),
+        ack_needed: true,
+    };
+    old.incr_out_seqno(&mut msg);
+    assert!(matches!(old.out_qos(vec![msg]), QueueStatus::Ok(_)));
+
+    // client reconnects with clean_start as false.
+    let (mut new, downstream) = new_session_rx(&waker, &client_id, 10004);
+    new.take_over(old);
+    assert!(matches!(new.out_qos(Vec::new()), QueueStatus::Ok(_)));
+
+    let mut status = downstream.try_recvs("test");
+    let pkts = status.take_values();
+    assert_eq!(pkts.len(), 2);
+    match &pkts[0] {
+        v5::Packet::Publish(publish) => {
+            assert_eq!(publish.packet_id, Some(5));
+            assert!(publish.duplicate);
+        }
+        pkt => panic!("unexpected {:?}", pkt),
+    }
+    match &pkts[1] {
+        v5::Packet::Publish(publish) => {
+            assert_eq!(publish.packet_id, Some(6));
+            assert!(!publish.duplicate);
+        }
+        pkt => panic!("unexpected {:?}", pkt),
+    }
+}
+
+#[test]
+fn test_session_expiry() {
+    let poll = mio::Poll::new().unwrap();
+    let waker = Arc::new(mio::Waker::new(poll.registry(), mio::Token(0)).unwrap());
+    let client_id = ClientID("test-session-expiry".to_string());
+
+    let new_connect = |sei: Option<u32>| {
+        let properties = v5::ConnectProperties {
+            session_expiry_interval: sei,
+            ..v5::ConnectProperties::default()
+        };
+        v5::Connect {
+            properties: Some(properties),
+            ..v5::Connect::default()
+        }
+    };
+    let now = time::Instant::now();
+
+    // active sessions never expire.
+    let (session, _) = new_session_with(&waker, &client_id, 10005, new_connect(Some(0)));
+    assert!(!session.is_expired(now));
+
+    // immediate, missing session_expiry_interval is same as ZERO.
+    for sei in [None, Some(0)] {
+        let (session, _) = new_session_with(&waker, &client_id, 10005, new_connect(sei));
+        let session = session.into_reconnect();
+        assert!(session.is_expired(time::Instant::now()));
+    }
+
+    // finite
+    let (session, _) = new_session_with(&waker, &client_id, 10006, new_connect(Some(2)));
+    let session = session.into_reconnect();
+    assert!(!session.is_expired(time::Instant::now()));
+    assert!(session.is_expired(time::Instant::now() + time::Duration::from_secs(3)));
+
+    // indefinite
+    let sei = Some(0xFFFFFFFF);
+    let (session, _) = new_session_with(&waker, &client_id, 10007, new_connect(sei));
+    let session = session.into_reconnect();
+    let later = time::Instant::now() + time::Duration::from_secs(0x1_0000_0000);
+    assert!(!session.is_expired(later));
+}
+
+#[test]
+fn test_session_maximum_qos() {
+    use crate::broker::shard::test_util::new_active_shard;
+
+    let poll = mio::Poll::new().unwrap();
+    let waker = Arc::new(mio::Waker::new(poll.registry(), mio::Token(0)).unwrap());
+    let client_id = ClientID("test-session-maximum-qos".to_string());
+    assert_eq!(Config::default().mqtt_maximum_qos(), v5::QoS::AtLeastOnce);
+
+    let (mut shard, _app_rx) = new_active_shard();
+    let mut session = new_session(&waker, &client_id, 10008);
+
+    let connack = session.success_ack(&v5::Connect::default(), None, &shard);
+    let maximum_qos = connack.properties.unwrap().maximum_qos;
+    assert_eq!(maximum_qos, Some(v5::QoS::AtLeastOnce));
+
+    // QoS2 PUBLISH against a QoS1 broker.
+    let publish = new_publish(v5::QoS::ExactlyOnce, Some(1));
+    let err = session.rx_publish(&mut shard, publish).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ProtocolError);
+    assert_eq!(err.code(), ReasonCode::QoSNotSupported);
+
+    // QoS2 SUBSCRIBE is granted QoS1.
+    let rfr = v5::RetainForwardRule::OnEverySubscribe;
+    let sub = v5::Subscribe {
+        packet_id: 1,
+        properties: None,
+        filters: vec![v5::SubscribeFilter {
+            topic_filter: TopicFilter::from("a/b/#".to_string()),
+            opt: v5::SubscriptionOpt::new(rfr, false, false, v5::QoS::ExactlyOnce),
+        }],
+    };
+    let (msg, _retains) = session.rx_subscribe(&mut shard, sub).unwrap();
+    match &msg {
+        Message::ClientAck { packet: v5::Packet::SubAck(sub_ack) } => {
+            assert_eq!(sub_ack.return_codes, vec![v5::SubAckReasonCode::QoS1]);
+        }
+        msg => panic!("unexpected {:?}", msg),
+    }
+    let subscrs = session.state.as_subscriptions();
+    assert_eq!(subscrs.values().next().unwrap().qos, v5::QoS::AtLeastOnce);
+}
+
+#[test]
+fn test_session_capabilities() {
+    use crate::broker::shard::test_util::new_active_shard;
+
+    let poll = mio::Poll::new().unwrap();
+    let waker = Arc::new(mio::Waker::new(poll.registry(), mio::Token(0)).unwrap());
+    let client_id = ClientID("test-session-capabilities".to_string());
+
+    let (mut shard, _app_rx) = new_active_shard();
+    let mut session = new_session(&waker, &client_id, 10009);
+    session.config = Config {
+        mqtt_retain_available: false,
+        mqtt_wildcard_subscription_available: false,
+        ..Config::default()
+    };
+
+    let props = session.success_ack(&v5::Connect::default(), None, &shard).properties;
+    let props = props.unwrap();
+    assert_eq!(props.retain_available, Some(false));
+    assert_eq!(props.wildcard_subscription_available, Some(false));
+    assert_eq!(props.subscription_identifiers_available, Some(true));
+    assert_eq!(props.shared_subscription_available, Some(false));
+
+    // retain PUBLISH when retain is unavailable.
+    let mut publish = new_publish(v5::QoS::AtMostOnce, None);
+    publish.retain = true;
+    let err = session.rx_publish(&mut shard, publish).unwrap_err();
+    assert_eq!(err.code(), ReasonCode::RetainNotSupported);
+
+    // wildcard SUBSCRIBE when wildcards are unavailable.
+    let rfr = v5::RetainForwardRule::OnEverySubscribe;
+    let opt = v5::SubscriptionOpt::new(rfr, false, false, v5::QoS::AtMostOnce);
+    let filters = ["a/+/c", "a/b/c", "$share/g/a/b"]
+        .iter()
+        .map(|tf| v5::SubscribeFilter {
+            topic_filter: TopicFilter::from(tf.to_string()),
+            opt: opt.clone(),
+        })
+        .collect();
+    let sub = v5::Subscribe { packet_id: 1, properties: None, filters };
+    let (msg, _retains) = session.rx_subscribe(&mut shard, sub).unwrap();
+    match &msg {
+        Message::ClientAck { packet: v5::Packet::SubAck(sub_ack) } => {
+            let codes = vec![
+                v5::SubAckReasonCode::WildcardSubscriptionsNotSupported,
+                v5::SubAckReasonCode::QoS0,
+                v5::SubAckReasonCode::SharedSubscriptionsNotSupported,
+            ];
+            assert_eq!(sub_ack.return_codes, codes);
+        }
+        msg => panic!("unexpected {:?}", msg),
+    }
+    assert_eq!(session.state.as_subscriptions().len(), 1);
+}
+
+#[test]
+fn test_inbound_aliases() {
+    let topic = |s: &str| TopicName::from(s.to_string());
+
+    let mut aliases = InboundAliases::new(Some(2));
+    assert!(aliases.resolve(1).is_none());
+
+    assert_eq!(aliases.register(1, topic("a/b")).unwrap(), None);
+    assert_eq!(aliases.resolve(1), Some(&topic("a/b")));
+
+    // re-register overwrites the alias.
+    assert_eq!(aliases.register(1, topic("a/c")).unwrap(), Some(topic("a/b")));
+    assert_eq!(aliases.resolve(1), Some(&topic("a/c")));
+
+    // ZERO and above topic_alias_max.
+    for alias in [0, 3] {
+        let err = aliases.register(alias, topic("a/d")).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ProtocolError);
+        assert_eq!(err.code(), ReasonCode::TopicAliasInvalid);
+    }
+    assert!(aliases.resolve(2).is_none());
+
+    // topic-aliases disabled.
+    let mut aliases = InboundAliases::new(None);
+    assert!(aliases.register(1, topic("a/b")).is_err());
+}
+
+#[test]
+fn test_outbound_aliases() {
+    let publish = |s: &str| {
+        let mut publish = new_publish(v5::QoS::AtMostOnce, None);
+        publish.topic_name = TopicName::from(s.to_string());
+        publish
+    };
+    let alias = |p: &v5::Publish| p.properties.as_ref().and_then(|p| p.topic_alias);
+
+    let mut aliases = OutboundAliases::new(Some(1));
+
+    // first publish carries full topic-name along with the new alias.
+    let mut p1 = publish("a/b");
+    assert!(aliases.alias_publish(&mut p1));
+    assert_eq!(p1.topic_name, TopicName::from("a/b".to_string()));
+    assert_eq!(alias(&p1), Some(1));
+    p1.encode().unwrap();
+
+    // second publish of the same topic carries empty topic-name + alias.
+    let mut p2 = publish("a/b");
+    assert!(!aliases.alias_publish(&mut p2));
+    assert_eq!(p2.topic_name.len(), 0);
+    assert_eq!(alias(&p2), Some(1));
+    p2.encode().unwrap();
+
+    // alias table is full, "a/b" is evicted and its alias is re-assigned to "a/c",
+    // with full topic-name.
+    let mut p3 = publish("a/c");
+    assert!(aliases.alias_publish(&mut p3));
+    assert_eq!(p3.topic_name, TopicName::from("a/c".to_string()));
+    assert_eq!(alias(&p3), Some(1));
+
+    // evicted topic is re-aliased with full topic-name.
+    let mut p3 = publish("a/b");
+    assert!(aliases.alias_publish(&mut p3));
+    assert_eq!(p3.topic_name, TopicName::from("a/b".to_string()));
+    assert_eq!(alias(&p3), Some(1));
+
+    // topic-aliases disabled by client.
+    let mut aliases = OutboundAliases::new(None);
+    let mut p4 = publish("a/b");
+    assert!(!aliases.alias_publish(&mut p4));
+    assert_eq!(alias(&p4), None);
+}
+
+#[test]
+fn test_outbound_aliases_lru() {
+    let publish = |s: &str| {
+        let mut publish = new_publish(v5::QoS::AtMostOnce, None);
+        publish.topic_name = TopicName::from(s.to_string());
+        publish
+    };
+    let alias = |p: &v5::Publish| p.properties.as_ref().and_then(|p| p.topic_alias);
+
+    let mut aliases = OutboundAliases::new(Some(2));
+
+    // (topic, expected-alias, is-new-alias)
+    let rotation = [
+        ("t/1", 1, true),
+        ("t/2", 2, true),
+        ("t/1", 1, false), // t/2 is now the least-recently-used
+        ("t/3", 2, true),  // evict t/2
+        ("t/1", 1, false),
+        ("t/2", 2, true), // evict t/3
+        ("t/3", 1, true), // evict t/1
+        ("t/2", 2, false),
+        ("t/3", 1, false),
+    ];
+    for (topic, want, new) in rotation.iter() {
+        let mut p = publish(topic);
+        assert_eq!(aliases.alias_publish(&mut p), *new, "{}", topic);
+        assert_eq!(alias(&p), Some(*want), "{}", topic);
+        match new {
+            true => assert_eq!(p.topic_name, TopicName::from(topic.to_string())),
+            false => assert_eq!(p.topic_name.len(), 0),
+        }
+        p.encode().unwrap();
+    }
+    assert_eq!(aliases.aliases.len(), 2);
+    assert_eq!(aliases.lru.len(), 2);
+
+    // unregistered alias is re-used, without evicting other topics.
+    aliases.unregister(&TopicName::from("t/2".to_string()));
+    let mut p = publish("t/4");
+    assert!(aliases.alias_publish(&mut p));
+    assert_eq!(alias(&p), Some(2));
+    let mut p = publish("t/3");
+    assert!(!aliases.alias_publish(&mut p));
+    assert_eq!(alias(&p), Some(1));
+}
+
+#[test]
+fn test_session_send_quota() {
+    let poll = mio::Poll::new().unwrap();
+    let waker = Arc::new(mio::Waker::new(poll.registry(), mio::Token(0)).unwrap());
+    let client_id = ClientID("test-session-send-quota".to_string());
+
+    let receive_maximum = 2;
+    let mut connect = v5::Connect::default();
+    connect.properties = Some(v5::ConnectProperties {
+        receive_maximum: Some(receive_maximum),
+        ..v5::ConnectProperties::default()
+    });
+    let (mut session, downstream) = new_session_with(&waker, &client_id, 10011, connect);
+
+    let msgs: Vec<Message> = (0..(receive_maximum + 1))
+        .map(|_| {
+            let mut msg = Message::Routed {
+                src_shard_id: 0,
+                client_id: client_id.clone(),
+                inp_seqno: InpSeqno::from(1),
+                out_seqno: OutSeqno::default(),
+                publish: new_publish(v5::QoS::AtLeastOnce, None),
+                ack_needed: true,
+            };
+            session.incr_out_seqno(&mut msg);
+            msg
+        })
+        .collect();
+
+    // client's quota is exhausted after receive_maximum messages.
+    assert!(matches!(session.out_qos(msgs), QueueStatus::Ok(_)));
+    let pkts = downstream.try_recvs("test").take_values();
+    assert_eq!(pkts.len(), usize::from(receive_maximum));
+    assert!(!session.state.can_send());
+    assert!(matches!(session.out_qos(Vec::new()), QueueStatus::Block(_)));
+    assert!(downstream.try_recvs("test").take_values().is_empty());
+
+    // PUBACK restores the quota, and the stalled message is sent.
+    let puback = v5::Pub::new_pub_ack(1);
+    assert_eq!(session.state.rx_puback(&puback), Some(OutSeqno::from(1)));
+    assert_eq!(session.state.rx_puback(&puback), None);
+    assert!(session.state.can_send());
+    assert!(matches!(session.out_qos(Vec::new()), QueueStatus::Ok(_)));
+    let pkts = downstream.try_recvs("test").take_values();
+    assert_eq!(pkts.len(), 1);
+    match &pkts[0] {
+        v5::Packet::Publish(publish) => assert_eq!(publish.packet_id, Some(3)),
+        pkt => panic!("unexpected {:?}", pkt),
+    }
+    assert!(!session.state.can_send());
+}
+
+#[test]
+fn test_session_publish_order() {
+    let poll = mio::Poll::new().unwrap();
+    let waker = Arc::new(mio::Waker::new(poll.registry(), mio::Token(0)).unwrap());
+    let client_id = ClientID("test-session-publish-order".to_string());
+
+    let mut connect = v5::Connect::default();
+    connect.properties = Some(v5::ConnectProperties {
+        receive_maximum: Some(1),
+        ..v5::ConnectProperties::default()
+    });
+    let (mut session, downstream) = new_session_with(&waker, &client_id, 10012, connect);
+
+    use v5::QoS::{AtLeastOnce, AtMostOnce};
+    let qoss = [AtMostOnce, AtLeastOnce, AtMostOnce, AtLeastOnce, AtMostOnce];
+    let msgs: Vec<Message> = qoss
+        .iter()
+        .enumerate()
+        .map(|(i, qos)| {
+            let mut publish = new_publish(*qos, None);
+            publish.payload = Some(vec![i as u8]);
+            let mut msg = Message::Routed {
+                src_shard_id: 0,
+                client_id: client_id.clone(),
+                inp_seqno: InpSeqno::from(1),
+                out_seqno: OutSeqno::default(),
+                publish,
+                ack_needed: false,
+            };
+            session.incr_out_seqno(&mut msg);
+            msg
+        })
+        .collect();
+
+    let payloads = |pkts: Vec<v5::Packet>| -> Vec<u8> {
+        pkts.into_iter()
+            .map(|pkt| match pkt {
+                v5::Packet::Publish(publish) => publish.payload.unwrap()[0],
+                pkt => panic!("unexpected {:?}", pkt),
+            })
+            .collect()
+    };
+
+    // QoS-0 behind the QoS-1, that exceeds the quota, shall wait for its turn.
+    assert!(matches!(session.out_qos(msgs), QueueStatus::Ok(_)));
+    assert_eq!(payloads(downstream.try_recvs("test").take_values()), vec![0, 1, 2]);
+
+    session.state.rx_puback(&v5::Pub::new_pub_ack(1)).unwrap();
+    assert!(matches!(session.out_qos(Vec::new()), QueueStatus::Ok(_)));
+    assert_eq!(payloads(downstream.try_recvs("test").take_values()), vec![3, 4]);
+}
+
+#[test]
+fn test_session_max_packet_size() {
+    let poll = mio::Poll::new().unwrap();
+    let waker = Arc::new(mio::Waker::new(poll.registry(), mio::Token(0)).unwrap());
+    let client_id = ClientID("test-session-max-packet-size".to_string());
+
+    let mut connect = v5::Connect::default();
+    connect.properties = Some(v5::ConnectProperties {
+        receive_maximum: Some(4),
+        max_packet_size: Some(64),
+        ..v5::ConnectProperties::default()
+    });
+    let (mut session, downstream) = new_session_with(&waker, &client_id, 10013, connect);
+
+    use v5::QoS::{AtLeastOnce, AtMostOnce};
+    let payloads =
+        [(AtLeastOnce, 1), (AtLeastOnce, 100), (AtMostOnce, 100), (AtLeastOnce, 1)];
+    let msgs: Vec<Message> = payloads
+        .iter()
+        .map(|(qos, n)| {
+            let mut publish = new_publish(*qos, None);
+            publish.payload = Some(vec![0xAB; *n]);
+            let mut msg = Message::Routed {
+                src_shard_id: 0,
+                client_id: client_id.clone(),
+                inp_seqno: InpSeqno::from(1),
+                out_seqno: OutSeqno::default(),
+                publish,
+                ack_needed: true,
+            };
+            session.incr_out_seqno(&mut msg);
+            msg
+        })
+        .collect();
+
+    // oversized publishes are dropped, without consuming packet_id or quota.
+    assert!(matches!(session.out_qos(msgs), QueueStatus::Ok(_)));
+    let pkts = downstream.try_recvs("test").take_values();
+    let packet_ids: Vec<Option<PacketID>> = pkts
+        .into_iter()
+        .map(|pkt| match pkt {
+            v5::Packet::Publish(publish) => publish.packet_id,
+            pkt => panic!("unexpected {:?}", pkt),
+        })
+        .collect();
+    assert_eq!(packet_ids, vec![Some(1), Some(2)]);
+    assert_eq!(send_quota(&session), 2);
+    match &session.state {
+        SessionState::Active { n_oversized, back_log, .. } => {
+            assert_eq!(*n_oversized, 2);
+            assert!(back_log.is_empty());
+        }
+        ss => unreachable!("{:?}", ss),
+    }
+}
+
+#[test]
+fn test_session_server_keep_alive() {
+    use crate::broker::shard::test_util::new_active_shard;
+
+    let poll = mio::Poll::new().unwrap();
+    let waker = Arc::new(mio::Waker::new(poll.registry(), mio::Token(0)).unwrap());
+    let client_id = ClientID("test-session-server-keep-alive".to_string());
+    let (shard, _app_rx) = new_active_shard();
+
+    // (client keep-alive, server max keep-alive, advertised, read-timeout)
+    let testcases = [
+        (0, Some(60), Some(60), Some(90)),
+        (3600, Some(300), Some(300), Some(450)),
+        (30, Some(300), None, Some(45)),
+        (0, None, None, None),
+    ];
+    for (keep_alive, max, advertised, interval) in testcases.into_iter() {
+        let config = Config { mqtt_max_keep_alive: max, ..Config::default() };
+        let connect = v5::Connect { keep_alive, ..v5::Connect::default() };
+
+        let (miot_tx, _) = socket::pkt_channel(0, 16, Arc::clone(&waker), Arc::default());
+        let (_, session_rx) =
+            socket::pkt_channel(0, 16, Arc::clone(&waker), Arc::default());
+        let args = SessionArgs {
+            raddr: "127.0.0.1:10014".parse().unwrap(),
+            client_id: client_id.clone(),
+            shard_id: 0,
+            miot_tx,
+            session_rx,
+        };
+        let mut session = Session::start_active(args, config, &connect);
+        match &session.state {
+            SessionState::Active { keep_alive, .. } => {
+                assert_eq!(keep_alive.keep_alive(), interval)
+            }
+            ss => unreachable!("{:?}", ss),
+        }
+
+        let props = session.success_ack(&connect, None, &shard).properties.unwrap();
+        assert_eq!(props.server_keep_alive, advertised, "{} {:?}", keep_alive, max);
+    }
+}
+
+#[test]
+fn test_session_response_information() {
+    use crate::broker::shard::test_util::new_active_shard;
+
+    let poll = mio::Poll::new().unwrap();
+    let waker = Arc::new(mio::Waker::new(poll.registry(), mio::Token(0)).unwrap());
+    let client_id = ClientID("test-session-response-information".to_string());
+    let (shard, _app_rx) = new_active_shard();
+
+    let new_connect = |request_response_info| {
+        let properties = v5::ConnectProperties {
+            request_response_info,
+            ..v5::ConnectProperties::default()
+        };
+        v5::Connect {
+            properties: Some(properties),
+            ..v5::Connect::default()
+        }
+    };
+    let config = Config {
+        response_information: Some("response/".to_string()),
+        ..Config::default()
+    };
+
+    // (request_response_info, configured, expected)
+    let testcases = [
+        (Some(true), config.clone(), Some("response/".to_string())),
+        (Some(false), config.clone(), None),
+        (None, config.clone(), None),
+        (Some(true), Config::default(), None),
+    ];
+    for (rri, config, expected) in testcases.into_iter() {
+        let connect = new_connect(rri);
+        let (mut session, _) =
+            new_session_config(&waker, &client_id, 10015, connect.clone(), config);
+        let props = session.success_ack(&connect, None, &shard).properties.unwrap();
+        assert_eq!(props.response_information, expected, "{:?}", rri);
+    }
+}
+
+#[test]
+fn test_session_rx_pubrel() {
+    let poll = mio::Poll::new().unwrap();
+    let waker = Arc::new(mio::Waker::new(poll.registry(), mio::Token(0)).unwrap());
+    let client_id = ClientID("test-session-rx-pubrel".to_string());
+    let mut session = new_session(&waker, &client_id, 10015);
+
+    let inp_qos12 = |session: &Session| match &session.state {
+        SessionState::Active { inp_qos12, .. } => inp_qos12.clone(),
+        ss => unreachable!("{:?}", ss),
+    };
+    let pubcomp = |packet_id| v5::Packet::PubComp(v5::Pub::new_pub_comp(packet_id));
+
+    // PUBREL after PUBLISH releases the packet_id.
+    let publish = new_publish(v5::QoS::ExactlyOnce, Some(7));
+    session.state.book_qos(&publish).unwrap();
+    assert_eq!(inp_qos12(&session), vec![7]);
+    assert_eq!(session.state.rx_pubrel(7), pubcomp(7));
+    assert!(inp_qos12(&session).is_empty());
+
+    // duplicate PUBREL, and a bare PUBREL, are acknowledged with success.
+    assert_eq!(session.state.rx_pubrel(7), pubcomp(7));
+    assert_eq!(session.state.rx_pubrel(9), pubcomp(9));
+    match session.state.rx_pubrel(9) {
+        v5::Packet::PubComp(pubcomp) => assert_eq!(pubcomp.code, ReasonCode::Success),
+        pkt => panic!("unexpected {:?}", pkt),
+    }
+}
+
+#[test]
+fn test_session_backlog_policy() {
+    use crate::broker::BacklogPolicy;
+    use v5::QoS::{AtLeastOnce, AtMostOnce};
+
+    let poll = mio::Poll::new().unwrap();
+    let waker = Arc::new(mio::Waker::new(poll.registry(), mio::Token(0)).unwrap());
+    let client_id = ClientID("test-session-backlog-policy".to_string());
+
+    let new_msgs = |session: &mut Session| -> Vec<Message> {
+        let qoss =
+            [AtLeastOnce, AtMostOnce, AtMostOnce, AtLeastOnce, AtMostOnce, AtMostOnce];
+        qoss.iter()
+            .enumerate()
+            .map(|(i, qos)| {
+                let mut publish = new_publish(*qos, None);
+                publish.payload = Some(vec![i as u8]);
+                let mut msg = Message::Routed {
+                    src_shard_id: 0,
+                    client_id: client_id.clone(),
+                    inp_seqno: InpSeqno::from(1),
+                    out_seqno: OutSeqno::default(),
+                    publish,
+                    ack_needed: true,
+                };
+                session.incr_out_seqno(&mut msg);
+                msg
+            })
+            .collect()
+    };
+
+    // drop oldest QoS-0 messages, QoS-1 messages are retained.
+    let config = Config {
+        max_backlog_per_session: Some(4),
+        backlog_policy: BacklogPolicy::DropOldestQos0,
+        ..Config::default()
+    };
+    let connect = v5::Connect::default();
+    let (mut session, downstream) =
+        new_session_config(&waker, &client_id, 10015, connect.clone(), config);
+    let msgs = new_msgs(&mut session);
+    assert!(matches!(session.out_qos(msgs), QueueStatus::Ok(_)));
+    let payloads: Vec<(v5::QoS, u8)> = downstream
+        .try_recvs("test")
+        .take_values()
+        .into_iter()
+        .map(|pkt| match pkt {
+            v5::Packet::Publish(publish) => (publish.qos, publish.payload.unwrap()[0]),
+            pkt => panic!("unexpected {:?}", pkt),
+        })
+        .collect();
+    let refs = vec![(AtLeastOnce, 0), (AtLeastOnce, 3), (AtMostOnce, 4), (AtMostOnce, 5)];
+    assert_eq!(payloads, refs);
+
+    // QoS-1 messages alone exceed the limit.
+    let config = Config {
+        max_backlog_per_session: Some(1),
+        backlog_policy: BacklogPolicy::DropOldestQos0,
+        ..Config::default()
+    };
+    let (mut session, _downstream) =
+        new_session_config(&waker, &client_id, 10016, connect.clone(), config);
+    let msgs = new_msgs(&mut session);
+    assert!(matches!(session.out_qos(msgs), QueueStatus::Disconnected(_)));
+
+    // disconnect the client.
+    let config = Config {
+        max_backlog_per_session: Some(4),
+        backlog_policy: BacklogPolicy::DisconnectClient,
+        ..Config::default()
+    };
+    let (mut session, downstream) =
+        new_session_config(&waker, &client_id, 10017, connect, config);
+    let msgs = new_msgs(&mut session);
+    assert!(matches!(session.out_qos(msgs), QueueStatus::Disconnected(_)));
+    assert!(downstream.try_recvs("test").take_values().is_empty());
+}
+
+#[test]
+fn test_session_on_subscribe() {
+    use v5::{QoS, RetainForwardRule, SubscribeFilter, SubscriptionOpt};
+
+    let poll = mio::Poll::new().unwrap();
+    let waker = Arc::new(mio::Waker::new(poll.registry(), mio::Token(0)).unwrap());
+    let client_id = ClientID("test-session-on-subscribe".to_string());
+    let mut session = new_session(&waker, &client_id, 10018);
+
+    let trie = RetainedTrie::default();
+    for (topic, qos) in [("a/b", QoS::AtLeastOnce), ("a/c", QoS::AtMostOnce)] {
+        let mut publish = new_publish(qos, None);
+        publish.topic_name = TopicName::from(topic.to_string());
+        publish.retain = true;
+        trie.set(&publish.topic_name.clone(), publish);
+    }
+    let sys = TopicName::from("$SYS/uptime".to_string());
+    trie.set(&sys, new_publish(QoS::AtMostOnce, None));
+
+    let new_filter = |filter: &str, rfr| SubscribeFilter {
+        topic_filter: TopicFilter::from(filter.to_string()),
+        opt: SubscriptionOpt::new(rfr, false, false, QoS::AtLeastOnce),
+    };
+    let topics = |publishes: Vec<v5::Publish>| -> Vec<(String, QoS, bool)> {
+        let mut topics: Vec<(String, QoS, bool)> = publishes
+            .into_iter()
+            .map(|p| (p.topic_name.to_string(), p.qos, p.retain))
+            .collect();
+        topics.sort();
+        topics
+    };
+
+    // (rule, existed, expected)
+    let all = vec![
+        ("a/b".to_string(), QoS::AtLeastOnce, true),
+        ("a/c".to_string(), QoS::AtMostOnce, true),
+    ];
+    let testcases = [
+        (RetainForwardRule::OnEverySubscribe, false, all.clone()),
+        (RetainForwardRule::OnEverySubscribe, true, all.clone()),
+        (RetainForwardRule::OnNewSubscribe, false, all.clone()),
+        (RetainForwardRule::OnNewSubscribe, true, vec![]),
+        (RetainForwardRule::Never, false, vec![]),
+        (RetainForwardRule::Never, true, vec![]),
+    ];
+    for (rfr, existed, expected) in testcases.into_iter() {
+        let filter = new_filter("a/+", rfr.clone());
+        let publishes = session.on_subscribe(&filter, &trie, existed);
+        assert_eq!(topics(publishes), expected, "{:?} {}", rfr, existed);
+    }
+
+    // wildcard at the first level does not match `$SYS` topics.
+    let filter = new_filter("#", RetainForwardRule::OnEverySubscribe);
+    assert_eq!(topics(session.on_subscribe(&filter, &trie, false)), all);
+    let filter = new_filter("$SYS/#", RetainForwardRule::OnEverySubscribe);
+    let publishes = session.on_subscribe(&filter, &trie, false);
+    assert_eq!(publishes.len(), 1);
+    assert!(publishes[0].retain);
+}
+
+#[test]
+fn test_session_dedup_deliveries() {
+    use crate::VarU32;
+    use v5::{QoS, RetainForwardRule, SubscribeFilter, SubscriptionOpt};
+
+    let client_id = ClientID("test-session-dedup-deliveries".to_string());
+    let new_subscr = |filter: &str, qos, id| {
+        let filter = SubscribeFilter {
+            topic_filter: TopicFilter::from(filter.to_string()),
+            opt: SubscriptionOpt::new(RetainForwardRule::Never, false, false, qos),
+        };
+        let mut subscr = v5::Subscription::from_filter(&filter, client_id.clone(), 0);
+        subscr.subscription_id = id;
+        subscr
+    };
+
+    let mut publish = new_publish(QoS::ExactlyOnce, Some(1));
+    publish.topic_name = TopicName::from("a/b".to_string());
+
+    assert_eq!(Session::dedup_deliveries(vec![], &publish), None);
+
+    // one delivery, at the higher QoS, carrying both subscription ids.
+    let subs = vec![
+        new_subscr("a/#", QoS::AtMostOnce, Some(20)),
+        new_subscr("a/b", QoS::AtLeastOnce, Some(10)),
+    ];
+    let val = Session::dedup_deliveries(subs, &publish).unwrap();
+    assert_eq!(val.qos, QoS::AtLeastOnce);
+    assert_eq!(val.topic_name, publish.topic_name);
+    let ids = val.properties.unwrap().subscribtion_identifier;
+    assert_eq!(ids, vec![VarU32(10), VarU32(20)]);
+
+    // capped to the publish QoS, identifiers shared by filters are not repeated.
+    let mut publish = new_publish(QoS::AtMostOnce, None);
+    publish.topic_name = TopicName::from("a/b".to_string());
+    let subs = vec![
+        new_subscr("a/#", QoS::ExactlyOnce, Some(10)),
+        new_subscr("a/+", QoS::AtLeastOnce, Some(10)),
+        new_subscr("a/b", QoS::AtLeastOnce, None),
+    ];
+    let val = Session::dedup_deliveries(subs, &publish).unwrap();
+    assert_eq!(val.qos, QoS::AtMostOnce);
+    let ids = val.properties.unwrap().subscribtion_identifier;
+    assert_eq!(ids, vec![VarU32(10)]);
+}
+
+#[test]
+fn test_session_retransmit() {
+    let poll = mio::Poll::new().unwrap();
+    let waker = Arc::new(mio::Waker::new(poll.registry(), mio::Token(0)).unwrap());
+    let client_id = ClientID("test-session-retransmit".to_string());
+
+    let config = Config {
+        inflight_timeout: Some(10),
+        max_retransmits: 2,
+        ..Config::default()
+    };
+    let connect = v5::Connect::default();
+    let (mut session, downstream) =
+        new_session_config(&waker, &client_id, 10019, connect, config);
+
+    let msgs: Vec<Message> = (0..2)
+        .map(|_| {
+            let mut msg = Message::Routed {
+                src_shard_id: 0,
+                client_id: client_id.clone(),
+                inp_seqno: InpSeqno::from(1),
+                out_seqno: OutSeqno::default(),
+                publish: new_publish(v5::QoS::AtLeastOnce, None),
+                ack_needed: true,
+            };
+            session.incr_out_seqno(&mut msg);
+            msg
+        })
+        .collect();
+    let quota = send_quota(&session);
+    assert!(matches!(session.out_qos(msgs), QueueStatus::Ok(_)));
+    assert_eq!(downstream.try_recvs("test").take_values().len(), 2);
+
+    let recv_dups = || -> Vec<(Option<PacketID>, bool)> {
+        let pkts = downstream.try_recvs("test").take_values();
+        pkts.into_iter()
+            .map(|pkt| match pkt {
+                v5::Packet::Publish(publish) => (publish.packet_id, publish.duplicate),
+                pkt => panic!("unexpected {:?}", pkt),
+            })
+            .collect()
+    };
+
+    // nothing to re-deliver before the timeout.
+    let now = time::Instant::now();
+    assert!(matches!(session.retransmit(now), Ok(QueueStatus::Ok(_))));
+    assert!(recv_dups().is_empty());
+
+    // PUBACK for packet-1, un-acked packet-2 is re-delivered with DUP flag.
+    session.state.rx_puback(&v5::Pub::new_pub_ack(1));
+    let now = time::Instant::now() + time::Duration::from_secs(11);
+    assert!(matches!(session.retransmit(now), Ok(QueueStatus::Ok(_))));
+    assert_eq!(recv_dups(), vec![(Some(2), true)]);
+    assert_eq!(send_quota(&session), quota - 1);
+
+    // retransmit attempts are exhausted.
+    let now = time::Instant::now() + time::Duration::from_secs(22);
+    assert!(matches!(session.retransmit(now), Ok(QueueStatus::Ok(_))));
+    assert_eq!(recv_dups(), vec![(Some(2), true)]);
+    let now = time::Instant::now() + time::Duration::from_secs(33);
+    match session.retransmit(now) {
+        Err(err) => assert_eq!(err.kind(), ErrorKind::SlowClient),
+        Ok(_) => panic!("expected retransmits to be exhausted"),
+    }
+    assert!(recv_dups().is_empty());
+}
+
+#[test]
+fn test_session_receive_maximum() {
+    use crate::broker::shard::test_util::new_active_shard;
+
+    let poll = mio::Poll::new().unwrap();
+    let waker = Arc::new(mio::Waker::new(poll.registry(), mio::Token(0)).unwrap());
+    let client_id = ClientID("test-session-receive-maximum".to_string());
+
+    let (mut shard, _app_rx) = new_active_shard();
+    let (miot_tx, _downstream) =
+        socket::pkt_channel(0, 16, Arc::clone(&waker), Arc::default());
+    let (mut session_tx, session_rx) =
+        socket::pkt_channel(0, 16, Arc::clone(&waker), Arc::default());
+    let args = SessionArgs {
+        raddr: "127.0.0.1:10020".parse().unwrap(),
+        client_id: client_id.clone(),
+        shard_id: 0,
+        miot_tx,
+        session_rx,
+    };
+    let config = Config { mqtt_receive_maximum: 2, ..Config::default() };
+    let mut session = Session::start_active(args, config, &v5::Connect::default());
+
+    let props = session.success_ack(&v5::Connect::default(), None, &shard).properties;
+    assert_eq!(props.unwrap().receive_maximum, Some(2));
+    assert_eq!(session.to_receive_quota(), 2);
+
+    // client sends 3 QoS-1 PUBLISH, only 2 are read before the PUBACKs.
+    let pkts: Vec<v5::Packet> = (1..4)
+        .map(|id| v5::Packet::Publish(new_publish(v5::QoS::AtLeastOnce, Some(id))))
+        .collect();
+    assert!(matches!(session_tx.try_sends("test", pkts), QueueStatus::Ok(_)));
+
+    let (status, _) = session.route_packets(&mut shard).unwrap();
+    assert!(matches!(status, QueueStatus::Ok(_)));
+    assert_eq!(session.to_receive_quota(), 0);
+
+    let (status, _) = session.route_packets(&mut shard).unwrap();
+    assert!(matches!(status, QueueStatus::Block(_)));
+
+    // no subscribers, PUBACKs are queued right away, flushing them resumes reading
+    // the pending PUBLISH.
+    assert!(matches!(session.out_acks_flush(), QueueStatus::Ok(_)));
+    assert_eq!(session.to_receive_quota(), 2);
+
+    let (status, _) = session.route_packets(&mut shard).unwrap();
+    assert!(matches!(status, QueueStatus::Ok(_)));
+    assert_eq!(session.to_receive_quota(), 1);
+    let (status, _) = session.route_packets(&mut shard).unwrap();
+    assert!(matches!(status, QueueStatus::Block(_)));
+}
+
+#[test]
+fn test_session_receive_maximum_qos2() {
+    use crate::broker::shard::test_util::new_active_shard;
+
+    let poll = mio::Poll::new().unwrap();
+    let waker = Arc::new(mio::Waker::new(poll.registry(), mio::Token(0)).unwrap());
+    let client_id = ClientID("test-session-receive-maximum-qos2".to_string());
+
+    let (mut shard, _app_rx) = new_active_shard();
+    let (miot_tx, _downstream) =
+        socket::pkt_channel(0, 16, Arc::clone(&waker), Arc::default());
+    let (mut session_tx, session_rx) =
+        socket::pkt_channel(0, 16, Arc::clone(&waker), Arc::default());
+    let args = SessionArgs {
+        raddr: "127.0.0.1:10023".parse().unwrap(),
+        client_id: client_id.clone(),
+        shard_id: 0,
+        miot_tx,
+        session_rx,
+    };
+    let config = Config { mqtt_receive_maximum: 2, ..Config::default() };
+    let mut session = Session::start_active(args, config, &v5::Connect::default());
+
+    // client has 2 QoS-2 PUBLISH waiting for PUBREL, reaching the receive-maximum.
+    for id in 1..3 {
+        let publish = new_publish(v5::QoS::ExactlyOnce, Some(id));
+        session.state.book_qos(&publish).unwrap();
+    }
+    assert_eq!(session.to_receive_quota(), 0);
+
+    // PUBRELs, and the PUBLISH after them, are pending. PUBRELs are still read and
+    // release the packet-ids, PUBLISH is held back till the next read.
+    let pubrel = |packet_id| v5::Pub {
+        packet_type: v5::PacketType::PubRel,
+        packet_id,
+        code: ReasonCode::Success,
+        properties: None,
+    };
+    let mut pkts: Vec<v5::Packet> =
+        (1..3).map(|id| v5::Packet::PubRel(pubrel(id))).collect();
+    pkts.push(v5::Packet::Publish(new_publish(v5::QoS::AtLeastOnce, Some(3))));
+    assert!(matches!(session_tx.try_sends("test", pkts), QueueStatus::Ok(_)));
+    let (status, _) = session.route_packets(&mut shard).unwrap();
+    assert!(matches!(status, QueueStatus::Ok(_)));
+    assert_eq!(session.to_receive_quota(), 2);
+
+    let (status, _) = session.route_packets(&mut shard).unwrap();
+    assert!(matches!(status, QueueStatus::Ok(_)));
+    assert_eq!(session.to_receive_quota(), 1);
+    let (status, _) = session.route_packets(&mut shard).unwrap();
+    assert!(matches!(status, QueueStatus::Block(_)));
+}
+
+#[test]
+fn test_session_forward_user_properties() {
+    use crate::broker::shard::test_util::{new_active_shard, take_routed};
+    use crate::{UserProperty, VarU32};
+
+    let poll = mio::Poll::new().unwrap();
+    let waker = Arc::new(mio::Waker::new(poll.registry(), mio::Token(0)).unwrap());
+    let client_id = ClientID("test-session-forward-user-properties".to_string());
+
+    let (mut shard, _app_rx) = new_active_shard();
+    let topic_filter = TopicFilter::from("a/b/#".to_string());
+    let subscription = v5::Subscription {
+        topic_filter: topic_filter.clone(),
+        client_id: ClientID("test-subscriber".to_string()),
+        shard_id: 1,
+        subscription_id: Some(7),
+        qos: v5::QoS::AtMostOnce,
+        no_local: false,
+        retain_as_published: false,
+        retain_forward_rule: v5::RetainForwardRule::OnEverySubscribe,
+    };
+    shard.as_topic_filters().subscribe(&topic_filter, subscription);
+
+    let user_props = vec![UserProperty("k".to_string(), "v".to_string())];
+    let testcases = [(true, user_props.clone(), 10021), (false, vec![], 10022)];
+    for (forward, routed_props, port) in testcases.into_iter() {
+        let mut session = new_session(&waker, &client_id, port);
+        session.config = Config {
+            forward_user_properties: forward,
+            ..Config::default()
+        };
+
+        let mut publish = new_publish(v5::QoS::AtMostOnce, None);
+        publish.properties = Some(v5::PublishProperties {
+            user_properties: user_props.clone(),
+            ..v5::PublishProperties::default()
+        });
+        assert!(session.rx_publish(&mut shard, publish).unwrap().is_none());
+
+        match take_routed(&mut shard, 1).as_slice() {
+            [Message::Routed { publish, .. }] => {
+                let props = publish.properties.as_ref().unwrap();
+                assert_eq!(props.user_properties, routed_props, "{}", forward);
+                // subscription-identifier, added by the broker, is retained.
+                assert_eq!(props.subscribtion_identifier, vec![VarU32(7)]);
+            }
+            msgs => panic!("unexpected {:?}", msgs),
+        }
+
+        // retained messages delivered on SUBSCRIBE follow the same rule.
+        let trie = RetainedTrie::default();
+        let mut retain = new_publish(v5::QoS::AtMostOnce, None);
+        retain.retain = true;
+        retain.properties = Some(v5::PublishProperties {
+            user_properties: user_props.clone(),
+            ..v5::PublishProperties::default()
+        });
+        trie.set(&retain.topic_name.clone(), retain);
+        let filter = v5::SubscribeFilter {
+            topic_filter: topic_filter.clone(),
+            opt: v5::SubscriptionOpt::new(
+                v5::RetainForwardRule::OnEverySubscribe,
+                false,
+                false,
+                v5::QoS::AtMostOnce,
+            ),
+        };
+        match session.on_subscribe(&filter, &trie, false).as_slice() {
+            [publish] => {
+                let props = publish.properties.as_ref().unwrap();
+                assert_eq!(props.user_properties, routed_props, "{}", forward);
+            }
+            publishes => panic!("unexpected {:?}", publishes),
+        }
+    }
+}
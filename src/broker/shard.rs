@@ -1,20 +1,27 @@
 use log::{debug, error, info, trace};
 use uuid::Uuid;
 
-use std::{cmp, collections::BTreeMap, fmt, mem, result, sync::Arc};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering::SeqCst};
+use std::sync::Arc;
+use std::{
+    cmp,
+    collections::{BTreeMap, BTreeSet},
+    fmt, mem, result, time,
+};
 
 use crate::broker::thread::{Rx, Thread, Threadable, Tx};
 use crate::broker::{message, session, socket};
 use crate::broker::{AppTx, Config, RetainedTrie, Session, Shardable, SubscribedTrie};
-use crate::broker::{Cluster, Flusher, Message, Miot, MsgRx, QueueStatus, Socket};
-use crate::broker::{InpSeqno, OutSeqno, Timestamp};
+use crate::broker::{AtomicStats, ClientInfo, Timestamp, Work, WorkQueue};
+use crate::broker::{BrokerEvent, Cluster, Flusher, Message, Miot, MsgRx, QueueStatus};
+use crate::broker::{ConnGuard, InpSeqno, OutSeqno, Seqno, ShardMetrics, Socket};
 
-use crate::{v5, ClientID, ToJson, TopicName};
+use crate::{v5, ClientID, TimeoutValue, Timer, ToJson, TopicName};
 use crate::{Error, ErrorKind, ReasonCode, Result};
 
 type ThreadRx = Rx<Request, Result<Response>>;
 type QueueReq = crate::broker::thread::QueueReq<Request, Result<Response>>;
-type Acks = BTreeMap<u32, InpSeqno>;
+type Acks = BTreeMap<u32, Vec<InpSeqno>>;
 
 macro_rules! append_index {
     ($index:expr, $key:expr, $val:expr) => {{
@@ -92,12 +99,24 @@ pub struct ActiveLoop {
     /// Inner::Handle to corresponding miot-thread. Shall be dropped after close_wait
     /// call, when the thread returns, will point to Inner::Init.
     miot: Miot,
+    /// Bytes read from sockets and not yet received by sessions, shared by all the
+    /// connections in this shard. Refer to [Config::shard_max_buffer_bytes].
+    buffered: Arc<AtomicUsize>,
 
     /// Collection of sessions and corresponding clients managed by this shard. Shall be
     /// dropped after close_wait call, when the thread returns it will be empty.
     sessions: BTreeMap<ClientID, Session>,
-    /// Monotonically increasing `seqno`, starting from 1, that is bumped up for every
-    /// incoming PUBLISH (QoS-1 & 2) packet.
+    /// Collection of sessions whose client has disconnected. Session state is held
+    /// here, until the client reconnects with `clean_start` as false.
+    reconnects: BTreeMap<ClientID, Session>,
+    /// Will messages, from disconnected clients, waiting for will-delay-interval to
+    /// elapse before they are published.
+    will_timer: Timer<Arc<Will>>,
+    /// Index of pending will messages, an entry is removed when the will message is
+    /// either published or cancelled.
+    wills: BTreeMap<ClientID, Arc<Will>>,
+    /// Last allocated `seqno`, monotonically increasing from 1, that is bumped up for
+    /// every incoming PUBLISH (QoS-1 & 2) packet.
     inp_seqno: InpSeqno,
     /// Back log of messages that needs to be flushed to other local-shards.
     /// Message::Routed and Message::LocalAck shall first land here, the order of the
@@ -114,7 +133,8 @@ pub struct ActiveLoop {
     ///
     /// While routing the messages and pushing them in target's shards msg-queue, for
     /// each message, [Timestamp::last_routed] for [Timestamp::shard_id] value is
-    /// updated with InpSeqno.
+    /// updated with InpSeqno. QoS-0 messages that need no acknowledgement take the
+    /// fast path and are not tracked here.
     ///
     /// For every incoming Message::LocalAck, [Timestamp::last_acked] for
     /// [Timestamp::shard_id] matching [Message::LocalAck::shard_id] is updated with
@@ -127,6 +147,12 @@ pub struct ActiveLoop {
     ///   removed from `index` and session book-keeping. ACK is sent back to publishing
     ///   client.
     ack_timestamps: Vec<Timestamp>,
+    /// For each shard, messages routed from that shard and acknowledged by this
+    /// shard. Highest contiguous InpSeqno is periodically published as
+    /// Message::LocalAck, refer [LocalAcks].
+    local_acks: BTreeMap<u32, LocalAcks>,
+    /// Instant at which Message::LocalAck was last published to other shards.
+    local_ack_instant: time::Instant,
 
     /// Corresponding MsgTx handle for all other shards, as Shard::MsgTx,
     shard_queues: BTreeMap<u32, Shard>,
@@ -134,6 +160,9 @@ pub struct ActiveLoop {
     topic_filters: SubscribedTrie,
     /// MVCC clone of Cluster::retained_messages
     retained_messages: RetainedTrie,
+    /// Work queue for routable work, shared with other shards for stealing. Refer to
+    /// [Config::enable_work_stealing].
+    work_queue: Option<WorkQueue>,
 
     /// statistics
     stats: Stats,
@@ -179,6 +208,12 @@ pub struct FinState {
 pub struct Stats {
     pub n_events: usize,
     pub n_requests: usize,
+    /// Number of packets received from clients.
+    pub n_packets: usize,
+    /// Number of times an outbound queue, to a client or a shard, was full.
+    pub n_queue_blocks: usize,
+    /// Number of connections dropped because their queue was disconnected.
+    pub n_queue_drops: usize,
 }
 
 impl FinState {
@@ -255,6 +290,8 @@ pub struct SpawnArgs {
     pub flusher: Flusher,
     pub topic_filters: SubscribedTrie,
     pub retained_messages: RetainedTrie,
+    pub atomic_stats: Arc<AtomicStats>,
+    pub work_queue: Option<WorkQueue>,
 }
 
 impl Shard {
@@ -300,16 +337,23 @@ impl Shard {
                 cluster: Box::new(args.cluster),
                 flusher: args.flusher,
                 miot: Miot::default(),
+                buffered: Arc::new(AtomicUsize::new(0)),
 
                 sessions: BTreeMap::default(),
-                inp_seqno: 1,
+                reconnects: BTreeMap::default(),
+                will_timer: Timer::default(),
+                wills: BTreeMap::default(),
+                inp_seqno: InpSeqno::default(),
                 shard_back_log: BTreeMap::default(),
                 index: BTreeMap::default(),
                 ack_timestamps: Vec::default(),
+                local_acks: BTreeMap::default(),
+                local_ack_instant: time::Instant::now(),
 
                 shard_queues: BTreeMap::default(),
                 topic_filters: args.topic_filters,
                 retained_messages: args.retained_messages,
+                work_queue: args.work_queue,
 
                 stats: Stats::default(),
 
@@ -334,7 +378,7 @@ impl Shard {
             let (config, miot_id) = (self.config.clone(), self.shard_id);
             let miot = {
                 let miot = Miot::from_config(config, miot_id)?;
-                miot.spawn(shard.to_tx("miot"), app_tx.clone())?
+                miot.spawn(shard.to_tx("miot"), app_tx.clone(), args.atomic_stats)?
             };
             match &shard.inner {
                 Inner::Handle(Handle { thrd, .. }) => {
@@ -438,16 +482,29 @@ pub enum Request {
     AddSession(AddSessionArgs),
     FlushConnection { socket: Socket, err: Option<Error> },
     SendMessages { msgs: Vec<Message> },
+    PublishSys { publishes: Vec<v5::Publish> },
+    Metrics,
+    Clients,
+    SlowSubscribers { threshold: usize },
+    PendingWills,
+    CancelWill { client_id: ClientID },
+    Drain { deadline: time::Duration },
     Close,
 }
 
 pub enum Response {
     Ok,
+    Metrics(ShardMetrics),
+    Clients(Vec<ClientInfo>),
+    SlowSubscribers(Vec<ClientID>),
+    PendingWills(Vec<(ClientID, time::Instant)>),
+    CancelWill(bool),
 }
 
 pub struct AddSessionArgs {
     pub sock: mio::net::TcpStream,
     pub pkt: v5::Connect,
+    pub guard: Option<ConnGuard>,
 }
 
 // calls to interface with shard-thread.
@@ -476,6 +533,7 @@ impl Shard {
                 let req = Request::AddSession(args);
                 match thrd.request(req)?? {
                     Response::Ok => Ok(()),
+                    _ => unreachable!(),
                 }
             }
             _ => unreachable!(),
@@ -500,6 +558,105 @@ impl Shard {
         }
     }
 
+    /// Route broker originated `$SYS` publishes to matching subscribers.
+    pub fn publish_sys(&self, publishes: Vec<v5::Publish>) -> Result<()> {
+        match &self.inner {
+            Inner::Handle(Handle { thrd, .. }) => {
+                thrd.post(Request::PublishSys { publishes })?;
+                Ok(())
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Return a snapshot of this shard's statistics.
+    pub fn to_metrics(&self) -> Result<ShardMetrics> {
+        match &self.inner {
+            Inner::Handle(Handle { thrd, .. }) => {
+                match thrd.request(Request::Metrics)?? {
+                    Response::Metrics(metrics) => Ok(metrics),
+                    _ => unreachable!(),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Return a snapshot of clients connected to this shard.
+    pub fn clients(&self) -> Result<Vec<ClientInfo>> {
+        match &self.inner {
+            Inner::Handle(Handle { thrd, .. }) => {
+                match thrd.request(Request::Clients)?? {
+                    Response::Clients(clients) => Ok(clients),
+                    _ => unreachable!(),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Return clients whose outgoing back-log is longer than `threshold` messages,
+    /// refer [ClientOut::current_backlog_len].
+    ///
+    /// [ClientOut::current_backlog_len]: crate::broker::ClientOut::current_backlog_len
+    pub fn slow_subscribers(&self, threshold: usize) -> Result<Vec<ClientID>> {
+        match &self.inner {
+            Inner::Handle(Handle { thrd, .. }) => {
+                let req = Request::SlowSubscribers { threshold };
+                match thrd.request(req)?? {
+                    Response::SlowSubscribers(client_ids) => Ok(client_ids),
+                    _ => unreachable!(),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Return will messages scheduled for delivery, along with the instant at
+    /// which they shall be published, in client-id order.
+    pub fn pending_wills(&self) -> Result<Vec<(ClientID, time::Instant)>> {
+        match &self.inner {
+            Inner::Handle(Handle { thrd, .. }) => {
+                match thrd.request(Request::PendingWills)?? {
+                    Response::PendingWills(wills) => Ok(wills),
+                    _ => unreachable!(),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Cancel the pending will message for `client_id`, return whether there was
+    /// one. A cancelled will message is never published.
+    pub fn cancel_will(&self, client_id: &ClientID) -> Result<bool> {
+        match &self.inner {
+            Inner::Handle(Handle { thrd, .. }) => {
+                let req = Request::CancelWill { client_id: client_id.clone() };
+                match thrd.request(req)?? {
+                    Response::CancelWill(ok) => Ok(ok),
+                    _ => unreachable!(),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Flush pending packets to all the clients connected to this shard and
+    /// disconnect them with `ServerShutdown`. Return once all the connections are
+    /// flushed or `deadline` has elapsed.
+    pub fn drain(&self, deadline: time::Duration) -> Result<()> {
+        match &self.inner {
+            Inner::Handle(Handle { thrd, .. }) => {
+                let req = Request::Drain { deadline };
+                match thrd.request(req)?? {
+                    Response::Ok => Ok(()),
+                    _ => unreachable!(),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
     pub fn close_wait(mut self) -> Shard {
         let inner = mem::replace(&mut self.inner, Inner::Init);
         match inner {
@@ -531,7 +688,6 @@ impl Threadable for Shard {
 impl Shard {
     fn active_loop(mut self, rx: ThreadRx) -> Self {
         use crate::broker::POLL_EVENTS_SIZE;
-        use std::time;
 
         info!("{} spawn config:{}", self.prefix, self.to_config_json());
 
@@ -563,12 +719,14 @@ impl Shard {
             // This is where we do routing for all packets received from all session/sock
             // owned by this shard.
             let ack_out_seqnos = self.route_packets();
+            // Route work from this shard's work-queue, or steal from other shards.
+            self.route_works();
 
             // Other shards might have routed messages to a session owned by this shard,
             // we will handle it here and push them down to the socket.
-            let mut qos_acks = BTreeMap::<u32, InpSeqno>::default();
+            let mut qos_acks = Acks::default();
             let mut status = self.out_messages(&msg_rx, &mut qos_acks);
-            let qos_msgs = status.take_values(); // QoS-1 and QoS2 messages.
+            let qos_msgs = status.take_values(); // in arrival order, for all QoS.
             if let QueueStatus::Disconnected(_) = status {
                 error!("{:?} cascading shutdown via out_messages", self.prefix);
                 break;
@@ -585,6 +743,14 @@ impl Shard {
             self.out_acks_flush();
             self.return_local_acks(qos_acks);
 
+            // Ticker shall periodically wake up the shard, publish local acks, reap
+            // expired sessions, publish will messages whose will-delay-interval
+            // has elapsed and re-deliver timed-out inflight messages.
+            self.publish_local_acks(time::Instant::now());
+            self.expire_sessions();
+            self.will_expires();
+            self.retransmit_inflight(time::Instant::now());
+
             // wake up miot every time shard wakes up
             self.as_miot().wake()
         }
@@ -674,6 +840,14 @@ impl Shard {
                     let resp = self.handle_set_shard_queues(req);
                     err!(IPCFail, try: tx.send(Ok(resp))).ok();
                 }
+                (req @ AddSession { .. }, Some(tx)) if self.is_duplicate(&req) => {
+                    let res = self.reject_duplicate(req);
+                    err!(IPCFail, try: tx.send(res)).ok();
+                }
+                (req @ AddSession { .. }, Some(tx)) if self.is_full(&req) => {
+                    let res = self.refuse_session(req);
+                    err!(IPCFail, try: tx.send(res)).ok();
+                }
                 (req @ AddSession { .. }, Some(tx)) => {
                     let resp = self.handle_add_session(req);
                     err!(IPCFail, try: tx.send(Ok(resp))).ok();
@@ -681,6 +855,35 @@ impl Shard {
                 (req @ FlushConnection { .. }, None) => {
                     self.handle_flush_connection(req);
                 }
+                (PublishSys { publishes }, None) => {
+                    for publish in publishes.into_iter() {
+                        self.queue_publish(publish, None);
+                    }
+                }
+                (Metrics, Some(tx)) => {
+                    let resp = Response::Metrics(self.to_shard_metrics());
+                    err!(IPCFail, try: tx.send(Ok(resp))).ok();
+                }
+                (Clients, Some(tx)) => {
+                    let resp = self.handle_clients();
+                    err!(IPCFail, try: tx.send(Ok(resp))).ok();
+                }
+                (req @ SlowSubscribers { .. }, Some(tx)) => {
+                    let resp = self.handle_slow_subscribers(req);
+                    err!(IPCFail, try: tx.send(Ok(resp))).ok();
+                }
+                (PendingWills, Some(tx)) => {
+                    let resp = self.handle_pending_wills();
+                    err!(IPCFail, try: tx.send(Ok(resp))).ok();
+                }
+                (req @ CancelWill { .. }, Some(tx)) => {
+                    let resp = self.handle_cancel_will(req);
+                    err!(IPCFail, try: tx.send(Ok(resp))).ok();
+                }
+                (req @ Drain { .. }, Some(tx)) => {
+                    let resp = self.handle_drain(req);
+                    err!(IPCFail, try: tx.send(Ok(resp))).ok();
+                }
                 (req @ Close, Some(tx)) => {
                     let resp = self.handle_close(req);
                     err!(IPCFail, try: tx.send(Ok(resp))).ok();
@@ -700,10 +903,9 @@ impl Shard {
     // For each session, convert incoming packets to messages and route them to other
     // sessions/bridges.
     fn route_packets(&mut self) -> BTreeMap<ClientID, Vec<OutSeqno>> {
-        let mut inner = mem::replace(&mut self.inner, Inner::Init);
-
-        let ActiveLoop { sessions, .. } = match &mut inner {
-            Inner::MainActive(active_loop) => active_loop,
+        // sessions shall access the shard, like topic_filters, while routing packets.
+        let mut sessions = match &mut self.inner {
+            Inner::MainActive(ActiveLoop { sessions, .. }) => mem::take(sessions),
             _ => unreachable!(),
         };
 
@@ -718,6 +920,7 @@ impl Shard {
                         "{} raddr:{} disconnected connection",
                         self.prefix, session.raddr
                     );
+                    self.as_mut_stats().n_queue_drops += 1;
                     let err: Result<()> = err!(Disconnected, desc: "{}", self.prefix);
                     failed_sessions.push((client_id.clone(), err.unwrap_err()));
                     out_seqnos
@@ -726,7 +929,15 @@ impl Shard {
                     failed_sessions.push((client_id.clone(), err));
                     Vec::default()
                 }
-                Err(err) if err.kind() == ErrorKind::ProtocolError => {
+                Err(err)
+                    if err.kind() == ErrorKind::ProtocolError
+                        || err.kind() == ErrorKind::MalformedPacket =>
+                {
+                    let code = v5::DisconnReasonCode::from_error(&err);
+                    let status = session.protocol_disconnect(code);
+                    if let QueueStatus::Disconnected(_) = status {
+                        error!("{} fail sending DISCONNECT {:?}", self.prefix, code);
+                    }
                     failed_sessions.push((client_id.clone(), err));
                     Vec::default()
                 }
@@ -735,8 +946,10 @@ impl Shard {
             ack_out_seqnos.insert(client_id.clone(), out_seqnos);
         }
 
-        mem::drop(sessions);
-        let _init = mem::replace(&mut self.inner, inner);
+        match &mut self.inner {
+            Inner::MainActive(active_loop) => active_loop.sessions = sessions,
+            _ => unreachable!(),
+        }
 
         for (client_id, err) in failed_sessions {
             let miot = self.as_mut_miot();
@@ -755,7 +968,8 @@ impl Shard {
 
     // Flush outgoing messages, in `shard_back_log` from this shard to other shards.
     fn send_to_shards(&mut self) {
-        let ActiveLoop { shard_back_log, shard_queues, .. } = match &mut self.inner {
+        let ActiveLoop { shard_back_log, shard_queues, stats, .. } = match &mut self.inner
+        {
             Inner::MainActive(active_loop) => active_loop,
             _ => unreachable!(),
         };
@@ -763,13 +977,22 @@ impl Shard {
         let back_log = mem::replace(shard_back_log, BTreeMap::default());
         for (shard_id, msgs) in back_log.into_iter() {
             let shard = shard_queues.get_mut(&shard_id).unwrap();
+            for msg in msgs.iter() {
+                trace!(
+                    "{} shard_id:{} send msg:{}",
+                    self.prefix,
+                    shard_id,
+                    msg.summary()
+                );
+            }
 
             let mut status = shard.send_messages(msgs);
             // re-index the remaining messages, may be the other shard is busy.
             shard_back_log.insert(shard_id, status.take_values());
 
             match status {
-                QueueStatus::Ok(_) | QueueStatus::Block(_) => (),
+                QueueStatus::Ok(_) => (),
+                QueueStatus::Block(_) => stats.n_queue_blocks += 1,
                 QueueStatus::Disconnected(_) => {
                     // TODO: should this be logged at error-level
                     error!(
@@ -784,93 +1007,74 @@ impl Shard {
 
 // Handle out-going messages
 impl Shard {
-    // Receive messages for this shard, flush qos0 messages downstream and return
-    // qos12_msgs
+    // Receive messages for this shard and return them in their arrival order. QoS-0
+    // messages are returned along with QoS-1 & QoS-2 messages, so that they are
+    // delivered to subscribers in the same order.
     fn out_messages(&mut self, msg_rx: &MsgRx, acks: &mut Acks) -> QueueStatus<Message> {
         // receive messages targeting all the sessions.
         let mut status = msg_rx.try_recvs();
 
-        let mut qos0_msgs = BTreeMap::<ClientID, Vec<Message>>::default();
-        let mut qos12_msgs: Vec<Message> = Vec::default();
+        let mut out_msgs: Vec<Message> = Vec::default();
         for mut msg in status.take_values().into_iter() {
-            let ActiveLoop { sessions, .. } = match &mut self.inner {
-                Inner::MainActive(active_loop) => active_loop,
-                _ => unreachable!(),
-            };
-            match sessions.get_mut(msg.as_client_id()) {
-                Some(session) => session.incr_out_seqno(&mut msg),
-                None => unreachable!(),
+            trace!("{} recv msg:{}", self.prefix, msg.summary());
+            if let Message::LocalAck { shard_id, last_acked } = &msg {
+                self.book_acked_timestamps(*shard_id, *last_acked);
+                continue;
             }
 
-            match &msg {
-                Message::LocalAck { shard_id, last_acked } => {
-                    self.book_acked_timestamps(*shard_id, *last_acked);
-                    continue;
+            let ActiveLoop { sessions, reconnects, local_acks, .. } =
+                match &mut self.inner {
+                    Inner::MainActive(active_loop) => active_loop,
+                    _ => unreachable!(),
+                };
+            if let Message::Routed {
+                src_shard_id, inp_seqno, publish, ack_needed, ..
+            } = &msg
+            {
+                if *ack_needed || publish.qos != v5::QoS::AtMostOnce {
+                    let acks = local_acks.entry(*src_shard_id).or_default();
+                    acks.received(*inp_seqno);
+                }
+            }
+            let client_id = msg.as_client_id();
+            let offline = match sessions.get_mut(client_id) {
+                Some(session) => {
+                    session.incr_out_seqno(&mut msg);
+                    false
                 }
+                None => match reconnects.get_mut(client_id) {
+                    Some(session) => {
+                        session.incr_out_seqno(&mut msg);
+                        true
+                    }
+                    None => unreachable!(),
+                },
+            };
+
+            match &msg {
                 Message::Routed {
-                    src_shard_id,
-                    client_id,
-                    inp_seqno,
-                    publish,
-                    ack_needed,
-                    ..
+                    src_shard_id, inp_seqno, publish, ack_needed, ..
                 } => {
                     if publish.qos == v5::QoS::AtMostOnce {
                         if *ack_needed {
-                            acks.insert(*src_shard_id, *inp_seqno);
+                            append_index!(acks, *src_shard_id, *inp_seqno);
+                        }
+                        // QoS-0 messages are not queued for disconnected clients.
+                        if !offline {
+                            out_msgs.push(msg);
                         }
-                        append_index!(qos0_msgs, client_id.clone(), msg);
                     } else {
-                        qos12_msgs.push(msg)
+                        out_msgs.push(msg)
                     }
                 }
                 _ => unreachable!(),
             };
         }
 
-        self.out_qos0(qos0_msgs);
-
-        status.set_values(qos12_msgs);
+        status.set_values(out_msgs);
         status
     }
 
-    fn out_qos0(&mut self, qos0_msgs: BTreeMap<ClientID, Vec<Message>>) {
-        let sessions = match &mut self.inner {
-            Inner::MainActive(ActiveLoop { sessions, .. }) => sessions,
-            _ => unreachable!(),
-        };
-
-        let mut disconnecteds: Vec<ClientID> = vec![];
-        for (client_id, msgs) in qos0_msgs.into_iter() {
-            match sessions.get_mut(&client_id) {
-                Some(session) => {
-                    if let QueueStatus::Disconnected(_) = session.out_qos0(msgs) {
-                        disconnecteds.push(client_id)
-                    }
-                }
-                None => error!(
-                    "{} client_id:{} msg-rx session is gone",
-                    self.prefix, *client_id
-                ),
-            }
-        }
-
-        // drop slow connections.
-        for client_id in disconnecteds.into_iter() {
-            let ActiveLoop { miot, .. } = match &mut self.inner {
-                Inner::MainActive(active_loop) => active_loop,
-                _ => unreachable!(),
-            };
-
-            let res = allow_panic!(&self, miot.remove_connection(&client_id));
-            if let Some(socket) = res {
-                let err: Result<()> = err!(SlowClient, code: UnspecifiedError, "");
-                let req = Request::FlushConnection { socket, err: err.err() };
-                self.handle_flush_connection(req);
-            }
-        }
-    }
-
     // replicated messages comming from consensus loop, commit them.
     // acknowldegments for outgoing PUBLISH QoS-1 & QoS-2
     fn commit_acks(&mut self, ack_out_seqnos: BTreeMap<ClientID, Vec<OutSeqno>>) {
@@ -880,9 +1084,9 @@ impl Shard {
         };
 
         for (client_id, out_seqnos) in ack_out_seqnos.into_iter() {
-            match sessions.get_mut(&client_id) {
-                Some(session) => session.commit_acks(out_seqnos),
-                None => unreachable!(),
+            // session is gone, if client disconnected while routing packets.
+            if let Some(session) = sessions.get_mut(&client_id) {
+                session.commit_acks(out_seqnos)
             }
         }
     }
@@ -896,20 +1100,23 @@ impl Shard {
     }
 
     // replicated messages comming from consensus loop, commit them.
-    // Message::Routed, QoS-1 & QoS-2
+    // Message::Routed, QoS-1 & QoS-2, and QoS-0 that are not replicated.
     fn commit_messages_active(&mut self, msgs: Vec<Message>, acks: &mut Acks) {
         let mut qos_msgs = BTreeMap::<ClientID, Vec<Message>>::default();
         for msg in msgs.into_iter() {
             match &msg {
+                Message::Routed { client_id, .. } if msg.is_qos0() => {
+                    append_index!(qos_msgs, client_id.clone(), msg);
+                }
                 Message::Routed { src_shard_id, client_id, inp_seqno, .. } => {
-                    acks.insert(*src_shard_id, *inp_seqno);
+                    append_index!(acks, *src_shard_id, *inp_seqno);
                     append_index!(qos_msgs, client_id.clone(), msg);
                 }
                 _ => unreachable!(),
             }
         }
 
-        let ActiveLoop { sessions, .. } = match &mut self.inner {
+        let ActiveLoop { sessions, reconnects, stats, .. } = match &mut self.inner {
             Inner::MainActive(active_loop) => active_loop,
             _ => unreachable!(),
         };
@@ -918,10 +1125,19 @@ impl Shard {
         for (client_id, msgs) in qos_msgs.into_iter() {
             match sessions.get_mut(&client_id) {
                 Some(session) => match session.out_qos(msgs) {
-                    QueueStatus::Disconnected(_) => disconnecteds.push(client_id.clone()),
-                    _ => (),
+                    QueueStatus::Ok(_) => (),
+                    QueueStatus::Block(_) => stats.n_queue_blocks += 1,
+                    QueueStatus::Disconnected(_) => {
+                        stats.n_queue_drops += 1;
+                        disconnecteds.push(client_id.clone())
+                    }
+                },
+                None => match reconnects.get_mut(&client_id) {
+                    Some(session) => {
+                        session.out_qos(msgs);
+                    }
+                    None => unreachable!(),
                 },
-                None => unreachable!(),
             }
         }
 
@@ -936,7 +1152,7 @@ impl Shard {
 
             let res = allow_panic!(&self, miot.remove_connection(&client_id));
             if let Some(socket) = res {
-                let err: Result<()> = err!(SlowClient, code: UnspecifiedError, "");
+                let err: Result<()> = err!(SlowClient, code: QuotaExceeded, "");
                 let req = Request::FlushConnection { socket, err: err.err() };
                 self.handle_flush_connection(req);
             }
@@ -969,16 +1185,49 @@ impl Shard {
         }
     }
 
-    fn return_local_acks(&mut self, qos_acks: BTreeMap<u32, InpSeqno>) {
-        let ActiveLoop { shard_back_log, .. } = match &mut self.inner {
+    fn return_local_acks(&mut self, qos_acks: Acks) {
+        let ActiveLoop { local_acks, .. } = match &mut self.inner {
             Inner::MainActive(active_loop) => active_loop,
             _ => unreachable!(),
         };
 
+        for (target_shard_id, inp_seqnos) in qos_acks.into_iter() {
+            let acks = local_acks.entry(target_shard_id).or_default();
+            for inp_seqno in inp_seqnos.into_iter() {
+                acks.acked(inp_seqno);
+            }
+        }
+    }
+
+    // Publish Message::LocalAck to other shards, once every `local_ack_interval_ms`,
+    // only if the contiguous acknowledged InpSeqno has advanced since last publish.
+    fn publish_local_acks(&mut self, now: time::Instant) {
+        let interval = self.config.local_ack_interval_ms as u64;
+        let ActiveLoop {
+            local_acks,
+            local_ack_instant,
+            shard_back_log,
+            shard_queues,
+            ..
+        } = match &mut self.inner {
+            Inner::MainActive(active_loop) => active_loop,
+            _ => unreachable!(),
+        };
+
+        if now.saturating_duration_since(*local_ack_instant)
+            < time::Duration::from_millis(interval)
+        {
+            return;
+        }
+        *local_ack_instant = now;
+
         let shard_id = self.shard_id;
-        for (target_shard_id, inp_seqno) in qos_acks.into_iter() {
-            let msg = Message::LocalAck { shard_id, last_acked: inp_seqno };
-            append_index!(shard_back_log, target_shard_id, msg);
+        for target_shard_id in shard_queues.keys() {
+            let acks = local_acks.get_mut(target_shard_id);
+            if let Some(last_acked) = acks.and_then(|acks| acks.take_unpublished()) {
+                let msg = Message::LocalAck { shard_id, last_acked };
+                append_index!(shard_back_log, *target_shard_id, msg);
+            }
         }
 
         self.send_to_shards();
@@ -1017,14 +1266,22 @@ impl Shard {
         };
 
         let mut disconnecteds: Vec<ClientID> = vec![];
+        let mut resumed = false;
         for (client_id, session) in sessions.iter_mut() {
+            let paused = session.to_receive_quota() == 0;
             if let QueueStatus::Disconnected(_) = session.out_acks_flush() {
                 disconnecteds.push(client_id.clone());
             }
+            resumed |= paused && session.to_receive_quota() > 0;
         }
 
         mem::drop(sessions);
 
+        // sessions paused on receive-maximum can read their pending packets now.
+        if resumed {
+            allow_panic!(&self, self.to_waker().wake());
+        }
+
         // miot has disconnected, do a proper cleanup.
         for client_id in disconnecteds.into_iter() {
             let ActiveLoop { miot, .. } = match &mut self.inner {
@@ -1080,18 +1337,16 @@ impl Shard {
         subscrs
     }
 
-    pub fn incr_inp_seqno(&mut self) -> u64 {
+    pub fn incr_inp_seqno(&mut self) -> InpSeqno {
         match &mut self.inner {
-            Inner::MainActive(ActiveLoop { inp_seqno, .. }) => {
-                let seqno = *inp_seqno;
-                *inp_seqno = inp_seqno.saturating_add(1);
-                seqno
-            }
+            Inner::MainActive(ActiveLoop { inp_seqno, .. }) => inp_seqno.next(),
             _ => unreachable!(),
         }
     }
 
-    pub fn book_index(&mut self, qos: v5::QoS, msg: Message) {
+    /// Book `msg`, a [Message::Index], for incoming PUBLISH with `inp_seqno`, so that
+    /// it can be acknowledged once all the routed messages are acknowledged.
+    pub fn book_index(&mut self, qos: v5::QoS, inp_seqno: InpSeqno, msg: Message) {
         let ActiveLoop { index, .. } = match &mut self.inner {
             Inner::MainActive(active_loop) => active_loop,
             _ => unreachable!(),
@@ -1100,10 +1355,6 @@ impl Shard {
         match qos {
             v5::QoS::AtMostOnce => (),
             v5::QoS::AtLeastOnce => {
-                let inp_seqno = match &msg {
-                    Message::Routed { inp_seqno, .. } => *inp_seqno,
-                    _ => unreachable!(),
-                };
                 index.insert(inp_seqno, msg);
             }
             v5::QoS::ExactlyOnce => todo!(),
@@ -1116,13 +1367,18 @@ impl Shard {
             _ => unreachable!(),
         };
 
+        // QoS-0 fast path, target shard shall never acknowledge this message, hence
+        // it is neither indexed nor tracked in `ack_timestamps`.
         let inp_seqno = match &msg {
-            Message::Routed { inp_seqno, .. } => *inp_seqno,
+            Message::Routed { ack_needed: false, .. } if msg.is_qos0() => None,
+            Message::Routed { inp_seqno, .. } => Some(*inp_seqno),
             _ => unreachable!(),
         };
 
         append_index!(shard_back_log, target_shard_id, msg);
-        self.book_routed_timestamps(target_shard_id, inp_seqno);
+        if let Some(inp_seqno) = inp_seqno {
+            self.book_routed_timestamps(target_shard_id, inp_seqno);
+        }
     }
 
     fn book_routed_timestamps(&mut self, shard_id: u32, inp_seqno: InpSeqno) {
@@ -1134,8 +1390,12 @@ impl Shard {
         match ack_timestamps.binary_search_by_key(&shard_id, |t| t.shard_id) {
             Ok(off) => ack_timestamps[off].last_routed = inp_seqno,
             Err(off) => {
-                let t = Timestamp { shard_id, last_routed: inp_seqno, last_acked: 0 };
-                ack_timestamps[off] = t;
+                let t = Timestamp {
+                    shard_id,
+                    last_routed: inp_seqno,
+                    last_acked: InpSeqno::default(),
+                };
+                ack_timestamps.insert(off, t);
             }
         }
     }
@@ -1154,6 +1414,238 @@ impl Shard {
 }
 
 impl Shard {
+    // Remove sessions, waiting for reconnect, whose session_expiry_interval has
+    // elapsed since the client disconnected. Their subscriptions are removed too.
+    fn expire_sessions(&mut self) {
+        let ActiveLoop { reconnects, topic_filters, .. } = match &mut self.inner {
+            Inner::MainActive(active_loop) => active_loop,
+            _ => unreachable!(),
+        };
+
+        let now = time::Instant::now();
+        let client_ids: Vec<ClientID> = reconnects
+            .iter()
+            .filter(|(_, session)| session.is_expired(now))
+            .map(|(client_id, _)| client_id.clone())
+            .collect();
+
+        for client_id in client_ids.iter() {
+            let mut session = reconnects.remove(client_id).unwrap();
+            debug!("{} client_id:{} session expired", self.prefix, **client_id);
+            session.remove_topic_filters(topic_filters);
+            session.close();
+        }
+
+        // will message is published on session end, even if will-delay is pending.
+        for client_id in client_ids.iter() {
+            self.fire_will(client_id);
+        }
+    }
+
+    // Re-deliver inflight PUBLISH messages that are not acknowledged within
+    // `inflight_timeout`. Clients that exhaust `max_retransmits` are disconnected.
+    fn retransmit_inflight(&mut self, now: time::Instant) {
+        let ActiveLoop { sessions, stats, .. } = match &mut self.inner {
+            Inner::MainActive(active_loop) => active_loop,
+            _ => unreachable!(),
+        };
+
+        let mut disconnecteds: Vec<(ClientID, Option<Error>)> = vec![];
+        for (client_id, session) in sessions.iter_mut() {
+            match session.retransmit(now) {
+                Ok(QueueStatus::Ok(_)) => (),
+                Ok(QueueStatus::Block(_)) => stats.n_queue_blocks += 1,
+                Ok(QueueStatus::Disconnected(_)) => {
+                    disconnecteds.push((client_id.clone(), None));
+                }
+                Err(err) => disconnecteds.push((client_id.clone(), Some(err))),
+            }
+        }
+
+        for (client_id, err) in disconnecteds.into_iter() {
+            let ActiveLoop { miot, .. } = match &mut self.inner {
+                Inner::MainActive(active_loop) => active_loop,
+                _ => unreachable!(),
+            };
+
+            let res = allow_panic!(&self, miot.remove_connection(&client_id));
+            if let Some(socket) = res {
+                let err = err.or_else(|| {
+                    let err: Result<()> = err!(SlowClient, code: UnspecifiedError, "");
+                    err.err()
+                });
+                let req = Request::FlushConnection { socket, err };
+                self.handle_flush_connection(req);
+            }
+        }
+    }
+
+    // Schedule the will message, if any, for a session whose client went away.
+    fn schedule_will(&mut self, session: &mut Session) {
+        let delay = session.to_will_delay_interval();
+        let publish = match session.take_will() {
+            Some(publish) => publish,
+            None => return,
+        };
+        let client_id = session.client_id.clone();
+
+        let fire_at = time::Instant::now() + time::Duration::from_secs(delay.into());
+        let will = Arc::new(Will {
+            client_id: client_id.clone(),
+            publish,
+            fire_at,
+            deleted: AtomicBool::new(false),
+        });
+
+        if delay == 0 {
+            self.publish_will(&will);
+            return;
+        }
+
+        let ActiveLoop { will_timer, wills, .. } = match &mut self.inner {
+            Inner::MainActive(active_loop) => active_loop,
+            _ => unreachable!(),
+        };
+        debug!("{} client_id:{} will delay {}s", self.prefix, *client_id, delay);
+        will_timer.add_timeout(u64::from(delay), Arc::clone(&will));
+        if let Some(old_will) = wills.insert(client_id, will) {
+            old_will.delete(); // this will affect will_timer.
+        }
+    }
+
+    // Cancel pending will message for `client_id`, return whether there was one.
+    fn remove_will(&mut self, client_id: &ClientID) -> bool {
+        let ActiveLoop { wills, .. } = match &mut self.inner {
+            Inner::MainActive(active_loop) => active_loop,
+            _ => unreachable!(),
+        };
+
+        match wills.remove(client_id) {
+            Some(will) => {
+                debug!("{} client_id:{} will cancelled", self.prefix, **client_id);
+                will.delete(); // this will affect will_timer.
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Publish pending will message for `client_id` without waiting for will-delay.
+    fn fire_will(&mut self, client_id: &ClientID) {
+        let ActiveLoop { wills, .. } = match &mut self.inner {
+            Inner::MainActive(active_loop) => active_loop,
+            _ => unreachable!(),
+        };
+
+        if let Some(will) = wills.remove(client_id) {
+            will.delete(); // this will affect will_timer.
+            self.publish_will(&will);
+        }
+    }
+
+    fn will_expires(&mut self) {
+        let ActiveLoop { will_timer, wills, .. } = match &mut self.inner {
+            Inner::MainActive(active_loop) => active_loop,
+            _ => unreachable!(),
+        };
+
+        let mut expired = vec![];
+        for will in will_timer.expired(None) {
+            match wills.get(&will.client_id) {
+                Some(w) if Arc::ptr_eq(w, &will) => {
+                    wills.remove(&will.client_id);
+                    expired.push(will);
+                }
+                _ => (),
+            }
+        }
+
+        for will in expired.into_iter() {
+            self.publish_will(&will);
+        }
+    }
+
+    // Route the will message to all matching subscribers, like a PUBLISH received
+    // from the client.
+    fn publish_will(&mut self, will: &Will) {
+        let publish = will.publish.clone();
+        debug!("{} client_id:{} publish will {}", self.prefix, *will.client_id, publish);
+
+        if publish.retain && self.config.mqtt_retain_available {
+            let res = self.as_cluster().set_retain_topic(publish.clone());
+            if let Err(err) = res {
+                error!("{} fail to retain will-message err:{}", self.prefix, err);
+            }
+        }
+
+        self.queue_publish(publish, Some(will.client_id.clone()));
+    }
+
+    // Queue a broker originated `publish` in this shard's work-queue, so that other
+    // shards, when awake with an empty queue, can steal it. Without work-stealing, it is routed right away.
+    fn queue_publish(&mut self, publish: v5::Publish, origin: Option<ClientID>) {
+        match self.as_mut_work_queue() {
+            Some(queue) => queue.push(Work::Publish { publish, origin }),
+            None => self.route_publish(publish, origin.as_ref()),
+        }
+    }
+
+    // Pop a batch of work, either queued by this shard or stolen from other shards,
+    // and route them. If there is more work, wake up for the next batch.
+    fn route_works(&mut self) {
+        let batch = self.config.mqtt_pkt_batch_size as usize;
+        let works = match self.as_mut_work_queue() {
+            Some(queue) => queue.pop_batch(batch),
+            None => return,
+        };
+
+        if works.len() >= batch {
+            allow_panic!(&self, self.to_waker().wake());
+        }
+        for work in works.into_iter() {
+            match work {
+                Work::Publish { publish, origin } => {
+                    self.route_publish(publish, origin.as_ref())
+                }
+            }
+        }
+    }
+
+    // Route a broker originated `publish` to all matching subscribers. `origin` is
+    // the client on whose behalf the message is published, if any.
+    fn route_publish(&mut self, publish: v5::Publish, origin: Option<&ClientID>) {
+        let inp_seqno = self.incr_inp_seqno();
+        let subscrs = self.match_subscribers(&publish.topic_name);
+        for (id, (subscr, ids)) in subscrs.into_iter() {
+            if subscr.no_local && Some(&id) == origin {
+                continue;
+            }
+
+            let publish = {
+                let mut publish = publish.clone();
+                if let Some(props) = publish.properties.as_mut() {
+                    if !self.config.forward_user_properties {
+                        props.user_properties.clear();
+                    }
+                }
+                let retain = subscr.retain_as_published && publish.retain;
+                let qos = subscr.route_qos(&publish, self.config.mqtt_maximum_qos);
+                publish.set_fixed_header(retain, qos, false);
+                publish.set_subscription_ids(ids);
+                publish
+            };
+            let msg = Message::Routed {
+                src_shard_id: self.shard_id,
+                client_id: id,
+                inp_seqno,
+                out_seqno: OutSeqno::default(),
+                publish,
+                ack_needed: false,
+            };
+            self.route_to_client(subscr.shard_id, msg);
+        }
+    }
+
     fn handle_set_shard_queues(&mut self, req: Request) -> Response {
         let shard_queues = match req {
             Request::SetShardQueues(shard_queues) => shard_queues,
@@ -1168,31 +1660,158 @@ impl Shard {
         Response::Ok
     }
 
+    // Return true if the shard is hosting `max_sessions_per_shard` and `req` is not
+    // for a client whose session is already hosted by this shard.
+    fn is_full(&self, req: &Request) -> bool {
+        let ActiveLoop { sessions, reconnects, .. } = match &self.inner {
+            Inner::MainActive(active_loop) => active_loop,
+            _ => unreachable!(),
+        };
+        let client_id = match req {
+            Request::AddSession(AddSessionArgs { pkt, .. }) => &pkt.payload.client_id,
+            _ => unreachable!(),
+        };
+
+        match self.config.max_sessions_per_shard {
+            Some(_) if sessions.contains_key(client_id) => false,
+            Some(_) if reconnects.contains_key(client_id) => false,
+            Some(max) => (sessions.len() + reconnects.len()) >= (max as usize),
+            None => false,
+        }
+    }
+
+    // Return true if `req` is for a client-id whose session has a live connection,
+    // and [Config::reject_duplicate_client_id] is enabled.
+    fn is_duplicate(&self, req: &Request) -> bool {
+        let ActiveLoop { sessions, .. } = match &self.inner {
+            Inner::MainActive(active_loop) => active_loop,
+            _ => unreachable!(),
+        };
+        let client_id = match req {
+            Request::AddSession(AddSessionArgs { pkt, .. }) => &pkt.payload.client_id,
+            _ => unreachable!(),
+        };
+
+        self.config.reject_duplicate_client_id && sessions.contains_key(client_id)
+    }
+
+    // Reject the session with CONNACK ClientIdentifierNotValid, socket is closed when
+    // dropped. Existing session continues with its connection.
+    fn reject_duplicate(&self, req: Request) -> Result<Response> {
+        let AddSessionArgs { mut sock, pkt, .. } = match req {
+            Request::AddSession(args) => args,
+            _ => unreachable!(),
+        };
+
+        self.refuse_connack(&mut sock, v5::ConnackReasonCode::InvalidClientID)?;
+
+        err!(
+            InvalidInput,
+            code: InvalidClientID,
+            "{} client_id:{:?} duplicate client_id",
+            self.prefix,
+            pkt.payload.client_id
+        )
+    }
+
+    // Refuse the session with CONNACK QuotaExceeded, socket is closed when dropped.
+    fn refuse_session(&self, req: Request) -> Result<Response> {
+        let AddSessionArgs { mut sock, pkt, .. } = match req {
+            Request::AddSession(args) => args,
+            _ => unreachable!(),
+        };
+
+        self.refuse_connack(&mut sock, v5::ConnackReasonCode::QuotaExceeded)?;
+
+        err!(
+            ShardFull,
+            code: QuotaExceeded,
+            "{} client_id:{:?} shard full with {:?} sessions",
+            self.prefix,
+            pkt.payload.client_id,
+            self.config.max_sessions_per_shard
+        )
+    }
+
+    fn refuse_connack(
+        &self,
+        sock: &mut mio::net::TcpStream,
+        code: v5::ConnackReasonCode,
+    ) -> Result<()> {
+        use crate::{MQTTWrite, Packetize, SLEEP_10MS};
+
+        let max_size = self.config.mqtt_max_packet_size;
+        let timeout = {
+            let now = time::Instant::now();
+            let write_timeout = self.config.sock_mqtt_write_timeout;
+            now + time::Duration::from_secs(write_timeout as u64)
+        };
+
+        // socket is non-blocking, keep writing until CONNACK is fully written.
+        let cack = v5::ConnAck::from_reason_code(code);
+        let mut packetw = MQTTWrite::new(cack.encode()?.as_ref(), max_size);
+        loop {
+            let (val, would_block) = match packetw.write(sock) {
+                Ok(args) => args,
+                Err(err) => {
+                    error!("{} problem writing connack packet err:{}", self.prefix, err);
+                    break Err(err);
+                }
+            };
+            packetw = val;
+
+            if would_block && time::Instant::now() < timeout {
+                std::thread::sleep(SLEEP_10MS);
+            } else if would_block {
+                break err!(
+                    Disconnected,
+                    desc: "{} failed writing connack after {:?}",
+                    self.prefix,
+                    time::Instant::now()
+                );
+            } else {
+                break Ok(());
+            }
+        }
+    }
+
     fn handle_add_session(&mut self, req: Request) -> Response {
         use crate::broker::{miot::AddConnectionArgs, session::SessionArgs};
 
-        let AddSessionArgs { sock, pkt } = match req {
+        let AddSessionArgs { sock, pkt, guard } = match req {
             Request::AddSession(args) => args,
             _ => unreachable!(),
         };
-        let connect = pkt;
+        let mut connect = pkt;
         let raddr = sock.peer_addr().unwrap();
         let size = self.config.mqtt_pkt_batch_size as usize;
 
-        let client_id = ClientID::from_connect(&connect.payload.client_id);
+        // client-id is already validated during handshake.
+        let assigned = match connect.assign_client_id() {
+            Ok(assigned) => assigned,
+            Err(err) => {
+                error!("{} raddr:{} invalid client_id err:{}", self.prefix, raddr, err);
+                return Response::Ok;
+            }
+        };
+        let client_id = connect.payload.client_id.clone();
 
-        // TODO: handle connect.flags.clean_start here.
+        let (clean_start, _will_flag, _will_qos, _will_retain) = connect.flags.unwrap();
 
         // start the session here
         let (mut session, upstream, downstream) = {
             // This queue is wired up with miot-thread. This queue carries v5::Packet,
             // and there is a separate queue for every session.
-            let (upstream, session_rx) =
-                { socket::pkt_channel(self.shard_id, size, self.to_waker()) };
+            let (upstream, session_rx) = {
+                let buffered = self.to_buffered();
+                socket::pkt_channel(self.shard_id, size, self.to_waker(), buffered)
+            };
             // This queue is wired up with miot-thread. This queue carries v5::Packet,
             // and there is a separate queue for every session.
-            let (miot_tx, downstream) =
-                { socket::pkt_channel(self.shard_id, size, self.as_miot().to_waker()) };
+            let (miot_tx, downstream) = {
+                let (waker, buffered) = (self.as_miot().to_waker(), self.to_buffered());
+                socket::pkt_channel(self.shard_id, size, waker, buffered)
+            };
             let args = SessionArgs {
                 raddr,
                 client_id: client_id.clone(),
@@ -1204,55 +1823,54 @@ impl Shard {
             (session, upstream, downstream)
         };
 
+        // take over existing session, if already present for this client_id, the old
+        // connection shall be disconnected with SessionTakenOver.
+        let session_present = match self.take_over_session(&client_id, raddr) {
+            Some(old) if clean_start => {
+                let mut old = old;
+                old.remove_topic_filters(self.as_mut_topic_filters());
+                old.close();
+                // old session has ended, publish its pending will now.
+                self.fire_will(&client_id);
+                false
+            }
+            Some(old) => {
+                // client reconnected before will-delay, cancel the will.
+                self.remove_will(&client_id);
+                session.take_over(old);
+                true
+            }
+            None => false,
+        };
+
         // send back the connection acknowledgment CONNACK here.
         {
-            let packet = session.success_ack(&connect, self);
+            let mut packet = session.success_ack(&connect, assigned, self);
+            if session_present {
+                packet.set_session_present();
+            }
             let msgs = vec![Message::new_conn_ack(packet)];
             session.as_mut_out_acks().extend(msgs.into_iter());
 
             match session.out_acks_flush() {
                 QueueStatus::Disconnected(_) | QueueStatus::Block(_) => {
                     error!("{} raddr:{} fail to send CONNACK", self.prefix, raddr);
+                    session.remove_topic_filters(self.as_mut_topic_filters());
                     return Response::Ok;
                 }
                 QueueStatus::Ok(_) => {
                     info!("{} raddr:{} send CONNACK", self.prefix, raddr);
+                    let client_id = client_id.clone();
+                    let ev = BrokerEvent::ClientConnected { client_id, addr: raddr };
+                    self.as_cluster().emit(ev);
                 }
             }
         }
 
-        // add_connection further down shall wake miot-thread.
-        let ActiveLoop { sessions, miot, topic_filters, .. } = match &mut self.inner {
-            Inner::MainActive(active_loop) => active_loop,
-            _ => unreachable!(),
-        };
-
-        // nuke existing session, if already present for this client_id
-        if let Some(mut session) = sessions.remove(&client_id) {
-            info!(
-                "{} old_raddr:{} new_raddr:{} session take over",
-                self.prefix, session.raddr, raddr
-            );
-
-            // TODO: should we remove topic_filters or SessionTakenOver ?
-            session.remove_topic_filters(topic_filters);
-            session.close();
-
-            if let Some(socket) = allow_panic!(self, miot.remove_connection(&client_id)) {
-                mem::drop(sessions);
-                mem::drop(miot);
-                mem::drop(topic_filters);
-
-                let err: Result<()> = err!(
-                    SessionTakenOver,
-                    code: SessionTakenOver,
-                    "{} client {}",
-                    self.prefix,
-                    sock.peer_addr().unwrap()
-                );
-
-                let arg = Request::FlushConnection { socket, err: err.err() };
-                self.handle_flush_connection(arg);
+        // re-deliver un-acked and pending PUBLISH packets from resumed session.
+        if session_present {
+            if let QueueStatus::Disconnected(_) = session.out_qos(Vec::new()) {
+                error!("{} raddr:{} fail to re-deliver back-log", self.prefix, raddr);
             }
         }
 
@@ -1267,6 +1885,7 @@ impl Shard {
             let args = AddConnectionArgs {
                 client_id,
                 conn: sock,
+                guard,
                 upstream,
                 downstream,
                 max_packet_size: session.as_connect().max_packet_size(def),
@@ -1280,6 +1899,58 @@ impl Shard {
         Response::Ok
     }
 
+    // Remove the session for `client_id`, if present, and flush its connection with
+    // SessionTakenOver. Sessions waiting for reconnect are also looked up. Return the
+    // removed session.
+    fn take_over_session(
+        &mut self,
+        client_id: &ClientID,
+        raddr: std::net::SocketAddr,
+    ) -> Option<Session> {
+        let ActiveLoop { sessions, reconnects, .. } = match &mut self.inner {
+            Inner::MainActive(active_loop) => active_loop,
+            _ => unreachable!(),
+        };
+
+        if let Some(session) = reconnects.remove(client_id) {
+            info!(
+                "{} old_raddr:{} new_raddr:{} session reconnect",
+                self.prefix, session.raddr, raddr
+            );
+            return Some(session);
+        }
+
+        let mut session = sessions.remove(client_id)?;
+        info!(
+            "{} old_raddr:{} new_raddr:{} session take over",
+            self.prefix, session.raddr, raddr
+        );
+        self.schedule_will(&mut session);
+
+        // drop the old session's miot_tx, flusher shall drain the old connection
+        // until its miot_tx is disconnected.
+        let session = session.into_reconnect();
+
+        let ActiveLoop { miot, .. } = match &mut self.inner {
+            Inner::MainActive(active_loop) => active_loop,
+            _ => unreachable!(),
+        };
+        if let Some(socket) = allow_panic!(self, miot.remove_connection(client_id)) {
+            let err: Result<()> = err!(
+                SessionTakenOver,
+                code: SessionTakenOver,
+                "{} client {}",
+                self.prefix,
+                raddr
+            );
+
+            let arg = Request::FlushConnection { socket, err: err.err() };
+            self.handle_flush_connection(arg);
+        }
+
+        Some(session)
+    }
+
     fn handle_flush_connection(&mut self, req: Request) -> Response {
         use crate::broker::flush::FlushConnectionArgs;
 
@@ -1288,6 +1959,7 @@ impl Shard {
             _ => unreachable!(),
         };
 
+        // hold on to the session, until client reconnects with `clean_start` false.
         let session = {
             let ActiveLoop { sessions, .. } = match &mut self.inner {
                 Inner::MainActive(active_loop) => active_loop,
@@ -1295,13 +1967,22 @@ impl Shard {
             };
             sessions.remove(&socket.client_id)
         };
-        match session {
-            Some(mut session) => {
-                session.remove_topic_filters(self.as_mut_topic_filters());
-                session.close();
-            }
-            None => (),
+        if let Some(mut session) = session {
+            self.schedule_will(&mut session);
+
+            let ActiveLoop { reconnects, .. } = match &mut self.inner {
+                Inner::MainActive(active_loop) => active_loop,
+                _ => unreachable!(),
+            };
+            let client_id = socket.client_id.clone();
+            reconnects.insert(client_id, session.into_reconnect());
         }
+        // session_expiry_interval can be ZERO, in which case expire immediately.
+        self.expire_sessions();
+
+        let client_id = socket.client_id.clone();
+        let reason = err.as_ref().map(|err| err.code()).unwrap_or(ReasonCode::Success);
+        self.as_cluster().emit(BrokerEvent::ClientDisconnected { client_id, reason });
 
         let ActiveLoop { flusher, .. } = match &mut self.inner {
             Inner::MainActive(active_loop) => active_loop,
@@ -1313,6 +1994,108 @@ impl Shard {
         Response::Ok
     }
 
+    fn handle_clients(&self) -> Response {
+        let sessions = match &self.inner {
+            Inner::MainActive(ActiveLoop { sessions, .. }) => sessions,
+            _ => unreachable!(),
+        };
+
+        Response::Clients(sessions.values().map(|s| s.to_client_info()).collect())
+    }
+
+    fn handle_slow_subscribers(&self, req: Request) -> Response {
+        let threshold = match req {
+            Request::SlowSubscribers { threshold } => threshold,
+            _ => unreachable!(),
+        };
+        let sessions = match &self.inner {
+            Inner::MainActive(ActiveLoop { sessions, .. }) => sessions,
+            _ => unreachable!(),
+        };
+
+        let client_ids = sessions
+            .iter()
+            .filter(|(_, s)| s.as_client_out().current_backlog_len > threshold)
+            .map(|(client_id, _)| client_id.clone())
+            .collect();
+        Response::SlowSubscribers(client_ids)
+    }
+
+    fn handle_pending_wills(&self) -> Response {
+        let wills = match &self.inner {
+            Inner::MainActive(ActiveLoop { wills, .. }) => wills,
+            _ => unreachable!(),
+        };
+
+        let wills = wills.values().map(|w| (w.client_id.clone(), w.fire_at)).collect();
+        Response::PendingWills(wills)
+    }
+
+    fn handle_cancel_will(&mut self, req: Request) -> Response {
+        let client_id = match req {
+            Request::CancelWill { client_id } => client_id,
+            _ => unreachable!(),
+        };
+
+        Response::CancelWill(self.remove_will(&client_id))
+    }
+
+    fn handle_drain(&mut self, req: Request) -> Response {
+        let deadline = match req {
+            Request::Drain { deadline } => time::Instant::now() + deadline,
+            _ => unreachable!(),
+        };
+
+        let client_ids: Vec<ClientID> = match &self.inner {
+            Inner::MainActive(ActiveLoop { sessions, .. }) => {
+                sessions.keys().cloned().collect()
+            }
+            _ => unreachable!(),
+        };
+        info!("{} draining {} sessions", self.prefix, client_ids.len());
+
+        for client_id in client_ids.into_iter() {
+            if time::Instant::now() > deadline {
+                error!("{} drain deadline elapsed", self.prefix);
+                break;
+            }
+
+            self.drain_session(&client_id);
+
+            let ActiveLoop { miot, .. } = match &mut self.inner {
+                Inner::MainActive(active_loop) => active_loop,
+                _ => unreachable!(),
+            };
+            let res = allow_panic!(&self, miot.remove_connection(&client_id));
+            if let Some(socket) = res {
+                let err: Result<()> = err!(Disconnected, code: ServerShutdown, "drain");
+                let req = Request::FlushConnection { socket, err: err.err() };
+                self.handle_flush_connection(req);
+            }
+        }
+
+        Response::Ok
+    }
+
+    // Push back-logged messages, as much as the client can receive, and discard
+    // the will message. Client did not go away, server is shutting down.
+    fn drain_session(&mut self, client_id: &ClientID) {
+        let ActiveLoop { sessions, .. } = match &mut self.inner {
+            Inner::MainActive(active_loop) => active_loop,
+            _ => unreachable!(),
+        };
+
+        if let Some(session) = sessions.get_mut(client_id) {
+            session.out_qos(Vec::new());
+            if session.take_will().is_some() {
+                debug!(
+                    "{} client_id:{} will discarded on drain",
+                    self.prefix, **client_id
+                );
+            }
+        }
+    }
+
     fn handle_close(&mut self, req: Request) -> Response {
         match &self.inner {
             Inner::MainActive { .. } => self.handle_close_active(req),
@@ -1344,6 +2127,9 @@ impl Shard {
         for (client_id, sess) in active_loop.sessions.into_iter() {
             new_sessions.insert(client_id, sess.close());
         }
+        for (client_id, sess) in active_loop.reconnects.into_iter() {
+            new_sessions.insert(client_id, sess.close());
+        }
 
         let fin_state = FinState {
             miot,
@@ -1382,7 +2168,7 @@ impl Shard {
         let fin_state = FinState {
             miot: Miot::default(),
             sessions: new_sessions,
-            inp_seqno: 0,
+            inp_seqno: InpSeqno::default(),
             shard_back_log: BTreeMap::default(), // TODO
             ack_timestamps: Vec::default(),
             stats: Stats::default(),
@@ -1416,6 +2202,41 @@ impl Shard {
         }
     }
 
+    pub(crate) fn incr_n_packets(&mut self, count: usize) {
+        self.as_mut_stats().n_packets += count;
+    }
+
+    fn as_mut_stats(&mut self) -> &mut Stats {
+        match &mut self.inner {
+            Inner::MainActive(ActiveLoop { stats, .. }) => stats,
+            Inner::MainReplica(ReplicaLoop { stats, .. }) => stats,
+            inner => unreachable!("{} {:?}", self.prefix, inner),
+        }
+    }
+
+    fn to_shard_metrics(&self) -> ShardMetrics {
+        let (stats, n_sessions, n_reconnects) = match &self.inner {
+            Inner::MainActive(ActiveLoop { stats, sessions, reconnects, .. }) => {
+                (stats, sessions.len(), reconnects.len())
+            }
+            Inner::MainReplica(ReplicaLoop { stats, sessions, .. }) => {
+                (stats, sessions.len(), 0)
+            }
+            inner => unreachable!("{} {:?}", self.prefix, inner),
+        };
+
+        ShardMetrics {
+            shard_id: self.shard_id,
+            n_sessions,
+            n_reconnects,
+            n_events: stats.n_events,
+            n_requests: stats.n_requests,
+            n_packets: stats.n_packets,
+            n_queue_blocks: stats.n_queue_blocks,
+            n_queue_drops: stats.n_queue_drops,
+        }
+    }
+
     pub fn prefix(&self) -> String {
         let state = match &self.inner {
             Inner::Init => "init",
@@ -1492,10 +2313,97 @@ impl Shard {
         }
     }
 
+    pub fn as_mut_work_queue(&mut self) -> Option<&mut WorkQueue> {
+        match &mut self.inner {
+            Inner::MainActive(ActiveLoop { work_queue, .. }) => work_queue.as_mut(),
+            _ => unreachable!(),
+        }
+    }
+
     pub fn to_waker(&self) -> Arc<mio::Waker> {
         match &self.inner {
             Inner::MainActive(ActiveLoop { waker, .. }) => Arc::clone(waker),
             _ => unreachable!(),
         }
     }
+
+    pub fn to_buffered(&self) -> Arc<AtomicUsize> {
+        match &self.inner {
+            Inner::MainActive(ActiveLoop { buffered, .. }) => Arc::clone(buffered),
+            _ => unreachable!(),
+        }
+    }
 }
+
+/// Book-keeping of messages routed from a peer shard, that need acknowledgement.
+///
+/// Messages are acknowledged out of order, Message::LocalAck shall only carry the
+/// highest InpSeqno upto which every received message is acknowledged.
+#[derive(Default)]
+struct LocalAcks {
+    // received messages, yet to be acknowledged.
+    pending: BTreeSet<InpSeqno>,
+    // acknowledged messages, beyond the oldest pending message.
+    acked: BTreeSet<InpSeqno>,
+    // all received messages upto this seqno are acknowledged.
+    last_acked: InpSeqno,
+    // last_acked, last published to the peer shard.
+    last_published: InpSeqno,
+}
+
+impl LocalAcks {
+    fn received(&mut self, inp_seqno: InpSeqno) {
+        self.pending.insert(inp_seqno);
+    }
+
+    fn acked(&mut self, inp_seqno: InpSeqno) {
+        self.pending.remove(&inp_seqno);
+        self.acked.insert(inp_seqno);
+
+        let limit = self.pending.first().copied();
+        while let Some(seqno) = self.acked.first().copied() {
+            match limit {
+                Some(limit) if seqno > limit => break,
+                _ => (),
+            }
+            self.acked.pop_first();
+            self.last_acked = cmp::max(self.last_acked, seqno);
+        }
+    }
+
+    // Return last_acked, if it has advanced since it was last published.
+    fn take_unpublished(&mut self) -> Option<InpSeqno> {
+        if self.last_acked > self.last_published {
+            self.last_published = self.last_acked;
+            Some(self.last_acked)
+        } else {
+            None
+        }
+    }
+}
+
+/// Will message from a client, scheduled for publishing after will-delay-interval.
+pub struct Will {
+    client_id: ClientID,
+    publish: v5::Publish,
+    fire_at: time::Instant,
+    deleted: AtomicBool,
+}
+
+impl TimeoutValue for Arc<Will> {
+    fn delete(&self) {
+        self.deleted.store(true, SeqCst);
+    }
+
+    fn is_deleted(&self) -> bool {
+        self.deleted.load(SeqCst)
+    }
+}
+
+#[cfg(test)]
+#[path = "shard_test_util.rs"]
+pub(crate) mod test_util;
+
+#[cfg(test)]
+#[path = "shard_test.rs"]
+mod shard_test;
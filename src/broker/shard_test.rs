@@ -0,0 +1,482 @@
+use std::{thread, time};
+
+use crate::broker::{session::SessionArgs, socket};
+
+use super::test_util::new_active_shard;
+use super::*;
+
+fn new_will_session(shard: &Shard, client_id: &ClientID, delay: u32) -> Session {
+    let (miot_tx, _) = socket::pkt_channel(0, 16, shard.to_waker(), shard.to_buffered());
+    let (_, session_rx) =
+        socket::pkt_channel(0, 16, shard.to_waker(), shard.to_buffered());
+
+    let mut connect = v5::Connect::default();
+    connect.flags = v5::ConnectFlags::new(&[v5::ConnectFlags::WILL_FLAG]);
+    connect.payload.will_topic = Some(TopicName::from("will/topic".to_string()));
+    connect.payload.will_payload = Some(b"gone".to_vec());
+    connect.payload.will_properties = Some(v5::WillProperties {
+        will_delay_interval: Some(delay),
+        ..v5::WillProperties::default()
+    });
+
+    let args = SessionArgs {
+        raddr: "127.0.0.1:10010".parse().unwrap(),
+        client_id: client_id.clone(),
+        shard_id: 0,
+        miot_tx,
+        session_rx,
+    };
+    Session::start_active(args, Config::default(), &connect)
+}
+
+fn new_out_session(
+    shard: &Shard,
+    client_id: &ClientID,
+    receive_maximum: u16,
+) -> (Session, socket::PktRx) {
+    let (miot_tx, downstream) =
+        socket::pkt_channel(0, 16, shard.to_waker(), shard.to_buffered());
+    let (_, session_rx) =
+        socket::pkt_channel(0, 16, shard.to_waker(), shard.to_buffered());
+
+    let mut connect = v5::Connect::default();
+    connect.properties = Some(v5::ConnectProperties {
+        receive_maximum: Some(receive_maximum),
+        ..v5::ConnectProperties::default()
+    });
+
+    let args = SessionArgs {
+        raddr: "127.0.0.1:10011".parse().unwrap(),
+        client_id: client_id.clone(),
+        shard_id: 0,
+        miot_tx,
+        session_rx,
+    };
+    (Session::start_active(args, Config::default(), &connect), downstream)
+}
+
+fn subscribe(shard: &Shard, client_id: &ClientID, shard_id: u32) {
+    let topic_filter = crate::TopicFilter::from("will/#".to_string());
+    let subscription = v5::Subscription {
+        topic_filter: topic_filter.clone(),
+        client_id: client_id.clone(),
+        shard_id,
+        subscription_id: None,
+        qos: v5::QoS::AtMostOnce,
+        no_local: false,
+        retain_as_published: false,
+        retain_forward_rule: v5::RetainForwardRule::OnEverySubscribe,
+    };
+    shard.as_topic_filters().subscribe(&topic_filter, subscription);
+}
+
+fn routed_wills(shard: &Shard, shard_id: u32) -> usize {
+    match &shard.inner {
+        Inner::MainActive(ActiveLoop { shard_back_log, .. }) => {
+            shard_back_log.get(&shard_id).map(|msgs| msgs.len()).unwrap_or(0)
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn test_route_subscription_ids() {
+    let (mut shard, _app_rx) = new_active_shard();
+
+    // (client-id, topic-filter, subscription-id)
+    let subscriptions = [
+        ("sub-one", "ids/#", Some(1)),
+        ("sub-two", "ids/#", Some(2)),
+        ("sub-two", "ids/a/+", Some(3)),
+        ("sub-none", "ids/a/b", None),
+    ];
+    for (client_id, topic_filter, subscription_id) in subscriptions.into_iter() {
+        let topic_filter = crate::TopicFilter::from(topic_filter.to_string());
+        let subscription = v5::Subscription {
+            topic_filter: topic_filter.clone(),
+            client_id: ClientID(client_id.to_string()),
+            shard_id: 1,
+            subscription_id,
+            qos: v5::QoS::AtMostOnce,
+            no_local: false,
+            retain_as_published: false,
+            retain_forward_rule: v5::RetainForwardRule::OnEverySubscribe,
+        };
+        shard.as_topic_filters().subscribe(&topic_filter, subscription);
+    }
+
+    let publish = v5::Publish {
+        retain: false,
+        qos: v5::QoS::AtMostOnce,
+        duplicate: false,
+        topic_name: TopicName::from("ids/a/b".to_string()),
+        packet_id: None,
+        properties: None,
+        payload: Some(b"hello".to_vec()),
+    };
+    shard.route_publish(publish, None);
+
+    let mut routed: BTreeMap<String, Vec<u32>> = BTreeMap::default();
+    match &shard.inner {
+        Inner::MainActive(ActiveLoop { shard_back_log, .. }) => {
+            for msg in shard_back_log.get(&1).unwrap().iter() {
+                match msg {
+                    Message::Routed { client_id, publish, .. } => {
+                        let mut ids: Vec<u32> = match &publish.properties {
+                            Some(props) => {
+                                let ids = props.subscribtion_identifier.iter();
+                                ids.map(|id| **id).collect()
+                            }
+                            None => vec![],
+                        };
+                        ids.sort();
+                        routed.insert(client_id.to_string(), ids);
+                    }
+                    msg => panic!("unexpected {:?}", msg),
+                }
+            }
+        }
+        _ => unreachable!(),
+    }
+
+    assert_eq!(routed.len(), 3);
+    assert_eq!(routed["sub-one"], vec![1]);
+    assert_eq!(routed["sub-two"], vec![2, 3]);
+    assert_eq!(routed["sub-none"], Vec::<u32>::new());
+}
+
+#[test]
+fn test_will_delayed_delivery() {
+    let (mut shard, _app_rx) = new_active_shard();
+    let client_id = ClientID("test-will-publisher".to_string());
+    subscribe(&shard, &ClientID("test-will-subscriber".to_string()), 1);
+
+    let mut session = new_will_session(&shard, &client_id, 1);
+    shard.schedule_will(&mut session);
+    assert!(session.take_will().is_none());
+
+    shard.will_expires();
+    assert_eq!(routed_wills(&shard, 1), 0);
+
+    thread::sleep(time::Duration::from_millis(1100));
+    shard.will_expires();
+    assert_eq!(routed_wills(&shard, 1), 1);
+}
+
+#[test]
+fn test_will_disconnect_reason() {
+    use v5::DisconnReasonCode::{DiconnectWillMessage, NormalDisconnect};
+
+    let client_id = ClientID("test-will-publisher".to_string());
+    let subscriber = ClientID("test-will-subscriber".to_string());
+
+    // (DISCONNECT reason-code, if any, number of wills published)
+    let testcases =
+        [(Some(NormalDisconnect), 0), (Some(DiconnectWillMessage), 1), (None, 1)];
+    for (code, n) in testcases.into_iter() {
+        let (mut shard, _app_rx) = new_active_shard();
+        subscribe(&shard, &subscriber, 1);
+
+        let mut session = new_will_session(&shard, &client_id, 0);
+        if let Some(code) = code {
+            session.rx_disconnect(&v5::Disconnect::new(code, None));
+        }
+        shard.schedule_will(&mut session);
+        assert_eq!(routed_wills(&shard, 1), n, "{:?}", code);
+        assert!(session.take_will().is_none(), "{:?}", code);
+    }
+}
+
+#[test]
+fn test_will_reconnect_cancel() {
+    let (mut shard, _app_rx) = new_active_shard();
+    let client_id = ClientID("test-will-publisher".to_string());
+    subscribe(&shard, &ClientID("test-will-subscriber".to_string()), 1);
+
+    let mut session = new_will_session(&shard, &client_id, 1);
+    shard.schedule_will(&mut session);
+
+    // client reconnects before will-delay-interval.
+    assert!(shard.remove_will(&client_id));
+    assert!(!shard.remove_will(&client_id));
+
+    thread::sleep(time::Duration::from_millis(1100));
+    shard.will_expires();
+    assert_eq!(routed_wills(&shard, 1), 0);
+}
+
+#[test]
+fn test_pending_wills() {
+    let (mut shard, _app_rx) = new_active_shard();
+    let client_id = ClientID("test-will-publisher".to_string());
+    subscribe(&shard, &ClientID("test-will-subscriber".to_string()), 1);
+
+    let start = time::Instant::now();
+    let mut session = new_will_session(&shard, &client_id, 1);
+    shard.schedule_will(&mut session);
+
+    let wills = match shard.handle_pending_wills() {
+        Response::PendingWills(wills) => wills,
+        _ => unreachable!(),
+    };
+    assert_eq!(wills.len(), 1);
+    assert_eq!(wills[0].0, client_id);
+    assert!(wills[0].1 >= start + time::Duration::from_secs(1));
+    assert!(wills[0].1 <= time::Instant::now() + time::Duration::from_secs(1));
+
+    let req = Request::CancelWill { client_id: client_id.clone() };
+    assert!(matches!(shard.handle_cancel_will(req), Response::CancelWill(true)));
+    let req = Request::CancelWill { client_id: client_id.clone() };
+    assert!(matches!(shard.handle_cancel_will(req), Response::CancelWill(false)));
+    match shard.handle_pending_wills() {
+        Response::PendingWills(wills) => assert!(wills.is_empty()),
+        _ => unreachable!(),
+    }
+
+    thread::sleep(time::Duration::from_millis(1100));
+    shard.will_expires();
+    assert_eq!(routed_wills(&shard, 1), 0);
+}
+
+#[test]
+fn test_will_without_delay() {
+    let (mut shard, _app_rx) = new_active_shard();
+    let client_id = ClientID("test-will-publisher".to_string());
+    subscribe(&shard, &ClientID("test-will-subscriber".to_string()), 1);
+
+    let mut session = new_will_session(&shard, &client_id, 0);
+    shard.schedule_will(&mut session);
+    assert_eq!(routed_wills(&shard, 1), 1);
+}
+
+#[test]
+fn test_will_discarded_on_drain() {
+    let (mut shard, _app_rx) = new_active_shard();
+    let client_id = ClientID("test-will-publisher".to_string());
+    subscribe(&shard, &ClientID("test-will-subscriber".to_string()), 1);
+
+    let session = new_will_session(&shard, &client_id, 0);
+    match &mut shard.inner {
+        Inner::MainActive(ActiveLoop { sessions, .. }) => {
+            sessions.insert(client_id.clone(), session);
+        }
+        _ => unreachable!(),
+    }
+    shard.drain_session(&client_id);
+
+    // connection is flushed after drain, will message is not published.
+    let mut session = match &mut shard.inner {
+        Inner::MainActive(ActiveLoop { sessions, .. }) => {
+            sessions.remove(&client_id).unwrap()
+        }
+        _ => unreachable!(),
+    };
+    shard.schedule_will(&mut session);
+    assert_eq!(routed_wills(&shard, 1), 0);
+    match shard.handle_pending_wills() {
+        Response::PendingWills(wills) => assert!(wills.is_empty()),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn test_local_ack_interval() {
+    let (mut shard, _app_rx) = new_active_shard();
+
+    let mut msg_rxs = BTreeMap::default();
+    let mut shard_queues = BTreeMap::default();
+    for peer_id in [1, 2] {
+        let (msg_tx, msg_rx) = message::msg_channel(peer_id, 16, shard.to_waker());
+        let peer = Shard {
+            name: shard.name.clone(),
+            shard_id: peer_id,
+            uuid: Uuid::new_v4(),
+            prefix: String::default(),
+            config: shard.config.clone(),
+            inner: Inner::MsgTx(shard.to_waker(), msg_tx),
+        };
+        shard_queues.insert(peer_id, peer);
+        msg_rxs.insert(peer_id, msg_rx);
+    }
+    shard.handle_set_shard_queues(Request::SetShardQueues(shard_queues));
+
+    let start = time::Instant::now();
+    let acks = |acks: &[(u32, u64)]| {
+        let mut index = Acks::default();
+        for (id, seqno) in acks.iter() {
+            append_index!(index, *id, InpSeqno::from(*seqno));
+        }
+        index
+    };
+    shard.return_local_acks(acks(&[(1, 10), (2, 20)]));
+    shard.return_local_acks(acks(&[(1, 15)]));
+
+    // interval has not elapsed.
+    shard.publish_local_acks(start);
+    for msg_rx in msg_rxs.values() {
+        assert!(msg_rx.try_recvs().take_values().is_empty());
+    }
+
+    let interval = time::Duration::from_millis(Config::DEF_LOCAL_ACK_INTERVAL_MS as u64);
+    let interval = interval + time::Duration::from_millis(1);
+    let local_acks = |msg_rx: &message::MsgRx| -> Vec<u64> {
+        let msgs = msg_rx.try_recvs().take_values();
+        msgs.iter()
+            .map(|msg| match msg {
+                Message::LocalAck { shard_id: 0, last_acked } => u64::from(*last_acked),
+                msg => panic!("unexpected {:?}", msg),
+            })
+            .collect()
+    };
+    shard.publish_local_acks(start + interval);
+    assert_eq!(local_acks(&msg_rxs[&1]), vec![15]);
+    assert_eq!(local_acks(&msg_rxs[&2]), vec![20]);
+
+    // acknowledgements have not advanced, nothing to publish.
+    shard.publish_local_acks(start + interval * 2);
+    assert!(local_acks(&msg_rxs[&1]).is_empty());
+    assert!(local_acks(&msg_rxs[&2]).is_empty());
+
+    // out of order acknowledgement is not published past the pending message.
+    match &mut shard.inner {
+        Inner::MainActive(ActiveLoop { local_acks, .. }) => {
+            let acks = local_acks.get_mut(&1).unwrap();
+            acks.received(InpSeqno::from(30));
+            acks.received(InpSeqno::from(40));
+        }
+        _ => unreachable!(),
+    }
+    shard.return_local_acks(acks(&[(1, 40)]));
+    shard.publish_local_acks(start + interval * 3);
+    assert!(local_acks(&msg_rxs[&1]).is_empty());
+
+    shard.return_local_acks(acks(&[(1, 30)]));
+    shard.publish_local_acks(start + interval * 4);
+    assert_eq!(local_acks(&msg_rxs[&1]), vec![40]);
+}
+
+#[test]
+fn test_slow_subscribers() {
+    let (mut shard, _app_rx) = new_active_shard();
+
+    // slow client acknowledges one PUBLISH at a time, the rest pile up in back-log.
+    let slow_id = ClientID("test-slow-subscriber".to_string());
+    let (mut slow, downstream) = new_out_session(&shard, &slow_id, 1);
+    let msgs: Vec<Message> = (0..10)
+        .map(|_| {
+            let mut msg = Message::Routed {
+                src_shard_id: 0,
+                client_id: slow_id.clone(),
+                inp_seqno: InpSeqno::from(1),
+                out_seqno: OutSeqno::default(),
+                publish: v5::Publish {
+                    retain: false,
+                    qos: v5::QoS::AtLeastOnce,
+                    duplicate: false,
+                    topic_name: TopicName::from("a/b/c".to_string()),
+                    packet_id: None,
+                    properties: None,
+                    payload: Some(b"hello".to_vec()),
+                },
+                ack_needed: true,
+            };
+            slow.incr_out_seqno(&mut msg);
+            msg
+        })
+        .collect();
+    slow.out_qos(msgs);
+    assert_eq!(downstream.try_recvs("test").take_values().len(), 1);
+
+    let out = slow.to_client_info().client_out;
+    assert_eq!(out.messages_delivered, 1);
+    assert_eq!(out.messages_dropped, 0);
+    assert!(out.bytes_out > 0);
+    assert_eq!(out.current_backlog_len, 9);
+    assert_eq!(out.max_backlog_len, 10);
+    assert!(out.last_flush_instant.is_some());
+
+    let idle_id = ClientID("test-idle-subscriber".to_string());
+    let (idle, _idle_downstream) = new_out_session(&shard, &idle_id, 1);
+    let out = idle.to_client_info().client_out;
+    assert_eq!(out.messages_delivered, 0);
+    assert_eq!(out.current_backlog_len, 0);
+    assert!(out.last_flush_instant.is_none());
+
+    match &mut shard.inner {
+        Inner::MainActive(ActiveLoop { sessions, .. }) => {
+            sessions.insert(slow_id.clone(), slow);
+            sessions.insert(idle_id.clone(), idle);
+        }
+        _ => unreachable!(),
+    }
+
+    let req = Request::SlowSubscribers { threshold: 5 };
+    match shard.handle_slow_subscribers(req) {
+        Response::SlowSubscribers(client_ids) => assert_eq!(client_ids, vec![slow_id]),
+        _ => unreachable!(),
+    }
+    let req = Request::SlowSubscribers { threshold: 9 };
+    match shard.handle_slow_subscribers(req) {
+        Response::SlowSubscribers(client_ids) => assert!(client_ids.is_empty()),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn test_route_qos0_fast_path() {
+    let (mut shard, _app_rx) = new_active_shard();
+    let client_id = ClientID("test-qos0-subscriber".to_string());
+
+    let topic_filter = crate::TopicFilter::from("qos0/#".to_string());
+    let subscription = v5::Subscription {
+        topic_filter: topic_filter.clone(),
+        client_id: client_id.clone(),
+        shard_id: 1,
+        subscription_id: None,
+        qos: v5::QoS::AtLeastOnce,
+        no_local: false,
+        retain_as_published: false,
+        retain_forward_rule: v5::RetainForwardRule::OnEverySubscribe,
+    };
+    shard.as_topic_filters().subscribe(&topic_filter, subscription);
+
+    let publish = v5::Publish {
+        retain: false,
+        qos: v5::QoS::AtMostOnce,
+        duplicate: false,
+        topic_name: TopicName::from("qos0/a".to_string()),
+        packet_id: None,
+        properties: None,
+        payload: Some(b"hello".to_vec()),
+    };
+    shard.route_publish(publish, None);
+
+    // routed to the target shard, without indexing or tracking acknowledgement.
+    let mut msgs = match &mut shard.inner {
+        Inner::MainActive(ActiveLoop {
+            shard_back_log, index, ack_timestamps, ..
+        }) => {
+            assert!(index.is_empty());
+            assert!(ack_timestamps.is_empty());
+            shard_back_log.remove(&1).unwrap()
+        }
+        _ => unreachable!(),
+    };
+    assert_eq!(msgs.len(), 1);
+    assert!(msgs[0].is_qos0());
+
+    // delivered to subscriber, without packet-id or flow-control quota.
+    let (mut session, downstream) = new_out_session(&shard, &client_id, 1);
+    session.incr_out_seqno(&mut msgs[0]);
+    assert!(matches!(session.out_qos(msgs), QueueStatus::Ok(_)));
+    match downstream.try_recvs("test").take_values().as_slice() {
+        [v5::Packet::Publish(publish)] => {
+            assert_eq!(publish.qos, v5::QoS::AtMostOnce);
+            assert_eq!(publish.packet_id, None);
+        }
+        pkts => panic!("unexpected {:?}", pkts),
+    }
+    let info = session.to_client_info();
+    assert_eq!(info.n_inflight_out, 0);
+    assert_eq!(info.client_out.messages_delivered, 1);
+}
@@ -0,0 +1,62 @@
+// Shard fixtures shared by test modules across the broker.
+
+use std::sync::{atomic::AtomicUsize, mpsc};
+use std::time;
+
+use super::*;
+
+pub(crate) fn new_active_shard() -> (Shard, mpsc::Receiver<String>) {
+    let poll = mio::Poll::new().unwrap();
+    let waker = Arc::new(mio::Waker::new(poll.registry(), Shard::WAKE_TOKEN).unwrap());
+    let (app_tx, app_rx) = mpsc::sync_channel(16);
+
+    let config = Config::default();
+    let mut shard = Shard {
+        name: config.name.clone(),
+        shard_id: 0,
+        uuid: Uuid::new_v4(),
+        prefix: String::default(),
+        config,
+        inner: Inner::MainActive(ActiveLoop {
+            poll,
+            waker,
+            cluster: Box::new(Cluster::default()),
+            flusher: Flusher::default(),
+            miot: Miot::default(),
+            buffered: Arc::new(AtomicUsize::new(0)),
+
+            sessions: BTreeMap::default(),
+            reconnects: BTreeMap::default(),
+            will_timer: Timer::default(),
+            wills: BTreeMap::default(),
+            inp_seqno: InpSeqno::default(),
+            shard_back_log: BTreeMap::default(),
+            index: BTreeMap::default(),
+            ack_timestamps: Vec::default(),
+            local_acks: BTreeMap::default(),
+            local_ack_instant: time::Instant::now(),
+
+            shard_queues: BTreeMap::default(),
+            topic_filters: SubscribedTrie::default(),
+            retained_messages: RetainedTrie::default(),
+            work_queue: None,
+
+            stats: Stats::default(),
+
+            app_tx,
+        }),
+    };
+    shard.prefix = shard.prefix();
+
+    (shard, app_rx)
+}
+
+// Take messages routed to `shard_id`, yet to be sent to that shard.
+pub(crate) fn take_routed(shard: &mut Shard, shard_id: u32) -> Vec<Message> {
+    match &mut shard.inner {
+        Inner::MainActive(ActiveLoop { shard_back_log, .. }) => {
+            shard_back_log.remove(&shard_id).unwrap_or_default()
+        }
+        _ => unreachable!(),
+    }
+}
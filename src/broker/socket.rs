@@ -1,12 +1,12 @@
 use log::{error, trace, warn};
 
-use std::sync::{mpsc, Arc};
-use std::{collections::VecDeque, mem, time};
+use std::sync::{atomic::AtomicUsize, atomic::Ordering::SeqCst, mpsc, Arc};
+use std::{collections::VecDeque, io, mem, time};
 
-use crate::broker::{Config, QueueStatus};
+use crate::broker::{BufferPool, Config, ConnEvent, ConnGuard, ConnState, QueueStatus};
 
-use crate::{v5, ClientID, MQTTRead, MQTTWrite, Packetize};
-use crate::{ErrorKind, Result};
+use crate::{v5, ClientID, MQTTRead, MQTTWrite};
+use crate::{Error, ErrorKind, ReasonCode, Result};
 
 pub type QueuePkt = QueueStatus<v5::Packet>;
 
@@ -14,8 +14,9 @@ pub type QueuePkt = QueueStatus<v5::Packet>;
 #[derive(Clone)]
 pub struct PktTx {
     miot_id: u32, // packet queue for shard/miot is same for both.
-    tx: mpsc::SyncSender<v5::Packet>, // shard/miot incoming packet queue.
+    tx: mpsc::SyncSender<(v5::Packet, usize)>, // shard/miot incoming packet queue.
     waker: Arc<mio::Waker>, // shard/miot waker
+    buffered: Arc<AtomicUsize>, // bytes buffered across all sockets of the shard.
     count: usize,
 }
 
@@ -32,20 +33,34 @@ impl Drop for PktTx {
 
 impl PktTx {
     pub fn try_sends(&mut self, prefix: &str, pkts: Vec<v5::Packet>) -> QueuePkt {
+        let pkts = pkts.into_iter().map(|pkt| (pkt, 0)).collect();
+        let mut status = self.try_sends_sized(prefix, pkts);
+        let pkts = status.take_values().into_iter().map(|(pkt, _)| pkt).collect();
+        status.map(pkts)
+    }
+
+    /// Same as `try_sends`, but each packet is paired with the number of bytes it
+    /// was charged, while reading from socket. Bytes are released when the packet
+    /// is received on the other end.
+    pub fn try_sends_sized(
+        &mut self,
+        prefix: &str,
+        pkts: Vec<(v5::Packet, usize)>,
+    ) -> QueueStatus<(v5::Packet, usize)> {
         let mut iter = pkts.into_iter();
         loop {
             match iter.next() {
-                Some(pkt) => match self.tx.try_send(pkt) {
+                Some(item) => match self.tx.try_send(item) {
                     Ok(()) => self.count += 1,
-                    Err(mpsc::TrySendError::Full(pkt)) => {
-                        let mut pkts: Vec<v5::Packet> = Vec::from_iter(iter);
-                        pkts.insert(0, pkt);
+                    Err(mpsc::TrySendError::Full(item)) => {
+                        let mut pkts: Vec<(v5::Packet, usize)> = Vec::from_iter(iter);
+                        pkts.insert(0, item);
                         break QueueStatus::Block(pkts);
                     }
-                    Err(mpsc::TrySendError::Disconnected(pkt)) => {
+                    Err(mpsc::TrySendError::Disconnected(item)) => {
                         warn!("{} receiver disconnected ...", prefix);
-                        let mut pkts: Vec<v5::Packet> = Vec::from_iter(iter);
-                        pkts.insert(0, pkt);
+                        let mut pkts: Vec<(v5::Packet, usize)> = Vec::from_iter(iter);
+                        pkts.insert(0, item);
                         break QueueStatus::Disconnected(pkts);
                     }
                 },
@@ -53,12 +68,37 @@ impl PktTx {
             }
         }
     }
+
+    /// Return whether bytes buffered across the shard has reached `limit`.
+    pub fn is_buffer_full(&self, limit: usize) -> bool {
+        self.buffered.load(SeqCst) >= limit
+    }
+
+    /// Charge `n` bytes, read from socket, to the shard.
+    pub fn charge(&self, n: usize) {
+        self.buffered.fetch_add(n, SeqCst);
+    }
 }
 
 /// Type implement the rx-handle for a packet-queue.
 pub struct PktRx {
     pkt_batch_size: usize,
-    rx: mpsc::Receiver<v5::Packet>,
+    rx: mpsc::Receiver<(v5::Packet, usize)>,
+    buffered: Arc<AtomicUsize>,
+    // QoS>0 PUBLISH received beyond the quota, refer `try_recvs_quota`.
+    held: Option<Box<(v5::Packet, usize)>>,
+}
+
+impl Drop for PktRx {
+    fn drop(&mut self) {
+        // release bytes charged for packets that shall never be received.
+        if let Some(item) = self.held.take() {
+            self.buffered.fetch_sub(item.1, SeqCst);
+        }
+        while let Ok((_, n)) = self.rx.try_recv() {
+            self.buffered.fetch_sub(n, SeqCst);
+        }
+    }
 }
 
 impl PktRx {
@@ -66,8 +106,12 @@ impl PktRx {
         let mut pkts = Vec::with_capacity(self.pkt_batch_size);
         loop {
             match self.rx.try_recv() {
-                Ok(pkt) if pkts.len() < self.pkt_batch_size => pkts.push(pkt),
-                Ok(pkt) => {
+                Ok((pkt, n)) if pkts.len() < self.pkt_batch_size => {
+                    self.buffered.fetch_sub(n, SeqCst);
+                    pkts.push(pkt)
+                }
+                Ok((pkt, n)) => {
+                    self.buffered.fetch_sub(n, SeqCst);
                     pkts.push(pkt);
                     break QueueStatus::Ok(pkts);
                 }
@@ -78,6 +122,45 @@ impl PktRx {
             }
         }
     }
+
+    /// Same as `try_recvs`, but receive no more than `quota` QoS>0 PUBLISH packets.
+    /// Other packets, like PUBREL, are received even when `quota` is ZERO. The first
+    /// QoS>0 PUBLISH beyond `quota` is held back, and receiving stops there, until
+    /// a later call with a non-ZERO quota.
+    pub fn try_recvs_quota(
+        &mut self,
+        _prefix: &str,
+        mut quota: usize,
+    ) -> QueueStatus<v5::Packet> {
+        let mut pkts = Vec::with_capacity(self.pkt_batch_size);
+        loop {
+            if pkts.len() >= self.pkt_batch_size {
+                break QueueStatus::Ok(pkts);
+            }
+            let (pkt, n) = match self.held.take() {
+                Some(item) => *item,
+                None => match self.rx.try_recv() {
+                    Ok(item) => item,
+                    Err(mpsc::TryRecvError::Empty) => break QueueStatus::Block(pkts),
+                    Err(mpsc::TryRecvError::Disconnected) => {
+                        break QueueStatus::Disconnected(pkts);
+                    }
+                },
+            };
+            match &pkt {
+                v5::Packet::Publish(publ) if publ.qos != v5::QoS::AtMostOnce => {
+                    if quota == 0 {
+                        self.held = Some(Box::new((pkt, n)));
+                        break QueueStatus::Block(pkts);
+                    }
+                    quota -= 1;
+                }
+                _ => (),
+            }
+            self.buffered.fetch_sub(n, SeqCst);
+            pkts.push(pkt)
+        }
+    }
 }
 
 #[derive(Default)]
@@ -97,10 +180,68 @@ impl Stats {
     }
 }
 
+/// Type implement lock-free counters aggregated across all shards.
+///
+/// Shared by every shard's [Miot] thread, that increment them in their hot path, so
+/// that [Cluster::stats] can read a near-real-time snapshot without IPC.
+///
+/// [Miot]: crate::broker::Miot
+/// [Cluster::stats]: crate::broker::Cluster::stats
+#[derive(Default)]
+pub struct AtomicStats {
+    items: AtomicUsize,
+    bytes: AtomicUsize,
+    connections: AtomicUsize,
+}
+
+/// Snapshot of [AtomicStats], refer [Cluster::stats].
+///
+/// [Cluster::stats]: crate::broker::Cluster::stats
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StatsSnapshot {
+    /// Number of packets written to client sockets.
+    pub items: usize,
+    /// Number of bytes written to client sockets.
+    pub bytes: usize,
+    /// Number of client connections currently held by all shards.
+    pub connections: usize,
+}
+
+impl AtomicStats {
+    /// Add per-socket `stats` to the aggregate.
+    pub fn incr(&self, stats: &Stats) {
+        self.items.fetch_add(stats.items, SeqCst);
+        self.bytes.fetch_add(stats.bytes, SeqCst);
+    }
+
+    pub fn incr_connections(&self, n: usize) {
+        self.connections.fetch_add(n, SeqCst);
+    }
+
+    pub fn decr_connections(&self, n: usize) {
+        self.connections.fetch_sub(n, SeqCst);
+    }
+
+    pub fn to_snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            items: self.items.load(SeqCst),
+            bytes: self.bytes.load(SeqCst),
+            connections: self.connections.load(SeqCst),
+        }
+    }
+}
+
 /// Type encapsulates the socket connection and associated data-structures.
-pub struct Socket {
+///
+/// Connection is a [mio::net::TcpStream] by default, tests can substitute it with
+/// any non-blocking `io::Read + io::Write` type, refer to [MemSocket].
+///
+/// [MemSocket]: crate::broker::MemSocket
+pub struct Socket<C = mio::net::TcpStream> {
     pub client_id: ClientID,
-    pub conn: mio::net::TcpStream,
+    pub conn: C,
+    /// Live-connection accounting for [crate::broker::Listener].
+    pub guard: Option<ConnGuard>,
     pub token: mio::Token,
     pub rd: Source,
     pub wt: Sink,
@@ -110,8 +251,20 @@ pub struct Source {
     pub pr: MQTTRead,
     pub timeout: Option<time::SystemTime>,
     pub session_tx: PktTx,
-    // All incoming MQTT packets on this socket first land here.
-    pub packets: VecDeque<v5::Packet>,
+    // All incoming MQTT packets on this socket first land here, along with the
+    // number of bytes charged for them.
+    pub packets: VecDeque<(v5::Packet, usize)>,
+    // First packet on a connection must be CONNECT, and only one CONNECT is allowed
+    // per connection. Active for connections handed over after handshake.
+    pub state: ConnState,
+}
+
+impl Drop for Source {
+    fn drop(&mut self) {
+        // release bytes charged for packets that are yet to be sent to the session.
+        let n: usize = self.packets.iter().map(|(_, n)| *n).sum();
+        self.session_tx.buffered.fetch_sub(n, SeqCst);
+    }
 }
 
 pub struct Sink {
@@ -120,9 +273,12 @@ pub struct Sink {
     pub miot_rx: PktRx,
     // All out-going MQTT packets on this socket first land here.
     pub packets: VecDeque<v5::Packet>,
+    // Set once a DISCONNECT packet is written to this socket, flusher shall not send
+    // another one while closing the connection.
+    pub disconnected: bool,
 }
 
-impl Socket {
+impl<C> Socket<C> {
     pub fn read_elapsed(&self) -> bool {
         let now = time::SystemTime::now();
         match &self.rd.timeout {
@@ -156,12 +312,24 @@ impl Socket {
             self.wt.timeout = None;
         }
     }
+
+    /// Push the write timeout further by `dur`, if it is already set.
+    pub fn extend_write_timeout(&mut self, dur: time::Duration) {
+        if let Some(timeout) = self.wt.timeout.as_mut() {
+            *timeout += dur;
+        }
+    }
 }
 
-impl Socket {
+impl<C: io::Read + io::Write> Socket<C> {
     // returned QueueStatus shall not carry any packets, packets are booked in Socket
-    // MalformedPacket, ProtocolError
-    pub fn read_packets(&mut self, prefix: &str, config: &Config) -> Result<QueuePkt> {
+    // MalformedPacket, ProtocolError, SlowClient
+    pub fn read_packets(
+        &mut self,
+        prefix: &str,
+        config: &Config,
+        pool: &mut BufferPool,
+    ) -> Result<QueuePkt> {
         let pkt_batch_size = config.mqtt_pkt_batch_size as usize;
 
         // before reading from socket, send remaining packets to shard.
@@ -172,7 +340,7 @@ impl Socket {
                 status @ QueueStatus::Disconnected(_) => break Ok(status),
             }
 
-            let mut status = self.read_packet(prefix, config)?;
+            let mut status = self.read_packet(prefix, config, pool)?;
             self.rd.packets.extend(status.take_values().into_iter());
 
             match status {
@@ -180,7 +348,7 @@ impl Socket {
                 QueueStatus::Ok(_) => break Ok(self.send_upstream(prefix)),
                 QueueStatus::Block(_) => break Ok(self.send_upstream(prefix)),
                 status @ QueueStatus::Disconnected(_) if self.rd.packets.len() == 0 => {
-                    break Ok(status)
+                    break Ok(status.map(Vec::new()))
                 }
                 QueueStatus::Disconnected(_) => break Ok(self.send_upstream(prefix)),
             };
@@ -189,11 +357,31 @@ impl Socket {
 
     // MalformedPacket, implies a DISCONNECT and socket close
     // ProtocolError, implies DISCONNECT and socket close
-    fn read_packet(&mut self, prefix: &str, config: &Config) -> Result<QueuePkt> {
+    // SlowClient, packet not read within read-timeout, implies DISCONNECT and close
+    fn read_packet(
+        &mut self,
+        prefix: &str,
+        config: &Config,
+        pool: &mut BufferPool,
+    ) -> Result<QueueStatus<(v5::Packet, usize)>> {
         use crate::MQTTRead::{Fin, Header, Init, Remain};
 
-        let disconnected = QueuePkt::Disconnected(Vec::new());
+        let disconnected = QueueStatus::Disconnected(Vec::new());
 
+        // hold back reading from socket, until buffered packets are drained.
+        let limit = config.shard_max_buffer_bytes as usize;
+        if self.rd.session_tx.is_buffer_full(limit) {
+            trace!("{} read held back, shard buffer full", prefix);
+            return Ok(QueueStatus::Block(Vec::new()));
+        }
+
+        // read buffer is detached while the socket is idle, refer BufferPool.
+        if self.rd.pr.is_detached() {
+            self.rd.pr.attach(pool.take());
+        }
+
+        // oversized packets are rejected by MQTTRead, with PacketTooLarge, as soon as
+        // the fixed-header is read.
         let pr = mem::replace(&mut self.rd.pr, MQTTRead::default());
         let mut pr = match pr.read(&mut self.conn) {
             Ok((pr, _would_block)) => pr,
@@ -202,25 +390,43 @@ impl Socket {
         };
 
         let status = match &pr {
+            // idle connection, keep-alive timeout is handled by the session.
+            Init { .. } if pr.is_idle() => {
+                self.set_read_timeout(false, config.sock_mqtt_read_timeout as u64);
+                QueueStatus::Block(Vec::new())
+            }
             Init { .. } | Header { .. } | Remain { .. } if !self.read_elapsed() => {
                 trace!("{} read retrying", prefix);
                 self.set_read_timeout(true, config.sock_mqtt_read_timeout as u64);
                 QueueStatus::Block(Vec::new())
             }
             Init { .. } | Header { .. } | Remain { .. } => {
-                error!("{} rd_timeout:{:?} disconnecting", prefix, self.rd.timeout);
+                let timeout = self.rd.timeout;
                 self.set_read_timeout(false, config.sock_mqtt_read_timeout as u64);
-                QueueStatus::Disconnected(Vec::new())
+                err!(
+                    SlowClient,
+                    code: UnspecifiedError,
+                    "{} rd_timeout:{:?} partial packet",
+                    prefix,
+                    timeout
+                )?
             }
-            Fin { .. } => {
+            Fin { data, .. } => {
+                let n = data.len();
                 self.set_read_timeout(false, config.sock_mqtt_read_timeout as u64);
-                let pkt = pr.parse()?;
+                let pkt = pr.parse_with(&config.to_decode_config())?;
+                // first packet must be CONNECT, refer ConnState.
+                self.rd.state.transition(ConnEvent::Packet(pkt.to_packet_type()))?;
+                self.rd.session_tx.charge(n);
                 pr = pr.reset();
-                QueueStatus::Ok(vec![pkt])
+                QueueStatus::Ok(vec![(pkt, n)])
             }
             MQTTRead::None => unreachable!(),
         };
 
+        if let Some(buf) = pr.detach() {
+            pool.give(buf);
+        }
         let _pr_none = mem::replace(&mut self.rd.pr, pr);
         Ok(status)
     }
@@ -230,19 +436,24 @@ impl Socket {
         let mut session_tx = self.rd.session_tx.clone(); // shard woken when dropped
 
         let pkts = self.rd.packets.drain(..).collect();
-        let mut status = session_tx.try_sends(prefix, pkts);
+        let mut status = session_tx.try_sends_sized(prefix, pkts);
         self.rd.packets = status.take_values().into(); // left over packets
 
-        status
+        status.map(Vec::new())
     }
 }
 
-impl Socket {
-    pub fn write_packets(&mut self, prefix: &str, config: &Config) -> (QueuePkt, Stats) {
+impl<C: io::Read + io::Write> Socket<C> {
+    pub fn write_packets(
+        &mut self,
+        prefix: &str,
+        config: &Config,
+        pool: &mut BufferPool,
+    ) -> (QueuePkt, Stats) {
         // before reading from socket, send remaining packets to connection.
         let mut stats = Stats::default();
         loop {
-            match self.flush_packets(prefix, config) {
+            match self.flush_packets(prefix, config, pool) {
                 (QueueStatus::Ok(_), flush_stats) => stats.update(&flush_stats),
                 (status @ QueueStatus::Block(_), flush_stats) => {
                     stats.update(&flush_stats);
@@ -260,7 +471,7 @@ impl Socket {
             match status {
                 QueueStatus::Ok(_) => (),
                 QueueStatus::Block(_) => {
-                    let (status, flush_stats) = self.flush_packets(prefix, config);
+                    let (status, flush_stats) = self.flush_packets(prefix, config, pool);
                     stats.update(&flush_stats);
                     break (status, stats);
                 }
@@ -270,9 +481,12 @@ impl Socket {
     }
 
     // QueueStatus shall not carry any packets
-    pub fn flush_packets(&mut self, prefix: &str, config: &Config) -> (QueuePkt, Stats) {
-        use std::io::Write;
-
+    pub fn flush_packets(
+        &mut self,
+        prefix: &str,
+        config: &Config,
+        pool: &mut BufferPool,
+    ) -> (QueuePkt, Stats) {
         let mut iter = {
             let packets = self.wt.packets.drain(..).collect::<Vec<v5::Packet>>();
             packets.into_iter()
@@ -286,30 +500,47 @@ impl Socket {
                 res @ QueueStatus::Block(_) => break res,
                 res @ QueueStatus::Disconnected(_) => break res,
             }
+            // previous blob is fully written, only now load the next one.
             if let Some(packet) = iter.next() {
-                let blob = match packet.encode() {
-                    Ok(blob) => blob,
-                    Err(err) => {
-                        let pt = packet.to_packet_type();
-                        error!("{} packet:{:?} skipping err:{}", prefix, pt, err);
-                        continue;
+                let mut data = pool.take();
+                if let Err(err) = packet.encode_into(&mut data) {
+                    let pt = packet.to_packet_type();
+                    error!("{} packet:{:?} skipping err:{}", prefix, pt, err);
+                    pool.give(data);
+                    continue;
+                }
+                stats.bytes += data.len();
+                match &packet {
+                    v5::Packet::Disconnect(_) => self.wt.disconnected = true,
+                    v5::Packet::ConnAck(connack)
+                        if connack.code == v5::ConnackReasonCode::Success =>
+                    {
+                        self.rd.state.transition(ConnEvent::ConnackSent).ok();
                     }
-                };
-                stats.bytes += blob.as_ref().len();
+                    _ => (),
+                }
                 match self.conn.flush() {
                     Ok(()) => {
-                        let mut pw = mem::replace(&mut self.wt.pw, MQTTWrite::default());
+                        let pw = mem::replace(&mut self.wt.pw, MQTTWrite::default());
                         stats.items += 1;
-                        pw = pw.reset(blob.as_ref());
+                        let (pw, old) = pw.reset_with(data);
+                        pool.give(old);
                         let _pw_none = mem::replace(&mut self.wt.pw, pw);
                     }
-                    Err(_) => break QueueStatus::Disconnected(Vec::new()),
+                    Err(_) => {
+                        pool.give(data);
+                        break QueueStatus::Disconnected(Vec::new());
+                    }
                 };
             } else {
                 break QueueStatus::Ok(Vec::new());
             }
         };
 
+        // write buffer is recycled once the last packet is flushed.
+        if let Some(buf) = self.wt.pw.detach() {
+            pool.give(buf);
+        }
         self.wt.packets.extend(iter);
 
         (res, stats)
@@ -319,8 +550,15 @@ impl Socket {
     fn write_packet(&mut self, prefix: &str, config: &Config) -> QueuePkt {
         use crate::MQTTWrite::{Fin, Init, Remain};
 
-        let pw = mem::replace(&mut self.wt.pw, MQTTWrite::default());
-        let (res, pw) = match pw.write(&mut self.conn) {
+        let mut pw = mem::replace(&mut self.wt.pw, MQTTWrite::default());
+        let res = loop {
+            match pw.write(&mut self.conn) {
+                // short write, connection may take more, keep writing the same blob.
+                Ok((val @ Remain { .. }, false)) => pw = val,
+                res => break res,
+            }
+        };
+        let (res, pw) = match res {
             Ok((pw, _would_block)) => match &pw {
                 Init { .. } | Remain { .. } if !self.write_elapsed() => {
                     trace!("{} write retrying", prefix);
@@ -353,10 +591,28 @@ impl Socket {
 ///
 /// `waker` is attached to the thread receiving this messages from the queue.
 /// When PktTx is dropped, thread will be woken up using `waker`.
-pub fn pkt_channel(miot_id: u32, size: usize, waker: Arc<mio::Waker>) -> (PktTx, PktRx) {
+///
+/// `buffered` accounts for bytes read from sockets and held in the queue, it is
+/// shared by all the packet-queues of a shard.
+pub fn pkt_channel(
+    miot_id: u32,
+    size: usize,
+    waker: Arc<mio::Waker>,
+    buffered: Arc<AtomicUsize>,
+) -> (PktTx, PktRx) {
     let (tx, rx) = mpsc::sync_channel(size);
-    let pkt_tx = PktTx { miot_id, tx, waker, count: usize::default() };
-    let pkt_rx = PktRx { pkt_batch_size: size, rx };
+    let pkt_tx = PktTx {
+        miot_id,
+        tx,
+        waker,
+        buffered: Arc::clone(&buffered),
+        count: usize::default(),
+    };
+    let pkt_rx = PktRx { pkt_batch_size: size, rx, buffered, held: None };
 
     (pkt_tx, pkt_rx)
 }
+
+#[cfg(test)]
+#[path = "socket_test.rs"]
+mod socket_test;
@@ -0,0 +1,293 @@
+use std::io::Write;
+use std::{net, thread, time};
+
+use super::*;
+use crate::Packetize;
+
+fn new_socket(
+    waker: &Arc<mio::Waker>,
+    buffered: &Arc<AtomicUsize>,
+    lis: &net::TcpListener,
+) -> (Socket, net::TcpStream, PktRx) {
+    let client = net::TcpStream::connect(lis.local_addr().unwrap()).unwrap();
+    let (conn, _) = lis.accept().unwrap();
+    conn.set_nonblocking(true).unwrap();
+
+    let (session_tx, session_rx) =
+        pkt_channel(0, 16, Arc::clone(waker), Arc::clone(buffered));
+    let (_, miot_rx) = pkt_channel(0, 16, Arc::clone(waker), Arc::clone(buffered));
+
+    let max_size = Config::DEF_MQTT_MAX_PACKET_SIZE;
+    let socket = Socket {
+        client_id: ClientID("test-socket".to_string()),
+        conn: mio::net::TcpStream::from_std(conn),
+        guard: None,
+        token: mio::Token(1),
+        rd: Source {
+            pr: MQTTRead::new(max_size),
+            timeout: None,
+            session_tx,
+            packets: VecDeque::default(),
+            state: ConnState::Active,
+        },
+        wt: Sink {
+            pw: MQTTWrite::new(&[], max_size),
+            timeout: None,
+            miot_rx,
+            packets: VecDeque::default(),
+            disconnected: false,
+        },
+    };
+    (socket, client, session_rx)
+}
+
+fn new_publish(size: usize) -> v5::Packet {
+    v5::Packet::Publish(v5::Publish {
+        retain: false,
+        qos: v5::QoS::AtMostOnce,
+        duplicate: false,
+        topic_name: crate::TopicName::from("a/b/c".to_string()),
+        packet_id: None,
+        properties: None,
+        payload: Some(vec![0xAB; size]),
+    })
+}
+
+// a packet is read from socket in several steps, refer to MQTTRead.
+fn read_packets(sock: &mut Socket, config: &Config) {
+    for _ in 0..4 {
+        sock.read_packets("test", config, &mut BufferPool::default()).unwrap();
+    }
+}
+
+#[test]
+fn test_socket_buffer_limit() {
+    let poll = mio::Poll::new().unwrap();
+    let waker = Arc::new(mio::Waker::new(poll.registry(), mio::Token(0)).unwrap());
+    let buffered = Arc::new(AtomicUsize::new(0));
+    let lis = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let config = Config { shard_max_buffer_bytes: 1000, ..Config::default() };
+
+    let (mut sock1, mut client1, rx1) = new_socket(&waker, &buffered, &lis);
+    let (mut sock2, mut client2, rx2) = new_socket(&waker, &buffered, &lis);
+
+    let blob = new_publish(1500).encode().unwrap();
+    client1.write_all(blob.as_ref()).unwrap();
+    client2.write_all(blob.as_ref()).unwrap();
+    thread::sleep(time::Duration::from_millis(100));
+
+    // first read goes through, charging the shard beyond its limit.
+    read_packets(&mut sock1, &config);
+    assert_eq!(buffered.load(SeqCst), blob.as_ref().len());
+
+    // second read is held back while the first is un-drained.
+    read_packets(&mut sock2, &config);
+    let status = sock2.read_packet("sock2", &config, &mut BufferPool::default()).unwrap();
+    assert!(matches!(status, QueueStatus::Block(pkts) if pkts.is_empty()));
+    assert!(rx2.try_recvs("rx2").take_values().is_empty());
+
+    // draining the first packet frees memory for the second read.
+    assert_eq!(rx1.try_recvs("rx1").take_values(), vec![new_publish(1500)]);
+    assert_eq!(buffered.load(SeqCst), 0);
+
+    read_packets(&mut sock2, &config);
+    assert_eq!(rx2.try_recvs("rx2").take_values(), vec![new_publish(1500)]);
+    assert_eq!(buffered.load(SeqCst), 0);
+}
+
+#[test]
+fn test_socket_buffer_release() {
+    let poll = mio::Poll::new().unwrap();
+    let waker = Arc::new(mio::Waker::new(poll.registry(), mio::Token(0)).unwrap());
+    let buffered = Arc::new(AtomicUsize::new(0));
+    let lis = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let config = Config::default();
+
+    let (mut sock, mut client, rx) = new_socket(&waker, &buffered, &lis);
+
+    let blob = new_publish(100).encode().unwrap();
+    for _ in 0..2 {
+        client.write_all(blob.as_ref()).unwrap();
+    }
+    thread::sleep(time::Duration::from_millis(100));
+    read_packets(&mut sock, &config);
+    assert_eq!(buffered.load(SeqCst), blob.as_ref().len() * 2);
+
+    // session goes away with packets still queued.
+    mem::drop(rx);
+    assert_eq!(buffered.load(SeqCst), 0);
+
+    // packets read after that are held by the socket, until it goes away.
+    client.write_all(blob.as_ref()).unwrap();
+    thread::sleep(time::Duration::from_millis(100));
+    for _ in 0..4 {
+        sock.read_packets("test", &config, &mut BufferPool::default()).ok();
+    }
+    assert_eq!(sock.rd.packets.len(), 1);
+    assert_eq!(buffered.load(SeqCst), blob.as_ref().len());
+    mem::drop(sock);
+    assert_eq!(buffered.load(SeqCst), 0);
+}
+
+#[test]
+fn test_socket_packet_too_large() {
+    let poll = mio::Poll::new().unwrap();
+    let waker = Arc::new(mio::Waker::new(poll.registry(), mio::Token(0)).unwrap());
+    let buffered = Arc::new(AtomicUsize::new(0));
+    let lis = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let config = Config {
+        mqtt_max_packet_size: 1024 * 1024,
+        ..Config::default()
+    };
+
+    let (mut sock, mut client, _rx) = new_socket(&waker, &buffered, &lis);
+
+    // fixed-header of a PUBLISH claiming 10MB, without the payload.
+    let remaining_len = crate::VarU32(10 * 1024 * 1024);
+    let fh = v5::FixedHeader::new(v5::PacketType::Publish, remaining_len).unwrap();
+    client.write_all(fh.encode().unwrap().as_ref()).unwrap();
+    thread::sleep(time::Duration::from_millis(100));
+
+    match sock.read_packets("test", &config, &mut BufferPool::default()) {
+        Err(err) => {
+            assert_eq!(err.kind(), ErrorKind::MalformedPacket);
+            assert_eq!(err.code(), crate::ReasonCode::PacketTooLarge);
+        }
+        Ok(_) => panic!("expected PacketTooLarge"),
+    }
+}
+
+#[test]
+fn test_socket_first_packet_connect() {
+    use crate::broker::{mem_socket, MemSocket};
+
+    let poll = mio::Poll::new().unwrap();
+    let waker = Arc::new(mio::Waker::new(poll.registry(), mio::Token(0)).unwrap());
+    let buffered = Arc::new(AtomicUsize::new(0));
+    let config = Config::default();
+
+    let connect = v5::Packet::Connect(v5::Connect::default());
+    let rfr = v5::RetainForwardRule::OnEverySubscribe;
+    let subscribe = v5::Packet::Subscribe(v5::Subscribe {
+        packet_id: 1,
+        properties: None,
+        filters: vec![v5::SubscribeFilter {
+            topic_filter: crate::TopicFilter::from("a/b".to_string()),
+            opt: v5::SubscriptionOpt::new(rfr, false, false, v5::QoS::AtMostOnce),
+        }],
+    });
+
+    // in-memory connection, fed bytes are readable right away.
+    let new_sock = || -> (MemSocket, PktRx) {
+        let (session_tx, session_rx) =
+            pkt_channel(0, 16, Arc::clone(&waker), Arc::clone(&buffered));
+        let (_, miot_rx) = pkt_channel(0, 16, Arc::clone(&waker), Arc::clone(&buffered));
+        let client_id = ClientID("test-first-packet".to_string());
+        let sock =
+            mem_socket(client_id, &config, session_tx, miot_rx, ConnState::Handshake);
+        (sock, session_rx)
+    };
+    let read = |sock: &mut MemSocket| -> Result<()> {
+        for _ in 0..4 {
+            sock.read_packets("test", &config, &mut BufferPool::default())?;
+        }
+        Ok(())
+    };
+
+    // SUBSCRIBE as the first packet is rejected.
+    let (mut sock, _rx) = new_sock();
+    sock.conn.feed(subscribe.encode().unwrap().as_ref());
+    let err = read(&mut sock).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ProtocolError);
+    assert_eq!(err.code(), crate::ReasonCode::ProtocolError);
+
+    // CONNECT followed by another CONNECT is rejected.
+    let (mut sock, rx) = new_sock();
+    sock.conn.feed(connect.encode().unwrap().as_ref());
+    read(&mut sock).unwrap();
+    assert_eq!(sock.rd.state, ConnState::Connecting);
+    assert_eq!(rx.try_recvs("rx").take_values(), vec![connect.clone()]);
+
+    sock.conn.feed(connect.encode().unwrap().as_ref());
+    let err = read(&mut sock).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ProtocolError);
+}
+
+#[test]
+fn test_atomic_stats() {
+    let (n_threads, n_iters) = (8, 10_000);
+    let atomic_stats = Arc::new(AtomicStats::default());
+
+    let handles: Vec<thread::JoinHandle<()>> = (0..n_threads)
+        .map(|_| {
+            let atomic_stats = Arc::clone(&atomic_stats);
+            thread::spawn(move || {
+                for i in 0..n_iters {
+                    atomic_stats.incr(&Stats { items: 1, bytes: 10 });
+                    atomic_stats.incr_connections(1);
+                    if i % 2 == 1 {
+                        atomic_stats.decr_connections(1);
+                    }
+                }
+            })
+        })
+        .collect();
+    for handle in handles.into_iter() {
+        handle.join().unwrap();
+    }
+
+    let snapshot = atomic_stats.to_snapshot();
+    assert_eq!(snapshot.items, n_threads * n_iters);
+    assert_eq!(snapshot.bytes, n_threads * n_iters * 10);
+    assert_eq!(snapshot.connections, n_threads * n_iters / 2);
+}
+
+#[test]
+fn test_socket_buffer_pool() {
+    let poll = mio::Poll::new().unwrap();
+    let waker = Arc::new(mio::Waker::new(poll.registry(), mio::Token(0)).unwrap());
+    let buffered = Arc::new(AtomicUsize::new(0));
+    let lis = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let config = Config::default();
+
+    let mut pool = BufferPool::new(4, 1024);
+    let (mut sock, mut client, rx) = new_socket(&waker, &buffered, &lis);
+    sock.rd.pr = MQTTRead::with_buffer(Vec::new(), config.mqtt_max_packet_size);
+    sock.wt.pw = MQTTWrite::with_buffer(Vec::new(), config.mqtt_max_packet_size);
+
+    // packets are encoded into pooled buffers, returned after they are flushed.
+    for i in 0..3 {
+        sock.wt.packets.extend(vec![new_publish(100), new_publish(2000)]);
+        match sock.flush_packets("test", &config, &mut pool) {
+            (QueueStatus::Ok(_), stats) => assert_eq!(stats.items, 2),
+            (_, _) => panic!("unexpected flush status"),
+        }
+        assert!(sock.wt.pw.detach().is_none());
+
+        let stats = pool.to_stats();
+        assert_eq!(stats.n_misses, 2, "{:?}", stats);
+        assert_eq!(stats.n_hits, i * 2, "{:?}", stats);
+        assert_eq!(stats.n_returns, (i + 1) * 2, "{:?}", stats);
+        assert_eq!(stats.n_pooled, 2, "{:?}", stats);
+    }
+
+    // read buffer is taken from the pool and returned after the packet is read.
+    client.write_all(new_publish(100).encode().unwrap().as_ref()).unwrap();
+    thread::sleep(time::Duration::from_millis(100));
+    for _ in 0..4 {
+        sock.read_packets("test", &config, &mut pool).unwrap();
+    }
+    assert_eq!(rx.try_recvs("rx").take_values(), vec![new_publish(100)]);
+    assert!(sock.rd.pr.is_detached());
+    assert_eq!(pool.to_stats().n_pooled, 2);
+    assert_eq!(pool.to_stats().n_misses, 2);
+
+    // pooled buffers are shrunk back to pool's capacity.
+    let mut pool = BufferPool::new(1, 1024);
+    pool.give(vec![0; 4096]);
+    pool.give(vec![0; 4096]);
+    let stats = pool.to_stats();
+    assert_eq!((stats.n_returns, stats.n_pooled), (1, 1));
+    let buf = pool.take();
+    assert!(buf.is_empty() && buf.capacity() < 4096);
+}
@@ -0,0 +1,129 @@
+use crossbeam_deque::{Steal, Stealer, Worker};
+
+use std::collections::BTreeMap;
+
+use crate::{v5, ClientID};
+
+/// Routable work, that is not affine to any session, and can be processed by any
+/// shard in the node.
+///
+/// Messages to and from a session, like [Message::Routed], are ordering sensitive
+/// and shall always be handled by the session's shard, hence they cannot be
+/// expressed as Work.
+///
+/// [Message::Routed]: crate::broker::Message::Routed
+#[derive(Debug)]
+pub enum Work {
+    /// Broker originated PUBLISH, like `$SYS` topics and will messages, that shall be
+    /// routed to matching subscribers. `origin` is the client on whose behalf the
+    /// message is published, if any.
+    Publish {
+        publish: v5::Publish,
+        origin: Option<ClientID>,
+    },
+}
+
+/// Type implement a per-shard work queue, when [Config::enable_work_stealing] is
+/// true. Shards push routable [Work] into their own queue, and a shard whose queue
+/// is empty steals work from other shard's queue. Stealing is attempted only when
+/// a shard wakes up for its own events, shards are not woken up to steal work.
+///
+/// [Config::enable_work_stealing]: crate::broker::Config::enable_work_stealing
+pub struct WorkQueue {
+    shard_id: u32,
+    local: Worker<Work>,
+    // (shard_id, stealer) for all other shards in this node.
+    stealers: Vec<(u32, Stealer<Work>)>,
+    stats: WorkStats,
+}
+
+/// Statistics for [WorkQueue].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WorkStats {
+    /// Number of work items pushed into this queue.
+    pub n_pushed: usize,
+    /// Number of work items processed by this shard, including the stolen ones.
+    pub n_popped: usize,
+    /// Number of work items stolen from other shards.
+    pub n_stolen: usize,
+}
+
+/// Create a work queue for each shard in `shard_ids`, wired to steal from one another.
+pub fn work_queues(shard_ids: &[u32]) -> BTreeMap<u32, WorkQueue> {
+    let workers: Vec<(u32, Worker<Work>)> =
+        shard_ids.iter().map(|id| (*id, Worker::new_fifo())).collect();
+    let stealers: Vec<(u32, Stealer<Work>)> =
+        workers.iter().map(|(id, w)| (*id, w.stealer())).collect();
+
+    let mut queues = BTreeMap::default();
+    for (shard_id, local) in workers.into_iter() {
+        let stealers = stealers.iter().filter(|(id, _)| *id != shard_id).cloned();
+        let queue = WorkQueue {
+            shard_id,
+            local,
+            stealers: stealers.collect(),
+            stats: WorkStats::default(),
+        };
+        queues.insert(shard_id, queue);
+    }
+
+    queues
+}
+
+impl WorkQueue {
+    pub fn push(&mut self, work: Work) {
+        self.stats.n_pushed += 1;
+        self.local.push(work)
+    }
+
+    /// Pop upto `batch` work items from this shard's queue, in their push order. If
+    /// this shard's queue is empty, steal a batch of work from other shards.
+    pub fn pop_batch(&mut self, batch: usize) -> Vec<Work> {
+        let mut works = Vec::default();
+        if self.local.is_empty() {
+            if let Some(work) = self.steal() {
+                works.push(work);
+            }
+        }
+        while works.len() < batch {
+            match self.local.pop() {
+                Some(work) => works.push(work),
+                None => break,
+            }
+        }
+
+        self.stats.n_popped += works.len();
+        works
+    }
+
+    pub fn to_shard_id(&self) -> u32 {
+        self.shard_id
+    }
+
+    pub fn to_stats(&self) -> WorkStats {
+        self.stats
+    }
+
+    // Steal a batch of work, from the first shard that has some, into local queue.
+    fn steal(&mut self) -> Option<Work> {
+        for (_, stealer) in self.stealers.iter() {
+            let work = loop {
+                match stealer.steal_batch_and_pop(&self.local) {
+                    Steal::Success(work) => break Some(work),
+                    Steal::Empty => break None,
+                    Steal::Retry => (),
+                }
+            };
+            if let Some(work) = work {
+                self.stats.n_stolen += 1 + self.local.len();
+                return Some(work);
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+#[path = "steal_test.rs"]
+mod steal_test;
@@ -0,0 +1,98 @@
+use std::sync::{atomic::AtomicUsize, atomic::Ordering::SeqCst, Arc};
+use std::{thread, time};
+
+use super::*;
+use crate::TopicName;
+
+fn new_work(i: usize) -> Work {
+    let publish = v5::Publish {
+        retain: false,
+        qos: v5::QoS::AtMostOnce,
+        duplicate: false,
+        topic_name: TopicName::from(format!("$SYS/broker/{}", i)),
+        packet_id: None,
+        properties: None,
+        payload: Some(i.to_string().into_bytes()),
+    };
+    Work::Publish { publish, origin: None }
+}
+
+#[test]
+fn test_work_queue_local() {
+    let mut queues = work_queues(&[0, 1, 2]);
+    assert_eq!(queues.len(), 3);
+
+    let queue = queues.get_mut(&0).unwrap();
+    assert_eq!(queue.to_shard_id(), 0);
+    for i in 0..10 {
+        queue.push(new_work(i));
+    }
+
+    // own work is popped in push order.
+    let topics: Vec<String> = queue
+        .pop_batch(4)
+        .into_iter()
+        .map(|Work::Publish { publish, .. }| publish.topic_name.to_string())
+        .collect();
+    assert_eq!(
+        topics,
+        vec!["$SYS/broker/0", "$SYS/broker/1", "$SYS/broker/2", "$SYS/broker/3"]
+    );
+
+    // idle shard steals from a busy shard.
+    let queue = queues.get_mut(&2).unwrap();
+    let works = queue.pop_batch(16);
+    assert!(!works.is_empty());
+    let stats = queue.to_stats();
+    assert_eq!(stats.n_pushed, 0);
+    assert_eq!(stats.n_popped, works.len());
+    assert!(stats.n_stolen >= works.len());
+
+    // remaining work is drained across shards, then nothing is left to steal.
+    let n = queues.values_mut().map(|q| q.pop_batch(16).len()).sum::<usize>();
+    assert_eq!(n + 4 + works.len(), 10);
+    assert!(queues.get_mut(&1).unwrap().pop_batch(16).is_empty());
+}
+
+#[test]
+fn test_work_queue_skewed_load() {
+    let (n_works, batch) = (1000, 16);
+
+    let mut queues = work_queues(&[0, 1]);
+    let mut hot = queues.remove(&0).unwrap();
+    let mut idle = queues.remove(&1).unwrap();
+
+    // all work lands on the hot shard.
+    for i in 0..n_works {
+        hot.push(new_work(i));
+    }
+
+    let done = Arc::new(AtomicUsize::new(0));
+    let handle = {
+        let done = Arc::clone(&done);
+        thread::spawn(move || {
+            while done.load(SeqCst) < n_works {
+                let n = idle.pop_batch(batch).len();
+                done.fetch_add(n, SeqCst);
+                if n == 0 {
+                    thread::sleep(time::Duration::from_micros(100));
+                }
+            }
+            idle
+        })
+    };
+
+    // hot shard is slow in processing its work.
+    while done.load(SeqCst) < n_works {
+        let n = hot.pop_batch(batch).len();
+        done.fetch_add(n, SeqCst);
+        thread::sleep(time::Duration::from_millis(1));
+    }
+    let idle = handle.join().unwrap();
+
+    let (hs, is) = (hot.to_stats(), idle.to_stats());
+    assert_eq!(hs.n_pushed, n_works);
+    assert_eq!(hs.n_popped + is.n_popped, n_works);
+    assert!(is.n_popped > 0, "{:?}", is);
+    assert!(is.n_stolen >= is.n_popped, "{:?}", is);
+}
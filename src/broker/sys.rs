@@ -0,0 +1,88 @@
+use std::time;
+
+use crate::broker::Metrics;
+use crate::{v5, TopicName};
+
+/// Type publish broker statistics, as retained messages, under `$SYS/broker/`.
+///
+/// SysPublisher is owned by the cluster thread, which is periodically woken up by
+/// the [Ticker]. Every `sys_interval` seconds, refer [Config::sys_interval], the
+/// following topics are published from [Metrics]:
+///
+/// * `$SYS/broker/clients/connected`, sessions with an active connection.
+/// * `$SYS/broker/clients/disconnected`, sessions waiting for their client.
+/// * `$SYS/broker/messages/received`, packets received from clients.
+/// * `$SYS/broker/messages/retained`, retained messages.
+/// * `$SYS/broker/subscriptions/count`, subscribed topic-filters.
+/// * `$SYS/broker/uptime`, seconds since broker started.
+///
+/// As per MQTT spec, topic-filters starting with a wildcard do not match `$SYS`
+/// topics, clients have to explicitly subscribe, say, to `$SYS/#`.
+///
+/// [Ticker]: crate::broker::Ticker
+/// [Config::sys_interval]: crate::broker::Config::sys_interval
+pub struct SysPublisher {
+    interval: time::Duration,
+    born: time::Instant,
+    last: Option<time::Instant>,
+}
+
+impl SysPublisher {
+    pub fn new(interval_secs: u32) -> SysPublisher {
+        SysPublisher {
+            interval: time::Duration::from_secs(u64::from(interval_secs)),
+            born: time::Instant::now(),
+            last: None,
+        }
+    }
+
+    /// Return whether `sys_interval` has elapsed since the last publish, as of `now`.
+    pub fn is_due(&self, now: time::Instant) -> bool {
+        match self.last {
+            Some(last) => now.saturating_duration_since(last) >= self.interval,
+            None => true,
+        }
+    }
+
+    /// Return retained PUBLISH messages for each `$SYS` topic, sourced from
+    /// `metrics`, and mark `now` as the last publish.
+    pub fn to_publishes(
+        &mut self,
+        now: time::Instant,
+        metrics: &Metrics,
+    ) -> Vec<v5::Publish> {
+        self.last = Some(now);
+
+        let shards = &metrics.shards;
+        let uptime = now.saturating_duration_since(self.born).as_secs();
+        let values = [
+            ("clients/connected", shards.iter().map(|m| m.n_sessions).sum::<usize>()),
+            ("clients/disconnected", shards.iter().map(|m| m.n_reconnects).sum()),
+            ("messages/received", shards.iter().map(|m| m.n_packets).sum()),
+            ("messages/retained", metrics.n_retained),
+            ("subscriptions/count", metrics.n_subscriptions),
+        ];
+
+        let mut publishes: Vec<v5::Publish> =
+            values.iter().map(|(topic, value)| new_publish(topic, *value)).collect();
+        publishes.push(new_publish("uptime", uptime as usize));
+
+        publishes
+    }
+}
+
+fn new_publish(topic: &str, value: usize) -> v5::Publish {
+    v5::Publish {
+        retain: true,
+        qos: v5::QoS::AtMostOnce,
+        duplicate: false,
+        topic_name: TopicName::from(format!("$SYS/broker/{}", topic)),
+        packet_id: None,
+        properties: None,
+        payload: Some(value.to_string().into_bytes()),
+    }
+}
+
+#[cfg(test)]
+#[path = "sys_test.rs"]
+mod sys_test;
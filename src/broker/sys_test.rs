@@ -0,0 +1,46 @@
+use super::*;
+
+use crate::broker::ShardMetrics;
+
+#[test]
+fn test_sys_publisher() {
+    let now = time::Instant::now();
+    let mut sp = SysPublisher::new(10);
+    assert!(sp.is_due(now));
+
+    let metrics = Metrics {
+        n_subscriptions: 3,
+        n_retained: 4,
+        shards: vec![
+            ShardMetrics {
+                n_sessions: 1,
+                n_packets: 10,
+                ..ShardMetrics::default()
+            },
+            ShardMetrics {
+                n_sessions: 2,
+                n_reconnects: 1,
+                ..ShardMetrics::default()
+            },
+        ],
+        ..Metrics::default()
+    };
+    let publishes = sp.to_publishes(now, &metrics);
+    assert!(!sp.is_due(now + time::Duration::from_secs(9)));
+    assert!(sp.is_due(now + time::Duration::from_secs(10)));
+
+    let refs = [
+        ("$SYS/broker/clients/connected", "3"),
+        ("$SYS/broker/clients/disconnected", "1"),
+        ("$SYS/broker/messages/received", "10"),
+        ("$SYS/broker/messages/retained", "4"),
+        ("$SYS/broker/subscriptions/count", "3"),
+        ("$SYS/broker/uptime", "0"),
+    ];
+    assert_eq!(publishes.len(), refs.len());
+    for (publish, (topic, value)) in publishes.iter().zip(refs.iter()) {
+        assert!(publish.retain);
+        assert_eq!(publish.topic_name, TopicName::from(topic.to_string()));
+        assert_eq!(publish.payload, Some(value.as_bytes().to_vec()));
+    }
+}
@@ -1,12 +1,13 @@
 use log::{debug, error, info, trace};
+use rand::Rng;
 
 use std::{mem, sync::mpsc, thread, time};
 
 use crate::broker::thread::{Rx, Thread, Threadable};
 use crate::broker::{AppTx, Cluster, Config, Shard};
 
+use crate::ToJson;
 use crate::{Error, ErrorKind, Result};
-use crate::{ToJson, SLEEP_10MS};
 
 /// Type implement a periodic ticker and wake up other threads.
 ///
@@ -94,7 +95,13 @@ impl Drop for Ticker {
 
 impl ToJson for Ticker {
     fn to_config_json(&self) -> String {
-        "".to_string()
+        format!(
+            concat!("{{ {:?}: {}, {:?}: {} }}"),
+            "ticker_interval_ms",
+            self.config.ticker_interval_ms,
+            "ticker_jitter_ms",
+            self.config.ticker_jitter_ms
+        )
     }
 
     fn to_stats_json(&self) -> String {
@@ -154,6 +161,23 @@ impl Ticker {
     }
 }
 
+impl Ticker {
+    /// Return the configured interval between ticks, excluding jitter.
+    pub fn interval(&self) -> time::Duration {
+        time::Duration::from_millis(self.config.ticker_interval_ms as u64)
+    }
+
+    // Return the duration to sleep before the next tick, interval plus a random
+    // jitter within [0, ticker_jitter_ms].
+    fn next_sleep(&self) -> time::Duration {
+        let jitter = match self.config.ticker_jitter_ms {
+            0 => 0,
+            n => rand::thread_rng().gen_range(0..=n),
+        };
+        self.interval() + time::Duration::from_millis(jitter as u64)
+    }
+}
+
 pub enum Request {
     Close,
 }
@@ -181,10 +205,10 @@ impl Threadable for Ticker {
     type Resp = Result<Response>;
 
     fn main_loop(mut self, rx: Rx<Request, Result<Response>>) -> Self {
-        info!("{} spawn thread, staring 10ms ticker", self.prefix);
+        info!("{} spawn thread config:{}", self.prefix, self.to_config_json());
 
         'outer: loop {
-            thread::sleep(SLEEP_10MS);
+            thread::sleep(self.next_sleep());
 
             loop {
                 match rx.try_recv() {
@@ -244,3 +268,7 @@ impl Ticker {
         format!("<t:{}:{}>", self.name, state)
     }
 }
+
+#[cfg(test)]
+#[path = "ticker_test.rs"]
+mod ticker_test;
@@ -0,0 +1,35 @@
+use super::*;
+
+#[test]
+fn test_ticker_interval() {
+    let config = Config {
+        ticker_interval_ms: 20,
+        ticker_jitter_ms: 0,
+        ..Config::default()
+    };
+    let ticker = Ticker::from_config(config).unwrap();
+    assert_eq!(ticker.interval(), time::Duration::from_millis(20));
+    for _ in 0..100 {
+        assert_eq!(ticker.next_sleep(), ticker.interval());
+    }
+}
+
+#[test]
+fn test_ticker_jitter() {
+    let config = Config {
+        ticker_interval_ms: 10,
+        ticker_jitter_ms: 5,
+        ..Config::default()
+    };
+    let ticker = Ticker::from_config(config).unwrap();
+
+    let (lower, upper) =
+        (time::Duration::from_millis(10), time::Duration::from_millis(15));
+    let mut jittered = false;
+    for _ in 0..1000 {
+        let sleep = ticker.next_sleep();
+        assert!(sleep >= lower && sleep <= upper, "{:?}", sleep);
+        jittered = jittered || sleep > lower;
+    }
+    assert!(jittered);
+}
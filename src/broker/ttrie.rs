@@ -1,7 +1,7 @@
-use std::{borrow::Borrow, sync::Arc};
+use std::{borrow::Borrow, collections::BTreeMap, sync::Arc, time};
 
 use crate::broker::Spinlock;
-use crate::{v5, v5::Subscription, IterTopicPath};
+use crate::{v5, v5::Subscription, ClientID, IterTopicPath, TopicFilter, TopicName};
 
 /// Type implement a MVCC trie for managing topic-subscriptions.
 ///
@@ -29,6 +29,15 @@ impl SubscribedTrie {
     pub fn clone(&self) -> SubscribedTrie {
         SubscribedTrie { inner: Arc::clone(&self.inner) }
     }
+
+    /// Return the number of unique topic-filters subscribed.
+    pub fn len(&self) -> usize {
+        self.inner.read().stats.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 }
 
 impl SubscribedTrie {
@@ -51,6 +60,8 @@ impl SubscribedTrie {
         K: IterTopicPath<'b>,
     {
         let in_levels = key.iter_topic_path();
+        // topic-names starting with `$` must not match wildcard at the first level.
+        let dollar = key.iter_topic_path().next().map(|l| l.starts_with('$'));
 
         let (mut stats, root) = {
             let inner = Arc::clone(&self.inner.read());
@@ -59,7 +70,7 @@ impl SubscribedTrie {
 
         stats.lookups = stats.lookups.saturating_add(1);
 
-        let matches = match root.match_topic(in_levels, true /*dollar-match*/) {
+        let matches = match root.match_topic(in_levels, dollar.unwrap_or(false)) {
             Some(vals) => {
                 stats.hits = stats.hits.saturating_add(1);
                 vals
@@ -72,6 +83,42 @@ impl SubscribedTrie {
 
         matches
     }
+
+    /// Return all topic-filters subscribed by `client_id`, along with their
+    /// subscription options.
+    pub fn subscriptions_of(
+        &self,
+        client_id: &ClientID,
+    ) -> Vec<(TopicFilter, v5::SubscriptionOpt)> {
+        self.client_subscriptions(client_id)
+            .into_iter()
+            .map(|s| {
+                let opt = v5::SubscriptionOpt::new(
+                    s.retain_forward_rule,
+                    s.retain_as_published,
+                    s.no_local,
+                    s.qos,
+                );
+                (s.topic_filter, opt)
+            })
+            .collect()
+    }
+
+    /// Remove all subscriptions of `client_id`, trie nodes left without any
+    /// subscription are pruned.
+    pub fn remove_client(&mut self, client_id: &ClientID) {
+        for value in self.client_subscriptions(client_id).iter() {
+            self.do_unsubscribe(&value.topic_filter, value);
+        }
+    }
+
+    fn client_subscriptions(&self, client_id: &ClientID) -> Vec<Subscription> {
+        let root = Arc::clone(&self.inner.read().root);
+
+        let mut acc = vec![];
+        root.collect_values(&mut acc, &|s: &Subscription| &s.client_id == client_id);
+        acc
+    }
 }
 
 impl SubscribedTrie {
@@ -129,6 +176,8 @@ impl SubscribedTrie {
 /// Indexed with TopicName and matched using TopicFilter.
 pub struct RetainedTrie {
     inner: Arc<Spinlock<Arc<Inner<v5::Publish>>>>,
+    // Retained messages with `message_expiry_interval`, indexed by topic-name.
+    expiry: Arc<Spinlock<BTreeMap<TopicName, time::Instant>>>,
 }
 
 impl Default for RetainedTrie {
@@ -137,31 +186,88 @@ impl Default for RetainedTrie {
             stats: Stats::default(),
             root: Arc::new(Node::<v5::Publish>::Root { children: Vec::default() }),
         };
-        RetainedTrie { inner: Arc::new(Spinlock::new(Arc::new(inner))) }
+        RetainedTrie {
+            inner: Arc::new(Spinlock::new(Arc::new(inner))),
+            expiry: Arc::new(Spinlock::new(BTreeMap::default())),
+        }
     }
 }
 
 impl RetainedTrie {
     pub fn clone(&self) -> RetainedTrie {
-        RetainedTrie { inner: Arc::clone(&self.inner) }
+        RetainedTrie {
+            inner: Arc::clone(&self.inner),
+            expiry: Arc::clone(&self.expiry),
+        }
+    }
+
+    /// Return the number of topics with a retained message.
+    pub fn len(&self) -> usize {
+        self.inner.read().stats.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 }
 
 impl RetainedTrie {
+    /// Retain `value` for topic `key`. If `value` carries a `message_expiry_interval`
+    /// it shall be purged after the interval, refer [RetainedTrie::purge_expired],
+    /// an interval of ZERO removes the retained message right away.
+    ///
+    /// As per MQTT spec, `value` with zero-length payload removes the retained
+    /// message for topic `key`, it is not retained.
     pub fn set<'b, K>(&self, key: &'b K, value: v5::Publish)
     where
         K: IterTopicPath<'b>,
     {
-        self.do_set(key, value)
+        let topic_name = to_topic_name(key);
+        let expiry = value.properties.as_ref().and_then(|p| p.message_expiry_interval);
+
+        match expiry {
+            _ if is_deleted(&value) => self.remove(key),
+            Some(0) => self.remove(key),
+            Some(secs) => {
+                self.do_set(key, value);
+                let interval = time::Duration::from_secs(secs.into());
+                self.expiry.write().insert(topic_name, time::Instant::now() + interval);
+            }
+            None => {
+                self.do_set(key, value);
+                self.expiry.write().remove(&topic_name);
+            }
+        }
     }
 
     pub fn remove<'a, K>(&self, key: &'a K)
     where
         K: IterTopicPath<'a>,
     {
+        self.expiry.write().remove(&to_topic_name(key));
         self.do_remove(key)
     }
 
+    /// Remove retained messages whose expiry interval has elapsed by `now`. Return
+    /// the number of messages removed.
+    pub fn purge_expired(&self, now: time::Instant) -> usize {
+        let topic_names: Vec<TopicName> = {
+            let mut expiry = self.expiry.write();
+            let topic_names: Vec<TopicName> = expiry
+                .iter()
+                .filter(|(_, deadline)| **deadline <= now)
+                .map(|(topic_name, _)| topic_name.clone())
+                .collect();
+            topic_names.iter().for_each(|topic_name| {
+                expiry.remove(topic_name);
+            });
+            topic_names
+        };
+
+        topic_names.iter().for_each(|topic_name| self.do_remove(topic_name));
+        topic_names.len()
+    }
+
     pub fn match_topic_filter<'b, K>(&self, key: &'b K) -> Option<v5::Publish>
     where
         K: IterTopicPath<'b>,
@@ -175,20 +281,37 @@ impl RetainedTrie {
 
         stats.lookups = stats.lookups.saturating_add(1);
 
+        // removed and purged topics can leave behind nodes without values.
         let res = match root.match_topic(in_levels, false) {
-            Some(mut vals) => {
+            Some(mut vals) if !vals.is_empty() => {
                 assert!(vals.len() == 1);
                 stats.hits = stats.hits.saturating_add(1);
                 Some(vals.remove(0))
             }
-            None => None,
+            _ => None,
         };
+        let res = res.filter(|value| !is_deleted(value));
 
         let inner = Inner { stats, root: Arc::clone(&root) };
         *self.inner.write() = Arc::new(inner);
 
         res
     }
+
+    /// Return retained messages for all topic-names matching the topic-filter `key`,
+    /// unlike [RetainedTrie::match_topic_filter], `key` can have wildcards.
+    pub fn match_retained<'b, K>(&self, key: &'b K) -> Vec<v5::Publish>
+    where
+        K: IterTopicPath<'b>,
+    {
+        let in_levels = key.iter_topic_path();
+        let root = Arc::clone(&self.inner.read().root);
+
+        let mut acc = vec![];
+        root.match_filter(in_levels, &mut acc);
+        acc.retain(|value| !is_deleted(value));
+        acc
+    }
 }
 
 impl RetainedTrie {
@@ -509,6 +632,69 @@ impl<V> Node<V> {
         }
     }
 
+    // walk the entire trie and gather values accepted by `filter`.
+    fn collect_values<F>(&self, acc: &mut Vec<V>, filter: &F)
+    where
+        F: Fn(&V) -> bool,
+        V: Clone,
+    {
+        let children = match self {
+            Node::Root { children } => children,
+            Node::Child { children, values, .. } => {
+                acc.extend(values.iter().filter(|v| filter(v)).cloned());
+                children
+            }
+        };
+        children.iter().for_each(|child| child.collect_values(acc, filter));
+    }
+
+    // match topic-filter `in_levels` with topic-names indexed in the trie.
+    fn match_filter<'a, I>(&self, mut in_levels: I, acc: &mut Vec<V>)
+    where
+        I: Iterator<Item = &'a str> + Clone,
+        V: Clone,
+    {
+        let (children, values) = match self {
+            Node::Root { children } => (children, None),
+            Node::Child { children, values, .. } => (children, Some(values)),
+        };
+
+        let in_level = match in_levels.next() {
+            Some(in_level) => in_level,
+            None => {
+                acc.extend(values.into_iter().flatten().cloned());
+                return;
+            }
+        };
+
+        // MQTT Spec. 4.7: wildcard at the first level must not match topic-names
+        // starting with `$`.
+        let wildcard = |child: &&Arc<Node<V>>| match self {
+            Node::Root { .. } => !child.as_name().starts_with('$'),
+            Node::Child { .. } => true,
+        };
+
+        match in_level {
+            "#" => {
+                // multi-level wildcard also matches the parent level.
+                acc.extend(values.into_iter().flatten().cloned());
+                for child in children.iter().filter(wildcard) {
+                    child.collect_values(acc, &|_| true)
+                }
+            }
+            "+" => {
+                for child in children.iter().filter(wildcard) {
+                    child.match_filter(in_levels.clone(), acc)
+                }
+            }
+            in_level => {
+                for child in children.iter().filter(|c| c.as_name() == in_level) {
+                    child.match_filter(in_levels.clone(), acc)
+                }
+            }
+        }
+    }
+
     fn match_topic<'a, I>(&self, mut in_levels: I, dollar: bool) -> Option<Vec<V>>
     where
         I: Iterator<Item = &'a str> + Clone,
@@ -572,6 +758,18 @@ fn match_level(in_lvl: &str, trie_level: &str) -> (bool, bool) {
     }
 }
 
+// retained PUBLISH with zero-length payload is a deletion marker.
+fn is_deleted(publish: &v5::Publish) -> bool {
+    publish.payload.as_ref().map(|p| p.is_empty()).unwrap_or(true)
+}
+
+fn to_topic_name<'a, K>(key: &'a K) -> TopicName
+where
+    K: IterTopicPath<'a>,
+{
+    TopicName::from(key.iter_topic_path().collect::<Vec<&str>>().join("/"))
+}
+
 fn compare_level(in_level: &str, trie_level: &str) -> bool {
     if in_level == trie_level {
         true
@@ -593,3 +791,7 @@ pub struct Stats {
     // number of hits
     pub hits: usize,
 }
+
+#[cfg(test)]
+#[path = "ttrie_test.rs"]
+mod ttrie_test;
@@ -0,0 +1,149 @@
+use std::{sync::Arc, time};
+
+use super::*;
+
+fn new_retain(topic: &str, expiry: Option<u32>) -> v5::Publish {
+    let properties = expiry.map(|secs| v5::PublishProperties {
+        message_expiry_interval: Some(secs),
+        ..v5::PublishProperties::default()
+    });
+    v5::Publish {
+        retain: true,
+        qos: v5::QoS::AtMostOnce,
+        duplicate: false,
+        topic_name: TopicName::from(topic.to_string()),
+        packet_id: None,
+        properties,
+        payload: Some(b"hello".to_vec()),
+    }
+}
+
+#[test]
+fn test_retained_trie_purge_expired() {
+    let trie = RetainedTrie::default();
+    let (a, b) = (TopicName::from("a/b".to_string()), TopicName::from("a/c".to_string()));
+
+    trie.set(&a, new_retain("a/b", Some(10)));
+    trie.set(&b, new_retain("a/c", None));
+    assert_eq!(trie.len(), 2);
+
+    // nothing has expired yet.
+    assert_eq!(trie.purge_expired(time::Instant::now()), 0);
+    assert_eq!(trie.len(), 2);
+
+    // finite expiry is purged once the interval elapses.
+    let now = time::Instant::now() + time::Duration::from_secs(11);
+    assert_eq!(trie.purge_expired(now), 1);
+    assert_eq!(trie.len(), 1);
+    assert!(trie.match_topic_filter(&a).is_none());
+
+    // message without expiry is retained indefinitely.
+    let now = time::Instant::now() + time::Duration::from_secs(1_000_000);
+    assert_eq!(trie.purge_expired(now), 0);
+    assert_eq!(trie.match_topic_filter(&b), Some(new_retain("a/c", None)));
+}
+
+#[test]
+fn test_retained_trie_expiry_reset() {
+    let trie = RetainedTrie::default();
+    let a = TopicName::from("a/b".to_string());
+
+    // replacing with a message without expiry, cancels the older expiry.
+    trie.set(&a, new_retain("a/b", Some(10)));
+    trie.set(&a, new_retain("a/b", None));
+    let now = time::Instant::now() + time::Duration::from_secs(11);
+    assert_eq!(trie.purge_expired(now), 0);
+    assert_eq!(trie.len(), 1);
+
+    // expiry interval of ZERO deletes the retained message immediately.
+    trie.set(&a, new_retain("a/b", Some(0)));
+    assert_eq!(trie.len(), 0);
+    assert!(trie.match_topic_filter(&a).is_none());
+    assert_eq!(trie.purge_expired(now), 0);
+}
+
+#[test]
+fn test_retained_trie_empty_payload() {
+    let trie = RetainedTrie::default();
+    let (a, b) = (TopicName::from("a/b".to_string()), TopicName::from("a/c".to_string()));
+    let filter = TopicFilter::from("a/+".to_string());
+
+    trie.set(&a, new_retain("a/b", None));
+    trie.set(&b, new_retain("a/c", None));
+    assert_eq!(trie.match_retained(&filter).len(), 2);
+
+    // retained message with zero-length payload clears the prior retained value.
+    let mut empty = new_retain("a/b", None);
+    empty.payload = Some(vec![]);
+    trie.set(&a, empty.clone());
+    assert_eq!(trie.len(), 1);
+    assert!(trie.match_topic_filter(&a).is_none());
+    assert_eq!(trie.match_retained(&filter), vec![new_retain("a/c", None)]);
+
+    // deleting an absent topic, or with missing payload, is not retained either.
+    empty.payload = None;
+    trie.set(&a, empty.clone());
+    trie.set(&b, empty);
+    assert_eq!(trie.len(), 0);
+    assert!(trie.match_retained(&filter).is_empty());
+}
+
+fn new_subscription(client_id: &ClientID, filter: &str, qos: v5::QoS) -> Subscription {
+    Subscription {
+        topic_filter: TopicFilter::from(filter.to_string()),
+        client_id: client_id.clone(),
+        shard_id: 0,
+        subscription_id: None,
+        qos,
+        no_local: false,
+        retain_as_published: false,
+        retain_forward_rule: v5::RetainForwardRule::OnEverySubscribe,
+    }
+}
+
+#[test]
+fn test_subscribed_trie_remove_client() {
+    use v5::{QoS, RetainForwardRule, SubscriptionOpt};
+
+    let mut trie = SubscribedTrie::default();
+    let (c1, c2) = (ClientID("c1".to_string()), ClientID("c2".to_string()));
+
+    let filters = [
+        ("a/b/c", QoS::AtMostOnce),
+        ("a/+/c", QoS::AtLeastOnce),
+        ("x/#", QoS::ExactlyOnce),
+    ];
+    for (filter, qos) in filters.iter() {
+        let sub = new_subscription(&c1, filter, *qos);
+        trie.subscribe(&sub.topic_filter.clone(), sub);
+    }
+    let other = new_subscription(&c2, "a/b/c", QoS::AtMostOnce);
+    trie.subscribe(&other.topic_filter.clone(), other.clone());
+    assert_eq!(trie.len(), 3);
+
+    let mut subs = trie.subscriptions_of(&c1);
+    subs.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut refs: Vec<(TopicFilter, SubscriptionOpt)> = filters
+        .iter()
+        .map(|(filter, qos)| {
+            let rfr = RetainForwardRule::OnEverySubscribe;
+            let opt = SubscriptionOpt::new(rfr, false, false, *qos);
+            (TopicFilter::from(filter.to_string()), opt)
+        })
+        .collect();
+    refs.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(subs, refs);
+    assert_eq!(trie.subscriptions_of(&c2).len(), 1);
+
+    trie.remove_client(&c1);
+    assert!(trie.subscriptions_of(&c1).is_empty());
+    assert_eq!(trie.len(), 1);
+
+    trie.remove_client(&c2);
+    assert!(trie.is_empty());
+    let root = Arc::clone(&trie.inner.read().root);
+    match root.as_ref() {
+        Node::Root { children } => assert!(children.is_empty()),
+        Node::Child { .. } => unreachable!(),
+    }
+}
@@ -1,9 +1,11 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 #[cfg(feature = "backtrace")]
 use std::backtrace::Backtrace;
 
 use std::{self, fmt, result};
 
-use crate::Result;
+use crate::{v5, Result};
 
 /// Macro to compose Error values.
 ///
@@ -186,6 +188,8 @@ pub struct Error {
     pub cause: Option<Box<dyn std::error::Error + Send>>,
     /// Location at which the error happens
     pub loc: String,
+    /// Byte offset, within the stream being decoded, at which decoding failed.
+    pub offset: Option<usize>,
     /// Call stack at the point where the error happened.
     #[cfg(feature = "backtrace")]
     pub backtrace: Backtrace,
@@ -199,6 +203,7 @@ impl Default for Error {
             code: None,
             cause: None,
             loc: String::default(),
+            offset: None,
             #[cfg(feature = "backtrace")]
             backtrace: Backtrace::force_capture(),
         }
@@ -329,6 +334,19 @@ impl Error {
         self.code.unwrap_or(ReasonCode::UnspecifiedError)
     }
 
+    /// Return the byte offset at which decoding failed, if this is a decode error.
+    pub fn offset(&self) -> Option<usize> {
+        self.offset
+    }
+
+    /// Shift the decode error's offset by `base`, offset at which the failing field
+    /// starts within the enclosing stream. Errors without an offset are located at
+    /// `base`.
+    pub(crate) fn at(mut self, base: usize) -> Error {
+        self.offset = Some(self.offset.unwrap_or(0) + base);
+        self
+    }
+
     pub fn has(&self, kind: ErrorKind) -> bool {
         if self.kind == kind {
             true
@@ -353,6 +371,8 @@ pub enum ErrorKind {
     UnsupportedProtocolVersion,
     InsufficientBytes,
     SessionTakenOver,
+    // broker error
+    ShardFull,
     // network error
     Disconnected,
     SlowClient,
@@ -371,6 +391,16 @@ pub enum ErrorKind {
     IOError,
 }
 
+impl ErrorKind {
+    /// Return the reason-code to DISCONNECT a client for an error of this kind,
+    /// refer [ReasonCode::from_error_kind].
+    pub fn default_disconnect_reason(&self) -> v5::DisconnReasonCode {
+        let code = ReasonCode::from_error_kind(*self) as u8;
+        v5::DisconnReasonCode::try_from(code)
+            .unwrap_or(v5::DisconnReasonCode::UnspecifiedError)
+    }
+}
+
 impl fmt::Display for ErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
         use ErrorKind::*;
@@ -385,6 +415,8 @@ impl fmt::Display for ErrorKind {
             InsufficientBytes => write!(f, "InsufficientBytes"),
             MalformedPacket => write!(f, "MalformedPacket"),
             SessionTakenOver => write!(f, "SessionTakenOver"),
+            // broker error
+            ShardFull => write!(f, "ShardFull"),
             // network error
             Disconnected => write!(f, "Disconnected"),
             SlowClient => write!(f, "SlowClient"),
@@ -406,6 +438,7 @@ impl fmt::Display for ErrorKind {
 }
 
 /// ReasonCode defined by `MQTT-spec`, each variant defines error value.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 #[repr(u8)]
 pub enum ReasonCode {
@@ -460,6 +493,27 @@ impl Default for ReasonCode {
     }
 }
 
+impl ReasonCode {
+    /// Return the client-facing reason-code for an error of `kind`, when the error
+    /// does not carry a reason-code of its own. Errors that are internal to the
+    /// broker map to `UnspecifiedError`.
+    pub fn from_error_kind(kind: ErrorKind) -> ReasonCode {
+        match kind {
+            ErrorKind::NoError => ReasonCode::Success,
+            ErrorKind::MalformedPacket => ReasonCode::MalformedPacket,
+            ErrorKind::InsufficientBytes => ReasonCode::MalformedPacket,
+            ErrorKind::ProtocolError => ReasonCode::ProtocolError,
+            ErrorKind::UnsupportedProtocolVersion => {
+                ReasonCode::UnsupportedProtocolVersion
+            }
+            ErrorKind::SessionTakenOver => ReasonCode::SessionTakenOver,
+            ErrorKind::ShardFull => ReasonCode::ServerBusy,
+            ErrorKind::SlowClient => ReasonCode::QuotaExceeded,
+            _ => ReasonCode::UnspecifiedError,
+        }
+    }
+}
+
 impl TryFrom<u8> for ReasonCode {
     type Error = Error;
 
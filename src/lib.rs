@@ -18,6 +18,13 @@
 //! a need for this. Enabling this will provide `arbitrary::Arbitrary` implementation
 //! for several types defined in this library.
 //!
+//! _*tokio*_, provides [v5::PacketCodec] implementing `tokio_util::codec` for MQTT
+//! packets, for applications that embed MQTT in a [tokio][tokio] runtime.
+//!
+//! _*serde*_, derives `serde::Serialize` and `serde::Deserialize` for CONNECT,
+//! SUBSCRIBE, PUBACK/PUBREC/PUBREL/PUBCOMP packets and their properties. Binary fields
+//! are serialized as base64 strings. Useful for logging, snapshots and test fixtures.
+//!
 //! By default `broker` and `client` features are enabled.
 //!
 //! #### Rust unstable features
@@ -31,6 +38,7 @@
 //! TODO
 //!
 //! [dep]: https://doc.rust-lang.org/cargo/reference/features.html#dependency-features
+//! [tokio]: https://tokio.rs
 //! [us1]: https://doc.rust-lang.org/beta/unstable-book/library-features/backtrace.html
 //! [us2]: https://doc.rust-lang.org/beta/unstable-book/library-features/backtrace-frames.html
 //! [us3]: https://doc.rust-lang.org/beta/unstable-book/library-features/error-iter.html
@@ -62,8 +70,33 @@ pub trait Packetize: Sized {
     /// expected that the stream is left at meaningful boundry to re-detect the error.
     fn decode<T: AsRef<[u8]>>(stream: T) -> Result<(Self, usize)>;
 
+    /// Same as [Packetize::decode], applying the limits in `cfg`. Default
+    /// implementation ignores `cfg`, packets and properties carrying user-properties
+    /// override this.
+    fn decode_with<T>(stream: T, _cfg: &v5::DecodeConfig) -> Result<(Self, usize)>
+    where
+        T: AsRef<[u8]>,
+    {
+        Self::decode(stream)
+    }
+
     /// Serialize value into bytes.
     fn encode(&self) -> Result<Blob>;
+
+    /// Return the size of this value, including the fixed-header for packets, when
+    /// encoded. Default implementation encodes the value, CONNECT, PUBLISH,
+    /// SUBSCRIBE, PUBACK/PUBREC/PUBREL/PUBCOMP and their properties override this
+    /// to compute the size from their fields, without allocation.
+    fn encoded_len(&self) -> Result<usize> {
+        Ok(self.encode()?.as_ref().len())
+    }
+
+    /// Validate that `stream` holds the entire packet framed by the fixed-header `fh`.
+    /// Packet decoders shall call this before decoding the variable-header and payload,
+    /// so that truncated packets are detected up front as `MalformedPacket`.
+    fn remaining_len_ok(stream: &[u8], fh: &v5::FixedHeader) -> Result<()> {
+        v5::remaining_len_ok(stream, fh)
+    }
 }
 
 /// Trait implemented by [TopicName] and [TopicFilter].
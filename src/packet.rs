@@ -59,6 +59,52 @@ impl MQTTRead {
         }
     }
 
+    /// Create a read state machine using `data` as its buffer. Pass an empty vector
+    /// to start detached, refer [MQTTRead::attach].
+    pub fn with_buffer(data: Vec<u8>, max_size: u32) -> MQTTRead {
+        MQTTRead::Init { data, max_size: max_size as usize }
+    }
+
+    /// Return whether no bytes of the next packet are read yet.
+    pub fn is_idle(&self) -> bool {
+        matches!(self, MQTTRead::Init { data, .. } if data.is_empty())
+    }
+
+    /// Return whether the read buffer is detached, refer [MQTTRead::detach].
+    pub fn is_detached(&self) -> bool {
+        matches!(self, MQTTRead::Init { data, .. } if data.capacity() == 0)
+    }
+
+    /// Attach `buf` as the read buffer, allowed only when detached.
+    pub fn attach(&mut self, buf: Vec<u8>) {
+        match self {
+            MQTTRead::Init { data, .. } if data.capacity() == 0 => *data = buf,
+            val => unreachable!("{:?} attach with buffer", val),
+        }
+    }
+
+    /// Detach the read buffer if no bytes of the next packet are read yet, so that
+    /// the buffer can be recycled while the socket is idle.
+    pub fn detach(&mut self) -> Option<Vec<u8>> {
+        match self {
+            MQTTRead::Init { data, .. } if data.is_empty() && data.capacity() > 0 => {
+                Some(std::mem::take(data))
+            }
+            _ => None,
+        }
+    }
+
+    /// Return the total length of the packet, including the fixed-header, as
+    /// declared by the fixed-header. Available only after the fixed-header is read.
+    pub fn declared_len(&self) -> Option<u32> {
+        match self {
+            MQTTRead::Remain { data, .. } | MQTTRead::Fin { data, .. } => {
+                Some(u32::try_from(data.len()).unwrap_or(u32::MAX))
+            }
+            MQTTRead::Init { .. } | MQTTRead::Header { .. } | MQTTRead::None => None,
+        }
+    }
+
     // return (self,would_block)
     // Disconnected, and implies a bad connection.
     // MalformedPacket, implies a DISCONNECT and socket close
@@ -197,69 +243,16 @@ impl MQTTRead {
     // MalformedPacket, implies a DISCONNECT and socket close
     // ProtocolError, implies DISCONNECT and socket close
     pub fn parse(&self) -> Result<v5::Packet> {
+        self.parse_with(&v5::DecodeConfig::default())
+    }
+
+    /// Same as [MQTTRead::parse], applying the decoder limits in `cfg`.
+    pub fn parse_with(&self, cfg: &v5::DecodeConfig) -> Result<v5::Packet> {
         let (pkt, n, m) = match self {
-            MQTTRead::Fin { data, fh, .. } => match fh.unwrap().0 {
-                v5::PacketType::Connect => {
-                    let (pkt, n) = v5::Connect::decode(&data)?;
-                    (v5::Packet::Connect(pkt), n, data.len())
-                }
-                v5::PacketType::ConnAck => {
-                    let (pkt, n) = v5::ConnAck::decode(&data)?;
-                    (v5::Packet::ConnAck(pkt), n, data.len())
-                }
-                v5::PacketType::Publish => {
-                    let (pkt, n) = v5::Publish::decode(&data)?;
-                    (v5::Packet::Publish(pkt), n, data.len())
-                }
-                v5::PacketType::PubAck => {
-                    let (pkt, n) = v5::Pub::decode(&data)?;
-                    (v5::Packet::PubAck(pkt), n, data.len())
-                }
-                v5::PacketType::PubRec => {
-                    let (pkt, n) = v5::Pub::decode(&data)?;
-                    (v5::Packet::PubRec(pkt), n, data.len())
-                }
-                v5::PacketType::PubRel => {
-                    let (pkt, n) = v5::Pub::decode(&data)?;
-                    (v5::Packet::PubRel(pkt), n, data.len())
-                }
-                v5::PacketType::PubComp => {
-                    let (pkt, n) = v5::Pub::decode(&data)?;
-                    (v5::Packet::PubComp(pkt), n, data.len())
-                }
-                v5::PacketType::Subscribe => {
-                    let (pkt, n) = v5::Subscribe::decode(&data)?;
-                    (v5::Packet::Subscribe(pkt), n, data.len())
-                }
-                v5::PacketType::SubAck => {
-                    let (pkt, n) = v5::SubAck::decode(&data)?;
-                    (v5::Packet::SubAck(pkt), n, data.len())
-                }
-                v5::PacketType::UnSubscribe => {
-                    let (pkt, n) = v5::UnSubscribe::decode(&data)?;
-                    (v5::Packet::UnSubscribe(pkt), n, data.len())
-                }
-                v5::PacketType::UnsubAck => {
-                    let (pkt, n) = v5::UnsubAck::decode(&data)?;
-                    (v5::Packet::UnsubAck(pkt), n, data.len())
-                }
-                v5::PacketType::PingReq => {
-                    let (_pkt, n) = v5::PingReq::decode(&data)?;
-                    (v5::Packet::PingReq, n, data.len())
-                }
-                v5::PacketType::PingResp => {
-                    let (_pkt, n) = v5::PingResp::decode(&data)?;
-                    (v5::Packet::PingResp, n, data.len())
-                }
-                v5::PacketType::Disconnect => {
-                    let (pkt, n) = v5::Disconnect::decode(&data)?;
-                    (v5::Packet::Disconnect(pkt), n, data.len())
-                }
-                v5::PacketType::Auth => {
-                    let (pkt, n) = v5::Auth::decode(&data)?;
-                    (v5::Packet::Auth(pkt), n, data.len())
-                }
-            },
+            MQTTRead::Fin { data, fh, .. } => {
+                let (pkt, n) = v5::Packet::decode_with_header(fh, data, cfg)?;
+                (pkt, n, data.len())
+            }
             _ => unreachable!(),
         };
 
@@ -352,6 +345,12 @@ impl MQTTWrite {
         MQTTWrite::Init { data, max_size: max_size as usize }
     }
 
+    /// Create a write state machine using `data` as its buffer, holding an encoded
+    /// packet. Pass an empty vector to start without a buffer.
+    pub fn with_buffer(data: Vec<u8>, max_size: u32) -> MQTTWrite {
+        MQTTWrite::Init { data, max_size: max_size as usize }
+    }
+
     // return (self,would_block)
     // errors shall be folded as Disconnected, and implies a bad connection.
     pub fn write<W: io::Write>(self, stream: &mut W) -> Result<(Self, bool)> {
@@ -420,6 +419,29 @@ impl MQTTWrite {
             _ => unreachable!(),
         }
     }
+
+    /// Same as reset, but `data` holding the encoded packet is used as the write
+    /// buffer. Return the old buffer, so that it can be recycled.
+    pub fn reset_with(self, data: Vec<u8>) -> (Self, Vec<u8>) {
+        match self {
+            MQTTWrite::Init { data: old, max_size }
+            | MQTTWrite::Fin { data: old, max_size } => {
+                (MQTTWrite::Init { data, max_size }, old)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Detach the write buffer once the packet is fully written, so that the buffer
+    /// can be recycled while the socket is idle.
+    pub fn detach(&mut self) -> Option<Vec<u8>> {
+        match self {
+            MQTTWrite::Fin { data, .. } if data.capacity() > 0 => {
+                Some(std::mem::take(data))
+            }
+            _ => None,
+        }
+    }
 }
 
 #[cfg(feature = "broker")]
@@ -1,5 +1,7 @@
 #[cfg(any(feature = "fuzzy", test))]
 use arbitrary::{Arbitrary, Error as ArbitraryError, Unstructured};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 #[cfg(any(feature = "fuzzy", test))]
 use std::result;
 
@@ -19,6 +21,7 @@ use crate::{IterTopicPath, Packetize};
 //       a given Client.
 
 /// Enumeration of different MQTT Protocol version.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(any(feature = "fuzzy", test), derive(Arbitrary))]
 pub enum MqttProtocol {
@@ -72,6 +75,7 @@ impl AsRef<[u8]> for Blob {
 }
 
 /// Type client-id implements a unique ID defined by MQTT specification.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord)]
 pub struct ClientID(pub String);
 
@@ -113,9 +117,36 @@ impl ClientID {
             _ => client_id.clone(),
         }
     }
+
+    /// Validate client-id supplied by remote client. Client-id must be within
+    /// 1-65535 bytes of UTF-8 and shall not contain control characters. A ZERO length
+    /// client-id is valid only if `allow_empty` is true, in which case server shall
+    /// assign a unique client-id.
+    pub fn validate(&self, allow_empty: bool) -> Result<()> {
+        match self.len() {
+            0 if allow_empty => Ok(()),
+            0 => err!(MalformedPacket, code: InvalidClientID, "ClientID empty"),
+            n if n > 65535 => {
+                err!(MalformedPacket, code: InvalidClientID, "ClientID len {}", n)
+            }
+            _ if self.chars().all(util::is_valid_utf8_code_point) => Ok(()),
+            _ => err!(MalformedPacket, code: InvalidClientID, "ClientID {:?}", self.0),
+        }
+    }
+
+    /// Map this client-id to one of `num_shards`, which must be a power of 2. Broker
+    /// uses the same mapping to partition sessions across shards, hence a client
+    /// will always map to the same shard no matter where or when it is computed.
+    pub fn shard_hash(&self, num_shards: u32) -> u32 {
+        let hash = cityhash_rs::cityhash_110_128(self.as_bytes());
+        let hash = (hash & 0xFFFFFFFFFFFFFFFF) ^ ((hash >> 64) & 0xFFFFFFFFFFFFFFFF);
+        let hash = ((hash & 0xFFFFFFFF) ^ ((hash >> 32) & 0xFFFFFFFF)) as u32;
+        hash & (num_shards - 1)
+    }
 }
 
 /// Type implement topic-name defined by MQTT specification.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
 pub struct TopicName(String);
 
@@ -204,6 +235,8 @@ impl<'a> IterTopicPath<'a> for TopicName {
 }
 
 impl TopicName {
+    /// Validate topic-name, it must be at least one character long and shall not
+    /// contain wildcard characters or the null character U+0000.
     pub fn validate(&self) -> Result<()> {
         // All Topic Names and Topic Filters MUST be at least one character long.
         if self.0.len() == 0 {
@@ -211,7 +244,7 @@ impl TopicName {
         }
 
         if self.0.chars().any(|ch| matches!(ch, '#' | '+' | '\u{0}')) {
-            err!(MalformedPacket, code: MalformedPacket, "")?;
+            err!(MalformedPacket, code: TopicNameInvalid, "TopicName {:?}", self.0)?;
         }
 
         Ok(())
@@ -219,6 +252,7 @@ impl TopicName {
 }
 
 /// Type implement topic-filter defined by MQTT specification.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
 pub struct TopicFilter(String);
 
@@ -311,15 +345,17 @@ impl<'a> Arbitrary<'a> for TopicFilter {
 }
 
 impl TopicFilter {
+    /// Validate topic-filter, multi-level wildcard `#` is allowed only as the last
+    /// level and single-level wildcard `+` must occupy an entire level.
     pub fn validate(&self) -> Result<()> {
         // All Topic Names and Topic Filters MUST be at least one character long.
         if self.0.len() == 0 {
             err!(MalformedPacket, code: MalformedPacket, "ZERO length TopicFilter")?;
         } else if self.0.chars().any(|ch| matches!(ch, '\u{0}')) {
-            err!(MalformedPacket, code: MalformedPacket, "")?;
+            err!(MalformedPacket, code: MalformedPacket, "TopicFilter {:?}", self.0)?;
         }
 
-        let levels = self.iter_topic_path();
+        let levels = self.levels();
 
         let mut iter = levels.clone().filter(|l| l.len() > 1);
         if iter.any(|l| l.chars().any(|c| matches!(c, '#' | '+'))) {
@@ -334,6 +370,21 @@ impl TopicFilter {
 
         Ok(())
     }
+
+    /// Return true if topic-filter contains `#` or `+` wildcard characters.
+    pub fn has_wildcards(&self) -> bool {
+        self.0.chars().any(|ch| matches!(ch, '#' | '+'))
+    }
+
+    /// Return true if topic-filter is a shared-subscription, `$share/{name}/{filter}`.
+    pub fn is_shared(&self) -> bool {
+        self.0.starts_with("$share/")
+    }
+
+    /// Iterate over the levels in topic-filter, levels are separated by `/`.
+    pub fn levels(&self) -> impl Iterator<Item = &str> + Clone {
+        self.iter_topic_path()
+    }
 }
 
 /// Type implement variable-length unsigned 32-bit integer.
@@ -344,6 +395,7 @@ impl TopicFilter {
 /// i/p stream: 0b0www_wwww 0b1zzz_zzzz 0b1yyy_yyyy 0b1xxx_xxxx, low-byte to high-byte
 /// o/p u32   : 0bwww_wwww_zzz_zzzz_yyy_yyyy_xxx_xxxx
 /// ```
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd)]
 pub struct VarU32(pub u32);
 
@@ -380,12 +432,20 @@ impl Packetize for VarU32 {
         for i in 0..n {
             let val = ((stream[i] as u32) & 0x7f) << (7 * (i as u32));
             out += val;
-            if stream[i] < 0x80 {
+            if i > 0 && stream[i] == 0 {
+                // trailing ZERO byte is a non-minimal encoding, like 0x80 0x00
+                err!(MalformedPacket, code: MalformedPacket, "VarU32 non-minimal")?;
+            } else if stream[i] < 0x80 {
                 return Ok((VarU32(out), i + 1));
             }
         }
 
-        err!(MalformedPacket, code: MalformedPacket, "VarU32::decode")
+        if n == mem::size_of::<u32>() {
+            // continuation bit set in the 4th byte, value > VarU32::MAX
+            err!(MalformedPacket, code: MalformedPacket, "VarU32 overflow")
+        } else {
+            err!(MalformedPacket, code: MalformedPacket, "VarU32::decode")
+        }
     }
 
     fn encode(&self) -> Result<Blob> {
@@ -422,10 +482,55 @@ impl Packetize for VarU32 {
 
 impl VarU32 {
     pub const MAX: VarU32 = VarU32(268_435_455);
+
+    /// Convert `len`, remaining-length of a packet, into VarU32. Fail with
+    /// InvalidInput if `len` exceeds [VarU32::MAX].
+    pub fn try_from_len(len: usize) -> Result<VarU32> {
+        match u32::try_from(len) {
+            Ok(val) if val <= *VarU32::MAX => Ok(VarU32(val)),
+            _ => err!(InvalidInput, desc: "packet too large: {} > {}", len, *VarU32::MAX),
+        }
+    }
 }
 
-/// Type alias for MQTT User-Property.
-pub type UserProperty = (String, String);
+/// Type implement MQTT User-Property, a (key, value) pair of UTF-8 strings.
+///
+/// Key can repeat, that is, same key can appear more than once in properties.
+#[cfg_attr(any(feature = "fuzzy", test), derive(Arbitrary))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct UserProperty(pub String, pub String);
+
+impl UserProperty {
+    pub fn new<K, V>(key: K, val: V) -> UserProperty
+    where
+        K: ToString,
+        V: ToString,
+    {
+        UserProperty(key.to_string(), val.to_string())
+    }
+
+    pub fn key(&self) -> &str {
+        &self.0
+    }
+
+    pub fn value(&self) -> &str {
+        &self.1
+    }
+}
+
+/// Return the value of first user-property matching `key`.
+pub(crate) fn user_prop<'a>(props: &'a [UserProperty], key: &str) -> Option<&'a str> {
+    props.iter().find(|p| p.0 == key).map(|p| p.1.as_str())
+}
+
+/// Return the values of all user-properties matching `key`, in their order.
+pub(crate) fn user_props<'a>(
+    props: &'a [UserProperty],
+    key: &'a str,
+) -> impl Iterator<Item = &'a str> + 'a {
+    props.iter().filter(move |p| p.0 == key).map(|p| p.1.as_str())
+}
 
 #[cfg(any(feature = "fuzzy", test))]
 pub fn valid_user_props<'a>(
@@ -441,7 +546,7 @@ pub fn valid_user_props<'a>(
 
         let key: String = uns.choose(&keys)?.to_string();
         let val: String = uns.choose(&vals)?.to_string();
-        props.push((key, val))
+        props.push(UserProperty(key, val))
     }
 
     Ok(props)
@@ -453,7 +558,7 @@ impl Packetize for UserProperty {
 
         let (key, m) = String::decode(stream)?;
         let (val, n) = String::decode(advance(stream, m)?)?;
-        Ok(((key, val), (m + n)))
+        Ok((UserProperty(key, val), (m + n)))
     }
 
     fn encode(&self) -> Result<Blob> {
@@ -482,7 +587,8 @@ impl Packetize for u8 {
 
         match stream.len() {
             n if n >= 1 => Ok((stream[0], 1)),
-            _ => err!(InsufficientBytes, code: MalformedPacket, "u8::decode()"),
+            n => err!(InsufficientBytes, code: MalformedPacket, "u8::decode()")
+                .map_err(|err: Error| err.at(n)),
         }
     }
 
@@ -503,7 +609,8 @@ impl Packetize for u16 {
 
         match stream.len() {
             n if n >= 2 => Ok((u16::from_be_bytes(stream[..2].try_into().unwrap()), 2)),
-            _ => err!(InsufficientBytes, code: MalformedPacket, "u16::decode()"),
+            n => err!(InsufficientBytes, code: MalformedPacket, "u16::decode()")
+                .map_err(|err: Error| err.at(n)),
         }
     }
 
@@ -524,7 +631,8 @@ impl Packetize for u32 {
 
         match stream.len() {
             n if n >= 4 => Ok((u32::from_be_bytes(stream[..4].try_into().unwrap()), 4)),
-            _ => err!(InsufficientBytes, code: MalformedPacket, "u32::decode()"),
+            n => err!(InsufficientBytes, code: MalformedPacket, "u32::decode()")
+                .map_err(|err: Error| err.at(n)),
         }
     }
 
@@ -549,12 +657,15 @@ impl Packetize for String {
             err!(InsufficientBytes, code: MalformedPacket, "String::decode")?;
         }
 
+        // ill-formed UTF-8 and code points like U+0000, refer util module, are
+        // treated as malformed packet.
         match std::str::from_utf8(&stream[2..2 + len]) {
             Ok(s) if !s.chars().all(util::is_valid_utf8_code_point) => {
                 err!(
                     MalformedPacket,
                     code: MalformedPacket,
-                    "String::encode invalid utf8 string"
+                    "String::decode invalid code point in {:?}",
+                    s
                 )
             }
             Ok(s) => Ok((s.to_string(), 2 + len)),
@@ -615,3 +726,7 @@ impl Packetize for Vec<u8> {
         }
     }
 }
+
+#[cfg(test)]
+#[path = "types_test.rs"]
+mod types_test;
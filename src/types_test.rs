@@ -0,0 +1,95 @@
+use super::*;
+
+#[test]
+fn test_var_u32_decode() {
+    // minimal encodings, including the 4-byte maximum.
+    let testcases: Vec<(&[u8], u32)> = vec![
+        (&[0x00], 0),
+        (&[0x7f], 127),
+        (&[0x80, 0x01], 128),
+        (&[0xff, 0xff, 0xff, 0x7f], *VarU32::MAX),
+    ];
+    for (stream, val) in testcases.into_iter() {
+        let (out, n) = VarU32::decode(stream).unwrap();
+        assert_eq!(out, VarU32(val), "{:?}", stream);
+        assert_eq!(n, stream.len(), "{:?}", stream);
+        assert_eq!(out.encode().unwrap().as_ref(), stream);
+    }
+
+    // non-minimal encodings and 5th continuation byte.
+    let testcases: Vec<&[u8]> = vec![
+        &[0x80, 0x00],
+        &[0xff, 0x80, 0x00],
+        &[0x80, 0x80, 0x80, 0x00],
+        &[0xff, 0xff, 0xff, 0xff, 0x01],
+    ];
+    for stream in testcases.into_iter() {
+        let err = VarU32::decode(stream).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::MalformedPacket, "{:?}", stream);
+        assert_eq!(err.code(), ReasonCode::MalformedPacket, "{:?}", stream);
+    }
+}
+
+#[test]
+fn test_var_u32_try_from_len() {
+    for len in [0, 127, 128, *VarU32::MAX as usize] {
+        assert_eq!(*VarU32::try_from_len(len).unwrap() as usize, len);
+    }
+
+    for len in [*VarU32::MAX as usize + 1, u32::MAX as usize, usize::MAX] {
+        let err = VarU32::try_from_len(len).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput, "{}", len);
+        let desc = format!("packet too large: {} > 268435455", len);
+        assert!(err.to_string().contains(&desc), "{}", err);
+    }
+}
+
+#[test]
+fn test_user_props() {
+    let props = crate::v5::PublishProperties {
+        user_properties: vec![
+            UserProperty::new("seqno", 10),
+            UserProperty::new("app", "mymq"),
+            UserProperty::new("seqno", 11),
+        ],
+        ..crate::v5::PublishProperties::default()
+    };
+
+    assert_eq!(props.user_prop("seqno"), Some("10"));
+    assert_eq!(props.user_props("seqno").collect::<Vec<&str>>(), vec!["10", "11"]);
+    assert_eq!(props.user_prop("app"), Some("mymq"));
+
+    assert_eq!(props.user_prop("missing"), None);
+    assert_eq!(props.user_props("missing").count(), 0);
+
+    let uprop = &props.user_properties[1];
+    assert_eq!((uprop.key(), uprop.value()), ("app", "mymq"));
+}
+
+#[test]
+fn test_client_id_shard_hash() {
+    // these mappings are persisted by way of session placement, they must not change.
+    let known = [
+        ("client-1", 1, 529),
+        ("client-2", 4, 180),
+        ("sensor/42", 2, 562),
+        ("", 13, 317),
+        ("ÿ", 8, 200),
+    ];
+    for (name, shard_16, shard_1024) in known.iter() {
+        let client_id = ClientID(name.to_string());
+        assert_eq!(client_id.shard_hash(1), 0, "{:?}", name);
+        assert_eq!(client_id.shard_hash(16), *shard_16, "{:?}", name);
+        assert_eq!(client_id.shard_hash(1024), *shard_1024, "{:?}", name);
+    }
+}
+
+#[test]
+fn test_int_big_endian() {
+    assert_eq!(0x1234_u16.encode().unwrap().as_ref(), &[0x12, 0x34]);
+    assert_eq!(u16::decode(&[0x12, 0x34]).unwrap(), (0x1234, 2));
+
+    let bytes = [0x01, 0x02, 0x03, 0x04];
+    assert_eq!(0x01020304_u32.encode().unwrap().as_ref(), &bytes);
+    assert_eq!(u32::decode(&bytes).unwrap(), (0x01020304, 4));
+}
@@ -14,6 +14,7 @@ pub fn advance(stream: &[u8], n: usize) -> Result<&[u8]> {
         Ok(&stream[n..])
     } else {
         err!(InsufficientBytes, code: MalformedPacket, "insufficient bytes in packet")
+            .map_err(|err: Error| err.at(stream.len()))
     }
 }
 
@@ -39,6 +40,33 @@ pub fn num_cores_ceiled() -> u32 {
     u32::try_from(ceil_power_of_2(u32::try_from(num_cpus::get()).unwrap())).unwrap()
 }
 
+/// Serialize optional binary data as base64 string, to be used with
+/// `#[serde(with = "crate::util::base64_opt")]`.
+#[cfg(feature = "serde")]
+pub mod base64_opt {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(val: &Option<Vec<u8>>, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match val {
+            Some(data) => s.serialize_some(&base64::encode(data)),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D>(d: D) -> Result<Option<Vec<u8>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match Option::<String>::deserialize(d)? {
+            Some(s) => Ok(Some(base64::decode(s).map_err(de::Error::custom)?)),
+            None => Ok(None),
+        }
+    }
+}
+
 #[cfg(test)]
 #[path = "util_test.rs"]
 mod util_test;
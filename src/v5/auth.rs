@@ -4,7 +4,7 @@ use arbitrary::{Arbitrary, Error as ArbitraryError, Unstructured};
 #[cfg(any(feature = "fuzzy", test))]
 use std::result;
 
-use crate::v5::{FixedHeader, PacketType, Property, PropertyType};
+use crate::v5::{DecodeConfig, FixedHeader, PacketType, Property, PropertyType};
 use crate::{util::advance, Blob, Packetize, UserProperty, VarU32};
 use crate::{Error, ErrorKind, ReasonCode, Result};
 
@@ -54,17 +54,25 @@ impl<'a> Arbitrary<'a> for Auth {
 
 impl Packetize for Auth {
     fn decode<T: AsRef<[u8]>>(stream: T) -> Result<(Self, usize)> {
+        Self::decode_with(stream, &DecodeConfig::default())
+    }
+
+    fn decode_with<T>(stream: T, cfg: &DecodeConfig) -> Result<(Self, usize)>
+    where
+        T: AsRef<[u8]>,
+    {
         let stream: &[u8] = stream.as_ref();
 
         let (fh, n) = dec_field!(FixedHeader, stream, 0);
-        fh.validate()?;
+        fh.validate_flags(PacketType::Auth)?;
+        Self::remaining_len_ok(stream, &fh)?;
 
         let (code, properties, n) = if *fh.remaining_len == 0 {
             (AuthReasonCode::Success, None, n)
         } else {
             let (code, n) = dec_field!(u8, stream, n);
             let code = AuthReasonCode::try_from(code)?;
-            let (properties, n) = dec_props!(AuthProperties, stream, n);
+            let (properties, n) = dec_props!(AuthProperties, stream, n, cfg);
             (code, properties, n)
         };
 
@@ -86,7 +94,7 @@ impl Packetize for Auth {
             data.extend_from_slice(VarU32(0).encode()?.as_ref());
         }
 
-        let fh = FixedHeader::new(PacketType::Auth, VarU32(data.len().try_into()?))?;
+        let fh = FixedHeader::new(PacketType::Auth, VarU32::try_from_len(data.len())?)?;
         data = insert_fixed_header(fh, data)?;
 
         Ok(Blob::Large { data })
@@ -145,6 +153,13 @@ impl<'a> Arbitrary<'a> for AuthProperties {
 
 impl Packetize for AuthProperties {
     fn decode<T: AsRef<[u8]>>(stream: T) -> Result<(Self, usize)> {
+        Self::decode_with(stream, &DecodeConfig::default())
+    }
+
+    fn decode_with<T>(stream: T, cfg: &DecodeConfig) -> Result<(Self, usize)>
+    where
+        T: AsRef<[u8]>,
+    {
         use crate::v5::Property::*;
 
         let stream: &[u8] = stream.as_ref();
@@ -171,10 +186,10 @@ impl Packetize for AuthProperties {
                 AuthenticationMethod(val) => authentication_method = Some(val),
                 AuthenticationData(val) => authentication_data = Some(val),
                 ReasonString(val) => props.reason_string = Some(val),
-                UserProp(val) => props.user_properties.push(val),
-                _ => {
-                    err!(ProtocolError, code: ProtocolError, "{} bad prop {:?}", PP, pt)?
+                UserProp(val) => {
+                    cfg.push_user_property(PP, &mut props.user_properties, val)?
                 }
+                _ => cfg.skip_property(PP, pt)?,
             };
         }
 
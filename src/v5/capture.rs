@@ -0,0 +1,86 @@
+//! Capture and replay raw MQTT frames.
+//!
+//! Capture file is a log of frames, each frame is a raw MQTT packet, as it appears
+//! on the wire, prefixed with its length encoded as 4-byte big-endian integer. Byte
+//! logs derived from tools like `tcpdump` can be converted into this format and fed
+//! through the decoder via [replay].
+
+use log::error;
+
+use std::{fs, io::Write, path};
+
+use crate::v5::Packet;
+use crate::{Error, ErrorKind, Packetize, ReasonCode, Result};
+
+/// Append `frame`, a single raw MQTT packet, to the capture file at `loc`. File is
+/// created if it does not exist.
+pub fn capture_write<P: AsRef<path::Path>>(loc: P, frame: &[u8]) -> Result<()> {
+    let loc = loc.as_ref();
+    let len = err!(
+        InvalidInput,
+        try: u32::try_from(frame.len()),
+        "capture frame too large {}",
+        frame.len()
+    )?;
+
+    let mut data = Vec::with_capacity(frame.len() + 4);
+    data.extend_from_slice(&len.to_be_bytes());
+    data.extend_from_slice(frame);
+
+    let mut opts = fs::OpenOptions::new();
+    let mut fd = err!(
+        IOError,
+        try: opts.append(true).create(true).open(loc),
+        "fail opening capture file {:?}",
+        loc
+    )?;
+    err!(IOError, try: fd.write_all(&data), "fail writing capture file {:?}", loc)?;
+
+    Ok(())
+}
+
+/// Read the capture file at `loc` and decode its frames in the order they were
+/// captured. Frames that fail to decode are skipped and reported.
+pub fn replay<P: AsRef<path::Path>>(loc: P) -> Result<Vec<Packet>> {
+    let loc = loc.as_ref();
+    let data = err!(IOError, try: fs::read(loc), "fail reading capture file {:?}", loc)?;
+
+    let (mut pkts, mut off) = (Vec::default(), 0);
+    while off < data.len() {
+        let n = match data.get(off..off + 4) {
+            Some(bytes) => u32::from_be_bytes(bytes.try_into().unwrap()) as usize,
+            None => err!(
+                MalformedPacket,
+                code: MalformedPacket,
+                "{:?} truncated frame-length at offset {}",
+                loc,
+                off
+            )?,
+        };
+        let frame = match data.get(off + 4..off + 4 + n) {
+            Some(frame) => frame,
+            None => err!(
+                MalformedPacket,
+                code: MalformedPacket,
+                "{:?} truncated frame at offset {}",
+                loc,
+                off
+            )?,
+        };
+
+        match Packet::decode(frame) {
+            Ok((pkt, m)) if m == n => pkts.push(pkt),
+            Ok((_, m)) => {
+                error!("{:?} frame at offset {} decoded {}/{} bytes", loc, off, m, n)
+            }
+            Err(err) => error!("{:?} frame at offset {} err:{}", loc, off, err),
+        }
+        off += 4 + n;
+    }
+
+    Ok(pkts)
+}
+
+#[cfg(test)]
+#[path = "capture_test.rs"]
+mod capture_test;
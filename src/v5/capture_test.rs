@@ -0,0 +1,39 @@
+use super::*;
+
+use crate::v5::SubscriptionOpt;
+use crate::v5::{Connect, QoS, RetainForwardRule, Subscribe, SubscribeFilter};
+use crate::TopicFilter;
+
+#[test]
+fn test_capture_replay() {
+    let loc =
+        std::env::temp_dir().join(format!("mymq-capture-{}.log", std::process::id()));
+    fs::remove_file(&loc).ok();
+
+    let rfr = RetainForwardRule::OnEverySubscribe;
+    let subscribe = Subscribe {
+        packet_id: 1,
+        properties: None,
+        filters: vec![SubscribeFilter {
+            topic_filter: TopicFilter::from("a/b/#".to_string()),
+            opt: SubscriptionOpt::new(rfr, false, false, QoS::AtLeastOnce),
+        }],
+    };
+    let pkts = vec![Packet::Connect(Connect::default()), Packet::Subscribe(subscribe)];
+
+    capture_write(&loc, pkts[0].encode().unwrap().as_ref()).unwrap();
+    // a frame that fails to decode is skipped.
+    capture_write(&loc, &[0xF0, 0x01, 0xFF]).unwrap();
+    capture_write(&loc, pkts[1].encode().unwrap().as_ref()).unwrap();
+
+    assert_eq!(replay(&loc).unwrap(), pkts);
+
+    // truncated capture is reported as error.
+    let mut data = fs::read(&loc).unwrap();
+    data.truncate(data.len() - 1);
+    fs::write(&loc, data).unwrap();
+    let err = replay(&loc).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::MalformedPacket);
+
+    fs::remove_file(&loc).ok();
+}
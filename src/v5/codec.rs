@@ -0,0 +1,77 @@
+use bytes::BytesMut;
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::v5::Packet;
+use crate::{Error, ErrorKind, Packetize, ReasonCode, Result, VarU32};
+
+/// Type implement [tokio_util::codec] for MQTT packets, to be used with
+/// [tokio_util::codec::Framed], [tokio_util::codec::FramedRead] and
+/// [tokio_util::codec::FramedWrite].
+///
+/// Bytes are accumulated until a full packet, as framed by its fixed-header, is
+/// available in the buffer.
+#[derive(Clone, Debug)]
+pub struct PacketCodec {
+    max_size: usize,
+}
+
+impl Default for PacketCodec {
+    fn default() -> PacketCodec {
+        // fixed-header can take upto 5 bytes.
+        PacketCodec { max_size: (*VarU32::MAX as usize) + 5 }
+    }
+}
+
+impl PacketCodec {
+    /// Create a codec that shall not decode packets larger than `max_size`.
+    pub fn new(max_size: u32) -> PacketCodec {
+        PacketCodec { max_size: max_size as usize }
+    }
+}
+
+impl Decoder for PacketCodec {
+    type Item = Packet;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Packet>> {
+        // remaining-length is encoded in 1 to 4 bytes, following byte1.
+        let m = match src.iter().skip(1).take(4).position(|b| *b < 0x80) {
+            Some(i) => i + 1,
+            None if src.len() < 5 => return Ok(None),
+            None => err!(MalformedPacket, code: MalformedPacket, "PacketCodec fh")?,
+        };
+        let (remaining_len, _) = VarU32::decode(&src[1..])?;
+
+        let pkt_len = 1 + m + usize::try_from(*remaining_len)?;
+        if pkt_len > self.max_size {
+            err!(MalformedPacket, code: PacketTooLarge, "PacketCodec len:{}", pkt_len)?;
+        }
+
+        if src.len() < pkt_len {
+            src.reserve(pkt_len - src.len());
+            return Ok(None);
+        }
+
+        let data = src.split_to(pkt_len);
+        match Packet::decode(&data)? {
+            (pkt, n) if n == pkt_len => Ok(Some(pkt)),
+            (_, n) => {
+                err!(MalformedPacket, code: MalformedPacket, "PacketCodec {}!={}", n, pkt_len)
+            }
+        }
+    }
+}
+
+impl Encoder<Packet> for PacketCodec {
+    type Error = Error;
+
+    fn encode(&mut self, pkt: Packet, dst: &mut BytesMut) -> Result<()> {
+        let blob = pkt.encode()?;
+        dst.extend_from_slice(blob.as_ref());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[path = "codec_test.rs"]
+mod codec_test;
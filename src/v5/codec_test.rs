@@ -0,0 +1,86 @@
+use futures::{SinkExt, StreamExt};
+use tokio_util::codec::{FramedRead, FramedWrite};
+
+use crate::v5::{self, QoS};
+use crate::{ClientID, TopicName};
+
+use super::*;
+
+fn new_packets() -> Vec<Packet> {
+    let mut connect = v5::Connect::default();
+    connect.payload.client_id = ClientID("test-codec".to_string());
+
+    let publish = v5::Publish {
+        retain: false,
+        qos: QoS::AtLeastOnce,
+        duplicate: false,
+        topic_name: TopicName::from("a/b/c".to_string()),
+        packet_id: Some(1),
+        properties: None,
+        // remaining-length takes 2 bytes.
+        payload: Some(vec![0xAB; 1000]),
+    };
+
+    vec![
+        Packet::Connect(connect),
+        Packet::Publish(publish),
+        Packet::PingReq,
+        Packet::Disconnect(v5::Disconnect::new(
+            v5::DisconnReasonCode::NormalDisconnect,
+            None,
+        )),
+    ]
+}
+
+#[tokio::test]
+async fn test_codec_framed() {
+    let (wt, rd) = tokio::io::duplex(64);
+    let pkts = new_packets();
+
+    let writer = {
+        let pkts = pkts.clone();
+        tokio::spawn(async move {
+            let mut framed = FramedWrite::new(wt, PacketCodec::default());
+            for pkt in pkts.into_iter() {
+                framed.send(pkt).await.unwrap();
+            }
+        })
+    };
+
+    let mut framed = FramedRead::new(rd, PacketCodec::default());
+    let mut out = Vec::new();
+    while let Some(pkt) = framed.next().await {
+        out.push(pkt.unwrap());
+    }
+    writer.await.unwrap();
+
+    assert_eq!(out, pkts);
+}
+
+#[test]
+fn test_codec_partial() {
+    let mut codec = PacketCodec::default();
+    for pkt in new_packets().into_iter() {
+        let blob = pkt.encode().unwrap();
+        let data = blob.as_ref();
+
+        let mut src = BytesMut::new();
+        for byte in data[..data.len() - 1].iter() {
+            src.extend_from_slice(&[*byte]);
+            assert!(codec.decode(&mut src).unwrap().is_none());
+        }
+        src.extend_from_slice(&data[data.len() - 1..]);
+        assert_eq!(codec.decode(&mut src).unwrap(), Some(pkt));
+        assert!(src.is_empty());
+    }
+}
+
+#[test]
+fn test_codec_max_size() {
+    let mut codec = PacketCodec::new(100);
+    let pkt = new_packets().remove(1);
+    let mut src = BytesMut::from(pkt.encode().unwrap().as_ref());
+
+    let err = codec.decode(&mut src).unwrap_err();
+    assert_eq!(err.code(), ReasonCode::PacketTooLarge);
+}
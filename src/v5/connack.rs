@@ -7,7 +7,7 @@ use std::result;
 use std::ops::{Deref, DerefMut};
 
 use crate::util::advance;
-use crate::v5::{FixedHeader, Property, PropertyType, QoS};
+use crate::v5::{DecodeConfig, FixedHeader, Property, PropertyType, QoS};
 use crate::{Blob, Packetize, UserProperty, VarU32};
 use crate::{Error, ErrorKind, ReasonCode, Result};
 
@@ -212,15 +212,25 @@ impl ConnAck {
 
 impl Packetize for ConnAck {
     fn decode<T: AsRef<[u8]>>(stream: T) -> Result<(Self, usize)> {
+        Self::decode_with(stream, &DecodeConfig::default())
+    }
+
+    fn decode_with<T>(stream: T, cfg: &DecodeConfig) -> Result<(Self, usize)>
+    where
+        T: AsRef<[u8]>,
+    {
+        use crate::v5::PacketType;
+
         let stream: &[u8] = stream.as_ref();
 
         let (fh, n) = dec_field!(FixedHeader, stream, 0);
-        fh.validate()?;
+        fh.validate_flags(PacketType::ConnAck)?;
+        Self::remaining_len_ok(stream, &fh)?;
 
         let (flags, n) = dec_field!(ConnackFlags, stream, n);
         let (code, n) = dec_field!(u8, stream, n);
         let code = ConnackReasonCode::try_from(code)?;
-        let (properties, n) = dec_props!(ConnAckProperties, stream, n);
+        let (properties, n) = dec_props!(ConnAckProperties, stream, n, cfg);
 
         let val = ConnAck { flags, code, properties };
 
@@ -241,7 +251,8 @@ impl Packetize for ConnAck {
             data.extend_from_slice(VarU32(0).encode()?.as_ref());
         }
 
-        let fh = FixedHeader::new(PacketType::ConnAck, VarU32(data.len().try_into()?))?;
+        let fh =
+            FixedHeader::new(PacketType::ConnAck, VarU32::try_from_len(data.len())?)?;
         data = insert_fixed_header(fh, data)?;
 
         Ok(Blob::Large { data })
@@ -337,6 +348,13 @@ impl<'a> Arbitrary<'a> for ConnAckProperties {
 
 impl Packetize for ConnAckProperties {
     fn decode<T: AsRef<[u8]>>(stream: T) -> Result<(Self, usize)> {
+        Self::decode_with(stream, &DecodeConfig::default())
+    }
+
+    fn decode_with<T>(stream: T, cfg: &DecodeConfig) -> Result<(Self, usize)>
+    where
+        T: AsRef<[u8]>,
+    {
         use crate::v5::Property::*;
 
         let stream: &[u8] = stream.as_ref();
@@ -381,7 +399,9 @@ impl Packetize for ConnAckProperties {
                 }
                 TopicAliasMaximum(val) => props.topic_alias_max = Some(val),
                 ReasonString(val) => props.reason_string = Some(val),
-                UserProp(val) => props.user_properties.push(val),
+                UserProp(val) => {
+                    cfg.push_user_property(PP, &mut props.user_properties, val)?
+                }
                 WildcardSubscriptionAvailable(0) => {
                     props.wildcard_subscription_available = Some(false);
                 }
@@ -423,9 +443,7 @@ impl Packetize for ConnAckProperties {
                 ServerReference(val) => props.server_reference = Some(val),
                 AuthenticationMethod(val) => props.authentication_method = Some(val),
                 AuthenticationData(val) => props.authentication_data = Some(val),
-                _ => {
-                    err!(ProtocolError, code: ProtocolError, "{} bad prop {:?}", PP, pt)?
-                }
+                _ => cfg.skip_property(PP, pt)?,
             };
         }
 
@@ -1,5 +1,7 @@
 #[cfg(any(feature = "fuzzy", test))]
 use arbitrary::{Arbitrary, Error as ArbitraryError, Unstructured};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 #[cfg(any(feature = "fuzzy", test))]
 use std::result;
@@ -7,13 +9,16 @@ use std::result;
 use std::ops::{Deref, DerefMut};
 
 use crate::util::advance;
-use crate::v5::{FixedHeader, PayloadFormat, Property, PropertyType, QoS, UserProperty};
+use crate::v5::{
+    DecodeConfig, FixedHeader, PayloadFormat, Property, PropertyType, QoS, UserProperty,
+};
 use crate::{Blob, ClientID, MqttProtocol, Packetize, TopicName, VarU32};
 use crate::{Error, ErrorKind, ReasonCode, Result};
 
 const PP: &'static str = "Packet::Connect";
 
 /// Flags carried by CONNECT packet
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Clone, Copy, Eq, PartialEq, Debug)]
 pub struct ConnectFlags(pub u8);
 
@@ -103,12 +108,18 @@ impl ConnectFlags {
     pub fn unwrap(&self) -> (bool, bool, QoS, bool) {
         let clean_start: bool = (self.0 & Self::CLEAN_START.0) > 0;
         let will_flag: bool = (self.0 & Self::WILL_FLAG.0) > 0;
-        let will_qos: QoS = (self.0 & Self::WILL_QOS_MASK >> 3).try_into().unwrap();
+        // reserved will-qos is rejected by Connect::validate, saturate here.
+        let will_qos = self.will_qos().unwrap_or(QoS::ExactlyOnce);
         let will_retain: bool = (self.0 & Self::WILL_RETAIN.0) > 0;
 
         (clean_start, will_flag, will_qos, will_retain)
     }
 
+    /// Return will-qos from bits 3 and 4, MalformedPacket for reserved value 3.
+    pub fn will_qos(&self) -> Result<QoS> {
+        QoS::try_from((self.0 & Self::WILL_QOS_MASK) >> 3)
+    }
+
     pub fn is_will_flag(&self) -> bool {
         (self.0 & Self::WILL_FLAG.0) > 0
     }
@@ -131,6 +142,7 @@ impl ConnectFlags {
 }
 
 /// CONNECT packet
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Connect {
     pub protocol_name: String,
@@ -142,13 +154,16 @@ pub struct Connect {
 }
 
 /// Payload in CONNECT packet
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub struct ConnectPayload {
     pub client_id: ClientID,
     pub will_properties: Option<WillProperties>,
     pub will_topic: Option<TopicName>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::util::base64_opt"))]
     pub will_payload: Option<Vec<u8>>,
     pub username: Option<String>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::util::base64_opt"))]
     pub password: Option<Vec<u8>>,
 }
 
@@ -249,12 +264,22 @@ impl<'a> Arbitrary<'a> for Connect {
 
 impl Packetize for Connect {
     fn decode<T: AsRef<[u8]>>(stream: T) -> Result<(Self, usize)> {
+        Self::decode_with(stream, &DecodeConfig::default())
+    }
+
+    fn decode_with<T>(stream: T, cfg: &DecodeConfig) -> Result<(Self, usize)>
+    where
+        T: AsRef<[u8]>,
+    {
+        use crate::v5::PacketType;
+
         let stream: &[u8] = stream.as_ref();
 
         // println!("Connect::decode {:?}", stream);
 
         let (fh, n) = dec_field!(FixedHeader, stream, 0);
-        fh.validate()?;
+        fh.validate_flags(PacketType::Connect)?;
+        Self::remaining_len_ok(stream, &fh)?;
 
         let (protocol_name, n) = dec_field!(String, stream, n);
         let (protocol_version, n) = {
@@ -263,12 +288,15 @@ impl Packetize for Connect {
         };
         let (flags, n) = dec_field!(ConnectFlags, stream, n);
         let (keep_alive, n) = dec_field!(u16, stream, n);
-        let (properties, n) = dec_props!(ConnectProperties, stream, n);
+        let (properties, n) = dec_props!(ConnectProperties, stream, n, cfg);
         let will_flag = flags.is_will_flag();
 
         // payload
         let (client_id, n) = dec_field!(String, stream, n);
-        let (will_properties, n) = dec_props!(WillProperties, stream, n; will_flag);
+        let (will_properties, n) = dec_props!(WillProperties, stream, n, cfg; will_flag);
+        // ZERO length will-properties are decoded as default properties.
+        let will_properties =
+            will_properties.or_else(|| will_flag.then(WillProperties::default));
         let (will_topic, n) = dec_field!(TopicName, stream, n; will_flag);
         let (will_payload, n) = dec_field!(Vec<u8>, stream, n; will_flag);
         let (username, n) = dec_field!(String, stream, n; flags.is_username());
@@ -328,16 +356,53 @@ impl Packetize for Connect {
             data.extend_from_slice(password.encode()?.as_ref());
         }
 
-        let fh = FixedHeader::new(PacketType::Connect, VarU32(data.len().try_into()?))?;
+        let fh =
+            FixedHeader::new(PacketType::Connect, VarU32::try_from_len(data.len())?)?;
         data = insert_fixed_header(fh, data)?;
 
         // println!("Connect::encode {:?}", data);
 
         Ok(Blob::Large { data })
     }
+
+    fn encoded_len(&self) -> Result<usize> {
+        use crate::v5::{field_len, packet_len, props_len};
+
+        self.validate()?;
+
+        // protocol-name, protocol-version, connect-flags, keep-alive and properties.
+        let mut n = field_len(self.protocol_name.len())? + 1 + 1 + 2;
+        n += props_len(self.properties.as_ref())?;
+
+        // payload
+        let pld = &self.payload;
+        n += field_len(pld.client_id.len())?;
+        if let Some(will_properties) = &pld.will_properties {
+            n += will_properties.encoded_len()?;
+        }
+        if let Some(will_topic) = &pld.will_topic {
+            n += field_len(will_topic.len())?;
+        }
+        if let Some(will_payload) = &pld.will_payload {
+            n += field_len(will_payload.len())?;
+        }
+        if let Some(username) = &pld.username {
+            n += field_len(username.len())?;
+        }
+        if let Some(password) = &pld.password {
+            n += field_len(password.len())?;
+        }
+
+        packet_len(n)
+    }
 }
 
 impl Connect {
+    /// Return the size of this packet, including the fixed-header, when encoded.
+    pub fn encoded_size(&self) -> Result<usize> {
+        self.encoded_len()
+    }
+
     pub fn normalize(&mut self) {
         if let Some(props) = &self.properties {
             if props.is_empty() {
@@ -351,6 +416,68 @@ impl Connect {
         }
     }
 
+    /// Set username, and password if supplied, along with their connect-flags.
+    pub fn with_credentials(mut self, user: &str, pass: Option<&[u8]>) -> Connect {
+        *self.flags |= *ConnectFlags::USERNAME;
+        self.payload.username = Some(user.to_string());
+        match pass {
+            Some(pass) => {
+                *self.flags |= *ConnectFlags::PASSWORD;
+                self.payload.password = Some(pass.to_vec());
+            }
+            None => {
+                *self.flags &= !*ConnectFlags::PASSWORD;
+                self.payload.password = None;
+            }
+        }
+        self
+    }
+
+    /// Set will-message, along with will-flag, will-qos and will-retain in
+    /// connect-flags.
+    pub fn with_will(
+        mut self,
+        topic: TopicName,
+        payload: Vec<u8>,
+        qos: QoS,
+        retain: bool,
+        props: WillProperties,
+    ) -> Connect {
+        let mask = ConnectFlags::WILL_QOS_MASK | *ConnectFlags::WILL_RETAIN;
+        let mut flags = (*self.flags & !mask) | *ConnectFlags::WILL_FLAG;
+        flags |= match qos {
+            QoS::AtMostOnce => *ConnectFlags::WILL_QOS0,
+            QoS::AtLeastOnce => *ConnectFlags::WILL_QOS1,
+            QoS::ExactlyOnce => *ConnectFlags::WILL_QOS2,
+        };
+        if retain {
+            flags |= *ConnectFlags::WILL_RETAIN;
+        }
+        self.flags = ConnectFlags(flags);
+
+        self.payload.will_properties = Some(props);
+        self.payload.will_topic = Some(topic);
+        self.payload.will_payload = Some(payload);
+        self
+    }
+
+    /// Validate the client-id in this packet and generate one if client has sent
+    /// a ZERO length client-id along with `clean_start`. Return the assigned
+    /// client-id, that shall be sent back to the client in CONNACK.
+    pub fn assign_client_id(&mut self) -> Result<Option<ClientID>> {
+        let (clean_start, _, _, _) = self.flags.unwrap();
+        self.payload.client_id.validate(clean_start)?;
+
+        match self.payload.client_id.len() {
+            0 => {
+                let client_id = ClientID::new_uuid_v4();
+                self.payload.client_id = client_id.clone();
+                Ok(Some(client_id))
+            }
+            _ => Ok(None),
+        }
+    }
+
     pub fn validate(&self) -> Result<()> {
         if self.protocol_name != "MQTT" {
             err!(
@@ -374,7 +501,7 @@ impl Connect {
         self.flags.validate()?;
 
         let flags = *self.flags;
-        QoS::try_from((flags & ConnectFlags::WILL_QOS_MASK) >> 3)?;
+        self.flags.will_qos()?;
         if (flags & *ConnectFlags::WILL_FLAG) > 0 {
             // NOTE: Spec says that properites and payload MUST be specified
             if self.payload.will_topic.is_none() {
@@ -402,11 +529,14 @@ impl Connect {
         }
 
         let pld = &self.payload;
+        if let Some(will_topic) = &pld.will_topic {
+            will_topic.validate()?;
+        }
         if let Some(true) = pld.will_properties.as_ref().map(|p| p.is_utf8()) {
             if let Err(err) = std::str::from_utf8(pld.will_payload.as_ref().unwrap()) {
                 err!(
                     MalformedPacket,
-                    code: MalformedPacket,
+                    code: PayloadFormatInvalid,
                     cause: err,
                     "{} will-message:payload not utf8",
                     PP
@@ -450,6 +580,7 @@ impl Connect {
 }
 
 /// Collection of MQTT properties allowed in CONNECT packet
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, Eq, PartialEq, Default)]
 pub struct ConnectProperties {
     pub session_expiry_interval: Option<u32>, // 0=disable, 0xFFFFFFFF=indefinite
@@ -459,6 +590,7 @@ pub struct ConnectProperties {
     pub request_response_info: Option<bool>,
     pub request_problem_info: Option<bool>,
     pub authentication_method: Option<String>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::util::base64_opt"))]
     pub authentication_data: Option<Vec<u8>>,
     pub user_properties: Vec<UserProperty>,
 }
@@ -494,6 +626,13 @@ impl<'a> Arbitrary<'a> for ConnectProperties {
 
 impl Packetize for ConnectProperties {
     fn decode<T: AsRef<[u8]>>(stream: T) -> Result<(Self, usize)> {
+        Self::decode_with(stream, &DecodeConfig::default())
+    }
+
+    fn decode_with<T>(stream: T, cfg: &DecodeConfig) -> Result<(Self, usize)>
+    where
+        T: AsRef<[u8]>,
+    {
         use crate::v5::Property::*;
 
         let stream: &[u8] = stream.as_ref();
@@ -545,12 +684,12 @@ impl Packetize for ConnectProperties {
                     "request-problem-information invalid {:?}",
                     val
                 )?,
-                UserProp(val) => props.user_properties.push(val),
+                UserProp(val) => {
+                    cfg.push_user_property(PP, &mut props.user_properties, val)?
+                }
                 AuthenticationMethod(val) => props.authentication_method = Some(val),
                 AuthenticationData(val) => props.authentication_data = Some(val),
-                _ => {
-                    err!(ProtocolError, code: ProtocolError, "{} bad prop {:?}", PP, pt)?
-                }
+                _ => cfg.skip_property(PP, pt)?,
             }
         }
 
@@ -585,6 +724,26 @@ impl Packetize for ConnectProperties {
 
         Ok(Blob::Large { data })
     }
+
+    fn encoded_len(&self) -> Result<usize> {
+        use crate::v5::{field_len, property_len, user_props_len};
+
+        let mut n = self.session_expiry_interval.map(|_| 5).unwrap_or(0);
+        n += self.receive_maximum.map(|_| 3).unwrap_or(0);
+        n += self.max_packet_size.map(|_| 5).unwrap_or(0);
+        n += self.topic_alias_max.map(|_| 3).unwrap_or(0);
+        n += self.request_response_info.map(|_| 2).unwrap_or(0);
+        n += self.request_problem_info.map(|_| 2).unwrap_or(0);
+        if let Some(val) = &self.authentication_method {
+            n += 1 + field_len(val.len())?;
+        }
+        if let Some(val) = &self.authentication_data {
+            n += 1 + field_len(val.len())?;
+        }
+        n += user_props_len(&self.user_properties)?;
+
+        property_len(n)
+    }
 }
 
 impl ConnectProperties {
@@ -611,7 +770,30 @@ impl ConnectProperties {
     }
 
     pub fn request_problem_info(&self) -> bool {
-        self.request_response_info.unwrap_or(true)
+        self.request_problem_info.unwrap_or(true)
+    }
+
+    /// Canonicalize CONNECT properties, so that two equivalent CONNECT packets
+    /// compare and encode the same. Session-expiry, receive-maximum, topic-alias
+    /// maximum and the request-info flags are dropped when set to their default,
+    /// and user-properties are sorted by (key, value).
+    pub fn normalize(&mut self) {
+        if let Some(0) = self.session_expiry_interval {
+            self.session_expiry_interval = None;
+        }
+        if let Some(Self::RECEIVE_MAXIMUM) = self.receive_maximum {
+            self.receive_maximum = None;
+        }
+        if let Some(Self::TOPIC_ALIAS_MAXIMUM) = self.topic_alias_max {
+            self.topic_alias_max = None;
+        }
+        if let Some(false) = self.request_response_info {
+            self.request_response_info = None;
+        }
+        if let Some(true) = self.request_problem_info {
+            self.request_problem_info = None;
+        }
+        self.user_properties.sort();
     }
 
     pub fn is_empty(&self) -> bool {
@@ -628,6 +810,7 @@ impl ConnectProperties {
 }
 
 /// Will Property carried in [ConnectPayload]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Clone, Default, Eq, PartialEq, Debug)]
 pub struct WillProperties {
     pub will_delay_interval: Option<u32>,
@@ -635,6 +818,7 @@ pub struct WillProperties {
     pub message_expiry_interval: Option<u32>,
     pub content_type: Option<String>,
     pub response_topic: Option<TopicName>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::util::base64_opt"))]
     pub correlation_data: Option<Vec<u8>>,
     pub user_properties: Vec<UserProperty>,
 }
@@ -669,6 +853,13 @@ impl<'a> Arbitrary<'a> for WillProperties {
 
 impl Packetize for WillProperties {
     fn decode<T: AsRef<[u8]>>(stream: T) -> Result<(Self, usize)> {
+        Self::decode_with(stream, &DecodeConfig::default())
+    }
+
+    fn decode_with<T>(stream: T, cfg: &DecodeConfig) -> Result<(Self, usize)>
+    where
+        T: AsRef<[u8]>,
+    {
         use crate::v5::Property::*;
 
         let stream: &[u8] = stream.as_ref();
@@ -704,14 +895,10 @@ impl Packetize for WillProperties {
                 ContentType(val) => wps.content_type = Some(val),
                 ResponseTopic(val) => wps.response_topic = Some(val),
                 CorrelationData(val) => wps.correlation_data = Some(val),
-                UserProp(val) => wps.user_properties.push(val),
-                _ => err!(
-                    ProtocolError,
-                    code: ProtocolError,
-                    "{} bad prop in will-message {:?}",
-                    PP,
-                    pt
-                )?,
+                UserProp(val) => {
+                    cfg.push_user_property(PP, &mut wps.user_properties, val)?
+                }
+                _ => cfg.skip_property("Packet::Connect will-message", pt)?,
             }
         }
 
@@ -741,6 +928,26 @@ impl Packetize for WillProperties {
 
         Ok(Blob::Large { data })
     }
+
+    fn encoded_len(&self) -> Result<usize> {
+        use crate::v5::{field_len, property_len, user_props_len};
+
+        let mut n = self.will_delay_interval.map(|_| 5).unwrap_or(0);
+        n += if self.payload_format_indicator.is_utf8() { 2 } else { 0 };
+        n += self.message_expiry_interval.map(|_| 5).unwrap_or(0);
+        if let Some(val) = &self.content_type {
+            n += 1 + field_len(val.len())?;
+        }
+        if let Some(val) = &self.response_topic {
+            n += 1 + field_len(val.len())?;
+        }
+        if let Some(val) = &self.correlation_data {
+            n += 1 + field_len(val.len())?;
+        }
+        n += user_props_len(&self.user_properties)?;
+
+        property_len(n)
+    }
 }
 
 impl WillProperties {
@@ -750,6 +957,15 @@ impl WillProperties {
         self.will_delay_interval.unwrap_or(Self::WILL_DELAY_INTERVAL)
     }
 
+    /// Canonicalize will properties, drop will-delay-interval when it is ZERO,
+    /// the default, and sort user-properties by (key, value).
+    pub fn normalize(&mut self) {
+        if let Some(Self::WILL_DELAY_INTERVAL) = self.will_delay_interval {
+            self.will_delay_interval = None;
+        }
+        self.user_properties.sort();
+    }
+
     pub fn is_utf8(&self) -> bool {
         self.payload_format_indicator == PayloadFormat::Utf8
     }
@@ -764,3 +980,7 @@ impl WillProperties {
             && self.user_properties.len() == 0
     }
 }
+
+#[cfg(test)]
+#[path = "connect_test.rs"]
+mod connect_test;
@@ -0,0 +1,403 @@
+#[cfg(feature = "serde")]
+use arbitrary::Unstructured;
+#[cfg(feature = "serde")]
+use rand::{prelude::random, rngs::StdRng, Rng, SeedableRng};
+
+use super::*;
+
+#[test]
+fn test_assign_client_id() {
+    // empty client-id with clean_start, server assigns one.
+    let mut connect = Connect::default();
+    connect.payload.client_id = ClientID("".to_string());
+    let assigned = connect.assign_client_id().unwrap().unwrap();
+    assert!(assigned.len() > 0);
+    assert_eq!(connect.payload.client_id, assigned);
+
+    // empty client-id without clean_start.
+    let flags = ConnectFlags::new(&[]);
+    let mut connect = Connect { flags, ..Connect::default() };
+    connect.payload.client_id = ClientID("".to_string());
+    let err = connect.assign_client_id().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::MalformedPacket);
+    assert_eq!(err.code(), ReasonCode::InvalidClientID);
+
+    // client supplied client-id is retained.
+    let mut connect = Connect { flags, ..Connect::default() };
+    connect.payload.client_id = ClientID("client-1".to_string());
+    assert_eq!(connect.assign_client_id().unwrap(), None);
+    assert_eq!(*connect.payload.client_id, "client-1");
+}
+
+#[test]
+fn test_client_id_validate() {
+    assert!(ClientID("".to_string()).validate(true).is_ok());
+    assert!(ClientID("a".repeat(65535)).validate(false).is_ok());
+
+    for client_id in ["a".repeat(65536), "client\u{0}".to_string(), "\n".to_string()] {
+        let err = ClientID(client_id).validate(true).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::MalformedPacket);
+        assert_eq!(err.code(), ReasonCode::InvalidClientID);
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_connect_serde() {
+    let seed: u64 = random();
+    println!("test_connect_serde seed:{}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    for _ in 0..100 {
+        let bytes: Vec<u8> = (0..16384).map(|_| rng.gen::<u8>()).collect();
+        let mut uns = Unstructured::new(&bytes);
+        let mut connect: Connect = uns.arbitrary().unwrap();
+        connect.normalize();
+
+        let s = serde_json::to_string(&connect).unwrap();
+        let mut val: Connect = serde_json::from_str(&s).unwrap();
+        val.normalize();
+        assert_eq!(val, connect, "{}", s);
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_connect_serde_base64() {
+    let flags = ConnectFlags::new(&[ConnectFlags::WILL_FLAG]);
+    let mut connect = Connect { flags, ..Connect::default() };
+    connect.payload.will_topic = Some(TopicName::from("will/topic".to_string()));
+    connect.payload.will_payload = Some(b"gone".to_vec());
+    connect.payload.will_properties = Some(WillProperties {
+        correlation_data: Some(vec![0xFF, 0x00]),
+        ..WillProperties::default()
+    });
+
+    let s = serde_json::to_string(&connect).unwrap();
+    assert!(s.contains(r#""will_payload":"Z29uZQ==""#), "{}", s);
+    assert!(s.contains(r#""correlation_data":"/wA=""#), "{}", s);
+
+    let val: Connect = serde_json::from_str(&s).unwrap();
+    assert_eq!(val, connect);
+    assert_eq!(val.encode().unwrap().as_ref(), connect.encode().unwrap().as_ref());
+}
+
+#[test]
+fn test_will_topic_validate() {
+    let new_connect = |will_topic: &str| {
+        let mut connect = Connect::default();
+        connect.flags = ConnectFlags::new(&[ConnectFlags::WILL_FLAG]);
+        connect.payload.will_topic = Some(TopicName::from(will_topic.to_string()));
+        connect.payload.will_payload = Some(b"gone".to_vec());
+        connect.payload.will_properties = Some(WillProperties::default());
+        connect
+    };
+
+    for will_topic in ["a/+/b", "a/\u{0}/b"] {
+        let err = new_connect(will_topic).validate().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::MalformedPacket, "{:?}", will_topic);
+        assert_eq!(err.code(), ReasonCode::TopicNameInvalid, "{:?}", will_topic);
+    }
+
+    new_connect("a/b/c").validate().unwrap();
+}
+
+#[test]
+fn test_connect_flags_unwrap() {
+    // out-of-range will-qos never panics.
+    for byte in 0..=u8::MAX {
+        ConnectFlags(byte).unwrap();
+    }
+}
+
+#[test]
+fn test_connect_properties_normalize() {
+    let uprop = UserProperty::new::<&str, &str>;
+
+    let mut props1 = ConnectProperties {
+        receive_maximum: Some(ConnectProperties::RECEIVE_MAXIMUM),
+        request_problem_info: Some(true),
+        user_properties: vec![uprop("b", "1"), uprop("a", "2"), uprop("a", "1")],
+        ..ConnectProperties::default()
+    };
+    let mut props2 = ConnectProperties {
+        user_properties: vec![uprop("a", "1"), uprop("b", "1"), uprop("a", "2")],
+        ..ConnectProperties::default()
+    };
+    assert_ne!(props1.encode().unwrap().as_ref(), props2.encode().unwrap().as_ref());
+
+    props1.normalize();
+    props2.normalize();
+    assert_eq!(props1, props2);
+    assert_eq!(props1.encode().unwrap().as_ref(), props2.encode().unwrap().as_ref());
+    assert_eq!(props1.receive_maximum(), ConnectProperties::RECEIVE_MAXIMUM);
+    assert!(props1.request_problem_info());
+}
+
+#[test]
+fn test_connect_flags_will_qos() {
+    let testcases = [
+        (ConnectFlags::WILL_QOS0, QoS::AtMostOnce),
+        (ConnectFlags::WILL_QOS1, QoS::AtLeastOnce),
+        (ConnectFlags::WILL_QOS2, QoS::ExactlyOnce),
+    ];
+    for (qos_flag, qos) in testcases.into_iter() {
+        let flags = ConnectFlags::new(&[ConnectFlags::WILL_FLAG, qos_flag]);
+        assert_eq!(flags.will_qos().unwrap(), qos);
+        assert_eq!(flags.unwrap(), (false, true, qos, false));
+    }
+
+    // reserved will-qos
+    assert!(ConnectFlags(0b_0001_1000).will_qos().is_err());
+}
+
+#[test]
+fn test_connect_oversized_will_payload() {
+    use crate::MQTTRead;
+
+    let max_size = 1024;
+    let mut connect = Connect::default();
+    connect.flags = ConnectFlags::new(&[ConnectFlags::WILL_FLAG]);
+    connect.payload.will_topic = Some(TopicName::from("a/b/c".to_string()));
+    connect.payload.will_payload = Some(vec![0xAB; 2 * max_size]);
+    connect.payload.will_properties = Some(WillProperties::default());
+
+    let data = connect.encode().unwrap().as_ref().to_vec();
+    assert_eq!(connect.encoded_size().unwrap(), data.len());
+    assert!(connect.encoded_size().unwrap() > max_size);
+
+    // rejected using the fixed-header, before reading the will payload.
+    let mut stream = std::io::Cursor::new(data[..5].to_vec());
+    let err = MQTTRead::new(max_size as u32).read(&mut stream).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::MalformedPacket);
+    assert_eq!(err.code(), ReasonCode::PacketTooLarge);
+
+    connect.payload.will_payload = Some(b"gone".to_vec());
+    assert!(connect.encoded_size().unwrap() < max_size);
+}
+
+#[test]
+fn test_connect_big_endian() {
+    let mut connect = Connect::default();
+    connect.keep_alive = 0x1234;
+    connect.properties = Some(ConnectProperties {
+        session_expiry_interval: Some(0x01020304),
+        ..ConnectProperties::default()
+    });
+    let data = connect.encode().unwrap().as_ref().to_vec();
+    let (_, n) = FixedHeader::decode(&data).unwrap();
+
+    // protocol-name (6 bytes), protocol-level and connect-flags precede keep-alive.
+    assert_eq!(&data[n + 8..n + 10], &[0x12, 0x34]);
+    // property-length, property-id precede session-expiry-interval.
+    assert_eq!(data[n + 11], PropertyType::SessionExpiryInterval as u8);
+    assert_eq!(&data[n + 12..n + 16], &[0x01, 0x02, 0x03, 0x04]);
+
+    assert_eq!(Connect::decode(&data).unwrap().0, connect);
+}
+
+#[test]
+fn test_connect_with_credentials() {
+    let connect = Connect::default().with_credentials("user", Some(b"pass"));
+    assert!(connect.flags.is_username());
+    assert!(connect.flags.is_password());
+    connect.validate().unwrap();
+    let data = connect.encode().unwrap().as_ref().to_vec();
+    assert_eq!(Connect::decode(&data).unwrap(), (connect.clone(), data.len()));
+
+    // password is cleared, along with its flag.
+    let connect = connect.with_credentials("user", None);
+    assert!(connect.flags.is_username());
+    assert!(!connect.flags.is_password());
+    assert_eq!(connect.payload.password, None);
+    let data = connect.encode().unwrap().as_ref().to_vec();
+    assert_eq!(Connect::decode(&data).unwrap(), (connect, data.len()));
+}
+
+#[test]
+fn test_connect_with_will() {
+    let topic = TopicName::from("will/topic".to_string());
+    let props = WillProperties {
+        will_delay_interval: Some(10),
+        ..WillProperties::default()
+    };
+
+    for qos in [QoS::AtMostOnce, QoS::AtLeastOnce, QoS::ExactlyOnce] {
+        for retain in [true, false] {
+            let connect = Connect::default()
+                .with_will(
+                    topic.clone(),
+                    b"gone".to_vec(),
+                    QoS::ExactlyOnce,
+                    true,
+                    props.clone(),
+                )
+                .with_will(topic.clone(), b"gone".to_vec(), qos, retain, props.clone());
+            let (clean_start, will_flag, will_qos, will_retain) = connect.flags.unwrap();
+            assert_eq!((clean_start, will_flag), (true, true));
+            assert_eq!((will_qos, will_retain), (qos, retain));
+            connect.validate().unwrap();
+
+            let data = connect.encode().unwrap().as_ref().to_vec();
+            assert_eq!(Connect::decode(&data).unwrap(), (connect, data.len()));
+        }
+    }
+}
+
+#[test]
+fn test_connect_zero_length_will_properties() {
+    let topic = TopicName::from("will/topic".to_string());
+    let props = WillProperties::default();
+    let connect =
+        Connect::default().with_will(topic, vec![], QoS::AtMostOnce, false, props);
+
+    // default will-properties are encoded as ZERO length properties.
+    let data = connect.encode().unwrap().as_ref().to_vec();
+    let (val, n) = Connect::decode(&data).unwrap();
+    assert_eq!(n, data.len());
+    assert_eq!(val.payload.will_properties, Some(WillProperties::default()));
+    val.validate().unwrap();
+    assert_eq!(val, connect);
+
+    // without will-flag, will-properties are not part of the payload.
+    let data = Connect::default().encode().unwrap().as_ref().to_vec();
+    let (val, _) = Connect::decode(&data).unwrap();
+    assert_eq!(val.payload.will_properties, None);
+}
+
+#[test]
+fn test_connect_decode_bad_string() {
+    // replace the first occurrence of `from` in `data` with `to`.
+    let patch = |data: &[u8], from: &[u8], to: &[u8]| -> Vec<u8> {
+        let off = data.windows(from.len()).position(|w| w == from).unwrap();
+        let mut data = data.to_vec();
+        data[off..off + to.len()].copy_from_slice(to);
+        data
+    };
+
+    let mut connect = Connect::default().with_credentials("user-name", Some(b"pass"));
+    connect.payload.client_id = ClientID("client-id".to_string());
+    connect.properties = Some(ConnectProperties {
+        authentication_method: Some("auth-method".to_string()),
+        user_properties: vec![UserProperty::new("user-key", "user-val")],
+        ..ConnectProperties::default()
+    });
+    let data = connect.encode().unwrap().as_ref().to_vec();
+    assert_eq!(Connect::decode(&data).unwrap().0, connect);
+
+    let fields: [&[u8]; 6] =
+        [b"MQTT", b"client-id", b"user-name", b"auth-method", b"user-key", b"user-val"];
+    for field in fields.iter() {
+        // embedded U+0000 and ill-formed UTF-8.
+        for bad in [&b"\x00"[..], &b"\xC3\x28"[..], &b"\xFF"[..]] {
+            let mut to = field.to_vec();
+            to[1..1 + bad.len()].copy_from_slice(bad);
+            let data = patch(&data, field, &to);
+
+            let err = Connect::decode(&data).unwrap_err();
+            let s = String::from_utf8_lossy(field);
+            assert_eq!(err.kind(), ErrorKind::MalformedPacket, "{} {:?}", s, bad);
+            assert_eq!(err.code(), ReasonCode::MalformedPacket, "{} {:?}", s, bad);
+        }
+    }
+}
+
+#[test]
+fn test_connect_decode_reserved_flags() {
+    let mut connect = Connect::default();
+    connect.payload.client_id = ClientID("client-id".to_string());
+    let data = connect.encode().unwrap().as_ref().to_vec();
+    assert_eq!(data[0], 0x10);
+    assert_eq!(Connect::decode(&data).unwrap(), (connect, data.len()));
+
+    // fixed-header flags for CONNECT are reserved, and must be 0b0000.
+    for flags in 1..=0x0F_u8 {
+        let mut data = data.clone();
+        data[0] |= flags;
+
+        let err = Connect::decode(&data).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::MalformedPacket, "flags:{:x}", flags);
+        assert_eq!(err.code(), ReasonCode::MalformedPacket, "flags:{:x}", flags);
+    }
+
+    // protocol-level other than 4 or 5 is unsupported.
+    let mut data = data.clone();
+    data[8] = 3;
+    let err = Connect::decode(&data).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::MalformedPacket);
+    assert_eq!(err.code(), ReasonCode::UnsupportedProtocolVersion);
+}
+
+#[test]
+fn test_connect_strict_properties() {
+    let connect = Connect {
+        properties: Some(ConnectProperties {
+            session_expiry_interval: Some(10),
+            ..ConnectProperties::default()
+        }),
+        ..Connect::default()
+    };
+    let mut data = connect.encode().unwrap().as_ref().to_vec();
+    assert_eq!(data[12], 5); // property-length, for session-expiry-interval.
+
+    // server-keep-alive is well-formed, but not allowed in CONNECT.
+    let spurious = [PropertyType::ServerKeepAlive as u8, 0x00, 0x0A];
+    data.splice(13..13, spurious.iter().cloned());
+    data[1] += 3;
+    data[12] += 3;
+
+    let err = Connect::decode(&data).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ProtocolError);
+    assert_eq!(err.code(), ReasonCode::ProtocolError);
+
+    let cfg = DecodeConfig {
+        strict_properties: false,
+        ..DecodeConfig::default()
+    };
+    assert_eq!(Connect::decode_with(&data, &cfg).unwrap(), (connect, data.len()));
+}
+
+#[test]
+fn test_connect_encoded_len() {
+    let mut connect = Connect::default();
+    connect.payload.client_id = ClientID("client-id".to_string());
+
+    let with_creds = connect.clone().with_credentials("user-name", Some(b"pass"));
+    let with_props = Connect {
+        properties: Some(ConnectProperties {
+            session_expiry_interval: Some(10),
+            authentication_method: Some("auth-method".to_string()),
+            user_properties: vec![UserProperty::new("key", "val")],
+            ..ConnectProperties::default()
+        }),
+        ..with_creds.clone()
+    };
+    // remaining length is encoded in three bytes.
+    let with_will = connect.clone().with_will(
+        TopicName::from("a/b/c".to_string()),
+        vec![0xAB; 20_000],
+        QoS::AtLeastOnce,
+        true,
+        WillProperties::default(),
+    );
+
+    for connect in [connect, with_creds, with_props, with_will].iter() {
+        let n = connect.encode().unwrap().as_ref().len();
+        assert_eq!(connect.encoded_len().unwrap(), n, "{:?}", connect.flags);
+    }
+}
+
+#[test]
+fn test_connect_properties_request_info() {
+    let props = ConnectProperties::default();
+    assert!(!props.request_response_info());
+    assert!(props.request_problem_info());
+
+    let props = ConnectProperties {
+        request_response_info: Some(true),
+        request_problem_info: Some(false),
+        ..ConnectProperties::default()
+    };
+    assert!(props.request_response_info());
+    assert!(!props.request_problem_info());
+}
@@ -5,7 +5,7 @@ use arbitrary::{Arbitrary, Error as ArbitraryError, Unstructured};
 use std::result;
 
 use crate::util::advance;
-use crate::v5::{FixedHeader, Property, PropertyType};
+use crate::v5::{DecodeConfig, FixedHeader, Property, PropertyType};
 use crate::{Blob, Packetize, UserProperty, VarU32};
 use crate::{Error, ErrorKind, ReasonCode, Result};
 
@@ -94,6 +94,19 @@ impl TryFrom<u8> for DisconnReasonCode {
     }
 }
 
+impl DisconnReasonCode {
+    /// Return the reason-code to DISCONNECT a client, whose connection is closed
+    /// due to `err`. Reason-code carried by `err` is used, if it is allowed in
+    /// DISCONNECT, otherwise falls back to [ErrorKind::default_disconnect_reason].
+    pub fn from_error(err: &Error) -> DisconnReasonCode {
+        let code = err.code.and_then(|code| DisconnReasonCode::try_from(code as u8).ok());
+        match code {
+            Some(code) => code,
+            None => err.kind().default_disconnect_reason(),
+        }
+    }
+}
+
 /// DISCONNECT Packet
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub struct Disconnect {
@@ -130,12 +143,22 @@ impl Disconnect {
 
 impl Packetize for Disconnect {
     fn decode<T: AsRef<[u8]>>(stream: T) -> Result<(Self, usize)> {
+        Self::decode_with(stream, &DecodeConfig::default())
+    }
+
+    fn decode_with<T>(stream: T, cfg: &DecodeConfig) -> Result<(Self, usize)>
+    where
+        T: AsRef<[u8]>,
+    {
+        use crate::v5::PacketType;
+
         let stream: &[u8] = stream.as_ref();
 
         // println!("Disconnect decode {:?}", stream);
 
         let (fh, n) = dec_field!(FixedHeader, stream, 0);
-        fh.validate()?;
+        fh.validate_flags(PacketType::Disconnect)?;
+        Self::remaining_len_ok(stream, &fh)?;
 
         let (val, n) = match *fh.remaining_len {
             0 => {
@@ -150,7 +173,7 @@ impl Packetize for Disconnect {
             _ => {
                 let (code, n) = dec_field!(u8, stream, n);
                 let code = DisconnReasonCode::try_from(code)?;
-                let (properties, n) = dec_props!(DisconnProperties, stream, n);
+                let (properties, n) = dec_props!(DisconnProperties, stream, n, cfg);
                 (Disconnect { code, properties }, n)
             }
         };
@@ -171,7 +194,7 @@ impl Packetize for Disconnect {
             data.extend_from_slice(VarU32(0).encode()?.as_ref());
         }
 
-        let fh = FixedHeader::new(Disconnect, VarU32(data.len().try_into()?))?;
+        let fh = FixedHeader::new(Disconnect, VarU32::try_from_len(data.len())?)?;
         data = insert_fixed_header(fh, data)?;
 
         // println!("Disconnect encoded {:?}", data);
@@ -228,6 +251,13 @@ impl<'a> Arbitrary<'a> for DisconnProperties {
 
 impl Packetize for DisconnProperties {
     fn decode<T: AsRef<[u8]>>(stream: T) -> Result<(Self, usize)> {
+        Self::decode_with(stream, &DecodeConfig::default())
+    }
+
+    fn decode_with<T>(stream: T, cfg: &DecodeConfig) -> Result<(Self, usize)>
+    where
+        T: AsRef<[u8]>,
+    {
         use crate::v5::Property::*;
 
         let stream: &[u8] = stream.as_ref();
@@ -252,10 +282,10 @@ impl Packetize for DisconnProperties {
                 SessionExpiryInterval(val) => props.session_expiry_interval = Some(val),
                 ReasonString(val) => props.reason_string = Some(val),
                 ServerReference(val) => props.server_reference = Some(val),
-                UserProp(val) => props.user_properties.push(val),
-                _ => {
-                    err!(ProtocolError, code: ProtocolError, "{} bad prop, {:?}", PP, pt)?
+                UserProp(val) => {
+                    cfg.push_user_property(PP, &mut props.user_properties, val)?
                 }
+                _ => cfg.skip_property(PP, pt)?,
             };
         }
 
@@ -2,10 +2,14 @@
 
 #[cfg(any(feature = "fuzzy", test))]
 use arbitrary::{Arbitrary, Error as ArbitraryError, Unstructured};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 use std::cmp;
 #[cfg(any(feature = "fuzzy", test))]
 use std::result;
+#[cfg(feature = "trace")]
+use std::sync::RwLock;
 
 use crate::util::advance;
 use crate::{Blob, ClientID, Packetize, TopicFilter, TopicName, UserProperty, VarU32};
@@ -18,14 +22,16 @@ use crate::{Error, ErrorKind, ReasonCode, Result};
 macro_rules! dec_field {
     ($type:ty, $stream:expr, $n:expr; $($pred:tt)*) => {{
         if $($pred)* {
-            let (val, m) = <$type>::decode(crate::util::advance($stream, $n)?)?;
+            let stream = crate::util::advance($stream, $n)?;
+            let (val, m) = <$type>::decode(stream).map_err(|err| err.at($n))?;
             (Some(val), $n + m)
         } else {
             (None, $n)
         }
     }};
     ($type:ty, $stream:expr, $n:expr) => {{
-        let (val, m) = <$type>::decode(crate::util::advance($stream, $n)?)?;
+        let stream = crate::util::advance($stream, $n)?;
+        let (val, m) = <$type>::decode(stream).map_err(|err| err.at($n))?;
         (val, $n + m)
     }};
 }
@@ -42,12 +48,14 @@ pub(crate) use dec_prop;
 
 /// MQTT packetization, decode a list of properties.
 macro_rules! dec_props {
-    ($type:ty, $stream:expr, $n:expr; $($pred:tt)*) => {{
+    ($type:ty, $stream:expr, $n:expr, $cfg:expr; $($pred:tt)*) => {{
         if $($pred)* {
-            match VarU32::decode(advance($stream, $n)?)? {
+            let stream = advance($stream, $n)?;
+            match VarU32::decode(stream).map_err(|err| err.at($n))? {
                 (VarU32(0), m) => (None, $n + m),
                 (VarU32(p), m) => {
-                    let (properties, r) = <$type>::decode(advance($stream, $n)?)?;
+                    let (properties, r) =
+                        <$type>::decode_with(stream, $cfg).map_err(|err| err.at($n))?;
                     let p = usize::try_from(p)?;
                     if r == (m + p) {
                         (Some(properties), $n + r)
@@ -57,7 +65,8 @@ macro_rules! dec_props {
                             code: ProtocolError,
                             "property len mismatching {}",
                             r
-                        )?
+                        )
+                        .map_err(|err: Error| err.at($n))?
                     }
                 }
             }
@@ -65,11 +74,13 @@ macro_rules! dec_props {
             (None, $n)
         }
     }};
-    ($type:ty, $stream:expr, $n:expr) => {{
-        match VarU32::decode(advance($stream, $n)?)? {
+    ($type:ty, $stream:expr, $n:expr, $cfg:expr) => {{
+        let stream = advance($stream, $n)?;
+        match VarU32::decode(stream).map_err(|err| err.at($n))? {
             (VarU32(0), m) => (None, $n + m),
             (VarU32(p), m) => {
-                let (properties, r) = <$type>::decode(advance($stream, $n)?)?;
+                let (properties, r) =
+                    <$type>::decode_with(stream, $cfg).map_err(|err| err.at($n))?;
                 let p = usize::try_from(p)?;
                 if r == (m + p) {
                     (Some(properties), $n + r)
@@ -79,7 +90,8 @@ macro_rules! dec_props {
                         code: ProtocolError,
                         "property len mismatching {}",
                         r
-                    )?
+                    )
+                    .map_err(|err: Error| err.at($n))?
                 }
             }
         }
@@ -105,6 +117,9 @@ macro_rules! enc_prop {
 pub(crate) use enc_prop;
 
 mod auth;
+pub mod capture;
+#[cfg(feature = "tokio")]
+mod codec;
 mod connack;
 mod connect;
 mod disconnect;
@@ -117,6 +132,8 @@ mod unsub;
 mod unsuback;
 
 pub use auth::{Auth, AuthProperties, AuthReasonCode};
+#[cfg(feature = "tokio")]
+pub use codec::PacketCodec;
 pub use connack::{ConnAck, ConnAckProperties, ConnackFlags, ConnackReasonCode};
 pub use connect::WillProperties;
 pub use connect::{Connect, ConnectFlags, ConnectPayload, ConnectProperties};
@@ -130,6 +147,36 @@ pub use suback::{SubAck, SubAckProperties, SubAckReasonCode};
 pub use unsub::{UnSubscribe, UnSubscribeProperties};
 pub use unsuback::{UnsubAck, UnsubAckProperties, UnsubAckReasonCode};
 
+macro_rules! impl_user_props {
+    ($($type:ty),*) => {$(
+        impl $type {
+            /// Return the value of first user-property matching `key`.
+            pub fn user_prop(&self, key: &str) -> Option<&str> {
+                crate::types::user_prop(&self.user_properties, key)
+            }
+
+            /// Return the values of all user-properties matching `key`.
+            pub fn user_props<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> {
+                crate::types::user_props(&self.user_properties, key)
+            }
+        }
+    )*};
+}
+
+impl_user_props!(
+    AuthProperties,
+    ConnAckProperties,
+    ConnectProperties,
+    WillProperties,
+    DisconnProperties,
+    PubProperties,
+    PublishProperties,
+    SubscribeProperties,
+    SubAckProperties,
+    UnSubscribeProperties,
+    UnsubAckProperties
+);
+
 /// Type captures an active subscription by client.
 #[derive(Clone, Debug)]
 pub struct Subscription {
@@ -201,15 +248,102 @@ impl<'a> Arbitrary<'a> for Subscription {
     }
 }
 
+// Subscription is not an MQTT packet, it is packetized to route subscriptions
+// across nodes. Wire format:
+//
+// * topic_filter, as UTF8 string.
+// * client_id, as UTF8 string.
+// * shard_id, as 4-byte big-endian integer.
+// * subscription_id, as variable-byte-integer, ZERO for None.
+// * subscription options, as in SUBSCRIBE packet.
+impl Packetize for Subscription {
+    fn decode<T: AsRef<[u8]>>(stream: T) -> Result<(Self, usize)> {
+        let stream: &[u8] = stream.as_ref();
+
+        let (topic_filter, n) = dec_field!(TopicFilter, stream, 0);
+        let (client_id, n) = dec_field!(String, stream, n);
+        let (shard_id, n) = dec_field!(u32, stream, n);
+        let (subscription_id, n) = dec_field!(VarU32, stream, n);
+        let (opt, n) = dec_field!(SubscriptionOpt, stream, n);
+
+        let (retain_forward_rule, retain_as_published, no_local, qos) = opt.unwrap();
+        let val = Subscription {
+            topic_filter,
+            client_id: ClientID(client_id),
+            shard_id,
+            subscription_id: Some(*subscription_id).filter(|id| *id > 0),
+            qos,
+            no_local,
+            retain_as_published,
+            retain_forward_rule,
+        };
+
+        val.validate()?;
+        Ok((val, n))
+    }
+
+    fn encode(&self) -> Result<Blob> {
+        self.validate()?;
+
+        let mut data = Vec::with_capacity(64);
+
+        let opt = SubscriptionOpt::new(
+            self.retain_forward_rule.clone(),
+            self.retain_as_published,
+            self.no_local,
+            self.qos,
+        );
+        let subscription_id = VarU32(self.subscription_id.unwrap_or(0));
+
+        data.extend_from_slice(self.topic_filter.encode()?.as_ref());
+        data.extend_from_slice(self.client_id.encode()?.as_ref());
+        data.extend_from_slice(self.shard_id.encode()?.as_ref());
+        data.extend_from_slice(subscription_id.encode()?.as_ref());
+        data.extend_from_slice(opt.encode()?.as_ref());
+
+        Ok(Blob::Large { data })
+    }
+}
+
 impl Subscription {
+    /// Create a subscription for `client_id`, hosted in `shard_id`, from a
+    /// topic-filter in SUBSCRIBE packet.
+    pub fn from_filter(
+        filter: &SubscribeFilter,
+        client_id: ClientID,
+        shard_id: u32,
+    ) -> Subscription {
+        let (retain_forward_rule, retain_as_published, no_local, qos) =
+            filter.opt.unwrap();
+        Subscription {
+            topic_filter: filter.topic_filter.clone(),
+            client_id,
+            shard_id,
+            subscription_id: None,
+            qos,
+            no_local,
+            retain_as_published,
+            retain_forward_rule,
+        }
+    }
+
     pub fn route_qos(&self, publish: &Publish, mqtt_maximum_qos: u8) -> QoS {
         let server_qos = QoS::try_from(mqtt_maximum_qos).unwrap();
         cmp::min(cmp::min(server_qos, publish.qos), self.qos)
     }
+
+    fn validate(&self) -> Result<()> {
+        if let Some(0) = self.subscription_id {
+            err!(ProtocolError, code: ProtocolError, "subscription_id is ZERO")?;
+        }
+        self.client_id.validate(true)?;
+        self.topic_filter.validate()
+    }
 }
 
 /// MQTT packet type
 #[cfg_attr(any(feature = "fuzzy", test), derive(Arbitrary))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PacketType {
     Connect = 1,
@@ -335,95 +469,162 @@ impl<'a> Arbitrary<'a> for Packet {
 
 impl Packetize for Packet {
     fn decode<T: AsRef<[u8]>>(stream: T) -> Result<(Self, usize)> {
+        Self::decode_with(stream, &DecodeConfig::default())
+    }
+
+    fn decode_with<T>(stream: T, cfg: &DecodeConfig) -> Result<(Self, usize)>
+    where
+        T: AsRef<[u8]>,
+    {
         let stream: &[u8] = stream.as_ref();
         let (fh, _) = FixedHeader::decode(stream)?;
+        Packet::decode_with_header(&fh, stream, cfg)
+    }
 
-        match fh.unwrap().0 {
+    fn encode(&self) -> Result<Blob> {
+        let blob = match self {
+            Packet::Connect(pkt) => pkt.encode(),
+            Packet::ConnAck(pkt) => pkt.encode(),
+            Packet::Publish(pkt) => pkt.encode(),
+            Packet::PubAck(pkt) => pkt.encode(),
+            Packet::PubRec(pkt) => pkt.encode(),
+            Packet::PubRel(pkt) => pkt.encode(),
+            Packet::PubComp(pkt) => pkt.encode(),
+            Packet::Subscribe(pkt) => pkt.encode(),
+            Packet::SubAck(pkt) => pkt.encode(),
+            Packet::UnSubscribe(pkt) => pkt.encode(),
+            Packet::UnsubAck(pkt) => pkt.encode(),
+            Packet::PingReq => PingReq.encode(),
+            Packet::PingResp => PingResp.encode(),
+            Packet::Disconnect(pkt) => pkt.encode(),
+            Packet::Auth(pkt) => pkt.encode(),
+        }?;
+
+        trace_packet(self.to_packet_type(), blob.as_ref());
+        Ok(blob)
+    }
+}
+
+impl Packet {
+    /// Encode this packet, appending it to `data`. PUBLISH packets are encoded
+    /// directly into `data`, other packets are small and copied into `data`.
+    pub fn encode_into(&self, data: &mut Vec<u8>) -> Result<()> {
+        match self {
+            Packet::Publish(pkt) => {
+                let n = data.len();
+                pkt.encode_into(data)?;
+                trace_packet(PacketType::Publish, &data[n..]);
+                Ok(())
+            }
+            pkt => {
+                data.extend_from_slice(pkt.encode()?.as_ref());
+                Ok(())
+            }
+        }
+    }
+
+    /// Decode packet from `stream`, dispatching on the packet-type in `fh`. `stream`
+    /// must start with the fixed-header that was already decoded into `fh`.
+    pub fn decode_with_header(
+        fh: &FixedHeader,
+        stream: &[u8],
+        cfg: &DecodeConfig,
+    ) -> Result<(Self, usize)> {
+        let (pkt, n) = match fh.unwrap().0 {
             PacketType::Connect => {
-                let (pkt, n) = Connect::decode(stream)?;
-                Ok((Packet::Connect(pkt), n))
+                let (pkt, n) = Connect::decode_with(stream, cfg)?;
+                (Packet::Connect(pkt), n)
             }
             PacketType::ConnAck => {
-                let (pkt, n) = ConnAck::decode(stream)?;
-                Ok((Packet::ConnAck(pkt), n))
+                let (pkt, n) = ConnAck::decode_with(stream, cfg)?;
+                (Packet::ConnAck(pkt), n)
             }
             PacketType::Publish => {
-                let (pkt, n) = Publish::decode(stream)?;
-                Ok((Packet::Publish(pkt), n))
+                let (pkt, n) = Publish::decode_with(stream, cfg)?;
+                (Packet::Publish(pkt), n)
             }
             PacketType::PubAck => {
-                let (pkt, n) = Pub::decode(stream)?;
-                Ok((Packet::PubAck(pkt), n))
+                let (pkt, n) = Pub::decode_with(stream, cfg)?;
+                (Packet::PubAck(pkt), n)
             }
             PacketType::PubRec => {
-                let (pkt, n) = Pub::decode(stream)?;
-                Ok((Packet::PubRec(pkt), n))
+                let (pkt, n) = Pub::decode_with(stream, cfg)?;
+                (Packet::PubRec(pkt), n)
             }
             PacketType::PubRel => {
-                let (pkt, n) = Pub::decode(stream)?;
-                Ok((Packet::PubRel(pkt), n))
+                let (pkt, n) = Pub::decode_with(stream, cfg)?;
+                (Packet::PubRel(pkt), n)
             }
             PacketType::PubComp => {
-                let (pkt, n) = Pub::decode(stream)?;
-                Ok((Packet::PubComp(pkt), n))
+                let (pkt, n) = Pub::decode_with(stream, cfg)?;
+                (Packet::PubComp(pkt), n)
             }
             PacketType::Subscribe => {
-                let (pkt, n) = Subscribe::decode(stream)?;
-                Ok((Packet::Subscribe(pkt), n))
+                let (pkt, n) = Subscribe::decode_with(stream, cfg)?;
+                (Packet::Subscribe(pkt), n)
             }
             PacketType::SubAck => {
-                let (pkt, n) = SubAck::decode(stream)?;
-                Ok((Packet::SubAck(pkt), n))
+                let (pkt, n) = SubAck::decode_with(stream, cfg)?;
+                (Packet::SubAck(pkt), n)
             }
             PacketType::UnSubscribe => {
-                let (pkt, n) = UnSubscribe::decode(stream)?;
-                Ok((Packet::UnSubscribe(pkt), n))
+                let (pkt, n) = UnSubscribe::decode_with(stream, cfg)?;
+                (Packet::UnSubscribe(pkt), n)
             }
             PacketType::UnsubAck => {
-                let (pkt, n) = UnsubAck::decode(stream)?;
-                Ok((Packet::UnsubAck(pkt), n))
+                let (pkt, n) = UnsubAck::decode_with(stream, cfg)?;
+                (Packet::UnsubAck(pkt), n)
             }
             PacketType::PingReq => {
                 let (_pkt, n) = PingReq::decode(stream)?;
-                Ok((Packet::PingReq, n))
+                (Packet::PingReq, n)
             }
             PacketType::PingResp => {
                 let (_pkt, n) = PingResp::decode(stream)?;
-                Ok((Packet::PingResp, n))
+                (Packet::PingResp, n)
             }
             PacketType::Disconnect => {
-                let (pkt, n) = Disconnect::decode(stream)?;
-                Ok((Packet::Disconnect(pkt), n))
+                let (pkt, n) = Disconnect::decode_with(stream, cfg)?;
+                (Packet::Disconnect(pkt), n)
             }
             PacketType::Auth => {
-                let (pkt, n) = Auth::decode(stream)?;
-                Ok((Packet::Auth(pkt), n))
+                let (pkt, n) = Auth::decode_with(stream, cfg)?;
+                (Packet::Auth(pkt), n)
             }
-        }
+        };
+
+        trace_packet(pkt.to_packet_type(), &stream[..n]);
+        Ok((pkt, n))
     }
 
-    fn encode(&self) -> Result<Blob> {
-        match self {
-            Packet::Connect(pkt) => pkt.encode(),
-            Packet::ConnAck(pkt) => pkt.encode(),
-            Packet::Publish(pkt) => pkt.encode(),
-            Packet::PubAck(pkt) => pkt.encode(),
-            Packet::PubRec(pkt) => pkt.encode(),
-            Packet::PubRel(pkt) => pkt.encode(),
-            Packet::PubComp(pkt) => pkt.encode(),
-            Packet::Subscribe(pkt) => pkt.encode(),
-            Packet::SubAck(pkt) => pkt.encode(),
-            Packet::UnSubscribe(pkt) => pkt.encode(),
-            Packet::UnsubAck(pkt) => pkt.encode(),
-            Packet::PingReq => PingReq.encode(),
-            Packet::PingResp => PingResp.encode(),
-            Packet::Disconnect(pkt) => pkt.encode(),
-            Packet::Auth(pkt) => pkt.encode(),
+    /// Decode back-to-back packets from `stream`, using each fixed-header's
+    /// remaining-length to frame the packets. Return the decoded packets and the
+    /// number of bytes consumed, a partial packet at the end of `stream` is left
+    /// unconsumed.
+    pub fn decode_many(stream: &[u8]) -> Result<(Vec<Packet>, usize)> {
+        let cfg = DecodeConfig::default();
+        let mut pkts = Vec::default();
+        let mut n = 0;
+        while n < stream.len() {
+            let data = &stream[n..];
+            // fixed-header is complete once the last byte of remaining-length is seen.
+            match data.iter().skip(1).take(4).position(|b| *b < 0x80) {
+                None if data.len() < 5 => break,
+                _ => (),
+            }
+            let (fh, m) = FixedHeader::decode(data)?;
+            let pkt_len = m + (*fh.remaining_len as usize);
+            if data.len() < pkt_len {
+                break;
+            }
+            let (pkt, m) = Packet::decode_with_header(&fh, &data[..pkt_len], &cfg)?;
+            pkts.push(pkt);
+            n += m;
         }
+
+        Ok((pkts, n))
     }
-}
 
-impl Packet {
     pub fn to_packet_type(&self) -> PacketType {
         match self {
             Packet::Connect(_) => PacketType::Connect,
@@ -468,6 +669,7 @@ impl Packet {
 
 /// Quality of service
 #[cfg_attr(any(feature = "fuzzy", test), derive(Arbitrary))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum QoS {
     AtMostOnce = 0,
@@ -500,6 +702,18 @@ impl From<QoS> for u8 {
     }
 }
 
+impl QoS {
+    /// Convert `val` to QoS, reserved values saturate to [QoS::ExactlyOnce]. Use
+    /// this only for values that are already validated, or for display/logging.
+    pub fn from_u8_saturating(val: u8) -> QoS {
+        match val {
+            0 => QoS::AtMostOnce,
+            1 => QoS::AtLeastOnce,
+            _ => QoS::ExactlyOnce,
+        }
+    }
+}
+
 /// Packet type from a byte
 ///
 /// ```ignore
@@ -690,6 +904,19 @@ impl FixedHeader {
         (pkt_type, retain, qos, dup)
     }
 
+    /// Encode fixed header and append it to `buf`. Packet encoders that can compute
+    /// the remaining length upfront shall call this on an empty `buf`, sized for the
+    /// whole packet, and append variable-header and payload after it. This avoids
+    /// shifting the packet to make room for the header, as in `insert_fixed_header`.
+    pub fn encode_into(&self, buf: &mut Vec<u8>) -> Result<()> {
+        self.validate()?;
+
+        buf.push(self.byte1);
+        buf.extend_from_slice(self.remaining_len.encode()?.as_ref());
+
+        Ok(())
+    }
+
     /// Length of fixed header. Byte 1 + (1..4) bytes. So fixed header
     /// len can vary from 2 bytes to 5 bytes 1..4 bytes are variable length encoded
     /// to represent remaining length
@@ -698,7 +925,7 @@ impl FixedHeader {
             n if n < 128 => 1,
             n if n < 16_384 => 2,
             n if n < 2_097_152 => 3,
-            n if n < *VarU32::MAX => 4,
+            n if n <= *VarU32::MAX => 4,
             n => err!(
                 MalformedPacket,
                 code: MalformedPacket,
@@ -711,34 +938,45 @@ impl FixedHeader {
     }
 
     pub fn validate(&self) -> Result<()> {
+        let _qos = QoS::try_from((self.byte1 & Self::HDR_QOS) >> 1)?;
+        let pkt_type = PacketType::try_from((self.byte1 & Self::HDR_PKT_TYPE) >> 4)?;
+
+        self.validate_flags(pkt_type)
+    }
+
+    /// Validate that this fixed-header is for `pkt_type` and that its reserved flag
+    /// bits are as required by the packet type. PUBREL, SUBSCRIBE and UNSUBSCRIBE
+    /// require `0b0010`, PUBLISH carries dup, qos and retain flags, and all other
+    /// packets require `0b0000`.
+    pub fn validate_flags(&self, pkt_type: PacketType) -> Result<()> {
         use PacketType::*;
-        use QoS::{AtLeastOnce, AtMostOnce};
 
-        let _qos = QoS::try_from((self.byte1 & Self::HDR_QOS) >> 1)?;
-        let _pkt_type = PacketType::try_from((self.byte1 & Self::HDR_PKT_TYPE) >> 4)?;
-
-        let (pkt_type, retain, qos, dup) = self.unwrap();
-        match pkt_type {
-            Connect if qos == AtMostOnce && !retain && !dup => Ok(()),
-            ConnAck if qos == AtMostOnce && !retain && !dup => Ok(()),
-            Publish => Ok(()),
-            PubAck if qos == AtMostOnce && !retain && !dup => Ok(()),
-            PubRec if qos == AtMostOnce && !retain && !dup => Ok(()),
-            PubRel if qos == AtLeastOnce && !retain && !dup => Ok(()),
-            PubComp if qos == AtMostOnce && !retain && !dup => Ok(()),
-            Subscribe if qos == AtLeastOnce && !retain && !dup => Ok(()),
-            SubAck if qos == AtMostOnce && !retain && !dup => Ok(()),
-            UnSubscribe if qos == AtLeastOnce && !retain && !dup => Ok(()),
-            UnsubAck if qos == AtMostOnce && !retain && !dup => Ok(()),
-            Disconnect if qos == AtMostOnce && !retain && !dup => Ok(()),
-            Auth if qos == AtMostOnce && !retain && !dup => Ok(()),
-            _ if retain || dup || qos != QoS::AtMostOnce => err!(
+        let flags = self.byte1 & !Self::HDR_PKT_TYPE;
+        match PacketType::try_from((self.byte1 & Self::HDR_PKT_TYPE) >> 4)? {
+            val if val == pkt_type => (),
+            val => err!(
                 MalformedPacket,
                 code: MalformedPacket,
-                "FixedHeader invalid flags byte1:0x{:x}",
+                "FixedHeader expected {:?} found {:?}",
+                pkt_type,
+                val
+            )?,
+        }
+
+        let ok = match pkt_type {
+            Publish => QoS::try_from((flags & Self::HDR_QOS) >> 1).is_ok(),
+            PubRel | Subscribe | UnSubscribe => flags == 0b0010,
+            _ => flags == 0b0000,
+        };
+        match ok {
+            true => Ok(()),
+            false => err!(
+                MalformedPacket,
+                code: MalformedPacket,
+                "FixedHeader invalid flags for {:?} byte1:0x{:x}",
+                pkt_type,
                 self.byte1
             ),
-            _ => Ok(()),
         }
     }
 }
@@ -1138,6 +1376,7 @@ impl Property {
 
 /// Possible payload values for PayloadFormatIndicator property.
 #[cfg_attr(any(feature = "fuzzy", test), derive(Arbitrary))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PayloadFormat {
     Binary = 0,
@@ -1188,6 +1427,17 @@ impl PayloadFormat {
     }
 }
 
+/// Refer to [Packetize::remaining_len_ok].
+pub(crate) fn remaining_len_ok(stream: &[u8], fh: &FixedHeader) -> Result<()> {
+    let fh_len = fh.encode()?.as_ref().len();
+    let m = fh_len + usize::try_from(*fh.remaining_len)?;
+    match stream.len() {
+        n if m <= n => Ok(()),
+        n => err!(MalformedPacket, code: MalformedPacket, "truncated packet {}<{}", n, m)
+            .map_err(|err: Error| err.at(n)),
+    }
+}
+
 fn insert_fixed_header(fh: FixedHeader, mut data: Vec<u8>) -> Result<Vec<u8>> {
     let a = data.len();
 
@@ -1202,6 +1452,127 @@ fn insert_fixed_header(fh: FixedHeader, mut data: Vec<u8>) -> Result<Vec<u8>> {
     Ok(data)
 }
 
+// Return the size of a packet, including its fixed-header, for `remaining_len`.
+fn packet_len(remaining_len: usize) -> Result<usize> {
+    let remaining_len = VarU32::try_from_len(remaining_len)?;
+    let fh = FixedHeader { byte1: 0, remaining_len };
+    Ok(fh.len()? + (*remaining_len as usize))
+}
+
+// Return the size of an encoded UTF-8 string or binary-data of `n` bytes, including
+// its 2-byte length prefix.
+fn field_len(n: usize) -> Result<usize> {
+    match n {
+        n if n > (u16::MAX as usize) => err!(ProtocolError, desc: "field length {}", n),
+        n => Ok(2 + n),
+    }
+}
+
+// Return the size of encoded properties, property-length alone for `None`.
+fn props_len<P: Packetize>(properties: Option<&P>) -> Result<usize> {
+    match properties {
+        Some(properties) => properties.encoded_len(),
+        None => Ok(1),
+    }
+}
+
+// Return the size of `n` bytes of encoded properties, including property-length.
+fn property_len(n: usize) -> Result<usize> {
+    let m = VarU32(u32::try_from(n)?).encode()?.as_ref().len();
+    Ok(m + n)
+}
+
+// Return the size of encoded user-properties. Like all other properties, the
+// property-identifier is encoded in a single byte.
+fn user_props_len(user_properties: &[UserProperty]) -> Result<usize> {
+    let mut n = 0;
+    for UserProperty(key, val) in user_properties.iter() {
+        n += 1 + field_len(key.len())? + field_len(val.len())?;
+    }
+    Ok(n)
+}
+
+/// Default limit on the number of user-properties decoded from a single packet's
+/// properties, refer [DecodeConfig::max_user_properties].
+pub const MAX_USER_PROPERTIES: usize = 1024;
+
+/// Limits and policies applied while decoding packets, refer [Packetize::decode_with].
+/// [Packetize::decode] uses the default configuration, broker derives this from
+/// its own configuration.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DecodeConfig {
+    /// Maximum number of user-properties allowed in a single packet's properties,
+    /// packets exceeding the limit fail with ProtocolError.
+    /// * **Default**: [MAX_USER_PROPERTIES]
+    pub max_user_properties: usize,
+    /// Policy for properties that are well-formed, but not allowed in the packet
+    /// being decoded. When true, such properties fail with ProtocolError, otherwise
+    /// they are skipped.
+    /// * **Default**: true
+    pub strict_properties: bool,
+}
+
+impl Default for DecodeConfig {
+    fn default() -> DecodeConfig {
+        DecodeConfig {
+            max_user_properties: MAX_USER_PROPERTIES,
+            strict_properties: true,
+        }
+    }
+}
+
+impl DecodeConfig {
+    // Push a decoded user-property, fail if `user_properties` exceeds the limit.
+    fn push_user_property(
+        &self,
+        pp: &str,
+        user_properties: &mut Vec<UserProperty>,
+        val: UserProperty,
+    ) -> Result<()> {
+        let max = self.max_user_properties;
+        if user_properties.len() >= max {
+            err!(ProtocolError, code: ProtocolError, "{} user-properties > {}", pp, max)?
+        }
+        user_properties.push(val);
+
+        Ok(())
+    }
+
+    // Handle a decoded property that is not allowed in packet `pp`.
+    fn skip_property(&self, pp: &str, pt: PropertyType) -> Result<()> {
+        match self.strict_properties {
+            true => err!(ProtocolError, code: ProtocolError, "{} bad prop {:?}", pp, pt),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Callback for tracing packets, refer [set_packet_tracer].
+#[cfg(feature = "trace")]
+pub type PacketTracer = Box<dyn Fn(&str, &[u8]) + Send + Sync>;
+
+#[cfg(feature = "trace")]
+static PACKET_TRACER: RwLock<Option<PacketTracer>> = RwLock::new(None);
+
+/// Set a global `tracer`, that is called with the packet-type's name and raw bytes
+/// of every packet encoded or decoded via [Packet]. Replaces the tracer that was
+/// set earlier, if any. Available with feature `trace`.
+#[cfg(feature = "trace")]
+pub fn set_packet_tracer(tracer: PacketTracer) {
+    *PACKET_TRACER.write().unwrap() = Some(tracer);
+}
+
+#[cfg(feature = "trace")]
+fn trace_packet(pkt_type: PacketType, data: &[u8]) {
+    if let Some(tracer) = PACKET_TRACER.read().unwrap().as_ref() {
+        tracer(&format!("{:?}", pkt_type), data)
+    }
+}
+
+#[cfg(not(feature = "trace"))]
+#[inline(always)]
+fn trace_packet(_pkt_type: PacketType, _data: &[u8]) {}
+
 fn insert_property_len(n: usize, mut data: Vec<u8>) -> Result<Vec<u8>> {
     let a = data.len();
 
@@ -1222,3 +1593,7 @@ fn insert_property_len(n: usize, mut data: Vec<u8>) -> Result<Vec<u8>> {
 //#[cfg(any(feature = "fuzzy", test))]
 //#[path = "mod_fuzzy.rs"]
 //mod mod_fuzzy;
+
+#[cfg(test)]
+#[path = "mod_test.rs"]
+mod mod_test;
@@ -0,0 +1,467 @@
+use arbitrary::Unstructured;
+use rand::{prelude::random, rngs::StdRng, Rng, SeedableRng};
+
+use std::collections::BTreeSet;
+
+use super::*;
+
+#[test]
+fn test_decode_truncated() {
+    let seed: u64 = random();
+    println!("test_decode_truncated seed:{}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let mut pkt_types = BTreeSet::new();
+    for _ in 0..500 {
+        let bytes: Vec<u8> = (0..16384).map(|_| rng.gen::<u8>()).collect();
+        let mut uns = Unstructured::new(&bytes);
+        let pkt: Packet = match uns.arbitrary() {
+            Ok(pkt) => pkt,
+            Err(_) => continue,
+        };
+        let blob = match pkt.encode() {
+            Ok(blob) => blob,
+            Err(_) => continue,
+        };
+        let data = blob.as_ref();
+        if Packet::decode(data).is_err() {
+            continue;
+        }
+        pkt_types.insert(u8::from(pkt.to_packet_type()));
+
+        let (_, fh_len) = FixedHeader::decode(data).unwrap();
+        for n in 0..data.len() {
+            match Packet::decode(&data[..n]) {
+                Ok(val) => panic!("{:?} decoded from {}/{} bytes", val, n, data.len()),
+                Err(err) if n >= fh_len => {
+                    assert_eq!(err.kind(), ErrorKind::MalformedPacket, "{:?}", pkt)
+                }
+                Err(_) => (),
+            }
+        }
+    }
+
+    assert_eq!(pkt_types.len(), 15, "{:?}", pkt_types);
+}
+
+#[test]
+fn test_qos() {
+    for val in 0..=u8::MAX {
+        let qos = QoS::from_u8_saturating(val);
+        match QoS::try_from(val) {
+            Ok(q) => assert_eq!(q, qos),
+            Err(_) => assert_eq!(qos, QoS::ExactlyOnce),
+        }
+    }
+
+    assert_eq!(u8::from(QoS::try_from(1).unwrap()), 1);
+    assert_eq!(std::cmp::min(QoS::ExactlyOnce, QoS::AtLeastOnce), QoS::AtLeastOnce);
+    assert!(QoS::AtMostOnce < QoS::AtLeastOnce);
+}
+
+#[test]
+fn test_packet_decode_with_header() {
+    let connect = Packet::Connect(Connect::default());
+    let rfr = RetainForwardRule::OnEverySubscribe;
+    let subscribe = Packet::Subscribe(Subscribe {
+        packet_id: 10,
+        properties: None,
+        filters: vec![SubscribeFilter {
+            topic_filter: TopicFilter::from("a/+/#".to_string()),
+            opt: SubscriptionOpt::new(rfr, false, false, QoS::AtLeastOnce),
+        }],
+    });
+
+    let mut stream = Vec::new();
+    stream.extend_from_slice(connect.encode().unwrap().as_ref());
+    stream.extend_from_slice(subscribe.encode().unwrap().as_ref());
+
+    let mut pkts = vec![];
+    let mut data = stream.as_slice();
+    while !data.is_empty() {
+        let (fh, _) = FixedHeader::decode(data).unwrap();
+        let (pkt, n) =
+            Packet::decode_with_header(&fh, data, &DecodeConfig::default()).unwrap();
+        assert_eq!(pkt.to_packet_type(), fh.unwrap().0);
+        pkts.push(pkt);
+        data = &data[n..];
+    }
+    assert_eq!(pkts, vec![connect, subscribe]);
+}
+
+#[test]
+fn test_packet_decode_many() {
+    let publish = |packet_id| {
+        Packet::Publish(Publish {
+            retain: false,
+            qos: QoS::AtLeastOnce,
+            duplicate: false,
+            topic_name: TopicName::from("a/b".to_string()),
+            packet_id: Some(packet_id),
+            properties: None,
+            payload: Some(vec![0xAB; 200]),
+        })
+    };
+    let pkts = vec![Packet::Connect(Connect::default()), Packet::PingReq, publish(1)];
+
+    let mut stream = Vec::new();
+    for pkt in pkts.iter() {
+        stream.extend_from_slice(pkt.encode().unwrap().as_ref());
+    }
+    assert_eq!(Packet::decode_many(&stream).unwrap(), (pkts.clone(), stream.len()));
+    assert_eq!(Packet::decode_many(&[]).unwrap(), (vec![], 0));
+
+    // three and a half packets, partial packet is not consumed, including a
+    // partial fixed-header.
+    let n = stream.len();
+    let tail = publish(2).encode().unwrap();
+    for m in 1..tail.as_ref().len() {
+        let mut data = stream.clone();
+        data.extend_from_slice(&tail.as_ref()[..m]);
+        assert_eq!(Packet::decode_many(&data).unwrap(), (pkts.clone(), n), "{}", m);
+    }
+}
+
+#[test]
+fn test_fixed_header_encode_into() {
+    for remaining_len in [0, 127, 128, 16_383, 16_384, 2_097_152, *VarU32::MAX] {
+        let fh = FixedHeader::new(PacketType::PubAck, VarU32(remaining_len)).unwrap();
+        let mut buf = vec![0xFF];
+        fh.encode_into(&mut buf).unwrap();
+        assert_eq!(&buf[1..], fh.encode().unwrap().as_ref());
+        assert_eq!(buf.len() - 1, fh.len().unwrap());
+    }
+}
+
+#[test]
+fn test_reason_code_from_error_kind() {
+    use crate::ReasonCode;
+
+    let kinds = [
+        (ErrorKind::MalformedPacket, ReasonCode::MalformedPacket, 0x81),
+        (ErrorKind::ProtocolError, ReasonCode::ProtocolError, 0x82),
+        (ErrorKind::SlowClient, ReasonCode::QuotaExceeded, 0x97),
+        (ErrorKind::ShardFull, ReasonCode::ServerBusy, 0x89),
+        (ErrorKind::IPCFail, ReasonCode::UnspecifiedError, 0x80),
+    ];
+    for (kind, code, val) in kinds.into_iter() {
+        assert_eq!(ReasonCode::from_error_kind(kind), code, "{}", kind);
+        assert_eq!(kind.default_disconnect_reason() as u8, val, "{}", kind);
+    }
+    let kind = ErrorKind::NoError;
+    assert_eq!(kind.default_disconnect_reason(), DisconnReasonCode::NormalDisconnect);
+}
+
+#[test]
+fn test_disconn_reason_code_from_error() {
+    use crate::{Error, ReasonCode, Result};
+
+    let errs: Vec<(Result<()>, DisconnReasonCode)> = vec![
+        (err!(MalformedPacket, desc: "flags"), DisconnReasonCode::MalformedPacket),
+        (err!(ProtocolError, desc: "packet"), DisconnReasonCode::ProtocolError),
+        // reason-code carried by the error takes precedence.
+        (
+            err!(MalformedPacket, code: PacketTooLarge, "too large"),
+            DisconnReasonCode::PacketTooLarge,
+        ),
+        (
+            err!(ProtocolError, code: KeepAliveTimeout, "keep-alive"),
+            DisconnReasonCode::KeepAliveTimeout,
+        ),
+        // reason-code not allowed in DISCONNECT falls back to error's kind.
+        (
+            err!(ProtocolError, code: NoMatchingSubscribers, "no match"),
+            DisconnReasonCode::ProtocolError,
+        ),
+        (err!(IOError, desc: "broken pipe"), DisconnReasonCode::UnspecifiedError),
+        (err!(SlowClient, desc: "blocked"), DisconnReasonCode::QuotaExceeded),
+        (
+            err!(SlowClient, code: UnspecifiedError, "timeout"),
+            DisconnReasonCode::UnspecifiedError,
+        ),
+    ];
+    for (res, code) in errs.into_iter() {
+        let err: Error = res.unwrap_err();
+        assert_eq!(DisconnReasonCode::from_error(&err), code, "{}", err);
+    }
+}
+
+#[test]
+fn test_fixed_header_validate_flags() {
+    let sub = Subscribe {
+        packet_id: 1,
+        properties: None,
+        filters: vec![SubscribeFilter {
+            topic_filter: TopicFilter::from("a/b".to_string()),
+            opt: SubscriptionOpt::new(
+                RetainForwardRule::OnEverySubscribe,
+                false,
+                false,
+                QoS::AtMostOnce,
+            ),
+        }],
+    };
+    let mut data = sub.encode().unwrap().as_ref().to_vec();
+    Subscribe::decode(&data).unwrap();
+    data[0] = 0b1000_0000; // SUBSCRIBE with flags 0b0000
+    let err = Subscribe::decode(&data).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::MalformedPacket);
+
+    let puback = Pub::new_pub_ack(1);
+    let mut data = puback.encode().unwrap().as_ref().to_vec();
+    Pub::decode(&data).unwrap();
+    data[0] = 0b0100_0010; // PUBACK with flags 0b0010
+    let err = Pub::decode(&data).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::MalformedPacket);
+
+    // packet-type must match the decoder.
+    let fh = FixedHeader::new(PacketType::PubAck, VarU32(2)).unwrap();
+    fh.validate_flags(PacketType::PubAck).unwrap();
+    let err = fh.validate_flags(PacketType::PubComp).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::MalformedPacket);
+    FixedHeader::new_pubrel(VarU32(2))
+        .unwrap()
+        .validate_flags(PacketType::PubRel)
+        .unwrap();
+}
+
+// Generate a test, that for `$n` arbitrary values of `$typ`, generated from a seeded
+// Unstructured, assert that `decode(encode(x)) == normalize(x)`.
+macro_rules! roundtrip_fuzz {
+    ($name:ident, $typ:ty, $normalize:expr, $n:expr) => {
+        #[test]
+        fn $name() {
+            let seed: u64 = random();
+            println!("{} seed:{}", stringify!($name), seed);
+            let mut rng = StdRng::seed_from_u64(seed);
+
+            let normalize: fn(&mut $typ) = $normalize;
+            let mut n_encoded = 0;
+            for _ in 0..$n {
+                let mut bytes = vec![0_u8; 4096];
+                rng.fill(bytes.as_mut_slice());
+                let mut uns = Unstructured::new(&bytes);
+                let val: $typ = match uns.arbitrary() {
+                    Ok(val) => val,
+                    Err(_) => continue,
+                };
+                // arbitrary values can be invalid, only valid ones are encoded.
+                let blob = match val.encode() {
+                    Ok(blob) => blob,
+                    Err(_) => continue,
+                };
+                n_encoded += 1;
+
+                let (out, n) = match <$typ>::decode(blob.as_ref()) {
+                    Ok(res) => res,
+                    Err(err) => panic!("seed:{} {:?} err:{}", seed, val, err),
+                };
+                assert_eq!(n, blob.as_ref().len(), "seed:{} {:?}", seed, val);
+
+                let mut val = val;
+                normalize(&mut val);
+                assert_eq!(out, val, "seed:{}", seed);
+            }
+            assert!(n_encoded > 0, "seed:{}", seed);
+        }
+    };
+}
+
+roundtrip_fuzz!(test_connect_roundtrip, Connect, Connect::normalize, 1000);
+roundtrip_fuzz!(test_connack_roundtrip, ConnAck, ConnAck::normalize, 1000);
+roundtrip_fuzz!(test_publish_roundtrip, Publish, Publish::normalize, 1000);
+roundtrip_fuzz!(test_pub_roundtrip, Pub, Pub::normalize, 1000);
+roundtrip_fuzz!(test_subscribe_roundtrip, Subscribe, Subscribe::normalize, 1000);
+roundtrip_fuzz!(test_suback_roundtrip, SubAck, SubAck::normalize, 1000);
+roundtrip_fuzz!(test_unsubscribe_roundtrip, UnSubscribe, UnSubscribe::normalize, 1000);
+roundtrip_fuzz!(test_unsuback_roundtrip, UnsubAck, UnsubAck::normalize, 1000);
+roundtrip_fuzz!(test_disconnect_roundtrip, Disconnect, Disconnect::normalize, 1000);
+roundtrip_fuzz!(test_auth_roundtrip, Auth, Auth::normalize, 1000);
+roundtrip_fuzz!(test_subscription_roundtrip, Subscription, |_| (), 1000);
+
+#[test]
+fn test_subscription_packetize() {
+    let filter = SubscribeFilter {
+        topic_filter: TopicFilter::from("a/+/c".to_string()),
+        opt: SubscriptionOpt::new(
+            RetainForwardRule::OnNewSubscribe,
+            true,
+            true,
+            QoS::AtLeastOnce,
+        ),
+    };
+    let client_id = ClientID("test-subscription-packetize".to_string());
+    let mut subscr = Subscription::from_filter(&filter, client_id.clone(), 7);
+    assert_eq!(subscr.topic_filter, filter.topic_filter);
+    assert_eq!(subscr.client_id, client_id);
+    assert_eq!(subscr.retain_forward_rule, RetainForwardRule::OnNewSubscribe);
+    assert!(subscr.retain_as_published && subscr.no_local);
+    assert_eq!(subscr.qos, QoS::AtLeastOnce);
+
+    for subscription_id in [None, Some(1), Some(268_435_455)] {
+        subscr.subscription_id = subscription_id;
+        let blob = subscr.encode().unwrap();
+        let data = blob.as_ref();
+        let (val, n) = Subscription::decode(data).unwrap();
+        assert_eq!((val.clone(), n), (subscr.clone(), data.len()));
+        assert_eq!(val.shard_id, 7);
+        for m in 0..data.len() {
+            assert!(Subscription::decode(&data[..m]).is_err(), "{}/{}", m, data.len());
+        }
+    }
+
+    // subscription-id of ZERO is not allowed.
+    subscr.subscription_id = Some(0);
+    assert!(subscr.encode().is_err());
+}
+
+#[test]
+fn test_properties_encoded_len() {
+    fn check<'a, T>(uns: &mut Unstructured<'a>, seed: u64) -> usize
+    where
+        T: Arbitrary<'a> + Packetize + std::fmt::Debug,
+    {
+        let val: T = match uns.arbitrary() {
+            Ok(val) => val,
+            Err(_) => return 0,
+        };
+        match val.encode() {
+            Ok(blob) => {
+                let n = blob.as_ref().len();
+                assert_eq!(val.encoded_len().unwrap(), n, "seed:{} {:?}", seed, val);
+                1
+            }
+            Err(_) => 0,
+        }
+    }
+
+    let seed: u64 = random();
+    println!("test_properties_encoded_len seed:{}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let mut n_encoded = 0;
+    for _ in 0..1000 {
+        let mut bytes = vec![0_u8; 4096];
+        rng.fill(bytes.as_mut_slice());
+        let mut uns = Unstructured::new(&bytes);
+        n_encoded += check::<ConnectProperties>(&mut uns, seed);
+        n_encoded += check::<WillProperties>(&mut uns, seed);
+        n_encoded += check::<PublishProperties>(&mut uns, seed);
+        n_encoded += check::<PubProperties>(&mut uns, seed);
+        n_encoded += check::<SubscribeProperties>(&mut uns, seed);
+    }
+    assert!(n_encoded > 0, "seed:{}", seed);
+}
+
+#[test]
+fn test_decode_max_user_properties() {
+    let seed: u64 = random();
+    println!("test_decode_max_user_properties seed:{}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let user_props = |n: usize| -> Vec<UserProperty> {
+        (0..n).map(|i| UserProperty(format!("k{}", i), "v".to_string())).collect()
+    };
+    let filter = SubscribeFilter {
+        topic_filter: TopicFilter::from("a/b".to_string()),
+        opt: SubscriptionOpt::new(
+            RetainForwardRule::OnEverySubscribe,
+            false,
+            false,
+            QoS::AtMostOnce,
+        ),
+    };
+
+    // limit boundaries, followed by random counts upto thousands.
+    let mut counts = vec![MAX_USER_PROPERTIES, MAX_USER_PROPERTIES + 1];
+    counts.extend((0..20).map(|_| rng.gen_range(1..(4 * MAX_USER_PROPERTIES))));
+
+    for n in counts.into_iter() {
+        let pkts = vec![
+            Packet::Connect(Connect {
+                properties: Some(ConnectProperties {
+                    user_properties: user_props(n),
+                    ..ConnectProperties::default()
+                }),
+                ..Connect::default()
+            }),
+            Packet::Connect(Connect::default().with_will(
+                TopicName::from("will/topic".to_string()),
+                b"gone".to_vec(),
+                QoS::AtMostOnce,
+                false,
+                WillProperties {
+                    user_properties: user_props(n),
+                    ..WillProperties::default()
+                },
+            )),
+            Packet::Subscribe(Subscribe {
+                packet_id: 1,
+                properties: Some(SubscribeProperties {
+                    user_properties: user_props(n),
+                    ..SubscribeProperties::default()
+                }),
+                filters: vec![filter.clone()],
+            }),
+            Packet::PubAck(Pub {
+                properties: Some(PubProperties {
+                    user_properties: user_props(n),
+                    ..PubProperties::default()
+                }),
+                ..Pub::new_pub_ack(1)
+            }),
+        ];
+
+        for pkt in pkts.into_iter() {
+            let data = pkt.encode().unwrap().as_ref().to_vec();
+            match Packet::decode(&data) {
+                Ok((val, m)) if n <= MAX_USER_PROPERTIES => {
+                    assert_eq!((val, m), (pkt.clone(), data.len()));
+                }
+                Ok(_) => panic!("{} user-properties, expected error", n),
+                Err(err) if n > MAX_USER_PROPERTIES => {
+                    assert_eq!(err.kind(), ErrorKind::ProtocolError, "{}", n);
+                    assert_eq!(err.code(), ReasonCode::ProtocolError, "{}", n);
+                }
+                Err(err) => panic!("{} user-properties, err:{}", n, err),
+            }
+
+            // limit supplied by the decoder's configuration.
+            let cfg = DecodeConfig { max_user_properties: n, ..DecodeConfig::default() };
+            assert_eq!(Packet::decode_with(&data, &cfg).unwrap(), (pkt, data.len()));
+            let cfg = DecodeConfig {
+                max_user_properties: n - 1,
+                ..DecodeConfig::default()
+            };
+            let err = Packet::decode_with(&data, &cfg).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ProtocolError, "{}", n);
+        }
+    }
+}
+
+#[cfg(feature = "trace")]
+#[test]
+fn test_packet_tracer() {
+    use std::sync::{Arc, Mutex};
+
+    let traces: Arc<Mutex<Vec<(String, Vec<u8>)>>> = Arc::new(Mutex::new(vec![]));
+    {
+        let traces = Arc::clone(&traces);
+        set_packet_tracer(Box::new(move |name: &str, data: &[u8]| {
+            traces.lock().unwrap().push((name.to_string(), data.to_vec()))
+        }));
+    }
+
+    let mut connect = Connect::default();
+    connect.payload.client_id = ClientID("packet-tracer".to_string());
+    let pkt = Packet::Connect(connect);
+
+    let data = pkt.encode().unwrap().as_ref().to_vec();
+    assert_eq!(Packet::decode(&data).unwrap(), (pkt, data.len()));
+
+    // tracer is process wide, skip packets traced by other tests.
+    let traces: Vec<(String, Vec<u8>)> =
+        traces.lock().unwrap().iter().filter(|(_, val)| val == &data).cloned().collect();
+    let name = "Connect".to_string();
+    assert_eq!(traces, vec![(name.clone(), data.clone()), (name, data)]);
+}
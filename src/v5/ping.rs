@@ -15,7 +15,8 @@ impl Packetize for PingReq {
         let stream: &[u8] = stream.as_ref();
 
         let (fh, n) = dec_field!(FixedHeader, stream, 0);
-        fh.validate()?;
+        fh.validate_flags(PacketType::PingReq)?;
+        Self::remaining_len_ok(stream, &fh)?;
 
         Ok((PingReq, n))
     }
@@ -40,7 +41,8 @@ impl Packetize for PingResp {
         let stream: &[u8] = stream.as_ref();
 
         let (fh, n) = dec_field!(FixedHeader, stream, 0);
-        fh.validate()?;
+        fh.validate_flags(PacketType::PingResp)?;
+        Self::remaining_len_ok(stream, &fh)?;
 
         Ok((PingResp, n))
     }
@@ -1,11 +1,13 @@
 #[cfg(any(feature = "fuzzy", test))]
 use arbitrary::{Arbitrary, Error as ArbitraryError, Unstructured};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 #[cfg(any(feature = "fuzzy", test))]
 use std::result;
 
 use crate::util::advance;
-use crate::v5::{self, FixedHeader, PacketType, Property, PropertyType};
+use crate::v5::{self, DecodeConfig, FixedHeader, PacketType, Property, PropertyType};
 use crate::{Blob, Packetize, UserProperty, VarU32};
 use crate::{Error, ErrorKind, ReasonCode, Result};
 
@@ -66,6 +68,7 @@ pub enum PubCompReasonCode {
 }
 
 /// PUBACK, PUBREC, PUBREL, PUBCOMP packets
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Pub {
     pub packet_type: PacketType,
@@ -107,14 +110,22 @@ impl<'a> Arbitrary<'a> for Pub {
 
 impl Packetize for Pub {
     fn decode<T: AsRef<[u8]>>(stream: T) -> Result<(Self, usize)> {
+        Self::decode_with(stream, &DecodeConfig::default())
+    }
+
+    fn decode_with<T>(stream: T, cfg: &DecodeConfig) -> Result<(Self, usize)>
+    where
+        T: AsRef<[u8]>,
+    {
         let stream: &[u8] = stream.as_ref();
 
         // println!("Pub::decode {:?}", stream);
 
         let (fh, n) = dec_field!(FixedHeader, stream, 0);
-        fh.validate()?;
-
         let (packet_type, _, _, _) = fh.unwrap();
+        fh.validate_flags(packet_type)?;
+        Self::remaining_len_ok(stream, &fh)?;
+
         let (packet_id, n) = dec_field!(u16, stream, n);
 
         let (packet, n) = match *fh.remaining_len {
@@ -138,7 +149,7 @@ impl Packetize for Pub {
                     let (val, n) = dec_field!(u8, stream, n);
                     (ReasonCode::try_from(val)?, n)
                 };
-                let (properties, n) = dec_props!(PubProperties, stream, n);
+                let (properties, n) = dec_props!(PubProperties, stream, n, cfg);
                 let packet = Pub { packet_type, packet_id, code, properties };
                 (packet, n)
             }
@@ -149,32 +160,43 @@ impl Packetize for Pub {
     }
 
     fn encode(&self) -> Result<Blob> {
-        use crate::v5::insert_fixed_header;
-
-        let mut data = Vec::with_capacity(64);
-
-        data.extend_from_slice(self.packet_id.encode()?.as_ref());
-        data.extend_from_slice((self.code as u8).encode()?.as_ref());
-        if let Some(properties) = &self.properties {
-            data.extend_from_slice(properties.encode()?.as_ref());
-        } else {
-            data.extend_from_slice(VarU32(0).encode()?.as_ref());
+        // without properties, packet-id, reason-code and property-length shall fit
+        // in a small blob, avoiding heap allocation.
+        if self.properties.is_none() {
+            let fh_blob = self.to_fixed_header(VarU32(4))?.encode()?;
+            let n = fh_blob.as_ref().len();
+
+            let mut data = [0_u8; 32];
+            data[..n].copy_from_slice(fh_blob.as_ref());
+            data[n..n + 2].copy_from_slice(&self.packet_id.to_be_bytes());
+            data[n + 2] = self.code as u8;
+            data[n + 3] = 0; // property length
+            return Ok(Blob::Small { data, size: n + 4 });
         }
 
-        let remlen = VarU32(data.len().try_into()?);
-        let fh = match self.packet_type {
-            PacketType::PubAck => FixedHeader::new(PacketType::PubAck, remlen)?,
-            PacketType::PubRel => FixedHeader::new_pubrel(remlen)?,
-            PacketType::PubRec => FixedHeader::new(PacketType::PubRec, remlen)?,
-            PacketType::PubComp => FixedHeader::new(PacketType::PubComp, remlen)?,
-            packet_type => err!(ProtocolError, desc: "packet_type {:?}", packet_type)?,
+        let properties = match &self.properties {
+            Some(properties) => properties.encode()?,
+            None => VarU32(0).encode()?,
         };
-        data = insert_fixed_header(fh, data)?;
 
-        // println!("Pub::encode {:?}", data);
+        // packet-id, reason-code and properties.
+        let remaining_len = 3 + properties.as_ref().len();
+        let fh = self.to_fixed_header(VarU32::try_from_len(remaining_len)?)?;
+
+        let mut data = Vec::with_capacity(fh.len()? + remaining_len);
+        fh.encode_into(&mut data)?;
+        data.extend_from_slice(&self.packet_id.to_be_bytes());
+        data.push(self.code as u8);
+        data.extend_from_slice(properties.as_ref());
 
         Ok(Blob::Large { data })
     }
+
+    fn encoded_len(&self) -> Result<usize> {
+        // packet-id, reason-code and properties.
+        let remaining_len = 3 + v5::props_len(self.properties.as_ref())?;
+        v5::packet_len(remaining_len)
+    }
 }
 
 impl Pub {
@@ -187,6 +209,24 @@ impl Pub {
         }
     }
 
+    pub fn new_pub_rec(packet_id: u16) -> Pub {
+        Pub {
+            packet_type: v5::PacketType::PubRec,
+            packet_id,
+            code: (PubRecReasonCode::Success as u8).try_into().unwrap(),
+            properties: None,
+        }
+    }
+
+    pub fn new_pub_comp(packet_id: u16) -> Pub {
+        Pub {
+            packet_type: v5::PacketType::PubComp,
+            packet_id,
+            code: (PubCompReasonCode::Success as u8).try_into().unwrap(),
+            properties: None,
+        }
+    }
+
     #[cfg(any(feature = "fuzzy", test))]
     pub fn normalize(&mut self) {
         if let Some(props) = &mut self.properties {
@@ -196,6 +236,17 @@ impl Pub {
         }
     }
 
+    fn to_fixed_header(&self, remlen: VarU32) -> Result<FixedHeader> {
+        let fh = match self.packet_type {
+            PacketType::PubAck => FixedHeader::new(PacketType::PubAck, remlen)?,
+            PacketType::PubRel => FixedHeader::new_pubrel(remlen)?,
+            PacketType::PubRec => FixedHeader::new(PacketType::PubRec, remlen)?,
+            PacketType::PubComp => FixedHeader::new(PacketType::PubComp, remlen)?,
+            packet_type => err!(ProtocolError, desc: "packet_type {:?}", packet_type)?,
+        };
+        Ok(fh)
+    }
+
     fn validate(&self) -> Result<()> {
         let invalid_code = match self.packet_type {
             PacketType::PubAck | PacketType::PubRec => match self.code {
@@ -227,6 +278,7 @@ impl Pub {
 }
 
 /// Collection of MQTT properties in PUBACK, PUBREC, PUBREL, PUBCOMP packets
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, Eq, PartialEq, Default)]
 pub struct PubProperties {
     /// Property::ReasonString
@@ -260,6 +312,13 @@ impl<'a> Arbitrary<'a> for PubProperties {
 
 impl Packetize for PubProperties {
     fn decode<T: AsRef<[u8]>>(stream: T) -> Result<(Self, usize)> {
+        Self::decode_with(stream, &DecodeConfig::default())
+    }
+
+    fn decode_with<T>(stream: T, cfg: &DecodeConfig) -> Result<(Self, usize)>
+    where
+        T: AsRef<[u8]>,
+    {
         let stream: &[u8] = stream.as_ref();
 
         let mut dups = [false; 256];
@@ -280,13 +339,12 @@ impl Packetize for PubProperties {
 
             match property {
                 Property::ReasonString(val) => props.reason_string = Some(val),
-                Property::UserProp(val) => props.user_properties.push(val),
-                _ => err!(
-                    ProtocolError,
-                    code: ProtocolError,
-                    "{:?} found in puback properties",
-                    pt
+                Property::UserProp(val) => cfg.push_user_property(
+                    "Packet::PubAck",
+                    &mut props.user_properties,
+                    val,
                 )?,
+                _ => cfg.skip_property("Packet::PubAck", pt)?,
             };
         }
 
@@ -308,11 +366,32 @@ impl Packetize for PubProperties {
 
         Ok(Blob::Large { data })
     }
+
+    fn encoded_len(&self) -> Result<usize> {
+        use crate::v5::{field_len, property_len, user_props_len};
+
+        let mut n = 0;
+        if let Some(val) = &self.reason_string {
+            n += 1 + field_len(val.len())?;
+        }
+        n += user_props_len(&self.user_properties)?;
+
+        property_len(n)
+    }
 }
 
 impl PubProperties {
+    /// Canonicalize properties, sort user-properties by (key, value).
+    pub fn normalize(&mut self) {
+        self.user_properties.sort();
+    }
+
     #[cfg(any(feature = "fuzzy", test))]
     pub fn is_empty(&self) -> bool {
         self.reason_string.is_none() && self.user_properties.len() == 0
     }
 }
+
+#[cfg(test)]
+#[path = "pubaclc_test.rs"]
+mod pubaclc_test;
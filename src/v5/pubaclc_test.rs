@@ -0,0 +1,150 @@
+use super::*;
+
+#[test]
+fn test_pub_encode_small() {
+    let packet_types =
+        [PacketType::PubAck, PacketType::PubRec, PacketType::PubRel, PacketType::PubComp];
+    for packet_type in packet_types.into_iter() {
+        let pkt = Pub {
+            packet_type,
+            packet_id: 0x1234,
+            code: ReasonCode::Success,
+            properties: None,
+        };
+        let blob = pkt.encode().unwrap();
+        assert!(matches!(blob, Blob::Small { size: 6, .. }), "{:?}", packet_type);
+
+        let (val, n) = Pub::decode(blob.as_ref()).unwrap();
+        assert_eq!(n, 6);
+        assert_eq!(val, pkt);
+    }
+
+    // with properties, fall back to the heap.
+    let properties = PubProperties {
+        reason_string: Some("reason".to_string()),
+        user_properties: Vec::default(),
+    };
+    let pkt = Pub {
+        packet_type: PacketType::PubAck,
+        packet_id: 0x1234,
+        code: ReasonCode::Success,
+        properties: Some(properties),
+    };
+    let blob = pkt.encode().unwrap();
+    match &blob {
+        Blob::Large { data } => assert_eq!(data.capacity(), data.len()),
+        blob => panic!("unexpected {:?}", blob),
+    }
+    assert_eq!(Pub::decode(blob.as_ref()).unwrap().0, pkt);
+}
+
+#[test]
+fn test_small_packets_encode_small() {
+    use crate::v5::{ConnectFlags, PingResp, QoS, RetainForwardRule, SubscriptionOpt};
+
+    assert!(matches!(PingResp.encode().unwrap(), Blob::Small { size: 2, .. }));
+
+    let flags = ConnectFlags::new(&[ConnectFlags::CLEAN_START]);
+    assert!(matches!(flags.encode().unwrap(), Blob::Small { size: 1, .. }));
+
+    let rfr = RetainForwardRule::OnEverySubscribe;
+    let opt = SubscriptionOpt::new(rfr, false, false, QoS::AtLeastOnce);
+    assert!(matches!(opt.encode().unwrap(), Blob::Small { size: 1, .. }));
+}
+
+#[test]
+fn test_pub_arbitrary_roundtrip() {
+    use rand::{prelude::random, rngs::StdRng, Rng, SeedableRng};
+    use std::collections::BTreeSet;
+
+    let seed: u64 = random();
+    println!("test_pub_arbitrary_roundtrip seed:{}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let mut packet_types = BTreeSet::new();
+    for _ in 0..1000 {
+        let bytes = rng.gen::<[u8; 32]>();
+        let mut uns = Unstructured::new(&bytes);
+        let mut pkt: Pub = uns.arbitrary().unwrap();
+        pkt.normalize();
+
+        match pkt.packet_type {
+            PacketType::PubRel | PacketType::PubComp => assert!(
+                matches!(pkt.code, ReasonCode::Success | ReasonCode::PacketIdNotFound),
+                "{:?}",
+                pkt
+            ),
+            _ => (),
+        }
+        packet_types.insert(pkt.packet_type as u8);
+
+        let blob = pkt.encode().unwrap();
+        let (val, n) = Pub::decode(blob.as_ref()).unwrap();
+        assert_eq!(n, blob.as_ref().len());
+        assert_eq!(pkt.encoded_len().unwrap(), n);
+        assert_eq!(val, pkt);
+    }
+    assert_eq!(packet_types.len(), 4);
+}
+
+#[test]
+fn test_pub_decode_bad_string() {
+    let packet_types =
+        [PacketType::PubAck, PacketType::PubRec, PacketType::PubRel, PacketType::PubComp];
+    for packet_type in packet_types.into_iter() {
+        let pkt = Pub {
+            packet_type,
+            packet_id: 0x1234,
+            code: ReasonCode::UnspecifiedError,
+            properties: Some(PubProperties {
+                reason_string: Some("reason".to_string()),
+                user_properties: vec![UserProperty::new("key", "val")],
+            }),
+        };
+        let pkt = match packet_type {
+            PacketType::PubRel | PacketType::PubComp => {
+                Pub { code: ReasonCode::PacketIdNotFound, ..pkt }
+            }
+            _ => pkt,
+        };
+        let data = pkt.encode().unwrap().as_ref().to_vec();
+        assert_eq!(Pub::decode(&data).unwrap().0, pkt);
+
+        let fields: [&[u8]; 3] = [b"reason", b"key", b"val"];
+        for field in fields.iter() {
+            // embedded U+0000 and ill-formed UTF-8.
+            for bad in [&b"\x00"[..], &b"\xC3\x28"[..], &b"\xFF"[..]] {
+                let off = data.windows(field.len()).position(|w| w == *field).unwrap();
+                let mut data = data.clone();
+                data[off + 1..off + 1 + bad.len()].copy_from_slice(bad);
+
+                let err = Pub::decode(&data).unwrap_err();
+                assert_eq!(err.kind(), ErrorKind::MalformedPacket, "{:?}", packet_type);
+                assert_eq!(err.code(), ReasonCode::MalformedPacket, "{:?}", packet_type);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_pub_encoded_len() {
+    let properties = PubProperties {
+        reason_string: Some("reason".to_string()),
+        user_properties: vec![UserProperty::new("key", "val")],
+    };
+    let pkts = [
+        Pub::new_pub_ack(1),
+        Pub::new_pub_rec(2),
+        Pub::new_pub_comp(3),
+        Pub {
+            packet_type: PacketType::PubRel,
+            packet_id: 4,
+            code: ReasonCode::PacketIdNotFound,
+            properties: Some(properties),
+        },
+    ];
+    for pkt in pkts.iter() {
+        let n = pkt.encode().unwrap().as_ref().len();
+        assert_eq!(pkt.encoded_len().unwrap(), n, "{:?}", pkt);
+    }
+}
@@ -4,7 +4,9 @@ use arbitrary::{Arbitrary, Error as ArbitraryError, Unstructured};
 use std::{cmp, fmt, result};
 
 use crate::util::advance;
-use crate::v5::{FixedHeader, PayloadFormat, Property, PropertyType, QoS};
+use crate::v5::{
+    DecodeConfig, FixedHeader, PacketType, PayloadFormat, Property, PropertyType, QoS,
+};
 use crate::{Blob, Packetize, TopicName, UserProperty, VarU32};
 use crate::{Error, ErrorKind, ReasonCode, Result};
 
@@ -79,22 +81,32 @@ impl<'a> Arbitrary<'a> for Publish {
 
 impl Packetize for Publish {
     fn decode<T: AsRef<[u8]>>(stream: T) -> Result<(Self, usize)> {
+        Self::decode_with(stream, &DecodeConfig::default())
+    }
+
+    fn decode_with<T>(stream: T, cfg: &DecodeConfig) -> Result<(Self, usize)>
+    where
+        T: AsRef<[u8]>,
+    {
         let stream: &[u8] = stream.as_ref();
 
         // println!("{:?}", stream);
 
         let (fh, fh_len) = dec_field!(FixedHeader, stream, 0);
-        fh.validate()?;
+        fh.validate_flags(PacketType::Publish)?;
+        Self::remaining_len_ok(stream, &fh)?;
         let (_, retain, qos, duplicate) = fh.unwrap();
 
-        let (topic_name, n) = dec_field!(TopicName, stream, fh_len);
+        // topic-name is validated by Publish::validate, it can be empty with alias.
+        let (topic_name, n) = dec_field!(String, stream, fh_len);
+        let topic_name = TopicName::from(topic_name);
         let (packet_id, n) = dec_field!(
             u16,
             stream,
             n;
             matches!(qos, QoS::AtLeastOnce | QoS::ExactlyOnce)
         );
-        let (properties, n) = dec_props!(PublishProperties, stream, n);
+        let (properties, n) = dec_props!(PublishProperties, stream, n, cfg);
 
         let (payload, n) = match fh_len + usize::try_from(*fh.remaining_len)? {
             m if m == n => (None, n),
@@ -117,38 +129,70 @@ impl Packetize for Publish {
     }
 
     fn encode(&self) -> Result<Blob> {
-        use crate::v5::insert_fixed_header;
+        let mut data = Vec::default();
+        self.encode_into(&mut data)?;
 
-        let mut data = Vec::with_capacity(64);
+        Ok(Blob::Large { data })
+    }
 
-        data.extend_from_slice(self.topic_name.encode()?.as_ref());
-        if let Some(packet_id) = self.packet_id {
-            data.extend_from_slice(packet_id.encode()?.as_ref());
-        }
-        if let Some(properties) = &self.properties {
-            data.extend_from_slice(properties.encode()?.as_ref());
-        } else {
-            data.extend_from_slice(VarU32(0).encode()?.as_ref());
-        }
-        if let Some(payload) = &self.payload {
-            data.extend_from_slice(payload)
-        }
+    fn encoded_len(&self) -> Result<usize> {
+        use crate::v5::{field_len, packet_len, props_len};
+
+        self.validate()?;
+
+        let mut n = field_len(self.topic_name.len())?;
+        n += self.packet_id.map(|_| 2).unwrap_or(0);
+        n += props_len(self.properties.as_ref())?;
+        n += self.payload.as_ref().map(|p| p.len()).unwrap_or(0);
 
+        packet_len(n)
+    }
+}
+
+impl Publish {
+    /// Encode this packet, appending it to `data`. Same as `encode`, except that
+    /// callers can supply a recycled buffer.
+    pub fn encode_into(&self, data: &mut Vec<u8>) -> Result<()> {
+        self.validate()?;
+
+        // topic-name is validated by Publish::validate, it can be empty with alias.
+        let topic_name = (*self.topic_name).encode()?;
+        let properties = match &self.properties {
+            Some(properties) => properties.encode()?,
+            None => VarU32(0).encode()?,
+        };
+        let payload: &[u8] = self.payload.as_deref().unwrap_or(&[]);
+
+        // remaining-length is computed upfront, so that the fixed-header is encoded
+        // first, and the packet is encoded into a single allocation without copies.
+        let remaining_len = topic_name.as_ref().len()
+            + self.packet_id.map(|_| 2).unwrap_or(0)
+            + properties.as_ref().len()
+            + payload.len();
         let fh = FixedHeader::new_publish(
             self.retain,
             self.qos,
             self.duplicate,
-            VarU32(data.len().try_into()?),
+            VarU32::try_from_len(remaining_len)?,
         )?;
-        data = insert_fixed_header(fh, data)?;
 
-        // println!("{:?}", data);
+        data.reserve(fh.len()? + remaining_len);
+        fh.encode_into(data)?;
+        data.extend_from_slice(topic_name.as_ref());
+        if let Some(packet_id) = self.packet_id {
+            data.extend_from_slice(&packet_id.to_be_bytes());
+        }
+        data.extend_from_slice(properties.as_ref());
+        data.extend_from_slice(payload);
 
-        Ok(Blob::Large { data })
+        Ok(())
+    }
+
+    /// Return the size of this packet, including the fixed-header, when encoded.
+    pub fn encoded_size(&self) -> Result<usize> {
+        self.encoded_len()
     }
-}
 
-impl Publish {
     pub fn set_fixed_header(&mut self, retain: bool, qos: QoS, dup: bool) -> &mut Self {
         self.retain = retain;
         self.qos = qos;
@@ -161,21 +205,38 @@ impl Publish {
         self
     }
 
+    /// Add subscription-identifier `id`, of a matching subscription, to this
+    /// message. A message delivered for several overlapping subscriptions carries
+    /// all their identifiers.
+    pub fn add_subscription_id(&mut self, id: VarU32) {
+        match &mut self.properties {
+            Some(props) => props.subscribtion_identifier.push(id),
+            None => {
+                self.properties = Some(PublishProperties {
+                    subscribtion_identifier: vec![id],
+                    ..PublishProperties::default()
+                });
+            }
+        }
+    }
+
     pub fn set_subscription_ids(&mut self, ids: Vec<u32>) {
         for id in ids.into_iter() {
-            match &mut self.properties {
-                Some(props) => props.subscribtion_identifier.push(VarU32(id)),
-                None => {
-                    self.properties = Some(PublishProperties {
-                        subscribtion_identifier: vec![VarU32(id)],
-                        ..PublishProperties::default()
-                    });
-                }
-            }
+            self.add_subscription_id(VarU32(id))
         }
     }
 
     fn validate(&self) -> Result<()> {
+        // topic-name can be ZERO length, if topic-alias is used.
+        match self.topic_alias() {
+            Some(_) if self.topic_name.is_empty() => (),
+            _ => self.topic_name.validate()?,
+        }
+        // response-topic, for request/response interaction, must be a topic-name.
+        if let Some(Some(topic)) = self.properties.as_ref().map(|p| &p.response_topic) {
+            topic.validate()?;
+        }
+
         match self.qos {
             QoS::AtMostOnce if self.duplicate => err!(
                 MalformedPacket,
@@ -280,6 +341,13 @@ impl<'a> Arbitrary<'a> for PublishProperties {
 
 impl Packetize for PublishProperties {
     fn decode<T: AsRef<[u8]>>(stream: T) -> Result<(Self, usize)> {
+        Self::decode_with(stream, &DecodeConfig::default())
+    }
+
+    fn decode_with<T>(stream: T, cfg: &DecodeConfig) -> Result<(Self, usize)>
+    where
+        T: AsRef<[u8]>,
+    {
         use crate::v5::Property::*;
 
         let stream: &[u8] = stream.as_ref();
@@ -314,10 +382,10 @@ impl Packetize for PublishProperties {
                 CorrelationData(val) => props.correlation_data = Some(val),
                 SubscriptionIdentifier(val) => props.subscribtion_identifier.push(val),
                 ContentType(val) => props.content_type = Some(val),
-                UserProp(val) => props.user_properties.push(val),
-                _ => {
-                    err!(ProtocolError, code: ProtocolError, "{} bad prop {:?}", PP, pt)?
+                UserProp(val) => {
+                    cfg.push_user_property(PP, &mut props.user_properties, val)?
                 }
+                _ => cfg.skip_property(PP, pt)?,
             }
         }
 
@@ -350,6 +418,29 @@ impl Packetize for PublishProperties {
 
         Ok(Blob::Large { data })
     }
+
+    fn encoded_len(&self) -> Result<usize> {
+        use crate::v5::{field_len, property_len, user_props_len};
+
+        let mut n = if self.payload_format_indicator.is_utf8() { 2 } else { 0 };
+        n += self.message_expiry_interval.map(|_| 5).unwrap_or(0);
+        n += self.topic_alias.map(|_| 3).unwrap_or(0);
+        if let Some(val) = &self.response_topic {
+            n += 1 + field_len(val.len())?;
+        }
+        if let Some(val) = &self.correlation_data {
+            n += 1 + field_len(val.len())?;
+        }
+        if let Some(val) = &self.content_type {
+            n += 1 + field_len(val.len())?;
+        }
+        for subid in self.subscribtion_identifier.iter() {
+            n += 1 + subid.encode()?.as_ref().len();
+        }
+        n += user_props_len(&self.user_properties)?;
+
+        property_len(n)
+    }
 }
 
 impl PublishProperties {
@@ -369,3 +460,7 @@ impl PublishProperties {
             && self.user_properties.len() == 0
     }
 }
+
+#[cfg(test)]
+#[path = "publish_test.rs"]
+mod publish_test;
@@ -0,0 +1,164 @@
+use super::*;
+
+fn new_publish(topic_name: &str) -> Publish {
+    Publish {
+        retain: false,
+        qos: QoS::AtMostOnce,
+        duplicate: false,
+        topic_name: TopicName::from(topic_name.to_string()),
+        packet_id: None,
+        properties: None,
+        payload: Some(b"hello".to_vec()),
+    }
+}
+
+#[test]
+fn test_topic_name_validate() {
+    for topic_name in ["a/+/b", "a/#", "a/\u{0}/b"] {
+        let err = new_publish(topic_name).validate().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::MalformedPacket, "{:?}", topic_name);
+        assert_eq!(err.code(), ReasonCode::TopicNameInvalid, "{:?}", topic_name);
+    }
+
+    new_publish("a/b/c").validate().unwrap();
+
+    // ZERO length topic-name is allowed only with topic-alias.
+    let mut publish = new_publish("");
+    assert!(publish.validate().is_err());
+    publish.properties = Some(PublishProperties {
+        topic_alias: Some(1),
+        ..PublishProperties::default()
+    });
+    publish.validate().unwrap();
+}
+
+#[test]
+fn test_response_topic_validate() {
+    let new_response = |response_topic: &str| {
+        let mut publish = new_publish("a/b/c");
+        publish.properties = Some(PublishProperties {
+            response_topic: Some(TopicName::from(response_topic.to_string())),
+            ..PublishProperties::default()
+        });
+        publish
+    };
+
+    new_response("response/a/b").validate().unwrap();
+    for response_topic in ["response/+", "response/#", ""] {
+        let publish = new_response(response_topic);
+        let err = publish.validate().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::MalformedPacket, "{:?}", response_topic);
+    }
+
+    // decoding a PUBLISH with invalid response-topic is also rejected.
+    let mut data = new_response("response/a").encode().unwrap().as_ref().to_vec();
+    let off = data.windows(10).position(|w| w == b"response/a").unwrap();
+    data[off + 9] = b'+';
+    let err = Publish::decode(&data).unwrap_err();
+    assert_eq!(err.code(), ReasonCode::TopicNameInvalid);
+}
+
+#[test]
+fn test_payload_format_validate() {
+    let new_utf8 = |payload: &[u8], payload_format_indicator| {
+        let mut publish = new_publish("a/b/c");
+        publish.payload = Some(payload.to_vec());
+        publish.properties = Some(PublishProperties {
+            payload_format_indicator,
+            ..PublishProperties::default()
+        });
+        publish
+    };
+
+    new_utf8("hello wörld".as_bytes(), PayloadFormat::Utf8).validate().unwrap();
+
+    let err = new_utf8(&[0xff, 0xfe], PayloadFormat::Utf8).validate().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::MalformedPacket);
+    assert_eq!(err.code(), ReasonCode::PayloadFormatInvalid);
+
+    // binary payload is not checked.
+    new_utf8(&[0xff, 0xfe], PayloadFormat::Binary).validate().unwrap();
+}
+
+#[test]
+fn test_publish_encode_single_alloc() {
+    use crate::v5::insert_fixed_header;
+
+    for size in [1, 100, 1024, 20_000].into_iter() {
+        let mut publish = new_publish("a/b/c");
+        publish.qos = QoS::AtLeastOnce;
+        publish.packet_id = Some(0x1234);
+        publish.payload = Some(vec![0xAB; size]);
+
+        let data = match publish.encode().unwrap() {
+            Blob::Large { data } => data,
+            blob => panic!("unexpected {:?}", blob),
+        };
+        // fixed-header is not inserted after the fact, packet fits the allocation.
+        assert_eq!(data.capacity(), data.len(), "{}", size);
+
+        let fh = FixedHeader::decode(&data).unwrap().0;
+        let n = fh.len().unwrap();
+        let refr = insert_fixed_header(fh, data[n..].to_vec()).unwrap();
+        assert_eq!(data, refr, "{}", size);
+
+        let (val, m) = Publish::decode(&data).unwrap();
+        assert_eq!(m, data.len());
+        assert_eq!(val, publish);
+    }
+}
+
+#[test]
+fn test_publish_encoded_len() {
+    let mut publish = new_publish("a/b/c");
+    publish.properties = Some(PublishProperties {
+        topic_alias: Some(1),
+        subscribtion_identifier: vec![VarU32(0x1234)],
+        user_properties: vec![UserProperty::new("key", "val")],
+        ..PublishProperties::default()
+    });
+
+    for size in [0, 1, 200, 20_000].into_iter() {
+        for qos in [QoS::AtMostOnce, QoS::AtLeastOnce, QoS::ExactlyOnce].into_iter() {
+            let mut publish = publish.clone();
+            publish.qos = qos;
+            publish.packet_id = (qos != QoS::AtMostOnce).then(|| 0x1234);
+            publish.payload = (size > 0).then(|| vec![0xAB; size]);
+
+            let n = publish.encode().unwrap().as_ref().len();
+            assert_eq!(publish.encoded_len().unwrap(), n, "{} {:?}", size, qos);
+        }
+    }
+}
+
+#[test]
+fn test_publish_encode_too_large() {
+    // Publish::encode_into frames remaining-length via VarU32::try_from_len, check
+    // the limit on the length arithmetic, without allocating a huge payload.
+    let publish = new_publish("a/b/c");
+    let payload_len = publish.payload.as_ref().unwrap().len();
+    // remaining-length less the payload, fixed-header is 2 bytes for this publish.
+    let overhead = publish.encoded_len().unwrap() - 2 - payload_len;
+    let max_payload = (*VarU32::MAX as usize) - overhead;
+
+    let val = VarU32::try_from_len(overhead + max_payload).unwrap();
+    assert_eq!(val, VarU32::MAX);
+
+    let err = VarU32::try_from_len(overhead + max_payload + 1).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert!(err.to_string().contains("packet too large"), "{}", err);
+}
+
+#[test]
+fn test_publish_add_subscription_id() {
+    let mut publish = new_publish("a/b/c");
+    assert!(publish.properties.is_none());
+
+    publish.add_subscription_id(VarU32(10));
+    publish.add_subscription_id(VarU32(20));
+    let props = publish.properties.as_ref().unwrap();
+    assert_eq!(props.subscribtion_identifier, vec![VarU32(10), VarU32(20)]);
+
+    let (val, _) = Publish::decode(publish.encode().unwrap()).unwrap();
+    assert_eq!(val, publish);
+}
@@ -1,16 +1,19 @@
 #[cfg(any(feature = "fuzzy", test))]
 use arbitrary::{Arbitrary, Error as ArbitraryError, Unstructured};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 #[cfg(any(feature = "fuzzy", test))]
 use std::result;
 
-use crate::v5::{FixedHeader, Property, PropertyType, QoS};
+use crate::v5::{DecodeConfig, FixedHeader, PacketType, Property, PropertyType, QoS};
 use crate::{util::advance, Blob, Packetize, TopicFilter, UserProperty, VarU32};
 use crate::{Error, ErrorKind, ReasonCode, Result};
 
 const PP: &'static str = "Packet::Subscribe";
 
 /// Subscription options carried in SUBSCRIBE Packet
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub struct SubscriptionOpt(u8);
 
@@ -61,15 +64,16 @@ impl SubscriptionOpt {
 
     /// Return (retain_forward_rule, retain_as_published, no_local, qos)
     pub fn unwrap(&self) -> (RetainForwardRule, bool, bool, QoS) {
-        let qos: QoS = (self.0 & Self::MAXIMUM_QOS).try_into().unwrap();
+        // reserved values are rejected by Subscribe::validate, saturate here.
+        let qos = QoS::from_u8_saturating(self.0 & Self::MAXIMUM_QOS);
         let nl: bool = (self.0 & Self::NO_LOCAL) > 0;
         let rap: bool = (self.0 & Self::RETAIN_AS_PUBLISHED) > 0;
-        (
-            RetainForwardRule::try_from((self.0 >> 4) & Self::RETAIN_HANDLING).unwrap(),
-            rap,
-            nl,
-            qos,
-        )
+        let rfr = match (self.0 & Self::RETAIN_HANDLING) >> 4 {
+            0 => RetainForwardRule::OnEverySubscribe,
+            1 => RetainForwardRule::OnNewSubscribe,
+            _ => RetainForwardRule::Never,
+        };
+        (rfr, rap, nl, qos)
     }
 
     fn validate(&self) -> Result<()> {
@@ -79,6 +83,7 @@ impl SubscriptionOpt {
 
 /// RetainForwardRule part of Subscription option defined by MQTT spec.
 #[cfg_attr(any(feature = "fuzzy", test), derive(Arbitrary))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum RetainForwardRule {
     OnEverySubscribe = 0,
@@ -112,6 +117,7 @@ impl From<RetainForwardRule> for u8 {
 }
 
 /// SUBSCRIBE Packet
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub struct Subscribe {
     pub packet_id: u16,
@@ -139,13 +145,21 @@ impl<'a> Arbitrary<'a> for Subscribe {
 
 impl Packetize for Subscribe {
     fn decode<T: AsRef<[u8]>>(stream: T) -> Result<(Self, usize)> {
+        Self::decode_with(stream, &DecodeConfig::default())
+    }
+
+    fn decode_with<T>(stream: T, cfg: &DecodeConfig) -> Result<(Self, usize)>
+    where
+        T: AsRef<[u8]>,
+    {
         let stream: &[u8] = stream.as_ref();
 
         let (fh, fh_len) = dec_field!(FixedHeader, stream, 0);
-        fh.validate()?;
+        fh.validate_flags(PacketType::Subscribe)?;
+        Self::remaining_len_ok(stream, &fh)?;
 
         let (packet_id, n) = dec_field!(u16, stream, fh_len);
-        let (properties, n) = dec_props!(SubscribeProperties, stream, n);
+        let (properties, n) = dec_props!(SubscribeProperties, stream, n, cfg);
         let (payload, n) = match fh_len + usize::try_from(*fh.remaining_len)? {
             m if m == n => {
                 err!(MalformedPacket, code: MalformedPacket, "{} in payload {}", PP, m)?
@@ -154,14 +168,10 @@ impl Packetize for Subscribe {
             m => err!(MalformedPacket, code: MalformedPacket, "{} in payload {}", PP, m)?,
         };
 
-        // Assume each entry will take 32 bytes.
-        let mut filters = Vec::with_capacity((payload.len() / 32) + 1);
-        let mut t = 0;
-        while t < payload.len() {
-            let (filter, m) = dec_field!(SubscribeFilter, payload, t);
-            t = m;
-            filters.push(filter);
-        }
+        let filters = {
+            let off = n - payload.len();
+            SubscribeFilter::decode_all(payload).map_err(|err| err.at(off))?
+        };
 
         let val = Subscribe { packet_id, properties, filters };
 
@@ -187,11 +197,25 @@ impl Packetize for Subscribe {
             data.extend_from_slice(filter.encode()?.as_ref());
         }
 
-        let fh = FixedHeader::new_subscribe(VarU32(data.len().try_into()?))?;
+        let fh = FixedHeader::new_subscribe(VarU32::try_from_len(data.len())?)?;
         data = insert_fixed_header(fh, data)?;
 
         Ok(Blob::Large { data })
     }
+
+    fn encoded_len(&self) -> Result<usize> {
+        use crate::v5::{field_len, packet_len, props_len};
+
+        self.validate()?;
+
+        let mut remaining_len = 2 + props_len(self.properties.as_ref())?;
+        for filter in self.filters.iter() {
+            // topic-filter and subscription-options.
+            remaining_len += field_len(filter.topic_filter.len())? + 1;
+        }
+
+        packet_len(remaining_len)
+    }
 }
 
 impl Subscribe {
@@ -235,6 +259,7 @@ impl Subscribe {
 }
 
 /// Collection of MQTT properties allowed in SUBSCRIBE packet
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone, Eq, PartialEq, Default)]
 pub struct SubscribeProperties {
     pub subscription_id: Option<VarU32>,
@@ -258,6 +283,13 @@ impl<'a> Arbitrary<'a> for SubscribeProperties {
 
 impl Packetize for SubscribeProperties {
     fn decode<T: AsRef<[u8]>>(stream: T) -> Result<(Self, usize)> {
+        Self::decode_with(stream, &DecodeConfig::default())
+    }
+
+    fn decode_with<T>(stream: T, cfg: &DecodeConfig) -> Result<(Self, usize)>
+    where
+        T: AsRef<[u8]>,
+    {
         use crate::v5::Property::*;
 
         let stream: &[u8] = stream.as_ref();
@@ -283,10 +315,10 @@ impl Packetize for SubscribeProperties {
                     err!(ProtocolError, code: ProtocolError, "{} subcr_ide:0", PP)?;
                 }
                 SubscriptionIdentifier(val) => props.subscription_id = Some(val),
-                UserProp(val) => props.user_properties.push(val),
-                _ => {
-                    err!(ProtocolError, code: ProtocolError, "{} bad prop {:?}", PP, pt)?
+                UserProp(val) => {
+                    cfg.push_user_property(PP, &mut props.user_properties, val)?
                 }
+                _ => cfg.skip_property(PP, pt)?,
             }
         }
 
@@ -308,9 +340,26 @@ impl Packetize for SubscribeProperties {
 
         Ok(Blob::Large { data })
     }
+
+    fn encoded_len(&self) -> Result<usize> {
+        use crate::v5::{property_len, user_props_len};
+
+        let mut n = match &self.subscription_id {
+            Some(val) => 1 + val.encode()?.as_ref().len(),
+            None => 0,
+        };
+        n += user_props_len(&self.user_properties)?;
+
+        property_len(n)
+    }
 }
 
 impl SubscribeProperties {
+    /// Canonicalize properties, sort user-properties by (key, value).
+    pub fn normalize(&mut self) {
+        self.user_properties.sort();
+    }
+
     #[cfg(any(feature = "fuzzy", test))]
     pub fn is_empty(&self) -> bool {
         self.subscription_id.is_none() && self.user_properties.len() == 0
@@ -318,6 +367,7 @@ impl SubscribeProperties {
 }
 
 /// SubscribeFilter defined in the SUBSCRIBE packet's payload.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub struct SubscribeFilter {
     pub topic_filter: TopicFilter,
@@ -360,7 +410,37 @@ impl Packetize for SubscribeFilter {
 }
 
 impl SubscribeFilter {
+    /// Decode all the subscribe-filters in SUBSCRIBE `payload`. Filters are counted
+    /// first, validating each topic-filter's length against the payload, so that
+    /// exact capacity is reserved before decoding them.
+    pub fn decode_all(payload: &[u8]) -> Result<Vec<SubscribeFilter>> {
+        let (mut count, mut t) = (0, 0);
+        while t < payload.len() {
+            let (len, m) = dec_field!(u16, payload, t);
+            t = m + usize::from(len) + 1; // topic-filter and subscription-option
+            if t > payload.len() {
+                err!(MalformedPacket, code: MalformedPacket, "{} filter len {}", PP, len)
+                    .map_err(|err: Error| err.at(payload.len()))?;
+            }
+            count += 1;
+        }
+
+        let mut filters = Vec::with_capacity(count);
+        let mut t = 0;
+        while t < payload.len() {
+            let (filter, m) = dec_field!(SubscribeFilter, payload, t);
+            t = m;
+            filters.push(filter);
+        }
+
+        Ok(filters)
+    }
+
     fn validate(&self) -> Result<()> {
-        Ok(())
+        self.topic_filter.validate()
     }
 }
+
+#[cfg(test)]
+#[path = "sub_test.rs"]
+mod sub_test;
@@ -0,0 +1,141 @@
+use super::*;
+
+fn new_filter(i: usize) -> SubscribeFilter {
+    let rfr = RetainForwardRule::OnEverySubscribe;
+    SubscribeFilter {
+        topic_filter: TopicFilter::from(format!("a/b/{}", i)),
+        opt: SubscriptionOpt::new(rfr, false, false, QoS::AtLeastOnce),
+    }
+}
+
+#[test]
+fn test_subscribe_decode_all() {
+    let filters: Vec<SubscribeFilter> = (0..500).map(new_filter).collect();
+    let sub = Subscribe {
+        packet_id: 1,
+        properties: None,
+        filters: filters.clone(),
+    };
+    let blob = sub.encode().unwrap();
+
+    let (val, n) = Subscribe::decode(blob.as_ref()).unwrap();
+    assert_eq!(n, blob.as_ref().len());
+    assert_eq!(val.filters, filters);
+    assert_eq!(val.filters.capacity(), 500);
+}
+
+#[test]
+fn test_subscribe_decode_all_truncated() {
+    let mut payload = Vec::new();
+    for i in 0..3 {
+        payload.extend_from_slice(new_filter(i).encode().unwrap().as_ref());
+    }
+    assert_eq!(SubscribeFilter::decode_all(&payload).unwrap().len(), 3);
+
+    // last filter's topic-filter length points beyond the payload.
+    payload.truncate(payload.len() - 2);
+    let err = SubscribeFilter::decode_all(&payload).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::MalformedPacket);
+}
+
+#[test]
+fn test_subscribe_decode_offset() {
+    let sub = Subscribe {
+        packet_id: 1,
+        properties: None,
+        filters: vec![new_filter(0), new_filter(1)],
+    };
+    let data = sub.encode().unwrap().as_ref().to_vec();
+    assert!(data[1] < 128, "single byte remaining_len");
+
+    // truncated packet, fixed-header claims more bytes than available.
+    let stream = &data[..data.len() - 2];
+    let err = Subscribe::decode(stream).unwrap_err();
+    assert_eq!(err.offset(), Some(stream.len()));
+
+    // consistent fixed-header, last filter is missing its subscription-option or
+    // part of its topic-filter length.
+    for trunc in [1, 7] {
+        let mut stream = data[..data.len() - trunc].to_vec();
+        stream[1] -= trunc as u8;
+        let err = Subscribe::decode(&stream).unwrap_err();
+        assert_eq!(err.code(), ReasonCode::MalformedPacket, "{}", trunc);
+        assert_eq!(err.offset(), Some(stream.len()), "{}", trunc);
+    }
+}
+
+#[test]
+fn test_subscribe_filter_validate() {
+    let rfr = RetainForwardRule::OnEverySubscribe;
+    let new_filter = |topic_filter: &str| SubscribeFilter {
+        topic_filter: TopicFilter::from(topic_filter.to_string()),
+        opt: SubscriptionOpt::new(rfr.clone(), false, false, QoS::AtLeastOnce),
+    };
+
+    for topic_filter in ["a/#/b", "a+/b", "a/b#", ""] {
+        let err = new_filter(topic_filter).validate().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::MalformedPacket, "{:?}", topic_filter);
+    }
+
+    let filter = new_filter("+/a/#");
+    filter.validate().unwrap();
+    let levels: Vec<&str> = filter.topic_filter.levels().collect();
+    assert_eq!(levels, vec!["+", "a", "#"]);
+}
+
+#[test]
+fn test_subscription_opt_unwrap() {
+    // reserved QoS and retain-handling values never panic.
+    for byte in 0..=u8::MAX {
+        let (_, _, _, qos) = SubscriptionOpt(byte).unwrap();
+        assert_eq!(qos, QoS::from_u8_saturating(byte & 0b11));
+    }
+
+    let opt =
+        SubscriptionOpt::new(RetainForwardRule::Never, true, false, QoS::AtLeastOnce);
+    assert_eq!(opt.unwrap(), (RetainForwardRule::Never, true, false, QoS::AtLeastOnce));
+}
+
+#[test]
+fn test_subscribe_big_endian() {
+    let sub = Subscribe {
+        packet_id: 0x1234,
+        properties: None,
+        filters: vec![new_filter(1)],
+    };
+    let data = sub.encode().unwrap().as_ref().to_vec();
+    let (_, n) = FixedHeader::decode(&data).unwrap();
+
+    assert_eq!(&data[n..n + 2], &[0x12, 0x34]);
+    assert_eq!(Subscribe::decode(&data).unwrap().0, sub);
+}
+
+#[test]
+fn test_subscribe_encoded_len() {
+    let properties = SubscribeProperties {
+        subscription_id: Some(VarU32(0x1234)),
+        user_properties: vec![UserProperty::new("key", "val")],
+    };
+    let subs = [
+        Subscribe {
+            packet_id: 1,
+            properties: None,
+            filters: vec![new_filter(1)],
+        },
+        Subscribe {
+            packet_id: 2,
+            properties: Some(properties),
+            filters: vec![new_filter(1), new_filter(2)],
+        },
+        // remaining length is encoded in two bytes.
+        Subscribe {
+            packet_id: 3,
+            properties: None,
+            filters: (0..100).map(new_filter).collect(),
+        },
+    ];
+    for sub in subs.iter() {
+        let n = sub.encode().unwrap().as_ref().len();
+        assert_eq!(sub.encoded_len().unwrap(), n, "{:?}", sub);
+    }
+}
@@ -5,7 +5,9 @@ use arbitrary::{Arbitrary, Error as ArbitraryError, Unstructured};
 use std::result;
 
 use crate::util::advance;
-use crate::v5::{FixedHeader, PacketType, Property, PropertyType};
+use crate::v5::{
+    DecodeConfig, FixedHeader, PacketType, Property, PropertyType, QoS, Subscribe,
+};
 use crate::{Blob, Packetize, UserProperty, VarU32};
 use crate::{Error, ErrorKind, ReasonCode, Result};
 
@@ -60,6 +62,16 @@ impl TryFrom<u8> for SubAckReasonCode {
     }
 }
 
+impl From<QoS> for SubAckReasonCode {
+    fn from(qos: QoS) -> SubAckReasonCode {
+        match qos {
+            QoS::AtMostOnce => SubAckReasonCode::QoS0,
+            QoS::AtLeastOnce => SubAckReasonCode::QoS1,
+            QoS::ExactlyOnce => SubAckReasonCode::QoS2,
+        }
+    }
+}
+
 /// SUBACK Packet
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct SubAck {
@@ -88,13 +100,21 @@ impl<'a> Arbitrary<'a> for SubAck {
 
 impl Packetize for SubAck {
     fn decode<T: AsRef<[u8]>>(stream: T) -> Result<(Self, usize)> {
+        Self::decode_with(stream, &DecodeConfig::default())
+    }
+
+    fn decode_with<T>(stream: T, cfg: &DecodeConfig) -> Result<(Self, usize)>
+    where
+        T: AsRef<[u8]>,
+    {
         let stream: &[u8] = stream.as_ref();
 
         let (fh, fh_len) = dec_field!(FixedHeader, stream, 0);
-        fh.validate()?;
+        fh.validate_flags(PacketType::SubAck)?;
+        Self::remaining_len_ok(stream, &fh)?;
 
         let (packet_id, n) = dec_field!(u16, stream, fh_len);
-        let (properties, n) = dec_props!(SubAckProperties, stream, n);
+        let (properties, n) = dec_props!(SubAckProperties, stream, n, cfg);
 
         let (payload, n) = match fh_len + usize::try_from(*fh.remaining_len)? {
             m if m == n => {
@@ -130,7 +150,7 @@ impl Packetize for SubAck {
             data.push(code as u8)
         }
 
-        let fh = FixedHeader::new(PacketType::SubAck, VarU32(data.len().try_into()?))?;
+        let fh = FixedHeader::new(PacketType::SubAck, VarU32::try_from_len(data.len())?)?;
         data = insert_fixed_header(fh, data)?;
 
         Ok(Blob::Large { data })
@@ -138,6 +158,24 @@ impl Packetize for SubAck {
 }
 
 impl SubAck {
+    /// Create SUBACK for `sub`, with one reason-code for each topic-filter in the
+    /// same order. `granted` QoS is expected for each filter, missing entries are
+    /// treated as [SubAckReasonCode::UnspecifiedError].
+    pub fn from_subscribe(sub: &Subscribe, granted: &[QoS]) -> SubAck {
+        let return_codes = (0..sub.filters.len())
+            .map(|i| match granted.get(i) {
+                Some(qos) => SubAckReasonCode::from(*qos),
+                None => SubAckReasonCode::UnspecifiedError,
+            })
+            .collect();
+
+        SubAck {
+            packet_id: sub.packet_id,
+            properties: None,
+            return_codes,
+        }
+    }
+
     #[cfg(any(feature = "fuzzy", test))]
     pub fn normalize(&mut self) {
         if let Some(props) = &mut self.properties {
@@ -184,6 +222,13 @@ impl<'a> Arbitrary<'a> for SubAckProperties {
 
 impl Packetize for SubAckProperties {
     fn decode<T: AsRef<[u8]>>(stream: T) -> Result<(Self, usize)> {
+        Self::decode_with(stream, &DecodeConfig::default())
+    }
+
+    fn decode_with<T>(stream: T, cfg: &DecodeConfig) -> Result<(Self, usize)>
+    where
+        T: AsRef<[u8]>,
+    {
         let stream: &[u8] = stream.as_ref();
 
         let mut dups = [false; 256];
@@ -204,10 +249,10 @@ impl Packetize for SubAckProperties {
 
             match property {
                 Property::ReasonString(val) => props.reason_string = Some(val),
-                Property::UserProp(val) => props.user_properties.push(val),
-                _ => {
-                    err!(ProtocolError, code: ProtocolError, "{} bad prop, {:?}", PP, pt)?
+                Property::UserProp(val) => {
+                    cfg.push_user_property(PP, &mut props.user_properties, val)?
                 }
+                _ => cfg.skip_property(PP, pt)?,
             };
         }
 
@@ -237,3 +282,7 @@ impl SubAckProperties {
         self.reason_string.is_none() && self.user_properties.len() == 0
     }
 }
+
+#[cfg(test)]
+#[path = "suback_test.rs"]
+mod suback_test;
@@ -0,0 +1,34 @@
+use crate::v5::{RetainForwardRule, SubscribeFilter, SubscriptionOpt};
+use crate::TopicFilter;
+
+use super::*;
+
+#[test]
+fn test_suback_from_subscribe() {
+    let rfr = RetainForwardRule::OnEverySubscribe;
+    let filters: Vec<SubscribeFilter> = ["a/b", "a/+", "#"]
+        .iter()
+        .map(|tf| SubscribeFilter {
+            topic_filter: TopicFilter::from(tf.to_string()),
+            opt: SubscriptionOpt::new(rfr.clone(), false, false, QoS::ExactlyOnce),
+        })
+        .collect();
+    let sub = Subscribe { packet_id: 7, properties: None, filters };
+
+    let granted = [QoS::AtMostOnce, QoS::AtLeastOnce, QoS::ExactlyOnce];
+    let sub_ack = SubAck::from_subscribe(&sub, &granted);
+    assert_eq!(sub_ack.packet_id, 7);
+    assert_eq!(sub_ack.return_codes.len(), sub.filters.len());
+    assert_eq!(
+        sub_ack.return_codes,
+        vec![SubAckReasonCode::QoS0, SubAckReasonCode::QoS1, SubAckReasonCode::QoS2]
+    );
+
+    let (val, _) = SubAck::decode(sub_ack.encode().unwrap()).unwrap();
+    assert_eq!(val, sub_ack);
+
+    // fewer granted QoS than filters.
+    let sub_ack = SubAck::from_subscribe(&sub, &granted[..1]);
+    assert_eq!(sub_ack.return_codes.len(), sub.filters.len());
+    assert_eq!(sub_ack.return_codes[2], SubAckReasonCode::UnspecifiedError);
+}
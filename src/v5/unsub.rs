@@ -4,7 +4,7 @@ use arbitrary::{Arbitrary, Error as ArbitraryError, Unstructured};
 #[cfg(any(feature = "fuzzy", test))]
 use std::result;
 
-use crate::v5::{FixedHeader, Property, PropertyType};
+use crate::v5::{DecodeConfig, FixedHeader, PacketType, Property, PropertyType};
 use crate::{util::advance, Blob, Packetize, TopicFilter, UserProperty, VarU32};
 use crate::{Error, ErrorKind, ReasonCode, Result};
 
@@ -38,13 +38,21 @@ impl<'a> Arbitrary<'a> for UnSubscribe {
 
 impl Packetize for UnSubscribe {
     fn decode<T: AsRef<[u8]>>(stream: T) -> Result<(Self, usize)> {
+        Self::decode_with(stream, &DecodeConfig::default())
+    }
+
+    fn decode_with<T>(stream: T, cfg: &DecodeConfig) -> Result<(Self, usize)>
+    where
+        T: AsRef<[u8]>,
+    {
         let stream: &[u8] = stream.as_ref();
 
         let (fh, fh_len) = dec_field!(FixedHeader, stream, 0);
-        fh.validate()?;
+        fh.validate_flags(PacketType::UnSubscribe)?;
+        Self::remaining_len_ok(stream, &fh)?;
 
         let (packet_id, n) = dec_field!(u16, stream, fh_len);
-        let (properties, n) = dec_props!(UnSubscribeProperties, stream, n);
+        let (properties, n) = dec_props!(UnSubscribeProperties, stream, n, cfg);
         let (payload, n) = match fh_len + usize::try_from(*fh.remaining_len)? {
             m if m == n => {
                 err!(ProtocolError, code: ProtocolError, "{} in payload {}", PP, m)?
@@ -84,7 +92,7 @@ impl Packetize for UnSubscribe {
             data.extend_from_slice(filter.encode()?.as_ref());
         }
 
-        let fh = FixedHeader::new_unsubscribe(VarU32(data.len().try_into()?))?;
+        let fh = FixedHeader::new_unsubscribe(VarU32::try_from_len(data.len())?)?;
         data = insert_fixed_header(fh, data)?;
 
         Ok(Blob::Large { data })
@@ -139,6 +147,13 @@ impl UnSubscribeProperties {
 
 impl Packetize for UnSubscribeProperties {
     fn decode<T: AsRef<[u8]>>(stream: T) -> Result<(Self, usize)> {
+        Self::decode_with(stream, &DecodeConfig::default())
+    }
+
+    fn decode_with<T>(stream: T, cfg: &DecodeConfig) -> Result<(Self, usize)>
+    where
+        T: AsRef<[u8]>,
+    {
         use crate::v5::Property::*;
 
         let stream: &[u8] = stream.as_ref();
@@ -160,10 +175,10 @@ impl Packetize for UnSubscribeProperties {
             dups[pt as usize] = true;
 
             match property {
-                UserProp(val) => props.user_properties.push(val),
-                _ => {
-                    err!(ProtocolError, code: ProtocolError, "{} bad prop {:?}", PP, pt)?
+                UserProp(val) => {
+                    cfg.push_user_property(PP, &mut props.user_properties, val)?
                 }
+                _ => cfg.skip_property(PP, pt)?,
             }
         }
 
@@ -5,7 +5,7 @@ use arbitrary::{Arbitrary, Error as ArbitraryError, Unstructured};
 use std::result;
 
 use crate::util::advance;
-use crate::v5::{FixedHeader, PacketType, Property, PropertyType};
+use crate::v5::{DecodeConfig, FixedHeader, PacketType, Property, PropertyType};
 use crate::{Blob, Packetize, UserProperty, VarU32};
 use crate::{Error, ErrorKind, ReasonCode, Result};
 
@@ -78,13 +78,21 @@ impl<'a> Arbitrary<'a> for UnsubAck {
 
 impl Packetize for UnsubAck {
     fn decode<T: AsRef<[u8]>>(stream: T) -> Result<(Self, usize)> {
+        Self::decode_with(stream, &DecodeConfig::default())
+    }
+
+    fn decode_with<T>(stream: T, cfg: &DecodeConfig) -> Result<(Self, usize)>
+    where
+        T: AsRef<[u8]>,
+    {
         let stream: &[u8] = stream.as_ref();
 
         let (fh, fh_len) = dec_field!(FixedHeader, stream, 0);
-        fh.validate()?;
+        fh.validate_flags(PacketType::UnsubAck)?;
+        Self::remaining_len_ok(stream, &fh)?;
 
         let (packet_id, n) = dec_field!(u16, stream, fh_len);
-        let (properties, n) = dec_props!(UnsubAckProperties, stream, n);
+        let (properties, n) = dec_props!(UnsubAckProperties, stream, n, cfg);
 
         let (payload, n) = match fh_len + usize::try_from(*fh.remaining_len)? {
             m if m == n => {
@@ -120,7 +128,8 @@ impl Packetize for UnsubAck {
             data.push(code as u8)
         }
 
-        let fh = FixedHeader::new(PacketType::UnsubAck, VarU32(data.len().try_into()?))?;
+        let fh =
+            FixedHeader::new(PacketType::UnsubAck, VarU32::try_from_len(data.len())?)?;
         data = insert_fixed_header(fh, data)?;
 
         Ok(Blob::Large { data })
@@ -174,6 +183,13 @@ impl<'a> Arbitrary<'a> for UnsubAckProperties {
 
 impl Packetize for UnsubAckProperties {
     fn decode<T: AsRef<[u8]>>(stream: T) -> Result<(Self, usize)> {
+        Self::decode_with(stream, &DecodeConfig::default())
+    }
+
+    fn decode_with<T>(stream: T, cfg: &DecodeConfig) -> Result<(Self, usize)>
+    where
+        T: AsRef<[u8]>,
+    {
         let stream: &[u8] = stream.as_ref();
 
         let mut dups = [false; 256];
@@ -194,10 +210,10 @@ impl Packetize for UnsubAckProperties {
 
             match property {
                 Property::ReasonString(val) => props.reason_string = Some(val),
-                Property::UserProp(val) => props.user_properties.push(val),
-                _ => {
-                    err!(ProtocolError, code: ProtocolError, "{} bad prop {:?}", PP, pt)?
+                Property::UserProp(val) => {
+                    cfg.push_user_property(PP, &mut props.user_properties, val)?
                 }
+                _ => cfg.skip_property(PP, pt)?,
             };
         }
 
@@ -0,0 +1,949 @@
+#![cfg(feature = "broker")]
+
+use std::io::{Read, Write};
+use std::{net, sync::mpsc, sync::Arc, sync::Mutex, time};
+
+use mymq::broker::{AllowAll, AuthResult, Authenticator, Authorizer, Cluster, Config};
+use mymq::broker::{BrokerEvent, EventSink};
+use mymq::ClientID;
+use mymq::{v5, MQTTRead, Packetize, ReasonCode, TopicFilter, TopicName, VarU32};
+
+const READ_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+struct Client {
+    sock: net::TcpStream,
+    packetr: Option<MQTTRead>,
+}
+
+impl Client {
+    fn new(port: u16) -> Client {
+        let sock = net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+        sock.set_read_timeout(Some(READ_TIMEOUT)).unwrap();
+        let packetr = Some(MQTTRead::new(Config::DEF_MQTT_MAX_PACKET_SIZE));
+
+        Client { sock, packetr }
+    }
+
+    fn connect(port: u16) -> Client {
+        let mut client = Client::new(port);
+        client.send(v5::Packet::Connect(v5::Connect::default()));
+        match client.recv() {
+            v5::Packet::ConnAck(connack) => {
+                assert_eq!(connack.code, v5::ConnackReasonCode::Success)
+            }
+            pkt => panic!("unexpected {:?}", pkt),
+        }
+
+        client
+    }
+
+    fn subscribe(&mut self, packet_id: u16, filter: &str, qos: v5::QoS) {
+        let sub = v5::Subscribe {
+            packet_id,
+            properties: None,
+            filters: vec![v5::SubscribeFilter {
+                topic_filter: TopicFilter::from(filter.to_string()),
+                opt: v5::SubscriptionOpt::new(
+                    v5::RetainForwardRule::OnEverySubscribe,
+                    false,
+                    false,
+                    qos,
+                ),
+            }],
+        };
+        self.send(v5::Packet::Subscribe(sub));
+        match self.recv() {
+            v5::Packet::SubAck(suback) => {
+                assert_eq!(suback.packet_id, packet_id);
+                assert_eq!(
+                    suback.return_codes,
+                    vec![v5::SubAckReasonCode::try_from(qos as u8).unwrap()]
+                );
+            }
+            pkt => panic!("unexpected {:?}", pkt),
+        }
+    }
+
+    fn send(&mut self, pkt: v5::Packet) {
+        self.sock.write_all(pkt.encode().unwrap().as_ref()).unwrap();
+    }
+
+    fn recv(&mut self) -> v5::Packet {
+        let mut packetr = self.packetr.take().unwrap();
+        loop {
+            packetr = match packetr.read(&mut self.sock) {
+                Ok((val, _would_block)) => val,
+                Err(err) => panic!("reading packet {}", err),
+            };
+            if let MQTTRead::Fin { .. } = &packetr {
+                let pkt = packetr.parse().unwrap();
+                self.packetr = Some(packetr.reset());
+                break pkt;
+            }
+        }
+    }
+}
+
+fn spawn_cluster(config: Config) -> (Cluster, u16, mpsc::Receiver<String>) {
+    spawn_cluster_with(config, Arc::new(AllowAll), Arc::new(AllowAll))
+}
+
+fn free_port() -> u16 {
+    let sock = net::TcpListener::bind("127.0.0.1:0").unwrap();
+    sock.local_addr().unwrap().port()
+}
+
+fn spawn_cluster_with(
+    config: Config,
+    authenticator: Arc<dyn Authenticator + Send + Sync>,
+    authorizer: Arc<dyn Authorizer + Send + Sync>,
+) -> (Cluster, u16, mpsc::Receiver<String>) {
+    let port = free_port();
+    let config = Config { port, num_shards: 1, ..config };
+    let (app_tx, app_rx) = mpsc::sync_channel(1024);
+    let cluster = Cluster::from_config_with(config, authenticator, authorizer).unwrap();
+    let cluster = cluster.spawn(app_tx).unwrap();
+
+    (cluster, port, app_rx)
+}
+
+#[test]
+fn test_subscribe_publish() {
+    let (cluster, port, _app_rx) = spawn_cluster(Config::default());
+
+    let mut subscriber = Client::connect(port);
+    subscriber.subscribe(1, "test/#", v5::QoS::AtLeastOnce);
+
+    let mut publisher = Client::connect(port);
+    let publish = v5::Publish {
+        retain: false,
+        qos: v5::QoS::AtLeastOnce,
+        duplicate: false,
+        topic_name: TopicName::from("test/a".to_string()),
+        packet_id: Some(1),
+        properties: None,
+        payload: Some(b"hello world".to_vec()),
+    };
+    publisher.send(v5::Packet::Publish(publish));
+
+    match subscriber.recv() {
+        v5::Packet::Publish(publish) => {
+            assert_eq!(publish.qos, v5::QoS::AtLeastOnce);
+            assert_eq!(publish.as_topic_name(), &TopicName::from("test/a".to_string()));
+            assert_eq!(publish.payload, Some(b"hello world".to_vec()));
+        }
+        pkt => panic!("unexpected {:?}", pkt),
+    }
+    match publisher.recv() {
+        v5::Packet::PubAck(puback) => assert_eq!(puback.packet_id, 1),
+        pkt => panic!("unexpected {:?}", pkt),
+    }
+
+    cluster.close_wait();
+}
+
+#[test]
+fn test_oversized_connect() {
+    let config = Config { mqtt_max_packet_size: 1024, ..Config::default() };
+    let (cluster, port, _app_rx) = spawn_cluster(config);
+
+    let mut connect = v5::Connect::default();
+    connect.flags = v5::ConnectFlags::new(&[v5::ConnectFlags::WILL_FLAG]);
+    connect.payload.will_topic = Some(TopicName::from("test/will".to_string()));
+    connect.payload.will_payload = Some(vec![0xAB; 4096]);
+    connect.payload.will_properties = Some(v5::WillProperties::default());
+    let data = connect.encode().unwrap().as_ref().to_vec();
+
+    // fixed-header alone shall be rejected, without waiting for the payload.
+    let mut client = Client::new(port);
+    client.sock.write_all(&data[..3]).unwrap();
+    match client.recv() {
+        v5::Packet::ConnAck(connack) => {
+            assert_eq!(connack.code, v5::ConnackReasonCode::PacketTooLarge)
+        }
+        pkt => panic!("unexpected {:?}", pkt),
+    }
+    let mut buf = [0_u8; 16];
+    assert_eq!(client.sock.read(&mut buf).unwrap(), 0);
+
+    cluster.close_wait();
+}
+
+#[test]
+fn test_max_sessions_per_shard() {
+    let config = Config {
+        max_sessions_per_shard: Some(2),
+        ..Config::default()
+    };
+    let (cluster, port, _app_rx) = spawn_cluster(config);
+
+    let _client1 = Client::connect(port);
+    let _client2 = Client::connect(port);
+
+    // shard is at capacity, next session is refused.
+    let mut client = Client::new(port);
+    client.send(v5::Packet::Connect(v5::Connect::default()));
+    match client.recv() {
+        v5::Packet::ConnAck(connack) => {
+            assert_eq!(connack.code, v5::ConnackReasonCode::QuotaExceeded)
+        }
+        pkt => panic!("unexpected {:?}", pkt),
+    }
+    let mut buf = [0_u8; 16];
+    assert_eq!(client.sock.read(&mut buf).unwrap(), 0);
+
+    // cluster is still serving the admitted sessions.
+    let metrics = cluster.metrics_text().unwrap();
+    assert!(metrics.contains("mymq_connections{shard=\"0\"} 2"), "{}", metrics);
+
+    cluster.close_wait();
+}
+
+fn connect_client_id(client: &mut Client, client_id: &str) -> v5::ConnackReasonCode {
+    let mut connect = v5::Connect::default();
+    connect.payload.client_id = ClientID(client_id.to_string());
+    client.send(v5::Packet::Connect(connect));
+    match client.recv() {
+        v5::Packet::ConnAck(connack) => connack.code,
+        pkt => panic!("unexpected {:?}", pkt),
+    }
+}
+
+#[test]
+fn test_duplicate_client_id_take_over() {
+    let (cluster, port, _app_rx) = spawn_cluster(Config::default());
+
+    let mut client1 = Client::new(port);
+    let code = connect_client_id(&mut client1, "duplicate-client");
+    assert_eq!(code, v5::ConnackReasonCode::Success);
+
+    // second connection takes over the session, first one is disconnected.
+    let mut client2 = Client::new(port);
+    let code = connect_client_id(&mut client2, "duplicate-client");
+    assert_eq!(code, v5::ConnackReasonCode::Success);
+    match client1.recv() {
+        v5::Packet::Disconnect(disconnect) => {
+            assert_eq!(disconnect.code, v5::DisconnReasonCode::SessionTakenOver)
+        }
+        pkt => panic!("unexpected {:?}", pkt),
+    }
+
+    client2.send(v5::Packet::PingReq);
+    assert!(matches!(client2.recv(), v5::Packet::PingResp));
+
+    cluster.close_wait();
+}
+
+#[test]
+fn test_duplicate_client_id_reject() {
+    let config = Config {
+        reject_duplicate_client_id: true,
+        ..Config::default()
+    };
+    let (cluster, port, _app_rx) = spawn_cluster(config);
+
+    let mut client1 = Client::new(port);
+    let code = connect_client_id(&mut client1, "duplicate-client");
+    assert_eq!(code, v5::ConnackReasonCode::Success);
+
+    // second connection is refused and closed.
+    let mut client2 = Client::new(port);
+    let code = connect_client_id(&mut client2, "duplicate-client");
+    assert_eq!(code, v5::ConnackReasonCode::InvalidClientID);
+    let mut buf = [0_u8; 16];
+    assert_eq!(client2.sock.read(&mut buf).unwrap(), 0);
+
+    // first connection is left undisturbed.
+    client1.send(v5::Packet::PingReq);
+    assert!(matches!(client1.recv(), v5::Packet::PingResp));
+
+    cluster.close_wait();
+}
+
+#[test]
+fn test_max_will_payload_size() {
+    let config = Config { max_will_payload_size: 16, ..Config::default() };
+    let (cluster, port, _app_rx) = spawn_cluster(config);
+
+    let will_connect = |size: usize| {
+        let mut connect = v5::Connect::default();
+        connect.flags = v5::ConnectFlags::new(&[v5::ConnectFlags::WILL_FLAG]);
+        connect.payload.will_topic = Some(TopicName::from("test/will".to_string()));
+        connect.payload.will_payload = Some(vec![0xAB; size]);
+        connect.payload.will_properties = Some(v5::WillProperties::default());
+        v5::Packet::Connect(connect)
+    };
+
+    let mut client = Client::new(port);
+    client.send(will_connect(17));
+    match client.recv() {
+        v5::Packet::ConnAck(connack) => {
+            assert_eq!(connack.code, v5::ConnackReasonCode::QuotaExceeded)
+        }
+        pkt => panic!("unexpected {:?}", pkt),
+    }
+    let mut buf = [0_u8; 16];
+    assert_eq!(client.sock.read(&mut buf).unwrap(), 0);
+
+    let mut client = Client::new(port);
+    client.send(will_connect(16));
+    match client.recv() {
+        v5::Packet::ConnAck(connack) => {
+            assert_eq!(connack.code, v5::ConnackReasonCode::Success)
+        }
+        pkt => panic!("unexpected {:?}", pkt),
+    }
+
+    cluster.close_wait();
+}
+
+#[test]
+fn test_max_retained_payload_size() {
+    let config = Config { max_retained_payload_size: 16, ..Config::default() };
+    let (cluster, port, _app_rx) = spawn_cluster(config);
+
+    let mut publisher = Client::connect(port);
+    let topics = [("test/big", 17), ("test/small", 16)];
+    for (i, (topic, size)) in topics.iter().enumerate() {
+        let publish = v5::Publish {
+            retain: true,
+            qos: v5::QoS::AtLeastOnce,
+            duplicate: false,
+            topic_name: TopicName::from(topic.to_string()),
+            packet_id: Some(i as u16 + 1),
+            properties: None,
+            payload: Some(vec![0xAB; *size]),
+        };
+        publisher.send(v5::Packet::Publish(publish));
+    }
+
+    // oversized retain PUBLISH is acknowledged with QuotaExceeded.
+    for (packet_id, code) in [(1, ReasonCode::QuotaExceeded), (2, ReasonCode::Success)] {
+        match publisher.recv() {
+            v5::Packet::PubAck(puback) => {
+                assert_eq!(puback.packet_id, packet_id);
+                assert_eq!(puback.code, code);
+            }
+            pkt => panic!("unexpected {:?}", pkt),
+        }
+    }
+
+    // and it is not retained.
+    let mut subscriber = Client::connect(port);
+    let sub = v5::Subscribe {
+        packet_id: 1,
+        properties: None,
+        filters: vec![v5::SubscribeFilter {
+            topic_filter: TopicFilter::from("test/#".to_string()),
+            opt: v5::SubscriptionOpt::new(
+                v5::RetainForwardRule::OnEverySubscribe,
+                false,
+                false,
+                v5::QoS::AtMostOnce,
+            ),
+        }],
+    };
+    subscriber.send(v5::Packet::Subscribe(sub));
+    subscriber.send(v5::Packet::PingReq);
+    // retained messages follow the SUBACK.
+    assert!(matches!(subscriber.recv(), v5::Packet::SubAck(_)));
+    let mut topics = vec![];
+    loop {
+        match subscriber.recv() {
+            v5::Packet::Publish(publish) => topics.push(publish.topic_name),
+            v5::Packet::PingResp => break,
+            pkt => panic!("unexpected {:?}", pkt),
+        }
+    }
+    assert_eq!(topics, vec![TopicName::from("test/small".to_string())]);
+
+    cluster.close_wait();
+}
+
+#[test]
+fn test_retained_overlapping_filters() {
+    let (cluster, port, _app_rx) = spawn_cluster(Config::default());
+
+    let mut publisher = Client::connect(port);
+    let publish = v5::Publish {
+        retain: true,
+        qos: v5::QoS::AtLeastOnce,
+        duplicate: false,
+        topic_name: TopicName::from("test/a/b".to_string()),
+        packet_id: Some(1),
+        properties: None,
+        payload: Some(b"retained".to_vec()),
+    };
+    publisher.send(v5::Packet::Publish(publish));
+    assert!(matches!(publisher.recv(), v5::Packet::PubAck(_)));
+
+    // both filters match the retained message, it is delivered once.
+    let mut subscriber = Client::connect(port);
+    let new_filter = |filter: &str, qos| v5::SubscribeFilter {
+        topic_filter: TopicFilter::from(filter.to_string()),
+        opt: v5::SubscriptionOpt::new(
+            v5::RetainForwardRule::OnEverySubscribe,
+            false,
+            false,
+            qos,
+        ),
+    };
+    let sub = v5::Subscribe {
+        packet_id: 1,
+        properties: Some(v5::SubscribeProperties {
+            subscription_id: Some(VarU32(7)),
+            user_properties: vec![],
+        }),
+        filters: vec![
+            new_filter("test/#", v5::QoS::AtMostOnce),
+            new_filter("test/a/b", v5::QoS::AtLeastOnce),
+        ],
+    };
+    subscriber.send(v5::Packet::Subscribe(sub));
+    subscriber.send(v5::Packet::PingReq);
+    // retained messages follow the SUBACK.
+    assert!(matches!(subscriber.recv(), v5::Packet::SubAck(_)));
+    let mut publishes = vec![];
+    loop {
+        match subscriber.recv() {
+            v5::Packet::Publish(publish) => publishes.push(publish),
+            v5::Packet::PingResp => break,
+            pkt => panic!("unexpected {:?}", pkt),
+        }
+    }
+    assert_eq!(publishes.len(), 1, "{:?}", publishes);
+    assert_eq!(publishes[0].qos, v5::QoS::AtLeastOnce);
+    assert!(publishes[0].retain);
+    let ids = publishes[0].properties.as_ref().unwrap().subscribtion_identifier.clone();
+    assert_eq!(ids, vec![VarU32(7)]);
+
+    cluster.close_wait();
+}
+
+#[test]
+fn test_retained_topic_alias() {
+    let (cluster, port, _app_rx) = spawn_cluster(Config::default());
+
+    let mut publisher = Client::connect(port);
+    let publish = v5::Publish {
+        retain: true,
+        qos: v5::QoS::AtLeastOnce,
+        duplicate: false,
+        topic_name: TopicName::from("test/alias".to_string()),
+        packet_id: Some(1),
+        properties: Some(v5::PublishProperties {
+            topic_alias: Some(1),
+            ..v5::PublishProperties::default()
+        }),
+        payload: Some(b"retained".to_vec()),
+    };
+    publisher.send(v5::Packet::Publish(publish));
+    assert!(matches!(publisher.recv(), v5::Packet::PubAck(_)));
+
+    // subscriber did not allow topic-aliases, publisher's alias is not leaked.
+    let mut subscriber = Client::connect(port);
+    subscriber.subscribe(1, "test/#", v5::QoS::AtMostOnce);
+    match subscriber.recv() {
+        v5::Packet::Publish(publish) => {
+            assert_eq!(publish.topic_name, TopicName::from("test/alias".to_string()));
+            assert_eq!(publish.payload, Some(b"retained".to_vec()));
+            let alias = publish.properties.as_ref().and_then(|p| p.topic_alias);
+            assert_eq!(alias, None);
+        }
+        pkt => panic!("unexpected {:?}", pkt),
+    }
+
+    // retained PUBLISH using only the alias is stored under the resolved topic.
+    let publish = v5::Publish {
+        retain: true,
+        qos: v5::QoS::AtLeastOnce,
+        duplicate: false,
+        topic_name: TopicName::from("".to_string()),
+        packet_id: Some(2),
+        properties: Some(v5::PublishProperties {
+            topic_alias: Some(1),
+            ..v5::PublishProperties::default()
+        }),
+        payload: Some(b"updated".to_vec()),
+    };
+    publisher.send(v5::Packet::Publish(publish));
+    assert!(matches!(publisher.recv(), v5::Packet::PubAck(_)));
+
+    let mut subscriber = Client::connect(port);
+    subscriber.subscribe(1, "#", v5::QoS::AtMostOnce);
+    match subscriber.recv() {
+        v5::Packet::Publish(publish) => {
+            assert_eq!(publish.topic_name, TopicName::from("test/alias".to_string()));
+            assert_eq!(publish.payload, Some(b"updated".to_vec()));
+            let alias = publish.properties.as_ref().and_then(|p| p.topic_alias);
+            assert_eq!(alias, None);
+        }
+        pkt => panic!("unexpected {:?}", pkt),
+    }
+
+    cluster.close_wait();
+}
+
+struct DenyAll;
+
+impl Authenticator for DenyAll {
+    fn authenticate(&self, _connect: &v5::Connect) -> AuthResult {
+        AuthResult::Reject(v5::ConnackReasonCode::NotAuthorized)
+    }
+}
+
+#[test]
+fn test_authenticate_reject() {
+    let (cluster, port, _app_rx) =
+        spawn_cluster_with(Config::default(), Arc::new(DenyAll), Arc::new(AllowAll));
+
+    let mut client = Client::new(port);
+    client.send(v5::Packet::Connect(v5::Connect::default()));
+    match client.recv() {
+        v5::Packet::ConnAck(connack) => {
+            assert_eq!(connack.code, v5::ConnackReasonCode::NotAuthorized)
+        }
+        pkt => panic!("unexpected {:?}", pkt),
+    }
+    let mut buf = [0_u8; 16];
+    assert_eq!(client.sock.read(&mut buf).unwrap(), 0);
+
+    cluster.close_wait();
+}
+
+#[test]
+fn test_publish_order() {
+    let (cluster, port, _app_rx) = spawn_cluster(Config::default());
+
+    let mut subscriber = Client::connect(port);
+    subscriber.subscribe(1, "test/#", v5::QoS::AtLeastOnce);
+
+    let mut publisher = Client::connect(port);
+    let qoss = [v5::QoS::AtMostOnce, v5::QoS::AtLeastOnce, v5::QoS::AtMostOnce];
+    // write all the packets at once, so that they are routed in the same batch.
+    let mut data = Vec::default();
+    for (i, qos) in qoss.iter().enumerate() {
+        let packet_id = match qos {
+            v5::QoS::AtMostOnce => None,
+            _ => Some(i as u16 + 1),
+        };
+        let publish = v5::Publish {
+            retain: false,
+            qos: *qos,
+            duplicate: false,
+            topic_name: TopicName::from("test/a".to_string()),
+            packet_id,
+            properties: None,
+            payload: Some(vec![i as u8]),
+        };
+        data.extend_from_slice(v5::Packet::Publish(publish).encode().unwrap().as_ref());
+    }
+    publisher.sock.write_all(&data).unwrap();
+
+    for (i, qos) in qoss.iter().enumerate() {
+        match subscriber.recv() {
+            v5::Packet::Publish(publish) => {
+                assert_eq!(publish.qos, *qos, "{}", i);
+                assert_eq!(publish.payload, Some(vec![i as u8]));
+            }
+            pkt => panic!("unexpected {:?}", pkt),
+        }
+    }
+
+    cluster.close_wait();
+}
+
+// deny publish and subscribe on topics with a `private` level.
+struct DenyPrivate;
+
+impl Authorizer for DenyPrivate {
+    fn can_publish(&self, _client: &ClientID, topic: &TopicName) -> bool {
+        !topic.split('/').any(|level| level == "private")
+    }
+
+    fn can_subscribe(&self, _client: &ClientID, filter: &TopicFilter) -> bool {
+        !filter.split('/').any(|level| level == "private")
+    }
+}
+
+#[test]
+fn test_authorize() {
+    let (cluster, port, _app_rx) =
+        spawn_cluster_with(Config::default(), Arc::new(AllowAll), Arc::new(DenyPrivate));
+
+    let mut subscriber = Client::connect(port);
+    let opt = v5::SubscriptionOpt::new(
+        v5::RetainForwardRule::OnEverySubscribe,
+        false,
+        false,
+        v5::QoS::AtLeastOnce,
+    );
+    let filters = ["test/private/#", "test/#"]
+        .iter()
+        .map(|tf| v5::SubscribeFilter {
+            topic_filter: TopicFilter::from(tf.to_string()),
+            opt: opt.clone(),
+        })
+        .collect();
+    let sub = v5::Subscribe { packet_id: 1, properties: None, filters };
+    subscriber.send(v5::Packet::Subscribe(sub));
+    match subscriber.recv() {
+        v5::Packet::SubAck(suback) => {
+            let codes =
+                vec![v5::SubAckReasonCode::NotAuthorized, v5::SubAckReasonCode::QoS1];
+            assert_eq!(suback.return_codes, codes);
+        }
+        pkt => panic!("unexpected {:?}", pkt),
+    }
+
+    let mut publisher = Client::connect(port);
+    let topics = [
+        ("test/private/a", v5::QoS::AtMostOnce),
+        ("test/private/b", v5::QoS::AtLeastOnce),
+        ("test/a", v5::QoS::AtMostOnce),
+    ];
+    for (i, (topic, qos)) in topics.iter().enumerate() {
+        let packet_id = match qos {
+            v5::QoS::AtMostOnce => None,
+            _ => Some(i as u16 + 1),
+        };
+        let publish = v5::Publish {
+            retain: false,
+            qos: *qos,
+            duplicate: false,
+            topic_name: TopicName::from(topic.to_string()),
+            packet_id,
+            properties: None,
+            payload: Some(vec![i as u8]),
+        };
+        publisher.send(v5::Packet::Publish(publish));
+    }
+
+    // QoS1 PUBLISH is acknowledged with NotAuthorized.
+    match publisher.recv() {
+        v5::Packet::PubAck(puback) => {
+            assert_eq!(puback.packet_id, 2);
+            assert_eq!(puback.code, ReasonCode::NotAuthorized);
+        }
+        pkt => panic!("unexpected {:?}", pkt),
+    }
+    // QoS0 PUBLISH on denied topic is silently dropped.
+    match subscriber.recv() {
+        v5::Packet::Publish(publish) => {
+            assert_eq!(publish.as_topic_name(), &TopicName::from("test/a".to_string()));
+        }
+        pkt => panic!("unexpected {:?}", pkt),
+    }
+
+    cluster.close_wait();
+}
+
+#[test]
+fn test_metrics_text() {
+    let (cluster, port, _app_rx) = spawn_cluster(Config::default());
+
+    let mut client = Client::connect(port);
+    client.subscribe(1, "test/#", v5::QoS::AtLeastOnce);
+
+    let text = cluster.metrics_text().unwrap();
+    assert!(text.contains("mymq_packets_total{shard=\"0\"} 1\n"), "{}", text);
+    assert!(text.contains("mymq_connections{shard=\"0\"} 1\n"), "{}", text);
+    assert!(text.contains("mymq_cluster_subscriptions 1\n"), "{}", text);
+
+    let mut types = Vec::default();
+    for line in text.lines() {
+        match line.strip_prefix("# TYPE ") {
+            Some(rest) => {
+                let parts: Vec<&str> = rest.split(' ').collect();
+                assert_eq!(parts.len(), 2, "{}", line);
+                assert!(parts[0].starts_with("mymq_"), "{}", line);
+                assert!(matches!(parts[1], "counter" | "gauge"), "{}", line);
+                types.push(parts[0].to_string());
+            }
+            None if line.starts_with("# HELP ") => (),
+            None => {
+                // every sample shall follow the TYPE line of its metric.
+                let name = line.split(|ch| ch == '{' || ch == ' ').next().unwrap();
+                assert_eq!(types.last().map(|s| s.as_str()), Some(name), "{}", line);
+            }
+        }
+    }
+    assert!(types.contains(&"mymq_packets_total".to_string()));
+
+    cluster.close_wait();
+}
+
+#[derive(Default)]
+struct MockSink {
+    events: Mutex<Vec<BrokerEvent>>,
+}
+
+impl EventSink for MockSink {
+    fn on_event(&self, ev: BrokerEvent) {
+        self.events.lock().unwrap().push(ev)
+    }
+}
+
+#[test]
+fn test_event_sink() {
+    let sink = Arc::new(MockSink::default());
+
+    let port = free_port();
+    let config = Config { port, num_shards: 1, ..Config::default() };
+    let mut cluster = Cluster::from_config(config).unwrap();
+    cluster.set_event_sink(Arc::clone(&sink) as Arc<dyn EventSink + Send + Sync>);
+    let (app_tx, _app_rx) = mpsc::sync_channel(1024);
+    let cluster = cluster.spawn(app_tx).unwrap();
+
+    let mut client = Client::connect(port);
+    let addr = client.sock.local_addr().unwrap();
+    client.subscribe(1, "test/#", v5::QoS::AtLeastOnce);
+    let disconnect = v5::Disconnect {
+        code: v5::DisconnReasonCode::NormalDisconnect,
+        properties: None,
+    };
+    client.send(v5::Packet::Disconnect(disconnect));
+
+    // disconnect is handled asynchronously, wait for the event.
+    let deadline = time::Instant::now() + time::Duration::from_secs(5);
+    while sink.events.lock().unwrap().len() < 3 && time::Instant::now() < deadline {
+        std::thread::sleep(time::Duration::from_millis(10));
+    }
+
+    let events = sink.events.lock().unwrap().clone();
+    assert_eq!(events.len(), 3, "{:?}", events);
+    let client_id = match &events[0] {
+        BrokerEvent::ClientConnected { client_id, addr: raddr } => {
+            assert_eq!(*raddr, addr);
+            client_id.clone()
+        }
+        ev => panic!("unexpected {:?}", ev),
+    };
+    let filters = vec![TopicFilter::from("test/#".to_string())];
+    assert_eq!(
+        events[1],
+        BrokerEvent::Subscribed { client_id: client_id.clone(), filters }
+    );
+    assert_eq!(
+        events[2],
+        BrokerEvent::ClientDisconnected { client_id, reason: ReasonCode::Success }
+    );
+    assert!(events[2].to_json().contains("\"event\": \"client_disconnected\""));
+
+    cluster.close_wait();
+}
+
+#[test]
+fn test_sys_topics() {
+    let config = Config { sys_interval: Some(1), ..Config::default() };
+    let (cluster, port, _app_rx) = spawn_cluster(config);
+
+    let mut wildcard = Client::connect(port);
+    wildcard.subscribe(1, "#", v5::QoS::AtMostOnce);
+    let mut sys = Client::connect(port);
+    sys.subscribe(1, "$SYS/#", v5::QoS::AtMostOnce);
+
+    // wait for the next round of $SYS publish, after both clients have connected.
+    let topic_name = TopicName::from("$SYS/broker/clients/connected".to_string());
+    loop {
+        match sys.recv() {
+            v5::Packet::Publish(publish) if publish.as_topic_name() == &topic_name => {
+                if publish.payload == Some(b"2".to_vec()) {
+                    break;
+                }
+            }
+            v5::Packet::Publish(_) => (),
+            pkt => panic!("unexpected {:?}", pkt),
+        }
+    }
+
+    // `#` must not match $SYS topics, first message shall be the one published here.
+    let publish = v5::Publish {
+        retain: false,
+        qos: v5::QoS::AtMostOnce,
+        duplicate: false,
+        topic_name: TopicName::from("test/a".to_string()),
+        packet_id: None,
+        properties: None,
+        payload: Some(b"hello world".to_vec()),
+    };
+    wildcard.send(v5::Packet::Publish(publish));
+    match wildcard.recv() {
+        v5::Packet::Publish(publish) => {
+            assert_eq!(publish.as_topic_name(), &TopicName::from("test/a".to_string()));
+        }
+        pkt => panic!("unexpected {:?}", pkt),
+    }
+
+    cluster.close_wait();
+}
+
+#[test]
+fn test_idle_timeout() {
+    let config = Config { idle_timeout: Some(1), ..Config::default() };
+    let (cluster, port, _app_rx) = spawn_cluster(config);
+
+    // client connects with keep-alive ZERO and goes silent.
+    let start = time::Instant::now();
+    let mut client = Client::connect(port);
+    match client.recv() {
+        v5::Packet::Disconnect(disconnect) => {
+            assert_eq!(disconnect.code, v5::DisconnReasonCode::KeepAliveTimeout)
+        }
+        pkt => panic!("unexpected {:?}", pkt),
+    }
+    assert!(start.elapsed() >= time::Duration::from_secs(1));
+
+    cluster.close_wait();
+}
+
+#[test]
+fn test_keep_alive_timeout() {
+    let (cluster, port, _app_rx) = spawn_cluster(Config::default());
+
+    // client connects with keep-alive of 1 second and goes silent.
+    let mut connect = v5::Connect::default();
+    connect.keep_alive = 1;
+    connect.payload.client_id = ClientID("test-keep-alive-timeout".to_string());
+
+    let start = time::Instant::now();
+    let mut client = Client::new(port);
+    client.send(v5::Packet::Connect(connect));
+    match client.recv() {
+        v5::Packet::ConnAck(connack) => {
+            assert_eq!(connack.code, v5::ConnackReasonCode::Success)
+        }
+        pkt => panic!("unexpected {:?}", pkt),
+    }
+    match client.recv() {
+        v5::Packet::Disconnect(disconnect) => {
+            assert_eq!(disconnect.code, v5::DisconnReasonCode::KeepAliveTimeout)
+        }
+        pkt => panic!("unexpected {:?}", pkt),
+    }
+    assert!(start.elapsed() >= time::Duration::from_secs(1));
+    let mut buf = [0_u8; 16];
+    assert_eq!(client.sock.read(&mut buf).unwrap(), 0);
+
+    cluster.close_wait();
+}
+
+#[test]
+fn test_socket_read_timeout() {
+    let config = Config { sock_mqtt_read_timeout: 1, ..Config::default() };
+    let (cluster, port, _app_rx) = spawn_cluster(config);
+
+    let mut connect = v5::Connect::default();
+    connect.keep_alive = 30;
+    connect.payload.client_id = ClientID("test-socket-read-timeout".to_string());
+
+    let mut client = Client::new(port);
+    client.send(v5::Packet::Connect(connect));
+    match client.recv() {
+        v5::Packet::ConnAck(connack) => {
+            assert_eq!(connack.code, v5::ConnackReasonCode::Success)
+        }
+        pkt => panic!("unexpected {:?}", pkt),
+    }
+
+    // idle beyond read-timeout, but within keep-alive.
+    std::thread::sleep(time::Duration::from_secs(2));
+    client.send(v5::Packet::PingReq);
+    assert_eq!(client.recv(), v5::Packet::PingResp);
+
+    // client stalls in the middle of a packet.
+    client.sock.write_all(&[0xC0]).unwrap();
+    match client.recv() {
+        v5::Packet::Disconnect(disconnect) => {
+            assert_eq!(disconnect.code, v5::DisconnReasonCode::UnspecifiedError)
+        }
+        pkt => panic!("unexpected {:?}", pkt),
+    }
+
+    cluster.close_wait();
+}
+
+#[test]
+fn test_clients() {
+    let (cluster, port, _app_rx) = spawn_cluster(Config::default());
+    assert!(cluster.clients().unwrap().is_empty());
+
+    let mut connect = v5::Connect::default();
+    connect.keep_alive = 30;
+    connect.payload.client_id = ClientID("test-clients".to_string());
+
+    let start = time::Instant::now();
+    let mut client = Client::new(port);
+    client.send(v5::Packet::Connect(connect));
+    match client.recv() {
+        v5::Packet::ConnAck(connack) => {
+            assert_eq!(connack.code, v5::ConnackReasonCode::Success)
+        }
+        pkt => panic!("unexpected {:?}", pkt),
+    }
+    client.subscribe(1, "test/#", v5::QoS::AtLeastOnce);
+
+    let clients = cluster.clients().unwrap();
+    assert_eq!(clients.len(), 1);
+    let info = &clients[0];
+    assert_eq!(info.client_id, ClientID("test-clients".to_string()));
+    assert_eq!(info.raddr, client.sock.local_addr().unwrap());
+    assert!(info.connected_at >= start);
+    assert_eq!(info.keep_alive, 30);
+    assert!(info.clean_start);
+    assert_eq!(info.n_subscriptions, 1);
+    assert_eq!(info.n_inflight_inp, 0);
+    assert_eq!(info.n_inflight_out, 0);
+
+    // CONNACK is accounted in cluster-wide stats, without IPC.
+    let stats = cluster.stats();
+    assert_eq!(stats.connections, 1);
+    assert!(stats.items >= 1 && stats.bytes > 0, "{:?}", stats);
+
+    cluster.close_wait();
+}
+
+#[test]
+fn test_malformed_subscribe_disconnect() {
+    let (cluster, port, _app_rx) = spawn_cluster(Config::default());
+
+    // SUBSCRIBE with fixed-header flags 0b0000, must be 0b0010.
+    let mut client = Client::connect(port);
+    let data = [0x80, 0x09, 0x00, 0x01, 0x00, 0x00, 0x03, b'a', b'/', b'b', 0x00];
+    client.sock.write_all(&data).unwrap();
+    match client.recv() {
+        v5::Packet::Disconnect(disconnect) => {
+            assert_eq!(disconnect.code, v5::DisconnReasonCode::MalformedPacket)
+        }
+        pkt => panic!("unexpected {:?}", pkt),
+    }
+    let mut buf = [0_u8; 16];
+    assert_eq!(client.sock.read(&mut buf).unwrap(), 0);
+
+    cluster.close_wait();
+}
+
+#[test]
+fn test_protocol_error_disconnect() {
+    let (cluster, port, _app_rx) = spawn_cluster(Config::default());
+
+    // SUBACK is not expected from the client, session disconnects with 0x82.
+    let mut client = Client::connect(port);
+    let suback = v5::SubAck {
+        packet_id: 1,
+        properties: None,
+        return_codes: vec![v5::SubAckReasonCode::QoS0],
+    };
+    client.send(v5::Packet::SubAck(suback));
+    match client.recv() {
+        v5::Packet::Disconnect(disconnect) => {
+            assert_eq!(disconnect.code, v5::DisconnReasonCode::ProtocolError)
+        }
+        pkt => panic!("unexpected {:?}", pkt),
+    }
+    // exactly one DISCONNECT, before the socket is closed.
+    let mut buf = [0_u8; 16];
+    assert_eq!(client.sock.read(&mut buf).unwrap(), 0);
+
+    cluster.close_wait();
+}